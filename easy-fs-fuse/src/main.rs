@@ -45,6 +45,13 @@ fn easy_fs_pack() -> std::io::Result<()> {
                 .takes_value(true)
                 .help("Executable target dir(with backslash)"),
         )
+        .arg(
+            Arg::with_name("extra")
+                .short("e")
+                .long("extra")
+                .takes_value(true)
+                .help("Dir of files packed as they are, such as static Linux programs"),
+        )
         .get_matches();
     let src_path = matches.value_of("source").unwrap();
     let target_path = matches.value_of("target").unwrap();
@@ -80,6 +87,16 @@ fn easy_fs_pack() -> std::io::Result<()> {
         // write data to easy-fs
         inode.write_at(0, all_data.as_slice());
     }
+    if let Some(extra_path) = matches.value_of("extra") {
+        for dir_entry in read_dir(extra_path)? {
+            let dir_entry = dir_entry?;
+            let name = dir_entry.file_name().into_string().unwrap();
+            let mut all_data: Vec<u8> = Vec::new();
+            File::open(dir_entry.path())?.read_to_end(&mut all_data)?;
+            let inode = root_inode.create(name.as_str()).unwrap();
+            inode.write_at(0, all_data.as_slice());
+        }
+    }
     // list apps
     for app in root_inode.ls() {
         println!("{}", app);
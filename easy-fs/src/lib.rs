@@ -14,5 +14,6 @@ use bitmap::Bitmap;
 use block_cache::{block_cache_sync_all, get_block_cache};
 pub use block_dev::BlockDevice;
 pub use efs::EasyFileSystem;
+pub use layout::NAME_LENGTH_LIMIT;
 use layout::*;
 pub use vfs::Inode;
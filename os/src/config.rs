@@ -1,14 +1,25 @@
 #[allow(unused)]
 
 pub const USER_STACK_SIZE: usize = 4096 * 2;
+/// Thread-local storage of each thread, right above its user stack.
+pub const USER_TLS_SIZE: usize = 4096;
+pub const USER_HEAP_LIMIT: usize = 0x100_0000;
 pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
 pub const KERNEL_HEAP_SIZE: usize = 0x20_0000;
+pub const MAX_HARTS: usize = 4;
 pub const MEMORY_END: usize = 0x80800000;
+pub const SWAP_SIZE: usize = 0x40_0000;
+pub const MMAP_BASE: usize = 0x10_0000_0000;
+pub const MMAP_TOP: usize = SIGRETURN_TRAMPOLINE;
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
 
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT_BASE: usize = TRAMPOLINE - PAGE_SIZE;
+/// The end of the user half of the address space.
+pub const USER_SPACE_TOP: usize = 0x40_0000_0000;
+/// Signal handlers return to this page, at the top of the user half.
+pub const SIGRETURN_TRAMPOLINE: usize = USER_SPACE_TOP - PAGE_SIZE;
 
 pub use crate::board::{CLOCK_FREQ, MMIO};
 
@@ -1,8 +1,12 @@
 use crate::sbi::console_putchar;
+use crate::sync::SpinLock;
 use core::fmt::{self, Write};
 
 struct Stdout;
 
+/// Keeps the output of different harts from interleaving.
+static STDOUT_LOCK: SpinLock<()> = SpinLock::new(());
+
 impl Write for Stdout {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         for c in s.chars() {
@@ -13,9 +17,18 @@ impl Write for Stdout {
 }
 
 pub fn print(args: fmt::Arguments) {
+    let _guard = STDOUT_LOCK.lock();
     Stdout.write_fmt(args).unwrap();
 }
 
+/// Print raw bytes, which need not be UTF-8.
+pub fn print_bytes(bytes: &[u8]) {
+    let _guard = STDOUT_LOCK.lock();
+    for byte in bytes {
+        console_putchar(*byte as usize);
+    }
+}
+
 #[macro_export]
 macro_rules! print {
     ($fmt: literal $(, $($arg: tt)+)?) => {
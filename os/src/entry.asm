@@ -1,12 +1,29 @@
     .section .text.entry
     .globl _start
 _start:
-    la sp, boot_stack_top
+    # a0: hart id of the boot hart
+    call set_boot_stack
     call rust_main
 
+    .globl _start_secondary
+_start_secondary:
+    # a0: hart id of a hart started through the HSM extension
+    call set_boot_stack
+    call rust_main_secondary
+
+set_boot_stack:
+    # keep the hart id in tp and give each hart its own boot stack
+    mv tp, a0
+    addi t0, a0, 1
+    slli t0, t0, 16
+    la sp, boot_stack
+    add sp, sp, t0
+    ret
+
     .section .bss.stack
     .globl boot_stack
 boot_stack:
-    .space 4096 * 16
+    # 64 KiB for each of the MAX_HARTS harts
+    .space 4096 * 16 * 4
     .globl boot_stack_top
 boot_stack_top:
@@ -0,0 +1,68 @@
+/// Why a syscall has failed, with the errno values of Linux. Syscalls return
+/// the negated value.
+#[allow(clippy::upper_case_acronyms)]
+#[repr(isize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SysError {
+    /// not allowed for the caller
+    EPERM = 1,
+    /// no such file
+    ENOENT = 2,
+    /// no such process, thread or group
+    ESRCH = 3,
+    /// interrupted by a signal
+    EINTR = 4,
+    /// an input or output error
+    EIO = 5,
+    /// the arguments are too long
+    E2BIG = 7,
+    /// not an executable
+    ENOEXEC = 8,
+    /// not an open file, or not open for this
+    EBADF = 9,
+    /// no such child
+    ECHILD = 10,
+    /// the call would block, or has to be tried again
+    EAGAIN = 11,
+    /// out of memory or address space
+    ENOMEM = 12,
+    /// the file or mapping doesn't allow it
+    EACCES = 13,
+    /// the memory is not mapped or not accessible
+    EFAULT = 14,
+    /// the file exists already
+    EEXIST = 17,
+    /// a path component is not a directory
+    ENOTDIR = 20,
+    /// the file is a directory
+    EISDIR = 21,
+    /// invalid argument
+    EINVAL = 22,
+    /// too many open files
+    EMFILE = 24,
+    /// not a terminal
+    ENOTTY = 25,
+    /// the file system is full
+    ENOSPC = 28,
+    /// the result doesn't fit
+    ERANGE = 34,
+    /// waiting would never end
+    EDEADLK = 35,
+    /// the path is too long
+    ENAMETOOLONG = 36,
+    /// no such syscall
+    ENOSYS = 38,
+    /// a wait with a timeout has given up
+    ETIMEDOUT = 110,
+}
+
+/// What a syscall handler returns, the value is returned to user mode as it
+/// is.
+pub type SysResult<T = usize> = Result<T, SysError>;
+
+impl SysError {
+    /// The value returned to user mode.
+    pub fn as_ret(self) -> isize {
+        -(self as isize)
+    }
+}
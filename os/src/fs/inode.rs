@@ -1,34 +1,60 @@
-use super::File;
+use super::{File, Stat, S_IFDIR, S_IFREG};
 use crate::drivers::BLOCK_DEVICE;
-use crate::mm::UserBuffer;
-use crate::sync::UPSafeCell;
+use crate::error::{SysError, SysResult};
+use crate::sync::SpinLock;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use bitflags::*;
-use easy_fs::{EasyFileSystem, Inode};
+use easy_fs::{EasyFileSystem, Inode, BLOCK_SZ, NAME_LENGTH_LIMIT};
 use lazy_static::*;
 
 pub struct OSInode {
     readable: bool,
     writable: bool,
-    inner: UPSafeCell<OSInodeInner>,
+    /// every write goes to the end of the file
+    append: bool,
+    inner: SpinLock<OSInodeInner>,
 }
 
 pub struct OSInodeInner {
+    /// in bytes, or in entries for a directory
     offset: usize,
     inode: Arc<Inode>,
 }
 
+/// `d_type` of a `struct linux_dirent64`.
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+
+/// A `struct linux_dirent64` for `name`, `next` being the offset of the
+/// next entry.
+fn dirent64(inode_id: u32, next: usize, d_type: u8, name: &str) -> Vec<u8> {
+    // the fixed part is 19 bytes, the record is padded to 8 bytes
+    let len = (19 + name.len() + 1 + 7) & !7;
+    let mut record = Vec::with_capacity(len);
+    record.extend_from_slice(&(inode_id as u64).to_ne_bytes());
+    record.extend_from_slice(&(next as i64).to_ne_bytes());
+    record.extend_from_slice(&(len as u16).to_ne_bytes());
+    record.push(d_type);
+    record.extend_from_slice(name.as_bytes());
+    record.resize(len, 0);
+    record
+}
+
 impl OSInode {
-    pub fn new(readable: bool, writable: bool, inode: Arc<Inode>) -> Self {
+    pub fn new(readable: bool, writable: bool, append: bool, inode: Arc<Inode>) -> Self {
         Self {
             readable,
             writable,
-            inner: unsafe { UPSafeCell::new(OSInodeInner { offset: 0, inode }) },
+            append,
+            inner: SpinLock::new(OSInodeInner { offset: 0, inode }),
         }
     }
+    pub fn is_dir(&self) -> bool {
+        self.inner.lock().inode.is_dir()
+    }
     pub fn read_all(&self) -> Vec<u8> {
-        let mut inner = self.inner.exclusive_access();
+        let mut inner = self.inner.lock();
         let mut buffer = [0u8; 512];
         let mut v: Vec<u8> = Vec::new();
         loop {
@@ -50,58 +76,127 @@ lazy_static! {
     };
 }
 
+/// The swap area of the kernel, which is hidden from processes.
+pub const SWAP_FILE_NAME: &str = "swap";
+
 pub fn list_apps() {
     println!("/**** APPS ****");
     for app in ROOT_INODE.ls() {
-        println!("{}", app);
+        if app != SWAP_FILE_NAME {
+            println!("{}", app);
+        }
     }
     println!("**************/")
 }
 
 bitflags! {
+    /// Flags of `sys_openat`, with the values of Linux.
     pub struct OpenFlags: u32 {
         const RDONLY = 0;
         const WRONLY = 1 << 0;
         const RDWR = 1 << 1;
-        const CREATE = 1 << 9;
-        const TRUNC = 1 << 10;
+        const CREATE = 0o100;
+        /// fail with EEXIST if the file exists, with CREATE
+        const EXCL = 0o200;
+        /// ignored, the console is never made the controlling terminal
+        const NOCTTY = 0o400;
+        const TRUNC = 0o1000;
+        const APPEND = 0o2000;
+        /// ignored, easy-fs never blocks
+        const NONBLOCK = 0o4000;
+        /// ignored, files are never that large
+        const LARGEFILE = 0o100000;
+        /// fail with ENOTDIR unless the file is a directory
+        const DIRECTORY = 0o200000;
+        /// ignored, there are no symbolic links
+        const NOFOLLOW = 0o400000;
+        /// ignored, files are never closed on exec
+        const CLOEXEC = 0o2000000;
     }
 }
 
 impl OpenFlags {
-    /// Do not check validity for simplicity
-    /// Return (readable, writable)
+    /// Return (readable, writable), 3 is taken as RDWR.
     pub fn read_write(&self) -> (bool, bool) {
-        if self.is_empty() {
-            (true, false)
-        } else if self.contains(Self::WRONLY) {
-            (false, true)
-        } else {
-            (true, true)
+        match self.bits() & 3 {
+            0 => (true, false),
+            1 => (false, true),
+            _ => (true, true),
         }
     }
 }
 
-pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+/// The name of the file in the root directory which `path` stands for, or
+/// "" for the root directory itself. It is the only directory, paths
+/// through any other fail with ENOENT.
+fn lookup_name(path: &str) -> SysResult<&str> {
+    if path.is_empty() {
+        return Err(SysError::ENOENT);
+    }
+    let mut name = path.trim_start_matches('/');
+    while let Some(rest) = name.strip_prefix("./") {
+        name = rest.trim_start_matches('/');
+    }
+    if name == "." || name == ".." {
+        name = "";
+    }
+    if name.contains('/') {
+        return Err(SysError::ENOENT);
+    }
+    if name.len() > NAME_LENGTH_LIMIT {
+        return Err(SysError::ENAMETOOLONG);
+    }
+    Ok(name)
+}
+
+/// Open the file at `path`, creating it with CREATE. Directories can only
+/// be opened for reading. The swap area can be neither opened nor created.
+pub fn open_file(path: &str, flags: OpenFlags) -> SysResult<Arc<OSInode>> {
     let (readable, writable) = flags.read_write();
-    if flags.contains(OpenFlags::CREATE) {
-        if let Some(inode) = ROOT_INODE.find(name) {
-            // clear size
-            inode.clear();
-            Some(Arc::new(OSInode::new(readable, writable, inode)))
-        } else {
-            // create file
-            ROOT_INODE
-                .create(name)
-                .map(|inode| Arc::new(OSInode::new(readable, writable, inode)))
+    let name = lookup_name(path)?;
+    let inode = if name.is_empty() {
+        Arc::clone(&ROOT_INODE)
+    } else if name == SWAP_FILE_NAME {
+        if flags.contains(OpenFlags::CREATE) {
+            return Err(SysError::EACCES);
+        }
+        return Err(SysError::ENOENT);
+    } else if let Some(inode) = ROOT_INODE.find(name) {
+        if flags.contains(OpenFlags::CREATE | OpenFlags::EXCL) {
+            return Err(SysError::EEXIST);
         }
+        inode
+    } else if flags.contains(OpenFlags::CREATE) {
+        // someone else may have just created it
+        ROOT_INODE.create(name).ok_or(SysError::EEXIST)?
     } else {
-        ROOT_INODE.find(name).map(|inode| {
-            if flags.contains(OpenFlags::TRUNC) {
-                inode.clear();
-            }
-            Arc::new(OSInode::new(readable, writable, inode))
-        })
+        return Err(SysError::ENOENT);
+    };
+    if inode.is_dir() {
+        if writable || flags.contains(OpenFlags::CREATE) {
+            return Err(SysError::EISDIR);
+        }
+    } else if flags.contains(OpenFlags::DIRECTORY) {
+        return Err(SysError::ENOTDIR);
+    } else if flags.contains(OpenFlags::TRUNC) {
+        inode.clear();
+    }
+    let append = flags.contains(OpenFlags::APPEND);
+    Ok(Arc::new(OSInode::new(readable, writable, append, inode)))
+}
+
+/// What `sys_fstat` reports about a file or directory of easy-fs.
+fn inode_stat(inode: &Inode) -> Stat {
+    let size = inode.size();
+    let mode = if inode.is_dir() { S_IFDIR } else { S_IFREG } | 0o755;
+    Stat {
+        ino: inode.inode_id() as u64,
+        mode,
+        nlink: 1,
+        size: size as i64,
+        blksize: BLOCK_SZ as i32,
+        blocks: ((size + BLOCK_SZ - 1) / BLOCK_SZ) as i64,
+        ..Default::default()
     }
 }
 
@@ -112,28 +207,67 @@ impl File for OSInode {
     fn writable(&self) -> bool {
         self.writable
     }
-    fn read(&self, mut buf: UserBuffer) -> usize {
-        let mut inner = self.inner.exclusive_access();
-        let mut total_read_size = 0usize;
-        for slice in buf.buffers.iter_mut() {
-            let read_size = inner.inode.read_at(inner.offset, *slice);
-            if read_size == 0 {
+    fn read(&self, buf: &mut [u8]) -> SysResult {
+        let mut inner = self.inner.lock();
+        if inner.inode.is_dir() {
+            return Err(SysError::EISDIR);
+        }
+        let read_size = inner.inode.read_at(inner.offset, buf);
+        inner.offset += read_size;
+        Ok(read_size)
+    }
+    /// Write as much as fits, failing only if nothing does.
+    fn write(&self, buf: &[u8]) -> SysResult {
+        let mut inner = self.inner.lock();
+        if self.append {
+            inner.offset = inner.inode.size();
+        }
+        let write_size = inner.inode.write_at(inner.offset, buf);
+        inner.offset += write_size;
+        if write_size == 0 && !buf.is_empty() {
+            return Err(SysError::ENOSPC);
+        }
+        Ok(write_size)
+    }
+    /// Directories can't be mapped.
+    fn inode(&self) -> Option<Arc<Inode>> {
+        Some(Arc::clone(&self.inner.lock().inode)).filter(|inode| !inode.is_dir())
+    }
+    fn stat(&self) -> Stat {
+        inode_stat(&self.inner.lock().inode)
+    }
+    /// "." and ".." come first, both being the root directory, the swap
+    /// area is left out.
+    fn read_dir(&self, buf: &mut [u8]) -> SysResult {
+        let mut inner = self.inner.lock();
+        if !inner.inode.is_dir() {
+            return Err(SysError::ENOTDIR);
+        }
+        let root_id = inner.inode.inode_id();
+        let mut names = inner.inode.ls();
+        names.retain(|name| name != SWAP_FILE_NAME);
+        let total = names.len() + 2;
+        let mut records = Vec::new();
+        while inner.offset < total {
+            let record = match inner.offset {
+                0 => dirent64(root_id, 1, DT_DIR, "."),
+                1 => dirent64(root_id, 2, DT_DIR, ".."),
+                i => {
+                    let name = names[i - 2].as_str();
+                    let inode_id = inner.inode.find(name).map_or(0, |inode| inode.inode_id());
+                    dirent64(inode_id, i + 1, DT_REG, name)
+                }
+            };
+            if records.len() + record.len() > buf.len() {
                 break;
             }
-            inner.offset += read_size;
-            total_read_size += read_size;
+            records.extend(record);
+            inner.offset += 1;
         }
-        total_read_size
-    }
-    fn write(&self, buf: UserBuffer) -> usize {
-        let mut inner = self.inner.exclusive_access();
-        let mut total_write_size = 0usize;
-        for slice in buf.buffers.iter() {
-            let write_size = inner.inode.write_at(inner.offset, *slice);
-            assert_eq!(write_size, slice.len());
-            inner.offset += write_size;
-            total_write_size += write_size;
+        if records.is_empty() && inner.offset < total {
+            return Err(SysError::EINVAL);
         }
-        total_write_size
+        buf[..records.len()].copy_from_slice(&records);
+        Ok(records.len())
     }
 }
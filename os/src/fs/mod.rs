@@ -2,15 +2,74 @@ mod inode;
 mod pipe;
 mod stdio;
 
-use crate::mm::UserBuffer;
+use crate::error::{SysError, SysResult};
+use alloc::sync::Arc;
+use easy_fs::Inode;
 
 pub trait File: Send + Sync {
     fn readable(&self) -> bool;
     fn writable(&self) -> bool;
-    fn read(&self, buf: UserBuffer) -> usize;
-    fn write(&self, buf: UserBuffer) -> usize;
+    /// Return how many bytes have been read, 0 at the end of the file. `buf`
+    /// is a kernel buffer, files which block never hold user memory.
+    fn read(&self, buf: &mut [u8]) -> SysResult;
+    /// Return how many bytes have been written.
+    fn write(&self, buf: &[u8]) -> SysResult;
+    /// Files on easy-fs can be mapped into memory through their inode.
+    fn inode(&self) -> Option<Arc<Inode>> {
+        None
+    }
+    /// The console is a terminal, with job control.
+    fn is_tty(&self) -> bool {
+        false
+    }
+    /// What `sys_fstat` reports about the file.
+    fn stat(&self) -> Stat;
+    /// Fill `buf` with `struct linux_dirent64` records of the entries not
+    /// listed yet, return how many bytes have been filled, 0 at the end.
+    /// Fail with EINVAL if the next record doesn't fit, and with ENOTDIR
+    /// unless the file is a directory.
+    fn read_dir(&self, _buf: &mut [u8]) -> SysResult {
+        Err(SysError::ENOTDIR)
+    }
 }
 
-pub use inode::{list_apps, open_file, OSInode, OpenFlags};
+/// The type bits of `st_mode`, and those of a regular file, a directory, a
+/// pipe and a terminal.
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFIFO: u32 = 0o010000;
+pub const S_IFCHR: u32 = 0o020000;
+
+/// `struct stat` of Linux on RISC-V. Nothing keeps track of owners or
+/// times, they are all 0.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stat {
+    pub dev: u64,
+    pub ino: u64,
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub rdev: u64,
+    __pad: u64,
+    pub size: i64,
+    pub blksize: i32,
+    __pad2: i32,
+    pub blocks: i64,
+    pub atime_sec: i64,
+    pub atime_nsec: u64,
+    pub mtime_sec: i64,
+    pub mtime_nsec: u64,
+    pub ctime_sec: i64,
+    pub ctime_nsec: u64,
+    __unused: [u32; 2],
+}
+
+pub use inode::{list_apps, open_file, OSInode, OpenFlags, ROOT_INODE, SWAP_FILE_NAME};
 pub use pipe::{make_pipe, Pipe};
-pub use stdio::{Stdin, Stdout};
+pub use stdio::{
+    console_background, console_foreground, console_hangup, console_poll, console_set_foreground,
+    console_set_session, Stdin, Stdout,
+};
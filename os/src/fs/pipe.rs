@@ -1,25 +1,25 @@
-use super::File;
-use crate::mm::UserBuffer;
-use crate::sync::UPSafeCell;
+use super::{File, Stat, S_IFIFO};
+use crate::error::{SysError, SysResult};
+use crate::sync::SpinLock;
 use alloc::sync::{Arc, Weak};
 
-use crate::task::suspend_current_and_run_next;
+use crate::task::{current_interrupted, suspend_current_and_run_next};
 
 pub struct Pipe {
     readable: bool,
     writable: bool,
-    buffer: Arc<UPSafeCell<PipeRingBuffer>>,
+    buffer: Arc<SpinLock<PipeRingBuffer>>,
 }
 
 impl Pipe {
-    pub fn read_end_with_buffer(buffer: Arc<UPSafeCell<PipeRingBuffer>>) -> Self {
+    pub fn read_end_with_buffer(buffer: Arc<SpinLock<PipeRingBuffer>>) -> Self {
         Self {
             readable: true,
             writable: false,
             buffer,
         }
     }
-    pub fn write_end_with_buffer(buffer: Arc<UPSafeCell<PipeRingBuffer>>) -> Self {
+    pub fn write_end_with_buffer(buffer: Arc<SpinLock<PipeRingBuffer>>) -> Self {
         Self {
             readable: false,
             writable: true,
@@ -98,13 +98,22 @@ impl PipeRingBuffer {
 
 /// Return (read_end, write_end)
 pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
-    let buffer = Arc::new(unsafe { UPSafeCell::new(PipeRingBuffer::new()) });
+    let buffer = Arc::new(SpinLock::new(PipeRingBuffer::new()));
     let read_end = Arc::new(Pipe::read_end_with_buffer(buffer.clone()));
     let write_end = Arc::new(Pipe::write_end_with_buffer(buffer.clone()));
-    buffer.exclusive_access().set_write_end(&write_end);
+    buffer.lock().set_write_end(&write_end);
     (read_end, write_end)
 }
 
+/// What a read or write interrupted after `size` bytes returns, those are
+/// not lost.
+fn interrupted(size: usize) -> SysResult {
+    match size {
+        0 => Err(SysError::EINTR),
+        size => Ok(size),
+    }
+}
+
 impl File for Pipe {
     fn readable(&self) -> bool {
         self.readable
@@ -112,55 +121,70 @@ impl File for Pipe {
     fn writable(&self) -> bool {
         self.writable
     }
-    fn read(&self, buf: UserBuffer) -> usize {
-        assert!(self.readable());
-        let mut buf_iter = buf.into_iter();
+    fn read(&self, buf: &mut [u8]) -> SysResult {
+        if !self.readable() {
+            return Err(SysError::EBADF);
+        }
+        let mut buf_iter = buf.iter_mut();
         let mut read_size = 0usize;
         loop {
-            let mut ring_buffer = self.buffer.exclusive_access();
+            let mut ring_buffer = self.buffer.lock();
             let loop_read = ring_buffer.available_read();
             if loop_read == 0 {
                 if ring_buffer.all_write_ends_closed() {
-                    return read_size;
+                    return Ok(read_size);
                 }
                 drop(ring_buffer);
+                if current_interrupted() {
+                    return interrupted(read_size);
+                }
                 suspend_current_and_run_next();
                 continue;
             }
             // read at most loop_read bytes
             for _ in 0..loop_read {
                 if let Some(byte_ref) = buf_iter.next() {
-                    unsafe {
-                        *byte_ref = ring_buffer.read_byte();
-                    }
+                    *byte_ref = ring_buffer.read_byte();
                     read_size += 1;
                 } else {
-                    return read_size;
+                    return Ok(read_size);
                 }
             }
         }
     }
-    fn write(&self, buf: UserBuffer) -> usize {
-        assert!(self.writable());
-        let mut buf_iter = buf.into_iter();
+    fn write(&self, buf: &[u8]) -> SysResult {
+        if !self.writable() {
+            return Err(SysError::EBADF);
+        }
+        let mut buf_iter = buf.iter();
         let mut write_size = 0usize;
         loop {
-            let mut ring_buffer = self.buffer.exclusive_access();
+            let mut ring_buffer = self.buffer.lock();
             let loop_write = ring_buffer.available_write();
             if loop_write == 0 {
                 drop(ring_buffer);
+                if current_interrupted() {
+                    return interrupted(write_size);
+                }
                 suspend_current_and_run_next();
                 continue;
             }
             // write at most loop_write bytes
             for _ in 0..loop_write {
                 if let Some(byte_ref) = buf_iter.next() {
-                    ring_buffer.write_byte(unsafe { *byte_ref });
+                    ring_buffer.write_byte(*byte_ref);
                     write_size += 1;
                 } else {
-                    return write_size;
+                    return Ok(write_size);
                 }
             }
         }
     }
+    fn stat(&self) -> Stat {
+        Stat {
+            mode: S_IFIFO | 0o600,
+            nlink: 1,
+            ..Default::default()
+        }
+    }
 }
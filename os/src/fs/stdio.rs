@@ -1,7 +1,132 @@
-use super::File;
-use crate::mm::UserBuffer;
+use super::{File, Stat, S_IFCHR};
+use crate::console::print_bytes;
+use crate::error::{SysError, SysResult};
 use crate::sbi::console_getchar;
-use crate::task::suspend_current_and_run_next;
+use crate::sync::SpinLock;
+use crate::task::{
+    current_interrupted, send_group_signal, suspend_current_and_run_next, SignalFlags,
+};
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+const CTRL_C: u8 = 0x03;
+const CTRL_Z: u8 = 0x1a;
+const CTRL_BACKSLASH: u8 = 0x1c;
+/// Characters typed beyond this while nobody reads are lost.
+const INPUT_MAX: usize = 4096;
+
+/// The console as the terminal of a session: typed characters are kept until
+/// they are read, except those sending signals to the foreground process
+/// group of the session.
+struct Console {
+    input: VecDeque<u8>,
+    /// the session the console is the controlling terminal of
+    session: Option<usize>,
+    foreground: usize,
+}
+
+lazy_static! {
+    static ref CONSOLE: SpinLock<Console> = SpinLock::new(Console {
+        input: VecDeque::new(),
+        session: None,
+        foreground: 0,
+    });
+}
+
+/// Move what has been typed to the input of the console. It is called on
+/// timer interrupts as well, so that Ctrl-C reaches a program which doesn't
+/// read the console.
+pub fn console_poll() {
+    let mut signals = Vec::new();
+    let mut console = CONSOLE.lock();
+    loop {
+        let c = console_getchar();
+        // nothing typed
+        if c as isize <= 0 {
+            break;
+        }
+        let signal = match c as u8 {
+            CTRL_C => SignalFlags::SIGINT,
+            CTRL_Z => SignalFlags::SIGTSTP,
+            CTRL_BACKSLASH => SignalFlags::SIGQUIT,
+            c => {
+                if console.input.len() < INPUT_MAX {
+                    console.input.push_back(c);
+                }
+                continue;
+            }
+        };
+        if console.session.is_some() {
+            signals.push((console.foreground, signal));
+        }
+    }
+    drop(console);
+    // sending signals locks PCBs
+    for (pgid, signal) in signals {
+        send_group_signal(pgid, signal);
+    }
+}
+
+/// Make the console the controlling terminal of session `sid`, with group
+/// `pgid` in the foreground. It is taken from another session only if
+/// `steal` is set. Return false if it belongs to another session.
+pub fn console_set_session(sid: usize, pgid: usize, steal: bool) -> bool {
+    let mut console = CONSOLE.lock();
+    if console.session.map_or(false, |session| session != sid) && !steal {
+        return false;
+    }
+    console.session = Some(sid);
+    console.foreground = pgid;
+    true
+}
+
+/// The foreground group, if the console belongs to session `sid`.
+pub fn console_foreground(sid: usize) -> Option<usize> {
+    let console = CONSOLE.lock();
+    Some(console.foreground).filter(|_| console.session == Some(sid))
+}
+
+/// Put group `pgid` in the foreground, if the console belongs to session
+/// `sid`. The group has to be one of the session.
+pub fn console_set_foreground(sid: usize, pgid: usize) -> bool {
+    let mut console = CONSOLE.lock();
+    if console.session != Some(sid) {
+        return false;
+    }
+    console.foreground = pgid;
+    true
+}
+
+/// Whether group `pgid` of session `sid` is in the background of the
+/// console, and so may not read it.
+pub fn console_background(sid: usize, pgid: usize) -> bool {
+    let console = CONSOLE.lock();
+    console.session == Some(sid) && console.foreground != pgid
+}
+
+/// The leader of session `sid` has exited, the console is no longer its
+/// terminal. Return the foreground group to hang up.
+pub fn console_hangup(sid: usize) -> Option<usize> {
+    let mut console = CONSOLE.lock();
+    if console.session != Some(sid) {
+        return None;
+    }
+    console.session = None;
+    Some(console.foreground)
+}
+
+/// The console as a character device, as `sys_fstat` reports it.
+fn console_stat() -> Stat {
+    Stat {
+        mode: S_IFCHR | 0o620,
+        nlink: 1,
+        // /dev/console
+        rdev: (5 << 8) | 1,
+        blksize: 1024,
+        ..Default::default()
+    }
+}
 
 pub struct Stdin;
 
@@ -14,27 +139,33 @@ impl File for Stdin {
     fn writable(&self) -> bool {
         false
     }
-    fn read(&self, mut user_buf: UserBuffer) -> usize {
-        assert_eq!(user_buf.len(), 1);
-        // busy loop
-        let mut c: usize;
-        loop {
-            c = console_getchar();
-            if c == 0 {
-                suspend_current_and_run_next();
-                continue;
-            } else {
-                break;
-            }
+    /// Only one character is read at a time.
+    fn read(&self, buf: &mut [u8]) -> SysResult {
+        if buf.is_empty() {
+            return Ok(0);
         }
-        let ch = c as u8;
-        unsafe {
-            user_buf.buffers[0].as_mut_ptr().write_volatile(ch);
-        }
-        1
+        // poll until something has been typed
+        let ch = loop {
+            console_poll();
+            if let Some(ch) = CONSOLE.lock().input.pop_front() {
+                break ch;
+            }
+            if current_interrupted() {
+                return Err(SysError::EINTR);
+            }
+            suspend_current_and_run_next();
+        };
+        buf[0] = ch;
+        Ok(1)
+    }
+    fn write(&self, _buf: &[u8]) -> SysResult {
+        Err(SysError::EBADF)
+    }
+    fn is_tty(&self) -> bool {
+        true
     }
-    fn write(&self, _user_buf: UserBuffer) -> usize {
-        panic!("Cannot write to stdin!");
+    fn stat(&self) -> Stat {
+        console_stat()
     }
 }
 
@@ -45,13 +176,19 @@ impl File for Stdout {
     fn writable(&self) -> bool {
         true
     }
-    fn read(&self, _user_buf: UserBuffer) -> usize {
-        panic!("Cannot read from stdout!");
+    fn read(&self, _buf: &mut [u8]) -> SysResult {
+        Err(SysError::EBADF)
     }
-    fn write(&self, user_buf: UserBuffer) -> usize {
-        for buffer in user_buf.buffers.iter() {
-            print!("{}", core::str::from_utf8(*buffer).unwrap());
-        }
-        user_buf.len()
+    /// Bytes go to the console as they are, they need not be UTF-8 and a
+    /// character may span two writes.
+    fn write(&self, buf: &[u8]) -> SysResult {
+        print_bytes(buf);
+        Ok(buf.len())
+    }
+    fn is_tty(&self) -> bool {
+        true
+    }
+    fn stat(&self) -> Stat {
+        console_stat()
     }
 }
@@ -0,0 +1,39 @@
+use crate::config::MAX_HARTS;
+use crate::sbi::hart_start;
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Bit `i` is set once hart `i` runs the kernel.
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+/// The kernel keeps the id of the running hart in `tp`, see `entry.asm`
+/// and `trap.S`.
+pub fn hart_id() -> usize {
+    let hartid;
+    unsafe {
+        asm!("mv {}, tp", out(reg) hartid);
+    }
+    hartid
+}
+
+pub fn set_online() {
+    ONLINE_HARTS.fetch_or(1 << hart_id(), Ordering::SeqCst);
+}
+
+/// Mask of the harts running the kernel except the current one.
+pub fn other_harts_mask() -> usize {
+    ONLINE_HARTS.load(Ordering::SeqCst) & !(1 << hart_id())
+}
+
+/// Ask the SBI to start the harts other than the boot hart at
+/// `_start_secondary`. Harts which do not exist are refused by the SBI.
+pub fn start_other_harts() {
+    extern "C" {
+        fn _start_secondary();
+    }
+    for hartid in (0..MAX_HARTS).filter(|id| *id != hart_id()) {
+        if hart_start(hartid, _start_secondary as usize, 0) {
+            println!("[kernel] starting hart {}", hartid);
+        }
+    }
+}
@@ -19,7 +19,9 @@ mod board;
 mod console;
 mod config;
 mod drivers;
+mod error;
 mod fs;
+mod hart;
 mod lang_items;
 mod mm;
 mod sbi;
@@ -45,9 +47,14 @@ fn clear_bss() {
 }
 
 #[no_mangle]
-pub fn rust_main() -> ! {
+pub fn rust_main(hartid: usize) -> ! {
     clear_bss();
     println!("[kernel] Hello, world!");
+    assert!(
+        hartid < config::MAX_HARTS,
+        "boot hart {} is not supported",
+        hartid
+    );
     mm::init();
     mm::remap_test();
     trap::init();
@@ -55,6 +62,22 @@ pub fn rust_main() -> ! {
     timer::set_next_trigger();
     fs::list_apps();
     task::add_initproc();
+    hart::set_online();
+    hart::start_other_harts();
     task::run_tasks();
     panic!("Unreachable in rust_main!");
 }
+
+/// Entry of the harts started by the boot hart, after the kernel has been
+/// initialized.
+#[no_mangle]
+pub fn rust_main_secondary(hartid: usize) -> ! {
+    mm::init_secondary();
+    trap::init();
+    trap::enable_timer_interrupt();
+    timer::set_next_trigger();
+    hart::set_online();
+    println!("[kernel] hart {} is online", hartid);
+    task::run_tasks();
+    panic!("Unreachable in rust_main_secondary!");
+}
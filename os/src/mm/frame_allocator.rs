@@ -1,6 +1,6 @@
 use super::{PhysAddr, PhysPageNum};
 use crate::config::MEMORY_END;
-use crate::sync::UPSafeCell;
+use crate::sync::{futex_frame_freed, SpinLock};
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
 use lazy_static::*;
@@ -28,6 +28,8 @@ impl Debug for FrameTracker {
 
 impl Drop for FrameTracker {
     fn drop(&mut self) {
+        // futex words in the frame are gone, e.g. unmapped
+        futex_frame_freed(self.ppn);
         frame_dealloc(self.ppn);
     }
 }
@@ -50,6 +52,9 @@ impl StackFrameAllocator {
         self.end = r.0;
         println!("last {} Physical Frames.", self.end - self.current);
     }
+    pub fn remaining(&self) -> usize {
+        self.end - self.current + self.recycled.len()
+    }
 }
 impl FrameAllocator for StackFrameAllocator {
     fn new() -> Self {
@@ -83,29 +88,44 @@ impl FrameAllocator for StackFrameAllocator {
 type FrameAllocatorImpl = StackFrameAllocator;
 
 lazy_static! {
-    pub static ref FRAME_ALLOCATOR: UPSafeCell<FrameAllocatorImpl> =
-        unsafe { UPSafeCell::new(FrameAllocatorImpl::new()) };
+    pub static ref FRAME_ALLOCATOR: SpinLock<FrameAllocatorImpl> =
+        SpinLock::new(FrameAllocatorImpl::new());
 }
 
 pub fn init_frame_allocator() {
     extern "C" {
         fn ekernel();
     }
-    FRAME_ALLOCATOR.exclusive_access().init(
+    FRAME_ALLOCATOR.lock().init(
         PhysAddr::from(ekernel as usize).ceil(),
         PhysAddr::from(MEMORY_END).floor(),
     );
 }
 
 pub fn frame_alloc() -> Option<FrameTracker> {
-    FRAME_ALLOCATOR
-        .exclusive_access()
-        .alloc()
-        .map(FrameTracker::new)
+    FRAME_ALLOCATOR.lock().alloc().map(FrameTracker::new)
+}
+
+/// Frames kept free for the kernel, so that page tables, kernel stacks and
+/// trap contexts can still be allocated while user pages fill the memory.
+pub const RESERVED_FRAMES: usize = 64;
+
+/// Allocate a frame for a user page, leaving `RESERVED_FRAMES` frames to the
+/// kernel.
+pub fn user_frame_alloc() -> Option<FrameTracker> {
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    if allocator.remaining() <= RESERVED_FRAMES {
+        return None;
+    }
+    allocator.alloc().map(FrameTracker::new)
 }
 
 pub fn frame_dealloc(ppn: PhysPageNum) {
-    FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
+    FRAME_ALLOCATOR.lock().dealloc(ppn);
+}
+
+pub fn frame_remaining() -> usize {
+    FRAME_ALLOCATOR.lock().remaining()
 }
 
 #[allow(unused)]
@@ -1,13 +1,24 @@
-use super::{frame_alloc, FrameTracker};
-use super::{PTEFlags, PageTable, PageTableEntry};
+use super::replace::page_mapped;
+use super::shm::{shm_detach, ShmSegment};
+use super::swap::{swap_slot_alloc, SwapSlot};
+use super::{frame_alloc, user_frame_alloc, FrameTracker};
+use super::{copy_to_user, PTEFlags, PageTable, PageTableEntry};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use super::{StepByOne, VPNRange};
-use crate::config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE};
-use crate::sync::UPSafeCell;
+use crate::config::{
+    MEMORY_END, MMAP_BASE, MMAP_TOP, MMIO, PAGE_SIZE, SIGRETURN_TRAMPOLINE, TRAMPOLINE,
+    USER_HEAP_LIMIT, USER_SPACE_TOP, USER_TLS_SIZE,
+};
+use crate::error::{SysError, SysResult};
+use crate::hart::other_harts_mask;
+use crate::sbi::remote_sfence_vma;
+use crate::sync::{futex_frame_waited, SpinLock};
+use crate::task::ProcessControlBlock;
 use alloc::collections::BTreeMap;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
 use core::arch::asm;
+use easy_fs::Inode;
 use lazy_static::*;
 use riscv::register::satp;
 
@@ -22,20 +33,55 @@ extern "C" {
     fn ebss();
     fn ekernel();
     fn strampoline();
+    fn ssigreturn();
 }
 
+/// Types of auxiliary vector entries which describe the ELF file.
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_ENTRY: usize = 9;
+
 lazy_static! {
-    pub static ref KERNEL_SPACE: Arc<UPSafeCell<MemorySet>> =
-        Arc::new(unsafe { UPSafeCell::new(MemorySet::new_kernel()) });
+    pub static ref KERNEL_SPACE: Arc<SpinLock<MemorySet>> =
+        Arc::new(SpinLock::new(MemorySet::new_kernel()));
 }
 
 pub fn kernel_token() -> usize {
-    KERNEL_SPACE.exclusive_access().token()
+    KERNEL_SPACE.lock().token()
+}
+
+/// Flush the translations of `[start_vpn, end_vpn)` on all harts after they
+/// have been removed or restricted. A hart flushes its TLB whenever it enters
+/// or leaves the kernel, but another hart may be running a thread of the same
+/// process, or still cache a kernel stack which has been freed.
+fn tlb_shootdown(start_vpn: VirtPageNum, end_vpn: VirtPageNum) {
+    let start_va: usize = VirtAddr::from(start_vpn).into();
+    let size = (end_vpn.0 - start_vpn.0) * PAGE_SIZE;
+    unsafe {
+        asm!("sfence.vma");
+    }
+    remote_sfence_vma(other_harts_mask(), start_va, size);
+}
+
+/// The initial content of the thread-local storage of a program, from its
+/// `PT_TLS` segment. Every thread gets a copy, followed by zeros for `.tbss`.
+#[derive(Clone)]
+struct TlsTemplate {
+    data: Vec<u8>,
+    /// tp points below the copy by the misalignment of the segment
+    offset: usize,
 }
 
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
+    /// the heap lies in `[heap_bottom, program_brk)`
+    heap_bottom: usize,
+    program_brk: usize,
+    tls: Option<TlsTemplate>,
+    /// the process whose user pages are handed to the replacement policy
+    owner: Weak<ProcessControlBlock>,
 }
 
 impl MemorySet {
@@ -43,6 +89,23 @@ impl MemorySet {
         Self {
             page_table: PageTable::new(),
             areas: Vec::new(),
+            heap_bottom: 0,
+            program_brk: 0,
+            tls: None,
+            owner: Weak::new(),
+        }
+    }
+    /// Make `process` the owner of the space, the user pages mapped already
+    /// are handed to the replacement policy, and those mapped later as they
+    /// are.
+    pub fn set_owner(&mut self, process: &Arc<ProcessControlBlock>) {
+        self.owner = Arc::downgrade(process);
+        for area in self.areas.iter() {
+            if area.map_perm.contains(MapPermission::U) {
+                for vpn in area.data_frames.keys() {
+                    page_mapped(&self.owner, *vpn);
+                }
+            }
         }
     }
     pub fn token(&self) -> usize {
@@ -60,6 +123,19 @@ impl MemorySet {
             None,
         );
     }
+    /// Frames of a lazy area are allocated on first access.
+    pub fn insert_lazy_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        permission: MapPermission,
+    ) {
+        self.push_lazy(
+            MapArea::new(start_va, end_va, MapType::Framed, permission),
+            None,
+            0,
+        );
+    }
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
         if let Some((idx, area)) = self
             .areas
@@ -68,16 +144,244 @@ impl MemorySet {
             .find(|(_, area)| area.vpn_range.get_start() == start_vpn)
         {
             area.unmap(&mut self.page_table);
+            tlb_shootdown(area.vpn_range.get_start(), area.vpn_range.get_end());
             self.areas.remove(idx);
         }
     }
+    /// Whether `[start_vpn, end_vpn)` overlaps no area.
+    pub fn is_free(&self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        self.areas.iter().all(|area| {
+            area.vpn_range.get_end() <= start_vpn || area.vpn_range.get_start() >= end_vpn
+        })
+    }
+    /// First fit search for `pages` free pages in `[MMAP_BASE, MMAP_TOP)`.
+    pub fn find_free_area(&self, pages: usize) -> Option<VirtPageNum> {
+        let mut start = VirtAddr::from(MMAP_BASE).floor();
+        let mut areas: Vec<&MapArea> = self
+            .areas
+            .iter()
+            .filter(|area| area.vpn_range.get_end() > start)
+            .collect();
+        areas.sort_by_key(|area| area.vpn_range.get_start());
+        for area in areas {
+            if area.vpn_range.get_start().0 >= start.0 + pages {
+                break;
+            }
+            start = start.max(area.vpn_range.get_end());
+        }
+        if start.0 + pages <= VirtAddr::from(MMAP_TOP).floor().0 {
+            Some(start)
+        } else {
+            None
+        }
+    }
+    /// Map `[start_va, end_va)` for mmap, assume that no conflicts.
+    /// Pages are backed on demand. Those of a shared mapping are kept by the
+    /// mapping, or by its file, so that every address space mapping them
+    /// sees the same frames however late it faults them in.
+    pub fn insert_mmap_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        permission: MapPermission,
+        shared: bool,
+        file: Option<MmapFile>,
+    ) {
+        let mut map_area = MapArea::new(start_va, end_va, MapType::Framed, permission);
+        if shared {
+            map_area.shared = true;
+            map_area.shared_pages = Some(match file.as_ref() {
+                Some(file) => SharedPages::of_file(&file.inode, file.offset / PAGE_SIZE),
+                None => SharedPages::new(0),
+            });
+        }
+        map_area.file = file;
+        self.push_lazy(map_area, None, 0);
+    }
+    /// Attach a shared memory segment at `start_va`, assume that no conflicts.
+    pub fn insert_shm_area(&mut self, start_va: VirtAddr, segment: Arc<ShmSegment>) {
+        let end_va: VirtAddr = (start_va.0 + segment.frames.len() * PAGE_SIZE).into();
+        let mut map_area = MapArea::new(
+            start_va,
+            end_va,
+            MapType::Framed,
+            MapPermission::R | MapPermission::W | MapPermission::U,
+        );
+        map_area.shared = true;
+        let mut vpn = start_va.floor();
+        for frame in segment.frames.iter() {
+            map_area.data_frames.insert(vpn, Arc::clone(frame));
+            vpn.step();
+        }
+        map_area.shm = Some(segment);
+        map_area.remap(&mut self.page_table);
+        self.areas.push(map_area);
+    }
+    /// Detach the shared memory segment attached at `start_vpn`.
+    /// Return false if there is none.
+    pub fn remove_shm_area(&mut self, start_vpn: VirtPageNum) -> bool {
+        if self
+            .areas
+            .iter()
+            .any(|area| area.vpn_range.get_start() == start_vpn && area.shm.is_some())
+        {
+            self.remove_area_with_start_vpn(start_vpn);
+            true
+        } else {
+            false
+        }
+    }
+    pub fn program_brk(&self) -> usize {
+        self.program_brk
+    }
+    /// Move the program break to `new_brk`, the heap pages are backed on
+    /// demand. Return false if the heap would shrink below its bottom, leave
+    /// the space reserved before the user stacks, or grow into another area
+    /// or the guard page below it.
+    pub fn set_program_brk(&mut self, new_brk: usize) -> bool {
+        if new_brk < self.heap_bottom || new_brk > self.heap_bottom + USER_HEAP_LIMIT {
+            return false;
+        }
+        let heap_start = VirtAddr::from(self.heap_bottom).floor();
+        let old_end = VirtAddr::from(self.program_brk).ceil();
+        let new_end = VirtAddr::from(new_brk).ceil();
+        if new_end < old_end {
+            self.remove_range(new_end, old_end);
+        } else if new_end > old_end {
+            if !self.is_free(old_end, VirtPageNum(new_end.0 + 1)) {
+                return false;
+            }
+            let heap_perm = MapPermission::R | MapPermission::W | MapPermission::U;
+            // extend the heap area unless it has been unmapped or changed by mmap
+            match self.areas.iter().position(|area| {
+                area.vpn_range.get_start() >= heap_start
+                    && area.vpn_range.get_end() == old_end
+                    && area.map_perm == heap_perm
+                    && !area.shared
+                    && area.file.is_none()
+            }) {
+                Some(idx) => {
+                    let area = &mut self.areas[idx];
+                    area.vpn_range = VPNRange::new(area.vpn_range.get_start(), new_end);
+                }
+                None => self.insert_lazy_area(old_end.into(), new_end.into(), heap_perm),
+            }
+        }
+        self.program_brk = new_brk;
+        true
+    }
+    /// Split the user areas overlapping `[start_vpn, end_vpn)` so that none of
+    /// them crosses a bound of the range.
+    fn split_user_areas(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) {
+        // tails are pushed to the back and split again if needed
+        let mut idx = 0;
+        while idx < self.areas.len() {
+            let area = &mut self.areas[idx];
+            let at = [start_vpn, end_vpn]
+                .iter()
+                .copied()
+                .find(|at| area.vpn_range.get_start() < *at && *at < area.vpn_range.get_end());
+            if let Some(at) = at.filter(|_| area.map_perm.contains(MapPermission::U)) {
+                let tail = area.split_off(at);
+                self.areas.push(tail);
+            }
+            idx += 1;
+        }
+    }
+    /// Unmap the user pages in `[start_vpn, end_vpn)`, splitting the areas
+    /// which are partially covered.
+    pub fn remove_range(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) {
+        self.split_user_areas(start_vpn, end_vpn);
+        let mut idx = 0;
+        while idx < self.areas.len() {
+            let area = &mut self.areas[idx];
+            if area.map_perm.contains(MapPermission::U)
+                && area.vpn_range.get_start() >= start_vpn
+                && area.vpn_range.get_end() <= end_vpn
+            {
+                area.unmap(&mut self.page_table);
+                self.areas.remove(idx);
+            } else {
+                idx += 1;
+            }
+        }
+        tlb_shootdown(start_vpn, end_vpn);
+    }
+    /// Change the permission of the user pages in `[start_vpn, end_vpn)` and
+    /// rewrite their PTEs.
+    /// Fail with ENOMEM if part of the range is not mapped, or with EACCES if
+    /// `permission` asks for writing a shared file mapping whose file is
+    /// read-only.
+    pub fn protect_range(
+        &mut self,
+        start_vpn: VirtPageNum,
+        end_vpn: VirtPageNum,
+        permission: MapPermission,
+    ) -> SysResult<()> {
+        let mut areas: Vec<&MapArea> = self
+            .areas
+            .iter()
+            .filter(|area| {
+                area.map_perm.contains(MapPermission::U)
+                    && area.vpn_range.get_end() > start_vpn
+                    && area.vpn_range.get_start() < end_vpn
+            })
+            .collect();
+        areas.sort_by_key(|area| area.vpn_range.get_start());
+        let mut next = start_vpn;
+        for area in areas {
+            if area.vpn_range.get_start() > next {
+                return Err(SysError::ENOMEM);
+            }
+            if area.shared
+                && permission.contains(MapPermission::W)
+                && !area.file.as_ref().map_or(true, |file| file.writable)
+            {
+                return Err(SysError::EACCES);
+            }
+            next = area.vpn_range.get_end();
+        }
+        if next < end_vpn {
+            return Err(SysError::ENOMEM);
+        }
+        self.split_user_areas(start_vpn, end_vpn);
+        for area in self.areas.iter_mut() {
+            if area.map_perm.contains(MapPermission::U)
+                && area.vpn_range.get_start() >= start_vpn
+                && area.vpn_range.get_end() <= end_vpn
+            {
+                area.map_perm = permission | MapPermission::U;
+                area.remap(&mut self.page_table);
+            }
+        }
+        tlb_shootdown(start_vpn, end_vpn);
+        Ok(())
+    }
     fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
         map_area.map(&mut self.page_table);
         if let Some(data) = data {
-            map_area.copy_data(&mut self.page_table, data);
+            map_area.copy_data(data, 0);
         }
         self.areas.push(map_area);
     }
+    /// Only map the pages covered by `data`, which starts `offset` bytes into
+    /// the first page, the rest is left to page faults. Return false if
+    /// there are not enough frames, the area is left out then.
+    fn push_lazy(&mut self, mut map_area: MapArea, data: Option<&[u8]>, offset: usize) -> bool {
+        if let Some(data) = data.filter(|data| !data.is_empty()) {
+            let mut vpn = map_area.vpn_range.get_start();
+            for _ in 0..(offset + data.len() + PAGE_SIZE - 1) / PAGE_SIZE {
+                if !map_area.map_user(&mut self.page_table, vpn, &self.owner) {
+                    map_area.unmap(&mut self.page_table);
+                    return false;
+                }
+                vpn.step();
+            }
+            map_area.copy_data(data, offset);
+        }
+        self.areas.push(map_area);
+        true
+    }
     /// Mention that trampoline is not collected by areas.
     fn map_trampoline(&mut self) {
         self.page_table.map(
@@ -86,6 +390,14 @@ impl MemorySet {
             PTEFlags::R | PTEFlags::X,
         );
     }
+    /// Unlike the trampoline, the sigreturn code is run in user mode.
+    fn map_sigreturn_trampoline(&mut self) {
+        self.page_table.map(
+            VirtAddr::from(SIGRETURN_TRAMPOLINE).into(),
+            PhysAddr::from(ssigreturn as usize).into(),
+            PTEFlags::R | PTEFlags::X | PTEFlags::U,
+        );
+    }
     /// Without kernel stacks.
     pub fn new_kernel() -> Self {
         let mut memory_set = Self::new_bare();
@@ -163,12 +475,124 @@ impl MemorySet {
         }
         memory_set
     }
-    /// Include sections in elf and trampoline,
-    /// also returns user_sp_base and entry point.
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
+    /// Whether `from_elf` can load `elf_data`: a 64-bit ELF file whose
+    /// program headers and segments lie within it, whose TLS segment fits in
+    /// a TLS block, and whose LOAD segments share no page and stay below the
+    /// sigreturn trampoline. xmas_elf panics on headers out of bounds.
+    pub fn elf_is_valid(elf_data: &[u8]) -> bool {
+        let elf = match xmas_elf::ElfFile::new(elf_data) {
+            Ok(elf) if elf.header.pt1.magic == [0x7f, 0x45, 0x4c, 0x46] => elf,
+            _ => return false,
+        };
+        let pt2 = &elf.header.pt2;
+        let ph_size = core::mem::size_of::<xmas_elf::program::ProgramHeader64>();
+        let headers_in_file = pt2
+            .ph_offset()
+            .checked_add(pt2.ph_count() as u64 * pt2.ph_entry_size() as u64)
+            .map_or(false, |end| end <= elf_data.len() as u64);
+        if elf.header.pt1.class() != xmas_elf::header::Class::SixtyFour
+            || pt2.ph_entry_size() as usize != ph_size
+            || !headers_in_file
+        {
+            return false;
+        }
+        let headers_valid = (0..pt2.ph_count()).all(|i| {
+            let ph = match elf.program_header(i) {
+                Ok(ph) => ph,
+                Err(_) => return false,
+            };
+            let in_file = ph
+                .offset()
+                .checked_add(ph.file_size())
+                .map_or(false, |end| end <= elf_data.len() as u64);
+            let in_memory = ph.virtual_addr().checked_add(ph.mem_size()).is_some()
+                && ph.file_size() <= ph.mem_size();
+            match ph.get_type() {
+                Ok(xmas_elf::program::Type::Load) => in_file && in_memory,
+                Ok(xmas_elf::program::Type::Tls) => {
+                    let align = (ph.align() as usize).max(1);
+                    let offset = ph.virtual_addr() as usize & (align - 1);
+                    in_file
+                        && align.is_power_of_two()
+                        && align <= PAGE_SIZE
+                        && ph.mem_size() <= USER_TLS_SIZE as u64
+                        && offset + ph.mem_size() as usize <= USER_TLS_SIZE
+                }
+                Ok(_) => true,
+                Err(_) => false,
+            }
+        });
+        if !headers_valid {
+            return false;
+        }
+        // each segment is mapped as an area of whole pages
+        let mut loads: Vec<(u64, u64)> = (0..pt2.ph_count())
+            .map(|i| elf.program_header(i).unwrap())
+            .filter(|ph| ph.get_type() == Ok(xmas_elf::program::Type::Load))
+            .map(|ph| (ph.virtual_addr(), ph.virtual_addr() + ph.mem_size()))
+            .collect();
+        if loads
+            .iter()
+            .any(|(_, end)| *end > SIGRETURN_TRAMPOLINE as u64)
+        {
+            return false;
+        }
+        let page_size = PAGE_SIZE as u64;
+        for (start, end) in loads.iter_mut() {
+            *start /= page_size;
+            *end = (*end + page_size - 1) / page_size;
+        }
+        loads.sort_unstable();
+        loads.windows(2).all(|pair| pair[0].1 <= pair[1].0)
+    }
+    /// The number of frames for user pages `from_elf` takes to load
+    /// `elf_data`, which has been checked by `elf_is_valid`: those of the
+    /// pages holding the contents of the LOAD segments.
+    pub fn elf_frames(elf_data: &[u8]) -> usize {
+        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
+        (0..elf.header.pt2.ph_count())
+            .map(|i| elf.program_header(i).unwrap())
+            .filter(|ph| ph.get_type() == Ok(xmas_elf::program::Type::Load) && ph.file_size() > 0)
+            .map(|ph| {
+                let start = ph.virtual_addr() as usize;
+                let end = start + ph.file_size() as usize;
+                VirtAddr::from(end).ceil().0 - VirtAddr::from(start).floor().0
+            })
+            .sum()
+    }
+    /// The entries of the auxiliary vector which describe the program of
+    /// `elf_data`, to be passed to it on the new user stack. The program
+    /// headers are found in the LOAD segment which covers them, AT_PHDR is
+    /// left out if there is none.
+    pub fn elf_aux_entries(elf_data: &[u8]) -> Vec<(usize, usize)> {
+        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
+        let pt2 = &elf.header.pt2;
+        let ph_offset = pt2.ph_offset();
+        let mut entries = Vec::new();
+        let phdr = (0..pt2.ph_count())
+            .map(|i| elf.program_header(i).unwrap())
+            .find(|ph| {
+                ph.get_type() == Ok(xmas_elf::program::Type::Load)
+                    && (ph.offset()..ph.offset() + ph.file_size()).contains(&ph_offset)
+            })
+            .map(|ph| (ph.virtual_addr() + ph_offset - ph.offset()) as usize);
+        if let Some(phdr) = phdr {
+            entries.push((AT_PHDR, phdr));
+        }
+        entries.push((AT_PHENT, pt2.ph_entry_size() as usize));
+        entries.push((AT_PHNUM, pt2.ph_count() as usize));
+        entries.push((AT_ENTRY, pt2.entry_point() as usize));
+        entries
+    }
+    /// Include sections in elf and trampoline, the heap starts right after
+    /// the last segment and user stacks are placed above it.
+    /// Also returns user_sp_base and entry point, or None if there are not
+    /// enough frames for the contents of the segments.
+    pub fn from_elf(elf_data: &[u8]) -> Option<(Self, usize, usize)> {
         let mut memory_set = Self::new_bare();
         // map trampoline
         memory_set.map_trampoline();
+        memory_set.map_sigreturn_trampoline();
         // map program headers of elf, with U flag
         let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
         let elf_header = elf.header;
@@ -194,40 +618,226 @@ impl MemorySet {
                 }
                 let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
                 max_end_vpn = map_area.vpn_range.get_end();
-                memory_set.push(
+                // .bss and the rest of the segment are backed on demand
+                if !memory_set.push_lazy(
                     map_area,
                     Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
+                    start_va.page_offset(),
+                ) {
+                    return None;
+                }
+            } else if ph.get_type().unwrap() == xmas_elf::program::Type::Tls {
+                // thread-local blocks are page aligned, tp points at them
+                let align = (ph.align() as usize).max(1);
+                let offset = ph.virtual_addr() as usize & (align - 1);
+                assert!(
+                    align <= PAGE_SIZE && offset + ph.mem_size() as usize <= USER_TLS_SIZE,
+                    "TLS segment too large!"
                 );
+                memory_set.tls = Some(TlsTemplate {
+                    data: elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]
+                        .to_vec(),
+                    offset,
+                });
             }
         }
         let max_end_va: VirtAddr = max_end_vpn.into();
-        let mut user_stack_base: usize = max_end_va.into();
+        memory_set.heap_bottom = max_end_va.into();
+        memory_set.program_brk = memory_set.heap_bottom;
+        let mut user_stack_base: usize = memory_set.heap_bottom + USER_HEAP_LIMIT;
         user_stack_base += PAGE_SIZE;
-        (
+        Some((
             memory_set,
             user_stack_base,
             elf.header.pt2.entry_point() as usize,
-        )
+        ))
     }
-    pub fn from_existed_user(user_space: &MemorySet) -> MemorySet {
+    /// User pages are shared with the new space copy-on-write: both sides map
+    /// them read-only and the first store duplicates the page. Pages of shared
+    /// mappings stay writable on both sides.
+    /// Trap contexts are written by the kernel directly, so they are copied.
+    pub fn from_existed_user(user_space: &mut MemorySet) -> MemorySet {
         let mut memory_set = Self::new_bare();
+        memory_set.heap_bottom = user_space.heap_bottom;
+        memory_set.program_brk = user_space.program_brk;
+        memory_set.tls = user_space.tls.clone();
         // map trampoline
         memory_set.map_trampoline();
-        // copy data sections/trap_context/user_stack
+        memory_set.map_sigreturn_trampoline();
+        // share data sections/user_stack and copy trap_context
         for area in user_space.areas.iter() {
-            let new_area = MapArea::from_another(area);
-            memory_set.push(new_area, None);
-            // copy data from another space
-            for vpn in area.vpn_range {
-                let src_ppn = user_space.translate(vpn).unwrap().ppn();
-                let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
-                dst_ppn
-                    .get_bytes_array()
-                    .copy_from_slice(src_ppn.get_bytes_array());
+            let mut new_area = MapArea::from_another(area);
+            if area.map_perm.contains(MapPermission::U) {
+                for (vpn, frame) in area.data_frames.iter() {
+                    new_area.data_frames.insert(*vpn, Arc::clone(frame));
+                }
+                // swapped pages are read into private frames on the first fault
+                for (vpn, slot) in area.swapped.iter() {
+                    new_area.swapped.insert(*vpn, Arc::clone(slot));
+                }
+                area.remap(&mut user_space.page_table);
+                tlb_shootdown(area.vpn_range.get_start(), area.vpn_range.get_end());
+                new_area.remap(&mut memory_set.page_table);
+                memory_set.areas.push(new_area);
+            } else {
+                memory_set.push(new_area, None);
+                // copy data from another space
+                for vpn in area.vpn_range {
+                    let src_ppn = user_space.translate(vpn).unwrap().ppn();
+                    let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
+                    dst_ppn
+                        .get_bytes_array()
+                        .copy_from_slice(src_ppn.get_bytes_array());
+                }
             }
         }
         memory_set
     }
+    /// Resolve a page fault at `vpn` caused by an `access` which is one of
+    /// R, W and X: back a lazy page with a fresh frame, read a swapped page
+    /// back, or give a copy-on-write page a private copy on a write.
+    /// Fail with EFAULT if the access is invalid and the fault is a real one,
+    /// or with ENOMEM if there is no frame left for the page or it can't be
+    /// read back.
+    pub fn handle_page_fault(&mut self, vpn: VirtPageNum, access: MapPermission) -> SysResult<()> {
+        let area = match self.areas.iter_mut().find(|area| area.contains(vpn)) {
+            Some(area) => area,
+            None => return Err(SysError::EFAULT),
+        };
+        if area.map_type != MapType::Framed || !area.map_perm.contains(access) {
+            return Err(SysError::EFAULT);
+        }
+        let resolved = match self.page_table.translate(vpn) {
+            Some(pte) if pte.is_valid() => {
+                if access == MapPermission::W && !pte.writable() {
+                    area.copy_on_write(&mut self.page_table, vpn)
+                } else {
+                    // the fault may have been resolved meanwhile by another
+                    // thread of the process on another hart
+                    let needed = PTEFlags::from_bits(access.bits).unwrap() | PTEFlags::U;
+                    if !pte.flags().contains(needed) {
+                        return Err(SysError::EFAULT);
+                    }
+                    true
+                }
+            }
+            _ => {
+                let mapped = area.map_user(&mut self.page_table, vpn, &self.owner);
+                if mapped {
+                    area.swapped.remove(&vpn);
+                }
+                mapped
+            }
+        };
+        if resolved {
+            Ok(())
+        } else {
+            Err(SysError::ENOMEM)
+        }
+    }
+    /// Write the page at `vpn` to the swap area and release its frame.
+    /// Return false if it is not a resident private user page, or the swap
+    /// area is full or can't be written, the page stays resident then.
+    pub fn swap_out(&mut self, vpn: VirtPageNum) -> bool {
+        match self.areas.iter_mut().find(|area| area.contains(vpn)) {
+            Some(area) if area.map_perm.contains(MapPermission::U) => {
+                area.swap_out(&mut self.page_table, vpn)
+            }
+            _ => false,
+        }
+    }
+    /// Return whether the page at `vpn` has been accessed since the last call,
+    /// or None if it is not resident.
+    pub fn test_and_clear_accessed(&mut self, vpn: VirtPageNum) -> Option<bool> {
+        let pte = self.page_table.translate(vpn).filter(|pte| pte.is_valid())?;
+        let accessed = pte.flags().contains(PTEFlags::A);
+        if accessed {
+            self.page_table.set_flags(vpn, pte.flags() - PTEFlags::A);
+        }
+        Some(accessed)
+    }
+    /// The kernel accesses user memory through physical addresses, which
+    /// bypasses the page table, so resolve lazy and copy-on-write pages
+    /// in `[start, start + len)` beforehand.
+    /// Fail like `handle_page_fault` if part of the range can't be accessed.
+    pub fn fault_in(&mut self, start: usize, len: usize, write: bool) -> SysResult<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        let start_vpn = VirtAddr::from(start).floor();
+        let end_vpn = VirtAddr::from(start + len).ceil();
+        for vpn in VPNRange::new(start_vpn, end_vpn) {
+            match self.page_table.translate(vpn) {
+                Some(pte)
+                    if pte.is_valid()
+                        && pte.flags().contains(PTEFlags::U)
+                        && (!write || pte.writable()) => {}
+                _ => {
+                    let access = if write {
+                        MapPermission::W
+                    } else {
+                        MapPermission::R
+                    };
+                    self.handle_page_fault(vpn, access)?;
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Write `data` to the user pages at `start` even if they are read-only,
+    /// as a debugger does to put breakpoints in the code of a process. A
+    /// private page gets a copy of its own first, so that the processes
+    /// sharing it copy-on-write don't see the change. Return false if part
+    /// of the range is not readable in user mode, or is a read-only shared
+    /// mapping, nothing is written then.
+    pub fn write_forced(&mut self, start: usize, data: &[u8]) -> bool {
+        if start
+            .checked_add(data.len())
+            .map_or(true, |end| end > USER_SPACE_TOP)
+            || self.fault_in(start, data.len(), false).is_err()
+        {
+            return false;
+        }
+        let start_vpn = VirtAddr::from(start).floor();
+        let end_vpn = VirtAddr::from(start + data.len()).ceil();
+        for vpn in VPNRange::new(start_vpn, end_vpn) {
+            match self.areas.iter_mut().find(|area| area.contains(vpn)) {
+                Some(area)
+                    if area.map_type == MapType::Framed
+                        && area.map_perm.contains(MapPermission::U) =>
+                {
+                    if !area.shared {
+                        if !area.copy_on_write(&mut self.page_table, vpn) {
+                            return false;
+                        }
+                    } else if !area.map_perm.contains(MapPermission::W) {
+                        return false;
+                    }
+                }
+                _ => return false,
+            }
+        }
+        // a few bytes at a time, for breakpoints
+        for (va, byte) in (start..).zip(data.iter()) {
+            let ppn = self.translate(VirtAddr::from(va).floor()).unwrap().ppn();
+            ppn.get_bytes_array()[va % PAGE_SIZE] = *byte;
+        }
+        true
+    }
+    /// Fill the thread-local storage block at `base` of a new thread with
+    /// the TLS template of the program, if it has one. Fail with ENOMEM if
+    /// there is no frame left for it.
+    pub fn init_tls(&mut self, base: usize) -> SysResult<()> {
+        let tls = match self.tls.take() {
+            Some(tls) => tls,
+            None => return Ok(()),
+        };
+        // the block is mapped with the thread, the rest of it stays zero
+        // until it is used
+        let copied = copy_to_user(self, base + tls.offset, &tls.data);
+        self.tls = Some(tls);
+        copied
+    }
     pub fn activate(&self) {
         let satp = self.page_table.token();
         unsafe {
@@ -244,11 +854,85 @@ impl MemorySet {
     }
 }
 
+/// The file behind a file-backed mapping.
+#[derive(Clone)]
+pub struct MmapFile {
+    pub inode: Arc<Inode>,
+    /// offset in the file of the first page of the area
+    pub offset: usize,
+    /// whether the file was opened for writing
+    pub writable: bool,
+}
+
+/// The pages of a shared mapping by index, kept for every area which maps
+/// it, so that all of them use the same frames. Those of a file are shared
+/// by all of its shared mappings, until none is left.
+pub struct SharedPages {
+    frames: SpinLock<BTreeMap<usize, Arc<FrameTracker>>>,
+}
+
+lazy_static! {
+    /// The pages of the files mapped shared somewhere, by inode id.
+    static ref FILE_PAGES: SpinLock<BTreeMap<u32, Weak<SharedPages>>> =
+        SpinLock::new(BTreeMap::new());
+}
+
+/// The shared pages of an area, whose first page has index `first`.
+#[derive(Clone)]
+pub struct SharedRange {
+    pages: Arc<SharedPages>,
+    first: usize,
+}
+
+impl SharedPages {
+    /// The pages of a new anonymous shared mapping.
+    pub fn new(first: usize) -> SharedRange {
+        SharedRange {
+            pages: Arc::new(Self {
+                frames: SpinLock::new(BTreeMap::new()),
+            }),
+            first,
+        }
+    }
+    /// The pages of the file of `inode`, from page `first` on.
+    pub fn of_file(inode: &Inode, first: usize) -> SharedRange {
+        let id = inode.inode_id();
+        let mut file_pages = FILE_PAGES.lock();
+        // forget the files which aren't mapped any more
+        file_pages.retain(|_, pages| pages.strong_count() > 0);
+        if let Some(pages) = file_pages.get(&id).and_then(Weak::upgrade) {
+            return SharedRange { pages, first };
+        }
+        let range = Self::new(first);
+        file_pages.insert(id, Arc::downgrade(&range.pages));
+        range
+    }
+    /// The frame of page `index`, allocated and filled by `fill` the first
+    /// time it is asked for.
+    fn frame(&self, index: usize, fill: impl FnOnce(PhysPageNum)) -> Option<Arc<FrameTracker>> {
+        let mut frames = self.frames.lock();
+        if let Some(frame) = frames.get(&index) {
+            return Some(Arc::clone(frame));
+        }
+        let frame = Arc::new(user_frame_alloc()?);
+        fill(frame.ppn);
+        frames.insert(index, Arc::clone(&frame));
+        Some(frame)
+    }
+}
+
 pub struct MapArea {
     vpn_range: VPNRange,
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    data_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
+    swapped: BTreeMap<VirtPageNum, Arc<SwapSlot>>,
     map_type: MapType,
     map_perm: MapPermission,
+    /// stores are visible to every address space mapping the area
+    shared: bool,
+    /// where the frames of a shared mapping are found
+    shared_pages: Option<SharedRange>,
+    file: Option<MmapFile>,
+    shm: Option<Arc<ShmSegment>>,
 }
 
 impl MapArea {
@@ -263,42 +947,257 @@ impl MapArea {
         Self {
             vpn_range: VPNRange::new(start_vpn, end_vpn),
             data_frames: BTreeMap::new(),
+            swapped: BTreeMap::new(),
             map_type,
             map_perm,
+            shared: false,
+            shared_pages: None,
+            file: None,
+            shm: None,
         }
     }
     pub fn from_another(another: &MapArea) -> Self {
         Self {
             vpn_range: VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
             data_frames: BTreeMap::new(),
+            swapped: BTreeMap::new(),
             map_type: another.map_type,
             map_perm: another.map_perm,
+            shared: another.shared,
+            shared_pages: another.shared_pages.clone(),
+            file: another.file.clone(),
+            shm: another.shm.clone(),
+        }
+    }
+    /// Return false if there is no frame left for the page, or the page
+    /// can't be read back from the swap area. User pages can't take the
+    /// frames kept for the kernel.
+    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        let reserved = !self.map_perm.contains(MapPermission::U);
+        self.map_one_from(page_table, vpn, reserved)
+    }
+    /// Map `vpn` like `map_one`, and hand the page to the replacement policy
+    /// as one of `owner`. The frames of user pages are all mapped here.
+    fn map_user(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+        owner: &Weak<ProcessControlBlock>,
+    ) -> bool {
+        if !self.map_one_from(page_table, vpn, false) {
+            return false;
         }
+        page_mapped(owner, vpn);
+        true
     }
-    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+    /// Map `vpn` like `map_one`, with a frame kept for the kernel if
+    /// `reserved` is set.
+    fn map_one_from(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+        reserved: bool,
+    ) -> bool {
         let ppn: PhysPageNum;
         match self.map_type {
             MapType::Identical => {
                 ppn = PhysPageNum(vpn.0);
             }
             MapType::Framed => {
-                let frame = frame_alloc().unwrap();
+                let frame = match self.shared_pages.as_ref() {
+                    Some(range) => {
+                        let index = range.first + (vpn.0 - self.vpn_range.get_start().0);
+                        range.pages.frame(index, |ppn| self.read_file(vpn, ppn))
+                    }
+                    None => {
+                        let frame = if reserved {
+                            frame_alloc()
+                        } else {
+                            user_frame_alloc()
+                        };
+                        // a swapped page is read back before it is mapped
+                        frame
+                            .filter(|frame| match self.swapped.get(&vpn) {
+                                Some(slot) => slot.read(frame.ppn),
+                                None => {
+                                    self.read_file(vpn, frame.ppn);
+                                    true
+                                }
+                            })
+                            .map(Arc::new)
+                    }
+                };
+                let frame = match frame {
+                    Some(frame) => frame,
+                    None => return false,
+                };
                 ppn = frame.ppn;
                 self.data_frames.insert(vpn, frame);
             }
         }
-        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
-        page_table.map(vpn, ppn, pte_flags);
+        // inaccessible pages keep their frames without a leaf PTE
+        if self.accessible() {
+            let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+            page_table.map(vpn, ppn, pte_flags);
+        }
+        true
     }
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        // pages of lazy areas may have never been touched or be swapped out
         if self.map_type == MapType::Framed {
-            self.data_frames.remove(&vpn);
+            self.swapped.remove(&vpn);
+            match self.data_frames.remove(&vpn) {
+                Some(frame) => self.write_back(vpn, frame.ppn),
+                None => return,
+            }
+        }
+        if page_table
+            .translate(vpn)
+            .map_or(false, |pte| pte.is_valid())
+        {
+            page_table.unmap(vpn);
+        }
+    }
+    pub fn contains(&self, vpn: VirtPageNum) -> bool {
+        vpn >= self.vpn_range.get_start() && vpn < self.vpn_range.get_end()
+    }
+    fn accessible(&self) -> bool {
+        self.map_perm
+            .intersects(MapPermission::R | MapPermission::W | MapPermission::X)
+    }
+    /// Flags to map `frame` with, or None if the area is inaccessible.
+    /// Private frames still referred to by another address space are mapped
+    /// without W so that the first store copies them.
+    fn pte_flags(&self, frame: &Arc<FrameTracker>) -> Option<PTEFlags> {
+        if !self.accessible() {
+            return None;
+        }
+        let mut perm = self.map_perm;
+        if !self.shared && Arc::strong_count(frame) > 1 {
+            perm -= MapPermission::W;
         }
-        page_table.unmap(vpn);
+        PTEFlags::from_bits(perm.bits)
     }
+    /// Rewrite the PTEs of the resident pages after `map_perm` or the
+    /// sharing of the frames has changed.
+    pub fn remap(&self, page_table: &mut PageTable) {
+        for (vpn, frame) in self.data_frames.iter() {
+            let valid = page_table
+                .translate(*vpn)
+                .map_or(false, |pte| pte.is_valid());
+            match self.pte_flags(frame) {
+                Some(pte_flags) if valid => page_table.set_flags(*vpn, pte_flags),
+                Some(pte_flags) => page_table.map(*vpn, frame.ppn, pte_flags),
+                None if valid => page_table.unmap(*vpn),
+                None => {}
+            }
+        }
+    }
+    /// Split the area at `at`, keeping `[start, at)` and returning `[at, end)`.
+    pub fn split_off(&mut self, at: VirtPageNum) -> Self {
+        let start = self.vpn_range.get_start();
+        let mut tail = Self::from_another(self);
+        tail.vpn_range = VPNRange::new(at, self.vpn_range.get_end());
+        if let Some(file) = tail.file.as_mut() {
+            file.offset += (at.0 - start.0) * PAGE_SIZE;
+        }
+        if let Some(range) = tail.shared_pages.as_mut() {
+            range.first += at.0 - start.0;
+        }
+        tail.data_frames = self.data_frames.split_off(&at);
+        tail.swapped = self.swapped.split_off(&at);
+        self.vpn_range = VPNRange::new(start, at);
+        tail
+    }
+    fn file_offset(&self, file: &MmapFile, vpn: VirtPageNum) -> usize {
+        file.offset + (vpn.0 - self.vpn_range.get_start().0) * PAGE_SIZE
+    }
+    /// Fill a fresh frame of a file-backed area, bytes past the end of the
+    /// file are left zero.
+    fn read_file(&self, vpn: VirtPageNum, ppn: PhysPageNum) {
+        if let Some(file) = self.file.as_ref() {
+            file.inode
+                .read_at(self.file_offset(file, vpn), ppn.get_bytes_array());
+        }
+    }
+    /// Write a page of a shared file mapping back to the file. The file is
+    /// never grown by a mapping.
+    fn write_back(&self, vpn: VirtPageNum, ppn: PhysPageNum) {
+        if !self.shared {
+            return;
+        }
+        if let Some(file) = self.file.as_ref().filter(|file| file.writable) {
+            let offset = self.file_offset(file, vpn);
+            let size = file.inode.size();
+            if offset < size {
+                let len = PAGE_SIZE.min(size - offset);
+                file.inode.write_at(offset, &ppn.get_bytes_array()[..len]);
+            }
+        }
+    }
+    /// Give `vpn` a private writable frame, copying the shared one if
+    /// another address space still refers to it. Return false if there is
+    /// no frame left for the copy.
+    pub fn copy_on_write(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        let frame = self.data_frames.get(&vpn).unwrap();
+        if Arc::strong_count(frame) == 1 {
+            page_table.set_flags(vpn, pte_flags);
+        } else {
+            let new_frame = match user_frame_alloc() {
+                Some(frame) => frame,
+                None => return false,
+            };
+            new_frame
+                .ppn
+                .get_bytes_array()
+                .copy_from_slice(frame.ppn.get_bytes_array());
+            page_table.unmap(vpn);
+            page_table.map(vpn, new_frame.ppn, pte_flags);
+            // other threads may still read the shared frame through the TLB
+            tlb_shootdown(vpn, VirtPageNum(vpn.0 + 1));
+            self.data_frames.insert(vpn, Arc::new(new_frame));
+        }
+        true
+    }
+    pub fn swap_out(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        match self.data_frames.get(&vpn) {
+            // pages shared by fork or by a shared mapping are never evicted,
+            // neither are futex words, whose frame keys the wait queue
+            Some(frame)
+                if !self.shared
+                    && Arc::strong_count(frame) == 1
+                    && !futex_frame_waited(frame.ppn) => {}
+            _ => return false,
+        }
+        let slot = match swap_slot_alloc() {
+            Some(slot) => slot,
+            None => return false,
+        };
+        let frame = self.data_frames.remove(&vpn).unwrap();
+        // no hart may write the page any more while it is saved, pages of
+        // inaccessible areas have no PTE
+        let pte = page_table.translate(vpn).filter(|pte| pte.is_valid());
+        if pte.is_some() {
+            page_table.unmap(vpn);
+            tlb_shootdown(vpn, VirtPageNum(vpn.0 + 1));
+        }
+        // the page stays resident if it can't be saved
+        if !slot.write(frame.ppn) {
+            if let Some(pte) = pte {
+                page_table.map(vpn, frame.ppn, pte.flags());
+            }
+            self.data_frames.insert(vpn, frame);
+            return false;
+        }
+        self.swapped.insert(vpn, Arc::new(slot));
+        true
+    }
+    /// Only used for areas of the kernel and trap contexts, whose frames are
+    /// kept free by `user_frame_alloc`.
     pub fn map(&mut self, page_table: &mut PageTable) {
         for vpn in self.vpn_range {
-            self.map_one(page_table, vpn);
+            assert!(self.map_one(page_table, vpn), "out of frames");
         }
     }
     pub fn unmap(&mut self, page_table: &mut PageTable) {
@@ -306,22 +1205,23 @@ impl MapArea {
             self.unmap_one(page_table, vpn);
         }
     }
-    /// data: start-aligned but maybe with shorter length
-    /// assume that all frames were cleared before
-    pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8]) {
+    /// data: starting `offset` bytes into the first page, maybe with shorter
+    /// length
+    /// assume that all frames were cleared before, inaccessible pages have
+    /// frames but no PTE
+    pub fn copy_data(&mut self, data: &[u8], offset: usize) {
         assert_eq!(self.map_type, MapType::Framed);
         let mut start: usize = 0;
+        let mut page_offset = offset;
         let mut current_vpn = self.vpn_range.get_start();
         let len = data.len();
         loop {
-            let src = &data[start..len.min(start + PAGE_SIZE)];
-            let dst = &mut page_table
-                .translate(current_vpn)
-                .unwrap()
-                .ppn()
-                .get_bytes_array()[..src.len()];
+            let src = &data[start..len.min(start + PAGE_SIZE - page_offset)];
+            let dst = &mut self.data_frames[&current_vpn].ppn.get_bytes_array()
+                [page_offset..page_offset + src.len()];
             dst.copy_from_slice(src);
-            start += PAGE_SIZE;
+            start += src.len();
+            page_offset = 0;
             if start >= len {
                 break;
             }
@@ -330,6 +1230,17 @@ impl MapArea {
     }
 }
 
+impl Drop for MapArea {
+    fn drop(&mut self) {
+        for (vpn, frame) in self.data_frames.iter() {
+            self.write_back(*vpn, frame.ppn);
+        }
+        if let Some(segment) = self.shm.take() {
+            shm_detach(segment);
+        }
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum MapType {
     Identical,
@@ -347,7 +1258,7 @@ bitflags! {
 
 #[allow(unused)]
 pub fn remap_test() {
-    let mut kernel_space = KERNEL_SPACE.exclusive_access();
+    let mut kernel_space = KERNEL_SPACE.lock();
     let mid_text: VirtAddr = ((stext as usize + etext as usize) / 2).into();
     let mid_rodata: VirtAddr = ((srodata as usize + erodata as usize) / 2).into();
     let mid_data: VirtAddr = ((sdata as usize + edata as usize) / 2).into();
@@ -3,20 +3,33 @@ mod frame_allocator;
 mod heap_allocator;
 mod memory_set;
 mod page_table;
+mod replace;
+mod shm;
+mod swap;
+mod user_access;
 
 use address::VPNRange;
 pub use address::{PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
-pub use frame_allocator::{frame_alloc, frame_dealloc, FrameTracker};
+pub use frame_allocator::{
+    frame_alloc, frame_dealloc, frame_remaining, user_frame_alloc, FrameTracker,
+};
 pub use memory_set::remap_test;
-pub use memory_set::{kernel_token, MapPermission, MemorySet, KERNEL_SPACE};
+pub use memory_set::{kernel_token, MapPermission, MemorySet, MmapFile, KERNEL_SPACE};
 use page_table::PTEFlags;
-pub use page_table::{
-    translated_byte_buffer, translated_ref, translated_refmut, translated_str, PageTable,
-    PageTableEntry, UserBuffer, UserBufferIterator,
-};
+pub use page_table::{PageTable, PageTableEntry};
+pub use replace::{handle_page_fault, reclaim_frames, replace_policy_name};
+pub use shm::{shm_create, shm_get, shm_remove};
+pub use swap::{swap_stat, SwapStat};
+pub use user_access::{copy_from_user, copy_to_user, UserPtr, UserSlice};
 
 pub fn init() {
     heap_allocator::init_heap();
     frame_allocator::init_frame_allocator();
-    KERNEL_SPACE.exclusive_access().activate();
+    KERNEL_SPACE.lock().activate();
+    println!("[kernel] page replacement policy: {}", replace_policy_name());
+}
+
+/// Switch a secondary hart to the kernel space built by the boot hart.
+pub fn init_secondary() {
+    KERNEL_SPACE.lock().activate();
 }
@@ -0,0 +1,37 @@
+use super::{ReplacePolicy, ResidentPage};
+use alloc::collections::VecDeque;
+
+/// Second chance: the hand skips and clears pages whose accessed bit is set.
+/// The front of `pages` is where the hand points to.
+pub struct ClockPolicy {
+    pages: VecDeque<ResidentPage>,
+}
+
+impl ReplacePolicy for ClockPolicy {
+    const NAME: &'static str = "Clock";
+    fn new() -> Self {
+        Self {
+            pages: VecDeque::new(),
+        }
+    }
+    fn insert(&mut self, page: ResidentPage) {
+        if !self.pages.contains(&page) {
+            self.pages.retain(ResidentPage::is_alive);
+            // place the new page right behind the hand
+            self.pages.push_back(page);
+        }
+    }
+    fn pick_victim(&mut self) -> Option<ResidentPage> {
+        // every page is cleared in the first round, so two rounds are enough
+        for _ in 0..2 * self.pages.len() {
+            let page = self.pages.pop_front().unwrap();
+            match page.test_and_clear_accessed() {
+                Some(true) => self.pages.push_back(page),
+                Some(false) => return Some(page),
+                // no longer resident
+                None => {}
+            }
+        }
+        self.pages.pop_front()
+    }
+}
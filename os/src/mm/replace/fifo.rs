@@ -0,0 +1,30 @@
+use super::{ReplacePolicy, ResidentPage};
+use alloc::collections::VecDeque;
+
+/// Evict the page which got its frame earliest.
+pub struct FifoPolicy {
+    queue: VecDeque<ResidentPage>,
+}
+
+impl ReplacePolicy for FifoPolicy {
+    const NAME: &'static str = "FIFO";
+    fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+        }
+    }
+    fn insert(&mut self, page: ResidentPage) {
+        if !self.queue.contains(&page) {
+            self.queue.retain(ResidentPage::is_alive);
+            self.queue.push_back(page);
+        }
+    }
+    fn pick_victim(&mut self) -> Option<ResidentPage> {
+        while let Some(page) = self.queue.pop_front() {
+            if page.is_alive() {
+                return Some(page);
+            }
+        }
+        None
+    }
+}
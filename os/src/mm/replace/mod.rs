@@ -0,0 +1,174 @@
+mod clock;
+mod fifo;
+mod working_set;
+
+use super::frame_allocator::RESERVED_FRAMES;
+use super::swap::{swap_slot_available, SWAP_STAT};
+use super::{frame_remaining, MapPermission, VirtPageNum};
+use crate::error::{SysError, SysResult};
+use crate::sync::{SpinLock, SpinLockGuard};
+use crate::task::ProcessControlBlock;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use lazy_static::*;
+
+#[allow(unused)]
+use clock::ClockPolicy;
+#[allow(unused)]
+use fifo::FifoPolicy;
+#[allow(unused)]
+use working_set::WorkingSetPolicy;
+
+/// A user page which has a frame and may be swapped out.
+#[derive(Clone)]
+pub struct ResidentPage {
+    pub process: Weak<ProcessControlBlock>,
+    pub vpn: VirtPageNum,
+}
+
+impl ResidentPage {
+    pub fn new(process: &Weak<ProcessControlBlock>, vpn: VirtPageNum) -> Self {
+        Self {
+            process: Weak::clone(process),
+            vpn,
+        }
+    }
+    /// Entries of exited processes can be dropped by policies.
+    pub fn is_alive(&self) -> bool {
+        self.process.strong_count() > 0
+    }
+    /// Return whether the page has been accessed since the last call,
+    /// or None if it is no longer resident.
+    pub fn test_and_clear_accessed(&self) -> Option<bool> {
+        let process = self.process.upgrade()?;
+        let mut process_inner = process.inner_exclusive_access();
+        process_inner.memory_set.test_and_clear_accessed(self.vpn)
+    }
+    fn swap_out(&self) -> bool {
+        if let Some(process) = self.process.upgrade() {
+            let mut process_inner = process.inner_exclusive_access();
+            process_inner.memory_set.swap_out(self.vpn)
+        } else {
+            false
+        }
+    }
+}
+
+impl PartialEq for ResidentPage {
+    fn eq(&self, other: &Self) -> bool {
+        self.vpn == other.vpn && Weak::ptr_eq(&self.process, &other.process)
+    }
+}
+
+/// Pages handed to a policy may be unmapped later without telling it,
+/// so a victim is only a candidate and is checked again when swapped out.
+pub trait ReplacePolicy {
+    const NAME: &'static str;
+    fn new() -> Self;
+    /// `page` has just got a frame.
+    fn insert(&mut self, page: ResidentPage);
+    /// A page fault is being handled.
+    fn on_fault(&mut self) {}
+    /// Remove a page to be swapped out from the policy.
+    fn pick_victim(&mut self) -> Option<ResidentPage>;
+}
+
+#[cfg(any(
+    all(feature = "swap_fifo", feature = "swap_clock"),
+    all(feature = "swap_fifo", feature = "swap_ws"),
+    all(feature = "swap_clock", feature = "swap_ws"),
+))]
+compile_error!("at most one of the swap_* features can be enabled");
+
+#[cfg(feature = "swap_clock")]
+type ReplacePolicyImpl = ClockPolicy;
+#[cfg(feature = "swap_ws")]
+type ReplacePolicyImpl = WorkingSetPolicy;
+#[cfg(not(any(feature = "swap_clock", feature = "swap_ws")))]
+type ReplacePolicyImpl = FifoPolicy;
+
+lazy_static! {
+    static ref REPLACE_POLICY: SpinLock<ReplacePolicyImpl> =
+        SpinLock::new(ReplacePolicyImpl::new());
+    /// Pages which have just got a frame. They are mapped with the PCB
+    /// borrowed, so they wait here for the policy, which borrows PCBs.
+    static ref MAPPED_PAGES: SpinLock<Vec<ResidentPage>> = SpinLock::new(Vec::new());
+}
+
+/// The policy, with the pages mapped since it was last used.
+fn replace_policy() -> SpinLockGuard<'static, ReplacePolicyImpl> {
+    let mut policy = REPLACE_POLICY.lock();
+    for page in MAPPED_PAGES.lock().drain(..) {
+        policy.insert(page);
+    }
+    policy
+}
+
+/// The page at `vpn` of `process` has just got a frame, it may be swapped
+/// out from now on. The PCB may be borrowed by the caller.
+pub fn page_mapped(process: &Weak<ProcessControlBlock>, vpn: VirtPageNum) {
+    if process.strong_count() > 0 {
+        MAPPED_PAGES.lock().push(ResidentPage::new(process, vpn));
+    }
+}
+
+pub fn replace_policy_name() -> &'static str {
+    ReplacePolicyImpl::NAME
+}
+
+/// Resolve a page fault of `process` caused by an `access` which is one of
+/// R, W and X, see `MemorySet::handle_page_fault` for the errors. Pages are
+/// swapped out first if there is no frame left for the page.
+/// No PCB may be borrowed by the caller since the policy inspects them.
+pub fn handle_page_fault(
+    process: &Arc<ProcessControlBlock>,
+    vpn: VirtPageNum,
+    access: MapPermission,
+) -> SysResult<()> {
+    replace_policy().on_fault();
+    let fault = || {
+        process
+            .inner_exclusive_access()
+            .memory_set
+            .handle_page_fault(vpn, access)
+    };
+    let mut resolved = fault();
+    if resolved == Err(SysError::ENOMEM) && reclaim_frames(1) {
+        resolved = fault();
+    }
+    if resolved.is_ok() {
+        SWAP_STAT.lock().page_faults += 1;
+    }
+    resolved
+}
+
+/// Swap pages out until `RESERVED_FRAMES` frames are free for the kernel and
+/// `frames` more for user pages. Return false if the swap area is full or no
+/// page can be swapped out. No PCB may be borrowed by the caller.
+pub fn reclaim_frames(frames: usize) -> bool {
+    // victims which can't be swapped out are handed back to the policy
+    // afterwards, so that they aren't picked again meanwhile
+    let mut kept = Vec::new();
+    let reclaimed = loop {
+        if frame_remaining() >= RESERVED_FRAMES + frames {
+            break true;
+        }
+        if !swap_slot_available() {
+            break false;
+        }
+        let victim = replace_policy().pick_victim();
+        match victim {
+            Some(page) => {
+                if !page.swap_out() {
+                    kept.push(page);
+                }
+            }
+            None => break false,
+        }
+    };
+    let mut policy = replace_policy();
+    for page in kept {
+        policy.insert(page);
+    }
+    reclaimed
+}
@@ -0,0 +1,60 @@
+use super::{ReplacePolicy, ResidentPage};
+use alloc::collections::VecDeque;
+
+/// Pages not accessed during the last `WINDOW` page faults are out of
+/// the working set of their process.
+const WINDOW: usize = 32;
+
+/// Working set: accessed bits are sampled on every page fault, pages out of
+/// the working set are evicted first, then the least recently used one.
+pub struct WorkingSetPolicy {
+    /// (page, virtual time of its last access)
+    pages: VecDeque<(ResidentPage, usize)>,
+    /// virtual time counted in page faults
+    now: usize,
+}
+
+impl ReplacePolicy for WorkingSetPolicy {
+    const NAME: &'static str = "Working-Set";
+    fn new() -> Self {
+        Self {
+            pages: VecDeque::new(),
+            now: 0,
+        }
+    }
+    fn insert(&mut self, page: ResidentPage) {
+        let now = self.now;
+        if let Some((_, last_use)) = self.pages.iter_mut().find(|(p, _)| *p == page) {
+            *last_use = now;
+        } else {
+            self.pages.push_back((page, now));
+        }
+    }
+    fn on_fault(&mut self) {
+        self.now += 1;
+        let pages = core::mem::take(&mut self.pages);
+        for (page, last_use) in pages {
+            match page.test_and_clear_accessed() {
+                Some(true) => self.pages.push_back((page, self.now)),
+                Some(false) => self.pages.push_back((page, last_use)),
+                // no longer resident
+                None => {}
+            }
+        }
+    }
+    fn pick_victim(&mut self) -> Option<ResidentPage> {
+        let now = self.now;
+        let idx = self
+            .pages
+            .iter()
+            .position(|(_, last_use)| now - *last_use > WINDOW)
+            .or_else(|| {
+                self.pages
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, (_, last_use))| *last_use)
+                    .map(|(idx, _)| idx)
+            })?;
+        self.pages.remove(idx).map(|(page, _)| page)
+    }
+}
@@ -0,0 +1,87 @@
+use super::{user_frame_alloc, FrameTracker};
+use crate::error::{SysError, SysResult};
+use crate::sync::SpinLock;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// A shared memory segment. Every address space attaching it holds a
+/// reference, so its frames are freed once it has been removed from the
+/// table and detached everywhere.
+pub struct ShmSegment {
+    pub key: usize,
+    /// pid of the process which has created it, the only one to remove it
+    pub creator: usize,
+    pub frames: Vec<Arc<FrameTracker>>,
+}
+
+lazy_static! {
+    static ref SHM_TABLE: SpinLock<Vec<Option<Arc<ShmSegment>>>> = SpinLock::new(Vec::new());
+}
+
+/// Return the id of the segment created with `key`, or create a segment of
+/// `pages` pages for process `pid` if there is none or `key` is 0.
+pub fn shm_create(key: usize, pages: usize, pid: usize) -> Option<usize> {
+    let mut table = SHM_TABLE.lock();
+    if key != 0 {
+        if let Some(id) = table
+            .iter()
+            .position(|segment| segment.as_ref().map_or(false, |segment| segment.key == key))
+        {
+            return Some(id).filter(|id| table[*id].as_ref().unwrap().frames.len() >= pages);
+        }
+    }
+    let frames = (0..pages)
+        .map(|_| user_frame_alloc().map(Arc::new))
+        .collect::<Option<Vec<_>>>()?;
+    let segment = Some(Arc::new(ShmSegment {
+        key,
+        creator: pid,
+        frames,
+    }));
+    if let Some(id) = table.iter().position(|segment| segment.is_none()) {
+        table[id] = segment;
+        Some(id)
+    } else {
+        table.push(segment);
+        Some(table.len() - 1)
+    }
+}
+
+pub fn shm_get(id: usize) -> Option<Arc<ShmSegment>> {
+    SHM_TABLE.lock().get(id).and_then(|segment| segment.clone())
+}
+
+/// The segment can no longer be attached, attached ones stay valid. Fail
+/// with EPERM unless it has been created by process `pid`.
+pub fn shm_remove(id: usize, pid: usize) -> SysResult<()> {
+    let mut table = SHM_TABLE.lock();
+    let entry = table.get_mut(id).ok_or(SysError::EINVAL)?;
+    match entry.as_ref() {
+        Some(segment) if segment.creator == pid => {}
+        Some(_) => return Err(SysError::EPERM),
+        None => return Err(SysError::EINVAL),
+    }
+    *entry = None;
+    Ok(())
+}
+
+/// Called when an attachment of `segment` goes away, the segment is removed
+/// if it is the last one.
+pub fn shm_detach(segment: Arc<ShmSegment>) {
+    let mut table = SHM_TABLE.lock();
+    // one reference is held by the table and the other one by the caller
+    if Arc::strong_count(&segment) == 2 {
+        if let Some(entry) = table.iter_mut().find(|entry| {
+            entry
+                .as_ref()
+                .map_or(false, |entry| Arc::ptr_eq(entry, &segment))
+        }) {
+            *entry = None;
+        }
+    }
+    // drop the reference with the table locked, so that the last one of
+    // two detaches on different harts always sees a count of 2
+    drop(segment);
+    drop(table);
+}
@@ -0,0 +1,123 @@
+use super::{frame_remaining, PhysPageNum};
+use crate::config::{PAGE_SIZE, SWAP_SIZE};
+use crate::fs::{ROOT_INODE, SWAP_FILE_NAME};
+use crate::sync::SpinLock;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use easy_fs::Inode;
+use lazy_static::*;
+
+const SWAP_SLOTS: usize = SWAP_SIZE / PAGE_SIZE;
+
+/// Counters of the page replacement subsystem.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct SwapStat {
+    /// page faults resolved by the kernel
+    pub page_faults: usize,
+    /// pages read back from the swap area
+    pub swap_ins: usize,
+    /// pages written to the swap area
+    pub swap_outs: usize,
+    /// frames left, filled in when read
+    pub free_frames: usize,
+}
+
+pub struct SwapSlotAllocator {
+    current: usize,
+    recycled: Vec<usize>,
+}
+
+impl SwapSlotAllocator {
+    pub fn new() -> Self {
+        Self {
+            current: 0,
+            recycled: Vec::new(),
+        }
+    }
+    pub fn alloc(&mut self) -> Option<usize> {
+        if let Some(slot) = self.recycled.pop() {
+            Some(slot)
+        } else if self.current == SWAP_SLOTS {
+            None
+        } else {
+            self.current += 1;
+            Some(self.current - 1)
+        }
+    }
+    pub fn dealloc(&mut self, slot: usize) {
+        assert!(slot < self.current);
+        assert!(
+            !self.recycled.iter().any(|s| *s == slot),
+            "swap slot {} has been deallocated!",
+            slot
+        );
+        self.recycled.push(slot);
+    }
+    pub fn available(&self) -> bool {
+        self.current < SWAP_SLOTS || !self.recycled.is_empty()
+    }
+}
+
+lazy_static! {
+    /// Swap area, a file reserved on easy-fs and emptied at boot.
+    static ref SWAP_FILE: Arc<Inode> = {
+        if let Some(inode) = ROOT_INODE.find(SWAP_FILE_NAME) {
+            inode.clear();
+            inode
+        } else {
+            ROOT_INODE.create(SWAP_FILE_NAME).unwrap()
+        }
+    };
+    static ref SWAP_SLOT_ALLOCATOR: SpinLock<SwapSlotAllocator> =
+        SpinLock::new(SwapSlotAllocator::new());
+    pub static ref SWAP_STAT: SpinLock<SwapStat> =
+        SpinLock::new(SwapStat::default());
+}
+
+/// A page-sized slot in the swap area, released when dropped.
+pub struct SwapSlot(usize);
+
+impl SwapSlot {
+    /// Save the content of frame `ppn` in the slot. Return false if easy-fs
+    /// is full.
+    pub fn write(&self, ppn: PhysPageNum) -> bool {
+        let size = SWAP_FILE.write_at(self.0 * PAGE_SIZE, ppn.get_bytes_array());
+        if size != PAGE_SIZE {
+            return false;
+        }
+        SWAP_STAT.lock().swap_outs += 1;
+        true
+    }
+    /// Load the content of the slot into frame `ppn`. Return false if the
+    /// slot can't be read whole.
+    pub fn read(&self, ppn: PhysPageNum) -> bool {
+        let size = SWAP_FILE.read_at(self.0 * PAGE_SIZE, ppn.get_bytes_array());
+        if size != PAGE_SIZE {
+            return false;
+        }
+        SWAP_STAT.lock().swap_ins += 1;
+        true
+    }
+}
+
+impl Drop for SwapSlot {
+    fn drop(&mut self) {
+        SWAP_SLOT_ALLOCATOR.lock().dealloc(self.0);
+    }
+}
+
+pub fn swap_slot_alloc() -> Option<SwapSlot> {
+    SWAP_SLOT_ALLOCATOR.lock().alloc().map(SwapSlot)
+}
+
+pub fn swap_slot_available() -> bool {
+    SWAP_SLOT_ALLOCATOR.lock().available()
+}
+
+pub fn swap_stat() -> SwapStat {
+    SwapStat {
+        free_frames: frame_remaining(),
+        ..*SWAP_STAT.lock()
+    }
+}
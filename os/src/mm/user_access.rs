@@ -0,0 +1,196 @@
+//! Access to the memory of a process from syscalls. Addresses given by user
+//! mode are checked against its page table on every access: they have to be
+//! in the user half, mapped in user mode, and readable, or writable when the
+//! kernel writes to them. Anything else fails with EFAULT instead of being
+//! trusted.
+
+use super::{MemorySet, PTEFlags, PhysAddr, PhysPageNum, VirtAddr};
+use crate::config::{PAGE_SIZE, USER_SPACE_TOP};
+use crate::error::{SysError, SysResult};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::{size_of, MaybeUninit};
+use core::slice;
+
+/// The frame behind the user page at `va`, after resolving lazy,
+/// copy-on-write and swapped out pages like a page fault would.
+fn user_frame(memory_set: &mut MemorySet, va: usize, write: bool) -> SysResult<PhysPageNum> {
+    if va >= USER_SPACE_TOP {
+        return Err(SysError::EFAULT);
+    }
+    memory_set.fault_in(va, 1, write)?;
+    match memory_set.translate(VirtAddr::from(va).floor()) {
+        Some(pte)
+            if pte.is_valid()
+                && pte.flags().contains(PTEFlags::U)
+                && pte.readable()
+                && (!write || pte.writable()) =>
+        {
+            Ok(pte.ppn())
+        }
+        _ => Err(SysError::EFAULT),
+    }
+}
+
+/// The bytes of `[start, start + len)`, one slice per page it touches. They
+/// can't outlive the borrow of `memory_set`, which could free the frames.
+fn user_bytes<'a>(
+    memory_set: &'a mut MemorySet,
+    start: usize,
+    len: usize,
+    write: bool,
+) -> SysResult<Vec<&'a mut [u8]>> {
+    let end = start
+        .checked_add(len)
+        .filter(|end| *end <= USER_SPACE_TOP)
+        .ok_or(SysError::EFAULT)?;
+    let mut buffers = Vec::new();
+    let mut va = start;
+    while va < end {
+        let offset = va % PAGE_SIZE;
+        let page_end = end.min(va - offset + PAGE_SIZE);
+        let ppn = user_frame(memory_set, va, write)?;
+        buffers.push(&mut ppn.get_bytes_array()[offset..offset + (page_end - va)]);
+        va = page_end;
+    }
+    Ok(buffers)
+}
+
+/// Copy `dst.len()` bytes from user memory at `src`.
+pub fn copy_from_user(memory_set: &mut MemorySet, src: usize, dst: &mut [u8]) -> SysResult<()> {
+    let mut offset = 0;
+    for buffer in user_bytes(memory_set, src, dst.len(), false)? {
+        dst[offset..offset + buffer.len()].copy_from_slice(buffer);
+        offset += buffer.len();
+    }
+    Ok(())
+}
+
+/// Copy `src` to user memory at `dst`. Nothing is written unless all of it
+/// can be.
+pub fn copy_to_user(memory_set: &mut MemorySet, dst: usize, src: &[u8]) -> SysResult<()> {
+    let mut offset = 0;
+    for buffer in user_bytes(memory_set, dst, src.len(), true)? {
+        buffer.copy_from_slice(&src[offset..offset + buffer.len()]);
+        offset += buffer.len();
+    }
+    Ok(())
+}
+
+/// A pointer to a `T` in user memory, which needn't be aligned and may cross
+/// a page boundary. `T` has to be valid for any bytes read into it.
+pub struct UserPtr<T> {
+    addr: usize,
+    _marker: PhantomData<*const T>,
+}
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T> From<*const T> for UserPtr<T> {
+    fn from(ptr: *const T) -> Self {
+        Self::new(ptr as usize)
+    }
+}
+
+impl<T> From<*mut T> for UserPtr<T> {
+    fn from(ptr: *mut T) -> Self {
+        Self::new(ptr as usize)
+    }
+}
+
+impl<T> UserPtr<T> {
+    pub fn new(addr: usize) -> Self {
+        Self {
+            addr,
+            _marker: PhantomData,
+        }
+    }
+    pub fn addr(self) -> usize {
+        self.addr
+    }
+    pub fn is_null(self) -> bool {
+        self.addr == 0
+    }
+    /// The pointer to the `count`th `T` after this one, it is checked when
+    /// it is accessed like any other.
+    pub fn add(self, count: usize) -> Self {
+        Self::new(self.addr.wrapping_add(count.wrapping_mul(size_of::<T>())))
+    }
+    /// Copy the value out of user memory.
+    pub fn read(self, memory_set: &mut MemorySet) -> SysResult<T> {
+        let mut value = MaybeUninit::<T>::uninit();
+        let bytes =
+            unsafe { slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>()) };
+        copy_from_user(memory_set, self.addr, bytes)?;
+        Ok(unsafe { value.assume_init() })
+    }
+    /// Copy `value` into user memory.
+    pub fn write(self, memory_set: &mut MemorySet, value: &T) -> SysResult<()> {
+        let bytes =
+            unsafe { slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+        copy_to_user(memory_set, self.addr, bytes)
+    }
+    /// The physical address of the value, for the kernel to access it
+    /// without the page table later on. It has to be aligned, and is made
+    /// writable if `write` is set, resolving copy-on-write first.
+    pub fn phys_addr(self, memory_set: &mut MemorySet, write: bool) -> SysResult<PhysAddr> {
+        if self.addr % core::mem::align_of::<T>() != 0 {
+            return Err(SysError::EFAULT);
+        }
+        // aligned values of a size up to a page don't cross pages
+        let ppn = user_frame(memory_set, self.addr, write)?;
+        let frame: PhysAddr = ppn.into();
+        Ok(PhysAddr(frame.0 + self.addr % PAGE_SIZE))
+    }
+}
+
+impl UserPtr<u8> {
+    /// Copy the string ending with `\0` out of user memory. Fail with
+    /// ENAMETOOLONG if it is longer than `max_len` bytes, and with EINVAL if
+    /// it isn't UTF-8.
+    pub fn read_str(self, memory_set: &mut MemorySet, max_len: usize) -> SysResult<String> {
+        let mut bytes = Vec::new();
+        let mut va = self.addr;
+        loop {
+            let offset = va % PAGE_SIZE;
+            let page = &user_frame(memory_set, va, false)?.get_bytes_array()[offset..];
+            let end = page.iter().position(|byte| *byte == 0);
+            bytes.extend_from_slice(&page[..end.unwrap_or(page.len())]);
+            if bytes.len() > max_len {
+                return Err(SysError::ENAMETOOLONG);
+            }
+            if end.is_some() {
+                break;
+            }
+            va += page.len();
+        }
+        String::from_utf8(bytes).map_err(|_| SysError::EINVAL)
+    }
+}
+
+/// `len` bytes of user memory at `addr`, read or written by files through a
+/// kernel buffer.
+#[derive(Clone, Copy)]
+pub struct UserSlice {
+    addr: usize,
+    len: usize,
+}
+
+impl UserSlice {
+    pub fn new(addr: usize, len: usize) -> Self {
+        Self { addr, len }
+    }
+    /// Check all the pages of the slice at once, for a file to read from,
+    /// or to write into if `write` is set. Nothing is kept of them, they are
+    /// accessed again with `copy_from_user` and `copy_to_user`.
+    pub fn check(self, memory_set: &mut MemorySet, write: bool) -> SysResult<()> {
+        user_bytes(memory_set, self.addr, self.len, write).map(|_| ())
+    }
+}
@@ -12,6 +12,11 @@ const SBI_REMOTE_SFENCE_VMA: usize = 6;
 const SBI_REMOTE_SFENCE_VMA_ASID: usize = 7;
 const SBI_SHUTDOWN: usize = 8;
 
+const SBI_EXT_HSM: usize = 0x48534D;
+const SBI_HSM_HART_START: usize = 0;
+const SBI_EXT_RFENCE: usize = 0x52464E43;
+const SBI_RFENCE_REMOTE_SFENCE_VMA: usize = 1;
+
 #[inline(always)]
 fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
     let mut ret;
@@ -27,6 +32,31 @@ fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
     ret
 }
 
+/// Call a function of an SBI extension, return (error, value).
+#[inline(always)]
+fn sbi_call_ext(
+    eid: usize,
+    fid: usize,
+    arg0: usize,
+    arg1: usize,
+    arg2: usize,
+    arg3: usize,
+) -> (isize, usize) {
+    let (error, value);
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("x10") arg0 => error,
+            inlateout("x11") arg1 => value,
+            in("x12") arg2,
+            in("x13") arg3,
+            in("x16") fid,
+            in("x17") eid,
+        );
+    }
+    (error, value)
+}
+
 pub fn set_timer(timer: usize) {
     sbi_call(SBI_SET_TIMER, timer, 0, 0);
 }
@@ -39,6 +69,45 @@ pub fn console_getchar() -> usize {
     sbi_call(SBI_CONSOLE_GETCHAR, 0, 0, 0)
 }
 
+/// Start hart `hartid` at physical address `start_addr` with `a0` set to
+/// its hart id and `a1` to `opaque`. Return false if the SBI refuses.
+pub fn hart_start(hartid: usize, start_addr: usize, opaque: usize) -> bool {
+    sbi_call_ext(
+        SBI_EXT_HSM,
+        SBI_HSM_HART_START,
+        hartid,
+        start_addr,
+        opaque,
+        0,
+    )
+    .0 == 0
+}
+
+/// Execute `sfence.vma` for `[start_addr, start_addr + size)` on the harts in
+/// `hart_mask`, and return after all of them are done.
+pub fn remote_sfence_vma(hart_mask: usize, start_addr: usize, size: usize) {
+    if hart_mask == 0 {
+        return;
+    }
+    let (error, _) = sbi_call_ext(
+        SBI_EXT_RFENCE,
+        SBI_RFENCE_REMOTE_SFENCE_VMA,
+        hart_mask,
+        0,
+        start_addr,
+        size,
+    );
+    if error != 0 {
+        // the legacy call takes the address of the mask
+        sbi_call(
+            SBI_REMOTE_SFENCE_VMA,
+            &hart_mask as *const _ as usize,
+            start_addr,
+            size,
+        );
+    }
+}
+
 pub fn shutdown() -> ! {
     sbi_call(SBI_SHUTDOWN, 0, 0, 0);
     panic!("It should shutdown!");
@@ -0,0 +1,50 @@
+use crate::sync::SpinLock;
+use crate::task::{
+    add_task, block_current, current_task, remove_waiter, TaskControlBlock, WaitResult,
+};
+use alloc::{collections::VecDeque, sync::Arc};
+
+/// Blocks tasks until `count` of them have arrived, then lets them all go
+/// and starts over.
+pub struct Barrier {
+    inner: SpinLock<BarrierInner>,
+}
+
+pub struct BarrierInner {
+    count: usize,
+    wait_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl Barrier {
+    pub fn new(count: usize) -> Self {
+        assert!(count > 0);
+        Self {
+            inner: SpinLock::new(BarrierInner {
+                count,
+                wait_queue: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Return true for the last task to arrive in each round, or None if
+    /// the wait is interrupted, the task doesn't count as arrived then.
+    pub fn wait(self: &Arc<Self>) -> Option<bool> {
+        let mut inner = self.inner.lock();
+        if inner.wait_queue.len() + 1 == inner.count {
+            for task in inner.wait_queue.drain(..) {
+                add_task(task);
+            }
+            return Some(true);
+        }
+        inner.wait_queue.push_back(current_task().unwrap());
+        drop(inner);
+        let barrier = Arc::clone(self);
+        let result = block_current(None, move |task, waiting| {
+            remove_waiter(&mut barrier.inner.lock().wait_queue, task, waiting)
+        });
+        match result {
+            WaitResult::Interrupted => None,
+            _ => Some(false),
+        }
+    }
+}
@@ -1,9 +1,11 @@
-use crate::sync::{Mutex, UPSafeCell};
-use crate::task::{add_task, block_current_and_run_next, current_task, TaskControlBlock};
+use crate::sync::{Mutex, SpinLock};
+use crate::task::{
+    add_task, block_current, current_task, remove_waiter, TaskControlBlock, WaitResult,
+};
 use alloc::{collections::VecDeque, sync::Arc};
 
 pub struct Condvar {
-    pub inner: UPSafeCell<CondvarInner>,
+    pub inner: SpinLock<CondvarInner>,
 }
 
 pub struct CondvarInner {
@@ -13,27 +15,46 @@ pub struct CondvarInner {
 impl Condvar {
     pub fn new() -> Self {
         Self {
-            inner: unsafe {
-                UPSafeCell::new(CondvarInner {
-                    wait_queue: VecDeque::new(),
-                })
-            },
+            inner: SpinLock::new(CondvarInner {
+                wait_queue: VecDeque::new(),
+            }),
         }
     }
 
     pub fn signal(&self) {
-        let mut inner = self.inner.exclusive_access();
+        let mut inner = self.inner.lock();
         if let Some(task) = inner.wait_queue.pop_front() {
             add_task(task);
         }
     }
 
-    pub fn wait(&self, mutex: Arc<dyn Mutex>) {
-        mutex.unlock();
-        let mut inner = self.inner.exclusive_access();
+    pub fn broadcast(&self) {
+        let mut inner = self.inner.lock();
+        for task in inner.wait_queue.drain(..) {
+            add_task(task);
+        }
+    }
+
+    /// Wait until signalled, giving up at `expire_ms` if there is one. The
+    /// mutex is locked again unless the wait or the relocking is
+    /// interrupted.
+    pub fn wait(self: &Arc<Self>, mutex: Arc<dyn Mutex>, expire_ms: Option<usize>) -> WaitResult {
+        // join the queue before unlocking, otherwise a signal from another
+        // hart in between would be lost
+        let mut inner = self.inner.lock();
         inner.wait_queue.push_back(current_task().unwrap());
         drop(inner);
-        block_current_and_run_next();
-        mutex.lock();
+        mutex.unlock();
+        let condvar = Arc::clone(self);
+        let result = block_current(expire_ms, move |task, waiting| {
+            remove_waiter(&mut condvar.inner.lock().wait_queue, task, waiting)
+        });
+        if result == WaitResult::Interrupted {
+            return result;
+        }
+        match mutex.lock(None) {
+            WaitResult::Woken => result,
+            relock => relock,
+        }
     }
 }
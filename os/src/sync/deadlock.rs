@@ -0,0 +1,143 @@
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Resource {
+    Mutex(usize),
+    Semaphore(usize),
+}
+
+/// Units of some resources, missing entries are zero.
+type Units = BTreeMap<Resource, usize>;
+
+/// Allocation and need of the mutexes and semaphores of a process, indexed
+/// by tid. It is kept up to date even while detection is disabled, so that
+/// it can be enabled at any time.
+#[derive(Default)]
+pub struct DeadlockDetector {
+    pub enabled: bool,
+    available: Units,
+    allocation: Vec<Units>,
+    need: Vec<Units>,
+}
+
+fn units(of: &Units, resource: Resource) -> usize {
+    of.get(&resource).copied().unwrap_or(0)
+}
+
+impl DeadlockDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The state of a forked child, which inherits no resources.
+    pub fn fork(&self) -> Self {
+        Self {
+            enabled: self.enabled,
+            ..Self::default()
+        }
+    }
+
+    /// A resource with `units` available units has been created, replacing
+    /// any former one with the same id.
+    pub fn add_resource(&mut self, resource: Resource, units: usize) {
+        self.available.insert(resource, units);
+        for row in self.allocation.iter_mut().chain(self.need.iter_mut()) {
+            row.remove(&resource);
+        }
+    }
+
+    fn rows(&mut self, tid: usize) -> (&mut Units, &mut Units) {
+        if self.allocation.len() <= tid {
+            self.allocation.resize_with(tid + 1, Units::new);
+            self.need.resize_with(tid + 1, Units::new);
+        }
+        (&mut self.allocation[tid], &mut self.need[tid])
+    }
+
+    /// Thread `tid` asks for one unit of `resource`, `live` are the tids of
+    /// the threads of the process which have not exited. If it would have to
+    /// wait and waiting could never end, the request is dropped and false is
+    /// returned. Otherwise the request stays pending until `acquire`.
+    ///
+    /// A semaphore may be raised by any thread, not only by those holding
+    /// some of it, so waiting on one can only go on forever once every other
+    /// thread is waiting too. Mutexes go through Banker's check alone.
+    pub fn request(&mut self, tid: usize, resource: Resource, live: &[usize]) -> bool {
+        *self.rows(tid).1.entry(resource).or_default() += 1;
+        if !self.enabled || units(&self.available, resource) > 0 {
+            return true;
+        }
+        let raisable = matches!(resource, Resource::Semaphore(_))
+            && live
+                .iter()
+                .any(|&other| other != tid && !self.is_waiting(other));
+        if raisable || self.is_safe() {
+            return true;
+        }
+        let need = &mut self.need[tid];
+        *need.get_mut(&resource).unwrap() -= 1;
+        false
+    }
+
+    /// Thread `tid` has given up its pending request for `resource`.
+    pub fn cancel(&mut self, tid: usize, resource: Resource) {
+        if let Some(units) = self.rows(tid).1.get_mut(&resource) {
+            *units = units.saturating_sub(1);
+        }
+    }
+
+    /// The pending request of `tid` for `resource` has been granted.
+    pub fn acquire(&mut self, tid: usize, resource: Resource) {
+        let (allocation, need) = self.rows(tid);
+        if let Some(units) = need.get_mut(&resource) {
+            *units = units.saturating_sub(1);
+        }
+        *allocation.entry(resource).or_default() += 1;
+        let available = self.available.entry(resource).or_default();
+        *available = available.saturating_sub(1);
+    }
+
+    /// Thread `tid` gives back one unit of `resource`. A semaphore may be
+    /// raised by a thread which holds none of it, the unit is then taken
+    /// from one of the threads which hold some, as if it had raised it.
+    pub fn release(&mut self, tid: usize, resource: Resource) {
+        self.rows(tid);
+        let owner = Some(tid)
+            .into_iter()
+            .chain(0..self.allocation.len())
+            .find(|&owner| units(&self.allocation[owner], resource) > 0);
+        if let Some(owner) = owner {
+            *self.allocation[owner].get_mut(&resource).unwrap() -= 1;
+        }
+        *self.available.entry(resource).or_default() += 1;
+    }
+
+    /// Whether thread `tid` has a pending request, it is blocked then.
+    fn is_waiting(&self, tid: usize) -> bool {
+        self.need
+            .get(tid)
+            .map_or(false, |need| need.values().any(|units| *units > 0))
+    }
+
+    /// Banker's safety check: whether the threads can finish one after
+    /// another, each one getting what it needs from the units available
+    /// and those released by the threads finished before.
+    fn is_safe(&self) -> bool {
+        let mut work = self.available.clone();
+        let mut finish = vec![false; self.need.len()];
+        while let Some(tid) = (0..self.need.len()).find(|&tid| {
+            !finish[tid]
+                && self.need[tid]
+                    .iter()
+                    .all(|(resource, need)| *need <= units(&work, *resource))
+        }) {
+            finish[tid] = true;
+            for (resource, units) in self.allocation[tid].iter() {
+                *work.entry(*resource).or_default() += units;
+            }
+        }
+        finish.into_iter().all(|finished| finished)
+    }
+}
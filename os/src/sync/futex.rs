@@ -0,0 +1,124 @@
+use crate::error::SysResult;
+use crate::mm::{MemorySet, PhysAddr, PhysPageNum, UserPtr};
+use crate::sync::SpinLock;
+use crate::task::{add_task, remove_waiter, TaskControlBlock};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use lazy_static::*;
+
+lazy_static! {
+    /// Wait queues keyed on the physical address of a user word, so that the
+    /// threads of a process as well as processes sharing the memory find the
+    /// same queue. Empty queues are removed.
+    static ref FUTEX_QUEUES: SpinLock<BTreeMap<usize, VecDeque<Arc<TaskControlBlock>>>> =
+        SpinLock::new(BTreeMap::new());
+}
+
+/// Return the physical address of the futex word at `uaddr`, or fail with
+/// EFAULT unless it is aligned and writable. Copy-on-write is resolved
+/// first, so that the word stays in the same frame when it is written later.
+pub fn futex_word(memory_set: &mut MemorySet, uaddr: usize) -> SysResult<PhysAddr> {
+    UserPtr::<u32>::new(uaddr).phys_addr(memory_set, true)
+}
+
+/// Queue `task` on the word at `pa` if it still holds `expected`, the check
+/// and the queueing are atomic with respect to wakeups. The caller has to
+/// block the task afterwards, and keep the page resident until then.
+/// Return false if the word has changed.
+pub fn futex_enqueue(pa: PhysAddr, expected: u32, task: Arc<TaskControlBlock>) -> bool {
+    let mut queues = FUTEX_QUEUES.lock();
+    if pa.get_ref::<AtomicU32>().load(Ordering::SeqCst) != expected {
+        return false;
+    }
+    queues.entry(pa.0).or_default().push_back(task);
+    true
+}
+
+/// Take `task` out of the queue it waits in, which may not be the one it
+/// was queued on since it may have been requeued. Return false if it has
+/// been woken up already.
+pub fn futex_cancel(task: &Arc<TaskControlBlock>, waiting: &AtomicBool) -> bool {
+    let mut queues = FUTEX_QUEUES.lock();
+    let pa = queues
+        .iter()
+        .find(|(_, queue)| queue.iter().any(|waiter| Arc::ptr_eq(waiter, task)))
+        .map(|(pa, _)| *pa);
+    let pa = match pa {
+        Some(pa) => pa,
+        None => return false,
+    };
+    let queue = queues.get_mut(&pa).unwrap();
+    let removed = remove_waiter(queue, task, waiting);
+    if queue.is_empty() {
+        queues.remove(&pa);
+    }
+    removed
+}
+
+/// Wake up at most `count` tasks waiting on the word at `pa`, then move at
+/// most `requeue` of the remaining ones to the word at `target`.
+/// Return how many tasks were woken up.
+pub fn futex_wake(pa: PhysAddr, count: usize, requeue: usize, target: PhysAddr) -> usize {
+    let mut queues = FUTEX_QUEUES.lock();
+    let mut queue = match queues.remove(&pa.0) {
+        Some(queue) => queue,
+        None => return 0,
+    };
+    let woken = count.min(queue.len());
+    for task in queue.drain(..woken) {
+        add_task(task);
+    }
+    let moved = requeue.min(queue.len());
+    if moved > 0 && target.0 != pa.0 {
+        let target_queue = queues.entry(target.0).or_default();
+        target_queue.extend(queue.drain(..moved));
+    }
+    if !queue.is_empty() {
+        queues.insert(pa.0, queue);
+    }
+    woken
+}
+
+/// Whether some task waits on a word in the frame, which must stay resident
+/// since it is the key of the wait queue.
+pub fn futex_frame_waited(ppn: PhysPageNum) -> bool {
+    let start: PhysAddr = ppn.into();
+    let end: PhysAddr = PhysPageNum(ppn.0 + 1).into();
+    FUTEX_QUEUES.lock().range(start.0..end.0).next().is_some()
+}
+
+/// Wake up the tasks waiting on words in a frame which is being freed, they
+/// would be found by words reusing it otherwise.
+pub fn futex_frame_freed(ppn: PhysPageNum) {
+    let start: PhysAddr = ppn.into();
+    let end: PhysAddr = PhysPageNum(ppn.0 + 1).into();
+    let mut queues = FUTEX_QUEUES.lock();
+    let keys: Vec<usize> = queues.range(start.0..end.0).map(|(pa, _)| *pa).collect();
+    let waiters: Vec<_> = keys
+        .iter()
+        .flat_map(|pa| queues.remove(pa).unwrap())
+        .collect();
+    drop(queues);
+    for task in waiters {
+        add_task(task);
+    }
+}
+
+/// Take an exiting task out of any queue, in case it is still in one.
+pub fn futex_remove_task(task: &Arc<TaskControlBlock>) {
+    FUTEX_QUEUES.lock().retain(|_, queue| {
+        queue.retain(|waiter| !Arc::ptr_eq(waiter, task));
+        !queue.is_empty()
+    });
+}
+
+/// Store zero to the word at `uaddr` and wake up one of its waiters, as done
+/// for CLONE_CHILD_CLEARTID when a thread exits. A bad address is ignored.
+pub fn futex_clear_and_wake(memory_set: &mut MemorySet, uaddr: usize) {
+    if let Ok(pa) = futex_word(memory_set, uaddr) {
+        pa.get_ref::<AtomicU32>().store(0, Ordering::SeqCst);
+        futex_wake(pa, 1, 0, pa);
+    }
+}
@@ -1,9 +1,22 @@
+mod barrier;
 mod condvar;
+mod deadlock;
+mod futex;
 mod mutex;
+mod rwlock;
 mod semaphore;
+mod spin;
 mod up;
 
+pub use barrier::Barrier;
 pub use condvar::Condvar;
+pub use deadlock::{DeadlockDetector, Resource};
+pub use futex::{
+    futex_cancel, futex_clear_and_wake, futex_enqueue, futex_frame_freed, futex_frame_waited,
+    futex_remove_task, futex_wake, futex_word,
+};
 pub use mutex::{Mutex, MutexBlocking, MutexSpin};
-pub use semaphore::Semaphore;
+pub use rwlock::{RwLock, RwLockPolicy};
+pub use semaphore::{semaphore_open, Semaphore};
+pub use spin::{SpinLock, SpinLockGuard};
 pub use up::UPSafeCell;
@@ -1,88 +1,219 @@
-use super::UPSafeCell;
-use crate::task::TaskControlBlock;
-use crate::task::{add_task, current_task};
-use crate::task::{block_current_and_run_next, suspend_current_and_run_next};
-use alloc::{collections::VecDeque, sync::Arc};
+use super::SpinLock;
+use crate::task::{add_task, current_task, TaskControlBlock};
+use crate::task::{block_current, current_interrupted, suspend_current_and_run_next};
+use crate::task::{remove_waiter, WaitResult};
+use crate::timer::get_time_ms;
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use core::cmp::Reverse;
+use core::sync::atomic::AtomicBool;
 
 pub trait Mutex: Sync + Send {
-    fn lock(&self);
-    fn unlock(&self);
+    /// Lock the mutex, giving up waiting at `expire_ms` if there is one.
+    /// It is held unless the wait has timed out or been interrupted.
+    fn lock(self: Arc<Self>, expire_ms: Option<usize>) -> WaitResult;
+    /// Return false if the mutex isn't locked by the current task.
+    fn unlock(&self) -> bool;
+}
+
+/// Whether `owner` is the current task.
+fn is_current(owner: &Option<Weak<TaskControlBlock>>) -> bool {
+    let task = current_task().unwrap();
+    owner
+        .as_ref()
+        .map_or(false, |owner| Weak::as_ptr(owner) == Arc::as_ptr(&task))
 }
 
 pub struct MutexSpin {
-    locked: UPSafeCell<bool>,
+    /// the owner if it is locked
+    locked: SpinLock<Option<Weak<TaskControlBlock>>>,
 }
 
 impl MutexSpin {
     pub fn new() -> Self {
         Self {
-            locked: unsafe { UPSafeCell::new(false) },
+            locked: SpinLock::new(None),
         }
     }
 }
 
 impl Mutex for MutexSpin {
-    fn lock(&self) {
+    fn lock(self: Arc<Self>, expire_ms: Option<usize>) -> WaitResult {
         loop {
-            let mut locked = self.locked.exclusive_access();
-            if *locked {
-                drop(locked);
-                suspend_current_and_run_next();
-                continue;
-            } else {
-                *locked = true;
-                return;
+            let mut locked = self.locked.lock();
+            if locked.is_none() {
+                *locked = Some(Arc::downgrade(&current_task().unwrap()));
+                return WaitResult::Woken;
+            }
+            drop(locked);
+            if expire_ms.map_or(false, |expire_ms| get_time_ms() >= expire_ms) {
+                return WaitResult::TimedOut;
             }
+            if current_interrupted() {
+                return WaitResult::Interrupted;
+            }
+            suspend_current_and_run_next();
         }
     }
 
-    fn unlock(&self) {
-        let mut locked = self.locked.exclusive_access();
-        *locked = false;
+    fn unlock(&self) -> bool {
+        let mut locked = self.locked.lock();
+        if !is_current(&locked) {
+            return false;
+        }
+        *locked = None;
+        true
     }
 }
 
+/// A mutex which hands itself to the waiter with the highest priority, and
+/// lends the priorities of the waiters to the owner, as well as to the owner
+/// of a mutex the owner is blocked on and so on, to avoid priority
+/// inversion.
 pub struct MutexBlocking {
-    inner: UPSafeCell<MutexBlockingInner>,
+    inner: SpinLock<MutexBlockingInner>,
 }
 
 pub struct MutexBlockingInner {
     locked: bool,
+    owner: Option<Weak<TaskControlBlock>>,
     wait_queue: VecDeque<Arc<TaskControlBlock>>,
 }
 
 impl MutexBlocking {
     pub fn new() -> Self {
         Self {
-            inner: unsafe {
-                UPSafeCell::new(MutexBlockingInner {
-                    locked: false,
-                    wait_queue: VecDeque::new(),
-                })
-            },
+            inner: SpinLock::new(MutexBlockingInner {
+                locked: false,
+                owner: None,
+                wait_queue: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Identifies the mutex in the priority inheritance state of the tasks.
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    /// Take the mutex and return true if it is free, or join the wait queue
+    /// and lend our priority to the owner.
+    fn take_or_wait(&self) -> bool {
+        let task = current_task().unwrap();
+        let mut mutex_inner = self.inner.lock();
+        if !mutex_inner.locked {
+            mutex_inner.locked = true;
+            mutex_inner.owner = Some(Arc::downgrade(&task));
+            task.inner_exclusive_access().sched.hold_mutex(self.id(), 0);
+            return true;
+        }
+        let owner = mutex_inner.owner.clone().unwrap();
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.sched.blocked_on = Some((self.id(), owner.clone()));
+        let priority = task_inner.sched.priority;
+        drop(task_inner);
+        mutex_inner.wait_queue.push_back(task);
+        drop(mutex_inner);
+        lend_priority(owner, self.id(), priority);
+        false
+    }
+
+    /// Called when a wait times out or is interrupted. The owner keeps only
+    /// the priorities lent by the remaining waiters, owners further along the
+    /// chain keep theirs until they unlock.
+    fn cancel_wait(&self, task: &Arc<TaskControlBlock>, waiting: &AtomicBool) -> bool {
+        let mut mutex_inner = self.inner.lock();
+        if !remove_waiter(&mut mutex_inner.wait_queue, task, waiting) {
+            return false;
+        }
+        task.inner_exclusive_access().sched.blocked_on = None;
+        let inherited = mutex_inner
+            .wait_queue
+            .iter()
+            .map(|task| task.inner_exclusive_access().sched.priority)
+            .max()
+            .unwrap_or(0);
+        let owner = mutex_inner.owner.as_ref().and_then(Weak::upgrade);
+        if let Some(owner) = owner.as_ref() {
+            owner
+                .inner_exclusive_access()
+                .sched
+                .update_inherited(self.id(), inherited);
+        }
+        // the owner may have exited, don't drop its last reference with the
+        // lock held
+        drop(mutex_inner);
+        true
+    }
+}
+
+/// Raise the priority of `owner` which holds `mutex` to `priority`, and pass
+/// it along the chain of owners blocked on other mutexes. Tasks are locked
+/// one at a time, a task which has released the mutex meanwhile is skipped.
+fn lend_priority(mut owner: Weak<TaskControlBlock>, mut mutex: usize, priority: usize) {
+    while let Some(task) = owner.upgrade() {
+        let mut task_inner = task.inner_exclusive_access();
+        if !task_inner.sched.inherit(mutex, priority) {
+            return;
+        }
+        match task_inner.sched.blocked_on.clone() {
+            Some((next_mutex, next_owner)) => {
+                mutex = next_mutex;
+                owner = next_owner;
+            }
+            None => return,
         }
     }
 }
 
 impl Mutex for MutexBlocking {
-    fn lock(&self) {
-        let mut mutex_inner = self.inner.exclusive_access();
-        if mutex_inner.locked {
-            mutex_inner.wait_queue.push_back(current_task().unwrap());
-            drop(mutex_inner);
-            block_current_and_run_next();
-        } else {
-            mutex_inner.locked = true;
+    fn lock(self: Arc<Self>, expire_ms: Option<usize>) -> WaitResult {
+        if self.take_or_wait() {
+            return WaitResult::Woken;
         }
+        // unlock hands the mutex over to us
+        block_current(expire_ms, move |task, waiting| {
+            self.cancel_wait(task, waiting)
+        })
     }
 
-    fn unlock(&self) {
-        let mut mutex_inner = self.inner.exclusive_access();
-        assert!(mutex_inner.locked);
-        if let Some(waking_task) = mutex_inner.wait_queue.pop_front() {
-            add_task(waking_task);
-        } else {
-            mutex_inner.locked = false;
+    fn unlock(&self) -> bool {
+        let task = current_task().unwrap();
+        let mut mutex_inner = self.inner.lock();
+        if !mutex_inner.locked || !is_current(&mutex_inner.owner) {
+            return false;
+        }
+        task.inner_exclusive_access().sched.release_mutex(self.id());
+        drop(task);
+        let priority = |task: &Arc<TaskControlBlock>| task.inner_exclusive_access().sched.priority;
+        // the first one among those with the highest priority
+        let next = mutex_inner
+            .wait_queue
+            .iter()
+            .enumerate()
+            .max_by_key(|(idx, task)| (priority(task), Reverse(*idx)))
+            .map(|(idx, _)| idx);
+        let waking_task = match next {
+            Some(idx) => mutex_inner.wait_queue.remove(idx).unwrap(),
+            None => {
+                mutex_inner.locked = false;
+                mutex_inner.owner = None;
+                return true;
+            }
+        };
+        let new_owner = Arc::downgrade(&waking_task);
+        let mut inherited = 0;
+        for task in mutex_inner.wait_queue.iter() {
+            let mut task_inner = task.inner_exclusive_access();
+            task_inner.sched.blocked_on = Some((self.id(), new_owner.clone()));
+            inherited = inherited.max(task_inner.sched.priority);
         }
+        let mut waking_inner = waking_task.inner_exclusive_access();
+        waking_inner.sched.blocked_on = None;
+        waking_inner.sched.hold_mutex(self.id(), inherited);
+        drop(waking_inner);
+        mutex_inner.owner = Some(new_owner);
+        add_task(waking_task);
+        true
     }
 }
@@ -0,0 +1,125 @@
+use crate::sync::SpinLock;
+use crate::task::{
+    add_task, block_current, current_task, remove_waiter, TaskControlBlock, WaitResult,
+};
+use alloc::{collections::VecDeque, sync::Arc};
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum RwLockPolicy {
+    /// readers may join the current ones even if writers are waiting, which
+    /// may starve the writers
+    PreferReader,
+    /// readers wait as long as writers do, which may starve the readers
+    PreferWriter,
+}
+
+/// A reader-writer lock which hands itself over on unlock, so woken up
+/// tasks don't have to try again.
+pub struct RwLock {
+    inner: SpinLock<RwLockInner>,
+}
+
+pub struct RwLockInner {
+    policy: RwLockPolicy,
+    readers: usize,
+    writer: bool,
+    read_queue: VecDeque<Arc<TaskControlBlock>>,
+    write_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl RwLockInner {
+    /// Let in either all the waiting readers or one writer, as the policy
+    /// prefers.
+    fn grant(&mut self) {
+        if self.writer || self.readers > 0 {
+            return;
+        }
+        let readers_first = match self.policy {
+            RwLockPolicy::PreferReader => !self.read_queue.is_empty(),
+            RwLockPolicy::PreferWriter => self.write_queue.is_empty(),
+        };
+        if readers_first {
+            self.readers = self.read_queue.len();
+            for task in self.read_queue.drain(..) {
+                add_task(task);
+            }
+        } else if let Some(task) = self.write_queue.pop_front() {
+            self.writer = true;
+            add_task(task);
+        }
+    }
+}
+
+impl RwLock {
+    pub fn new(policy: RwLockPolicy) -> Self {
+        Self {
+            inner: SpinLock::new(RwLockInner {
+                policy,
+                readers: 0,
+                writer: false,
+                read_queue: VecDeque::new(),
+                write_queue: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// The lock is held unless the wait is interrupted.
+    pub fn read_lock(self: &Arc<Self>) -> WaitResult {
+        let mut inner = self.inner.lock();
+        let writers_first =
+            inner.policy == RwLockPolicy::PreferWriter && !inner.write_queue.is_empty();
+        if inner.writer || writers_first {
+            inner.read_queue.push_back(current_task().unwrap());
+            drop(inner);
+            self.wait()
+        } else {
+            inner.readers += 1;
+            WaitResult::Woken
+        }
+    }
+
+    /// The lock is held unless the wait is interrupted.
+    pub fn write_lock(self: &Arc<Self>) -> WaitResult {
+        let mut inner = self.inner.lock();
+        if inner.writer || inner.readers > 0 {
+            inner.write_queue.push_back(current_task().unwrap());
+            drop(inner);
+            self.wait()
+        } else {
+            inner.writer = true;
+            WaitResult::Woken
+        }
+    }
+
+    /// Wait in one of the queues until the lock is handed over. A writer
+    /// giving up may let the readers behind it in.
+    fn wait(self: &Arc<Self>) -> WaitResult {
+        let rwlock = Arc::clone(self);
+        block_current(None, move |task, waiting| {
+            let mut inner = rwlock.inner.lock();
+            let inner = &mut *inner;
+            if !remove_waiter(&mut inner.read_queue, task, waiting)
+                && !remove_waiter(&mut inner.write_queue, task, waiting)
+            {
+                return false;
+            }
+            inner.grant();
+            true
+        })
+    }
+
+    /// Unlock as the writer if it is held for writing, or as one of the
+    /// readers otherwise. Return false if it isn't held at all.
+    pub fn unlock(&self) -> bool {
+        let mut inner = self.inner.lock();
+        if inner.writer {
+            inner.writer = false;
+        } else if inner.readers > 0 {
+            inner.readers -= 1;
+        } else {
+            return false;
+        }
+        inner.grant();
+        true
+    }
+}
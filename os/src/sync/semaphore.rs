@@ -1,9 +1,16 @@
-use crate::sync::UPSafeCell;
-use crate::task::{add_task, block_current_and_run_next, current_task, TaskControlBlock};
-use alloc::{collections::VecDeque, sync::Arc};
+use crate::sync::SpinLock;
+use crate::task::{
+    add_task, block_current, current_task, remove_waiter, TaskControlBlock, WaitResult,
+};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use lazy_static::*;
 
 pub struct Semaphore {
-    pub inner: UPSafeCell<SemaphoreInner>,
+    /// the key it is opened by in every process, 0 for those of a single
+    /// process, see `semaphore_open`
+    pub key: usize,
+    pub inner: SpinLock<SemaphoreInner>,
 }
 
 pub struct SemaphoreInner {
@@ -13,18 +20,21 @@ pub struct SemaphoreInner {
 
 impl Semaphore {
     pub fn new(res_count: usize) -> Self {
+        Self::with_key(0, res_count)
+    }
+
+    fn with_key(key: usize, res_count: usize) -> Self {
         Self {
-            inner: unsafe {
-                UPSafeCell::new(SemaphoreInner {
-                    count: res_count as isize,
-                    wait_queue: VecDeque::new(),
-                })
-            },
+            key,
+            inner: SpinLock::new(SemaphoreInner {
+                count: res_count as isize,
+                wait_queue: VecDeque::new(),
+            }),
         }
     }
 
     pub fn up(&self) {
-        let mut inner = self.inner.exclusive_access();
+        let mut inner = self.inner.lock();
         inner.count += 1;
         if inner.count <= 0 {
             if let Some(task) = inner.wait_queue.pop_front() {
@@ -33,13 +43,44 @@ impl Semaphore {
         }
     }
 
-    pub fn down(&self) {
-        let mut inner = self.inner.exclusive_access();
+    /// Give up waiting at `expire_ms` if there is one. The count is given
+    /// back if the wait times out or is interrupted.
+    pub fn down(self: &Arc<Self>, expire_ms: Option<usize>) -> WaitResult {
+        let mut inner = self.inner.lock();
         inner.count -= 1;
-        if inner.count < 0 {
-            inner.wait_queue.push_back(current_task().unwrap());
-            drop(inner);
-            block_current_and_run_next();
+        if inner.count >= 0 {
+            return WaitResult::Woken;
         }
+        inner.wait_queue.push_back(current_task().unwrap());
+        drop(inner);
+        let sem = Arc::clone(self);
+        block_current(expire_ms, move |task, waiting| {
+            let mut inner = sem.inner.lock();
+            if !remove_waiter(&mut inner.wait_queue, task, waiting) {
+                return false;
+            }
+            inner.count += 1;
+            true
+        })
+    }
+}
+
+lazy_static! {
+    /// Semaphores shared by processes, keyed on a number they agree on. One
+    /// is gone once no process has it open.
+    static ref NAMED_SEMAPHORES: SpinLock<BTreeMap<usize, Weak<Semaphore>>> =
+        SpinLock::new(BTreeMap::new());
+}
+
+/// Return the semaphore with `key`, which is created with `res_count` units
+/// if no process has it open.
+pub fn semaphore_open(key: usize, res_count: usize) -> Arc<Semaphore> {
+    let mut table = NAMED_SEMAPHORES.lock();
+    if let Some(sem) = table.get(&key).and_then(Weak::upgrade) {
+        return sem;
     }
+    table.retain(|_, sem| sem.strong_count() > 0);
+    let sem = Arc::new(Semaphore::with_key(key, res_count));
+    table.insert(key, Arc::downgrade(&sem));
+    sem
 }
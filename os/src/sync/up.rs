@@ -3,7 +3,8 @@ use core::cell::{RefCell, RefMut};
 /// Wrap a static data structure inside it so that we are
 /// able to access it without any `unsafe`.
 ///
-/// We should only use it in uniprocessor.
+/// We should only use it in uniprocessor, or for data that is only
+/// accessed by the hart owning it. Use `SpinLock` for shared data.
 ///
 /// In order to get mutable reference of inner data, call
 /// `exclusive_access`.
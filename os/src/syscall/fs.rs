@@ -1,99 +1,503 @@
-use crate::fs::{make_pipe, open_file, OpenFlags};
-use crate::mm::{translated_byte_buffer, translated_refmut, translated_str, UserBuffer};
-use crate::task::{current_process, current_user_token};
+use super::restart_syscall;
+use crate::config::PAGE_SIZE;
+use crate::error::{SysError, SysResult};
+use crate::fs::{
+    console_background, console_foreground, console_set_foreground, console_set_session, make_pipe,
+    open_file, File, OpenFlags, Stat, S_IFDIR, S_IFMT,
+};
+use crate::mm::{copy_from_user, copy_to_user, UserPtr, UserSlice};
+use crate::task::{
+    alloc_fd, current_blocks_or_ignores, current_process, process_group, send_group_signal,
+    FdTable, ProcessControlBlock, ProcessControlBlockInner, SignalFlags,
+};
 use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
 
-pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
-    let token = current_user_token();
-    let process = current_process();
-    let inner = process.inner_exclusive_access();
-    if fd >= inner.fd_table.len() {
-        return -1;
+/// Terminal requests of `sys_ioctl`, as on Linux.
+const TIOCSCTTY: usize = 0x540e;
+const TIOCGPGRP: usize = 0x540f;
+const TIOCSPGRP: usize = 0x5410;
+const TIOCGWINSZ: usize = 0x5413;
+/// The most bytes a read or write moves through the kernel at once.
+const BOUNCE_SIZE: usize = PAGE_SIZE;
+/// The longest path accepted, easy-fs names are much shorter anyway.
+pub const PATH_MAX: usize = 256;
+/// `dirfd` standing for the working directory, which is always the root.
+const AT_FDCWD: isize = -100;
+/// `flags` of `sys_newfstatat`: an empty path stands for `dirfd` itself.
+const AT_EMPTY_PATH: u32 = 0x1000;
+/// Ignored, there are no symbolic links.
+const AT_SYMLINK_NOFOLLOW: u32 = 0x100;
+/// Commands of `sys_fcntl`.
+const F_DUPFD: usize = 0;
+const F_GETFD: usize = 1;
+const F_SETFD: usize = 2;
+const F_GETFL: usize = 3;
+const F_SETFL: usize = 4;
+const F_DUPFD_CLOEXEC: usize = 1030;
+/// Fds are below this.
+const FD_MAX: usize = 1024;
+/// The most buffers `sys_readv` and `sys_writev` take.
+const IOV_MAX: usize = 1024;
+
+/// `struct iovec` of `sys_readv` and `sys_writev`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct IoVec {
+    base: usize,
+    len: usize,
+}
+
+/// `struct winsize` of TIOCGWINSZ.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct WinSize {
+    rows: u16,
+    cols: u16,
+    xpixel: u16,
+    ypixel: u16,
+}
+
+/// The open file at `fd`, or EBADF.
+fn get_file(inner: &ProcessControlBlockInner, fd: usize) -> SysResult<Arc<dyn File + Send + Sync>> {
+    inner
+        .fd_table
+        .lock()
+        .get(fd)
+        .cloned()
+        .flatten()
+        .ok_or(SysError::EBADF)
+}
+
+/// Paths are looked up from the root directory whatever `dirfd` is, as long
+/// as it is AT_FDCWD or an open directory, which can only be the root.
+/// Absolute paths don't look at it at all.
+fn check_dirfd(inner: &ProcessControlBlockInner, dirfd: isize, path: &str) -> SysResult<()> {
+    if path.starts_with('/') || dirfd == AT_FDCWD {
+        return Ok(());
+    }
+    if dirfd < 0 {
+        return Err(SysError::EBADF);
+    }
+    if get_file(inner, dirfd as usize)?.stat().mode & S_IFMT != S_IFDIR {
+        return Err(SysError::ENOTDIR);
+    }
+    Ok(())
+}
+
+/// The lowest free fd not below `min`, which stays free until the file is
+/// put there.
+fn alloc_fd_from(fd_table: &mut FdTable, min: usize) -> SysResult {
+    if min >= FD_MAX {
+        return Err(SysError::EINVAL);
+    }
+    if fd_table.len() <= min {
+        fd_table.resize(min + 1, None);
+    }
+    match (min..fd_table.len()).find(|fd| fd_table[*fd].is_none()) {
+        Some(fd) => Ok(fd),
+        None if fd_table.len() < FD_MAX => {
+            fd_table.push(None);
+            Ok(fd_table.len() - 1)
+        }
+        None => Err(SysError::EMFILE),
+    }
+}
+
+/// Write `len` bytes at `buf` to `file`, copying them to a kernel buffer a
+/// chunk at a time: the file may block, while user pages may be unmapped,
+/// copied on write or swapped out. Stop at the first chunk which isn't all
+/// written, a failure after something has been written is not reported.
+fn write_file(process: &ProcessControlBlock, file: &dyn File, buf: usize, len: usize) -> SysResult {
+    let mut bounce = vec![0u8; len.min(BOUNCE_SIZE)];
+    let mut written = 0;
+    while written < len {
+        let chunk = &mut bounce[..(len - written).min(BOUNCE_SIZE)];
+        let mut inner = process.inner_exclusive_access();
+        let copied = copy_from_user(&mut inner.memory_set, buf + written, chunk);
+        drop(inner);
+        match copied.and_then(|_| file.write(chunk)) {
+            Ok(size) => {
+                written += size;
+                if size < chunk.len() {
+                    break;
+                }
+            }
+            Err(_) if written > 0 => break,
+            Err(err) => return Err(err),
+        }
     }
-    if let Some(file) = &inner.fd_table[fd] {
-        if !file.writable() {
-            return -1;
+    Ok(written)
+}
+
+/// Read at most `len` bytes from `file` to `buf` through a kernel buffer,
+/// like `write_file`. Bytes read from the file are lost if the user memory
+/// has been unmapped in the meantime.
+fn read_file(process: &ProcessControlBlock, file: &dyn File, buf: usize, len: usize) -> SysResult {
+    let mut bounce = vec![0u8; len.min(BOUNCE_SIZE)];
+    let mut read = 0;
+    while read < len {
+        let chunk = &mut bounce[..(len - read).min(BOUNCE_SIZE)];
+        let result = file.read(chunk).and_then(|size| {
+            let mut inner = process.inner_exclusive_access();
+            copy_to_user(&mut inner.memory_set, buf + read, &chunk[..size])?;
+            Ok(size)
+        });
+        match result {
+            Ok(size) => {
+                read += size;
+                if size < chunk.len() {
+                    break;
+                }
+            }
+            Err(_) if read > 0 => break,
+            Err(err) => return Err(err),
         }
-        let file = file.clone();
-        // release current task TCB manually to avoid multi-borrow
+    }
+    Ok(read)
+}
+
+pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> SysResult {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let file = inner
+        .fd_table
+        .lock()
+        .get(fd)
+        .cloned()
+        .flatten()
+        .ok_or(SysError::EBADF)?;
+    if !file.writable() {
+        return Err(SysError::EBADF);
+    }
+    UserSlice::new(buf as usize, len).check(&mut inner.memory_set, false)?;
+    // release current task TCB manually to avoid multi-borrow
+    drop(inner);
+    write_file(&process, file.as_ref(), buf as usize, len)
+}
+
+pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> SysResult {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let file = inner
+        .fd_table
+        .lock()
+        .get(fd)
+        .cloned()
+        .flatten()
+        .ok_or(SysError::EBADF)?;
+    if !file.readable() {
+        return Err(SysError::EBADF);
+    }
+    if file.is_tty() && console_background(inner.sid, inner.pgid) {
+        let pgid = inner.pgid;
         drop(inner);
-        file.write(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
-    } else {
-        -1
+        return background_read(pgid);
+    }
+    UserSlice::new(buf as usize, len).check(&mut inner.memory_set, true)?;
+    // release current task TCB manually to avoid multi-borrow
+    drop(inner);
+    read_file(&process, file.as_ref(), buf as usize, len)
+}
+
+/// Open the file at `path`, see `check_dirfd` for `dirfd`. There are no
+/// permissions, `mode` is ignored.
+pub fn sys_openat(dirfd: isize, path: *const u8, flags: u32, _mode: usize) -> SysResult {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let path = UserPtr::from(path).read_str(&mut inner.memory_set, PATH_MAX)?;
+    check_dirfd(&inner, dirfd, &path)?;
+    drop(inner);
+    let flags = OpenFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    let inode = open_file(path.as_str(), flags)?;
+    let inner = process.inner_exclusive_access();
+    let mut fd_table = inner.fd_table.lock();
+    let fd = alloc_fd(&mut fd_table);
+    fd_table[fd] = Some(inode);
+    Ok(fd)
+}
+
+pub fn sys_close(fd: usize) -> SysResult {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let mut fd_table = inner.fd_table.lock();
+    match fd_table.get_mut(fd) {
+        Some(file @ Some(_)) => {
+            file.take();
+            Ok(0)
+        }
+        _ => Err(SysError::EBADF),
+    }
+}
+
+/// Only O_CLOEXEC may be given as `flags`, and it is ignored.
+pub fn sys_pipe2(pipe: *mut [i32; 2], flags: u32) -> SysResult {
+    if flags & !OpenFlags::CLOEXEC.bits() != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let (pipe_read, pipe_write) = make_pipe();
+    let mut fd_table = inner.fd_table.lock();
+    let read_fd = alloc_fd(&mut fd_table);
+    fd_table[read_fd] = Some(pipe_read);
+    let write_fd = alloc_fd(&mut fd_table);
+    fd_table[write_fd] = Some(pipe_write);
+    drop(fd_table);
+    // nothing stays open unless the fds can be stored
+    let fds = [read_fd as i32, write_fd as i32];
+    if let Err(err) = UserPtr::from(pipe).write(&mut inner.memory_set, &fds) {
+        let mut fd_table = inner.fd_table.lock();
+        fd_table[read_fd] = None;
+        fd_table[write_fd] = None;
+        return Err(err);
     }
+    Ok(0)
 }
 
-pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
-    let token = current_user_token();
+pub fn sys_dup(fd: usize) -> SysResult {
     let process = current_process();
     let inner = process.inner_exclusive_access();
-    if fd >= inner.fd_table.len() {
-        return -1;
+    let mut fd_table = inner.fd_table.lock();
+    let file = match fd_table.get(fd) {
+        Some(Some(file)) => Arc::clone(file),
+        _ => return Err(SysError::EBADF),
+    };
+    let new_fd = alloc_fd(&mut fd_table);
+    fd_table[new_fd] = Some(file);
+    Ok(new_fd)
+}
+
+/// Make `new_fd` refer to the file of `old_fd`, closing what it referred to.
+/// Only O_CLOEXEC may be given as `flags`, and it is ignored.
+pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: u32) -> SysResult {
+    if flags & !OpenFlags::CLOEXEC.bits() != 0 || old_fd == new_fd {
+        return Err(SysError::EINVAL);
+    }
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let file = get_file(&inner, old_fd)?;
+    if new_fd >= FD_MAX {
+        return Err(SysError::EBADF);
+    }
+    let mut fd_table = inner.fd_table.lock();
+    if fd_table.len() <= new_fd {
+        fd_table.resize(new_fd + 1, None);
     }
-    if let Some(file) = &inner.fd_table[fd] {
-        let file = file.clone();
-        if !file.readable() {
-            return -1;
+    // the old file is closed once the tables are released
+    let old_file = fd_table[new_fd].replace(file);
+    drop(fd_table);
+    drop(inner);
+    drop(old_file);
+    Ok(new_fd)
+}
+
+/// Close-on-exec is not kept track of: F_GETFD always returns 0 and F_SETFD
+/// does nothing. F_GETFL only returns the access mode, and F_SETFL does
+/// nothing either.
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> SysResult {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let file = get_file(&inner, fd)?;
+    match cmd {
+        F_DUPFD | F_DUPFD_CLOEXEC => {
+            let mut fd_table = inner.fd_table.lock();
+            let new_fd = alloc_fd_from(&mut fd_table, arg)?;
+            fd_table[new_fd] = Some(file);
+            Ok(new_fd)
         }
-        // release current task TCB manually to avoid multi-borrow
-        drop(inner);
-        file.read(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
-    } else {
-        -1
+        F_GETFD | F_SETFD | F_SETFL => Ok(0),
+        F_GETFL => Ok(match (file.readable(), file.writable()) {
+            (true, false) => OpenFlags::RDONLY,
+            (false, true) => OpenFlags::WRONLY,
+            _ => OpenFlags::RDWR,
+        }
+        .bits() as usize),
+        _ => Err(SysError::EINVAL),
     }
 }
 
-pub fn sys_open(path: *const u8, flags: u32) -> isize {
+/// Fill `buf` with the entries of directory `fd` which haven't been listed
+/// yet, see `File::read_dir`.
+pub fn sys_getdents64(fd: usize, buf: *mut u8, len: usize) -> SysResult {
     let process = current_process();
-    let token = current_user_token();
-    let path = translated_str(token, path);
-    if let Some(inode) = open_file(path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
-        let mut inner = process.inner_exclusive_access();
-        let fd = inner.alloc_fd();
-        inner.fd_table[fd] = Some(inode);
-        fd as isize
-    } else {
-        -1
+    let mut inner = process.inner_exclusive_access();
+    let file = get_file(&inner, fd)?;
+    UserSlice::new(buf as usize, len).check(&mut inner.memory_set, true)?;
+    drop(inner);
+    let mut records = vec![0u8; len.min(BOUNCE_SIZE)];
+    let size = file.read_dir(&mut records)?;
+    let mut inner = process.inner_exclusive_access();
+    copy_to_user(&mut inner.memory_set, buf as usize, &records[..size])?;
+    Ok(size)
+}
+
+/// The `struct iovec`s of `sys_readv` and `sys_writev`.
+fn read_iovecs(iov: *const IoVec, iovcnt: usize) -> SysResult<Vec<IoVec>> {
+    if iovcnt > IOV_MAX {
+        return Err(SysError::EINVAL);
+    }
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let iov = UserPtr::from(iov);
+    (0..iovcnt)
+        .map(|i| iov.add(i).read(&mut inner.memory_set))
+        .collect()
+}
+
+/// Read into each buffer in turn as `sys_read` does, stopping at the first
+/// which isn't filled. A failure after something has been read is not
+/// reported.
+pub fn sys_readv(fd: usize, iov: *const IoVec, iovcnt: usize) -> SysResult {
+    let mut total = 0;
+    for iovec in read_iovecs(iov, iovcnt)? {
+        match sys_read(fd, iovec.base as *const u8, iovec.len) {
+            Ok(len) => {
+                total += len;
+                if len < iovec.len {
+                    break;
+                }
+            }
+            Err(_) if total > 0 => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(total)
+}
+
+/// Write each buffer in turn as `sys_write` does, like `sys_readv`.
+pub fn sys_writev(fd: usize, iov: *const IoVec, iovcnt: usize) -> SysResult {
+    let mut total = 0;
+    for iovec in read_iovecs(iov, iovcnt)? {
+        match sys_write(fd, iovec.base as *const u8, iovec.len) {
+            Ok(len) => {
+                total += len;
+                if len < iovec.len {
+                    break;
+                }
+            }
+            Err(_) if total > 0 => break,
+            Err(err) => return Err(err),
+        }
     }
+    Ok(total)
 }
 
-pub fn sys_close(fd: usize) -> isize {
+pub fn sys_fstat(fd: usize, stat: *mut Stat) -> SysResult {
     let process = current_process();
     let mut inner = process.inner_exclusive_access();
-    if fd >= inner.fd_table.len() {
-        return -1;
+    let value = get_file(&inner, fd)?.stat();
+    UserPtr::from(stat).write(&mut inner.memory_set, &value)?;
+    Ok(0)
+}
+
+/// `sys_fstat` on the file at `path`, see `check_dirfd` for `dirfd`, or on
+/// `dirfd` itself if `path` is empty with AT_EMPTY_PATH.
+pub fn sys_newfstatat(dirfd: isize, path: *const u8, stat: *mut Stat, flags: u32) -> SysResult {
+    if flags & !(AT_EMPTY_PATH | AT_SYMLINK_NOFOLLOW) != 0 {
+        return Err(SysError::EINVAL);
     }
-    if inner.fd_table[fd].is_none() {
-        return -1;
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let path = UserPtr::from(path).read_str(&mut inner.memory_set, PATH_MAX)?;
+    let empty = path.is_empty() && flags & AT_EMPTY_PATH != 0;
+    if empty && dirfd != AT_FDCWD {
+        drop(inner);
+        return sys_fstat(dirfd as usize, stat);
     }
-    inner.fd_table[fd].take();
-    0
+    check_dirfd(&inner, dirfd, &path)?;
+    drop(inner);
+    let path = if empty { "." } else { path.as_str() };
+    let value = open_file(path, OpenFlags::RDONLY)?.stat();
+    let mut inner = process.inner_exclusive_access();
+    UserPtr::from(stat).write(&mut inner.memory_set, &value)?;
+    Ok(0)
 }
 
-pub fn sys_pipe(pipe: *mut usize) -> isize {
+/// The working directory is always the root. Return the length of the path
+/// with its `\0`, or fail with ERANGE if `size` is too small for it.
+pub fn sys_getcwd(buf: *mut u8, size: usize) -> SysResult {
+    let cwd = b"/\0";
+    if size < cwd.len() {
+        return Err(SysError::ERANGE);
+    }
     let process = current_process();
-    let token = current_user_token();
     let mut inner = process.inner_exclusive_access();
-    let (pipe_read, pipe_write) = make_pipe();
-    let read_fd = inner.alloc_fd();
-    inner.fd_table[read_fd] = Some(pipe_read);
-    let write_fd = inner.alloc_fd();
-    inner.fd_table[write_fd] = Some(pipe_write);
-    *translated_refmut(token, pipe) = read_fd;
-    *translated_refmut(token, unsafe { pipe.add(1) }) = write_fd;
-    0
+    copy_to_user(&mut inner.memory_set, buf as usize, cwd)?;
+    Ok(cwd.len())
+}
+
+/// A background process reading the console is stopped with its group by
+/// SIGTTIN, and reads again once it is continued. The read fails with EIO if
+/// the signal wouldn't stop it, as on Linux.
+fn background_read(pgid: usize) -> SysResult {
+    if current_blocks_or_ignores(SignalFlags::SIGTTIN) {
+        return Err(SysError::EIO);
+    }
+    send_group_signal(pgid, SignalFlags::SIGTTIN);
+    restart_syscall()
 }
 
-pub fn sys_dup(fd: usize) -> isize {
+/// Only the terminal requests on the console are known. TIOCSCTTY makes it
+/// the controlling terminal of the session led by the caller, taking it from
+/// another session if `arg` is 1. TIOCGPGRP and TIOCSPGRP get and set its
+/// foreground group, a group of the session, through the `i32` at `arg`.
+/// TIOCGWINSZ reports a size of 24 by 80.
+pub fn sys_ioctl(fd: usize, request: usize, arg: usize) -> SysResult {
     let process = current_process();
     let mut inner = process.inner_exclusive_access();
-    if fd >= inner.fd_table.len() {
-        return -1;
+    let file = inner
+        .fd_table
+        .lock()
+        .get(fd)
+        .cloned()
+        .flatten()
+        .ok_or(SysError::EBADF)?;
+    if !file.is_tty() {
+        return Err(SysError::ENOTTY);
+    }
+    let (pgid, sid) = (inner.pgid, inner.sid);
+    if request == TIOCGWINSZ {
+        let size = WinSize {
+            rows: 24,
+            cols: 80,
+            xpixel: 0,
+            ypixel: 0,
+        };
+        UserPtr::<WinSize>::new(arg).write(&mut inner.memory_set, &size)?;
+        return Ok(0);
     }
-    if inner.fd_table[fd].is_none() {
-        return -1;
+    let arg = UserPtr::<i32>::new(arg);
+    match request {
+        TIOCSCTTY => {
+            if sid != process.getpid() || !console_set_session(sid, pgid, arg.addr() == 1) {
+                return Err(SysError::EPERM);
+            }
+            Ok(0)
+        }
+        TIOCGPGRP => {
+            let foreground = console_foreground(sid).ok_or(SysError::ENOTTY)?;
+            arg.write(&mut inner.memory_set, &(foreground as i32))?;
+            Ok(0)
+        }
+        TIOCSPGRP => {
+            let foreground = arg.read(&mut inner.memory_set)?;
+            drop(inner);
+            if foreground < 0 {
+                return Err(SysError::EINVAL);
+            }
+            let in_session = process_group(foreground as usize)
+                .iter()
+                .any(|other| other.inner_exclusive_access().sid == sid);
+            if !in_session {
+                return Err(SysError::EPERM);
+            }
+            if !console_set_foreground(sid, foreground as usize) {
+                return Err(SysError::ENOTTY);
+            }
+            Ok(0)
+        }
+        _ => Err(SysError::ENOTTY),
     }
-    let new_fd = inner.alloc_fd();
-    inner.fd_table[new_fd] = Some(Arc::clone(inner.fd_table[fd].as_ref().unwrap()));
-    new_fd as isize
 }
@@ -0,0 +1,199 @@
+use crate::config::{MMAP_TOP, PAGE_SIZE};
+use crate::error::{SysError, SysResult};
+use crate::mm::{
+    shm_create, shm_get, shm_remove, swap_stat, MapPermission, MmapFile, SwapStat, UserPtr,
+    VirtAddr, VirtPageNum,
+};
+use crate::task::current_process;
+
+bitflags! {
+    pub struct MmapProt: u32 {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXEC = 1 << 2;
+    }
+}
+
+bitflags! {
+    pub struct MmapFlags: u32 {
+        const SHARED = 1 << 0;
+        const PRIVATE = 1 << 1;
+        const FIXED = 1 << 4;
+        const ANONYMOUS = 1 << 5;
+    }
+}
+
+impl MmapProt {
+    fn map_permission(&self) -> MapPermission {
+        let mut permission = MapPermission::U;
+        if self.contains(Self::READ) {
+            permission |= MapPermission::R;
+        }
+        // there are no write-only pages on RISC-V
+        if self.contains(Self::WRITE) {
+            permission |= MapPermission::R | MapPermission::W;
+        }
+        if self.contains(Self::EXEC) {
+            permission |= MapPermission::X;
+        }
+        permission
+    }
+}
+
+/// Return the page range of `[addr, addr + len)`, or EINVAL if `addr` is
+/// not page aligned or the range leaves the user part of the address space.
+fn user_page_range(addr: usize, len: usize) -> SysResult<(VirtPageNum, VirtPageNum)> {
+    if addr % PAGE_SIZE != 0 || len == 0 || len > MMAP_TOP || addr > MMAP_TOP - len {
+        return Err(SysError::EINVAL);
+    }
+    Ok((
+        VirtAddr::from(addr).floor(),
+        VirtAddr::from(addr + len).ceil(),
+    ))
+}
+
+/// Return the new program break, or the current one if `addr` is 0 or the
+/// heap cannot be moved there.
+pub fn sys_brk(addr: usize) -> SysResult {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if addr != 0 {
+        inner.memory_set.set_program_brk(addr);
+    }
+    Ok(inner.memory_set.program_brk())
+}
+
+pub fn sys_mmap(
+    addr: usize,
+    len: usize,
+    prot: u32,
+    flags: u32,
+    fd: isize,
+    offset: usize,
+) -> SysResult {
+    let prot = MmapProt::from_bits(prot).ok_or(SysError::EINVAL)?;
+    let flags = MmapFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    let shared = flags.contains(MmapFlags::SHARED);
+    if shared == flags.contains(MmapFlags::PRIVATE)
+        || offset % PAGE_SIZE != 0
+        || len == 0
+        || len > MMAP_TOP
+    {
+        return Err(SysError::EINVAL);
+    }
+    let pages = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+    let range = user_page_range(addr, len).ok().filter(|_| addr != 0);
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let file = if flags.contains(MmapFlags::ANONYMOUS) {
+        None
+    } else {
+        let file = inner
+            .fd_table
+            .lock()
+            .get(fd as usize)
+            .cloned()
+            .flatten()
+            .ok_or(SysError::EBADF)?;
+        // only files on easy-fs can be mapped
+        let inode = file.inode().ok_or(SysError::EINVAL)?;
+        if !file.readable() || (shared && prot.contains(MmapProt::WRITE) && !file.writable()) {
+            return Err(SysError::EACCES);
+        }
+        Some(MmapFile {
+            inode,
+            offset,
+            writable: file.writable(),
+        })
+    };
+    let start_vpn = match range {
+        Some((start_vpn, end_vpn)) if flags.contains(MmapFlags::FIXED) => {
+            inner.memory_set.remove_range(start_vpn, end_vpn);
+            start_vpn
+        }
+        None if flags.contains(MmapFlags::FIXED) => return Err(SysError::EINVAL),
+        // otherwise addr is only a hint
+        Some((start_vpn, end_vpn)) if inner.memory_set.is_free(start_vpn, end_vpn) => start_vpn,
+        _ => inner
+            .memory_set
+            .find_free_area(pages)
+            .ok_or(SysError::ENOMEM)?,
+    };
+    let start_va: VirtAddr = start_vpn.into();
+    let end_va: VirtAddr = VirtPageNum(start_vpn.0 + pages).into();
+    inner
+        .memory_set
+        .insert_mmap_area(start_va, end_va, prot.map_permission(), shared, file);
+    Ok(start_va.0)
+}
+
+pub fn sys_munmap(addr: usize, len: usize) -> SysResult {
+    let (start_vpn, end_vpn) = user_page_range(addr, len)?;
+    current_process()
+        .inner_exclusive_access()
+        .memory_set
+        .remove_range(start_vpn, end_vpn);
+    Ok(0)
+}
+
+/// Fail with ENOMEM if part of the range is not mapped, as on Linux.
+pub fn sys_mprotect(addr: usize, len: usize, prot: u32) -> SysResult {
+    let (start_vpn, end_vpn) = user_page_range(addr, len)?;
+    let prot = MmapProt::from_bits(prot).ok_or(SysError::EINVAL)?;
+    current_process()
+        .inner_exclusive_access()
+        .memory_set
+        .protect_range(start_vpn, end_vpn, prot.map_permission())?;
+    Ok(0)
+}
+
+pub fn sys_swap_stat(stat: *mut SwapStat) -> SysResult {
+    let value = swap_stat();
+    UserPtr::from(stat).write(
+        &mut current_process().inner_exclusive_access().memory_set,
+        &value,
+    )?;
+    Ok(0)
+}
+
+/// Return the id of the segment with `key`, which is created if needed.
+/// Key 0 always creates a new segment.
+pub fn sys_shm_create(key: usize, size: usize) -> SysResult {
+    if size == 0 || size > MMAP_TOP {
+        return Err(SysError::EINVAL);
+    }
+    let pid = current_process().getpid();
+    shm_create(key, (size + PAGE_SIZE - 1) / PAGE_SIZE, pid).ok_or(SysError::ENOMEM)
+}
+
+/// Return the address the segment is attached at.
+pub fn sys_shm_attach(id: usize) -> SysResult {
+    let segment = shm_get(id).ok_or(SysError::EINVAL)?;
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let start_vpn = inner
+        .memory_set
+        .find_free_area(segment.frames.len())
+        .ok_or(SysError::ENOMEM)?;
+    let start_va: VirtAddr = start_vpn.into();
+    inner.memory_set.insert_shm_area(start_va, segment);
+    Ok(start_va.0)
+}
+
+pub fn sys_shm_detach(addr: usize) -> SysResult {
+    if addr % PAGE_SIZE != 0
+        || !current_process()
+            .inner_exclusive_access()
+            .memory_set
+            .remove_shm_area(VirtAddr::from(addr).floor())
+    {
+        return Err(SysError::EINVAL);
+    }
+    Ok(0)
+}
+
+/// Only the process which has created the segment may remove it.
+pub fn sys_shm_remove(id: usize) -> SysResult {
+    shm_remove(id, current_process().getpid())?;
+    Ok(0)
+}
@@ -1,10 +1,18 @@
+use super::fs::PATH_MAX;
+use super::thread::new_thread;
+use crate::config::{PAGE_SIZE, USER_STACK_SIZE, USER_TLS_SIZE};
+use crate::error::{SysError, SysResult};
 use crate::fs::{open_file, OpenFlags};
-use crate::mm::{translated_ref, translated_refmut, translated_str};
+use crate::mm::copy_to_user;
+use crate::mm::{reclaim_frames, MemorySet, UserPtr};
 use crate::task::{
-    current_process, current_task, current_user_token, exit_current_and_run_next, pid2process,
-    suspend_current_and_run_next, SignalFlags,
+    add_task, current_add_signal, current_process, current_task, current_trap_cx,
+    exit_current_and_run_next, exit_current_group_and_run_next, pid2process, process_group,
+    processes, restore_signal_frame, send_signal, suspend_current_and_run_next, CloneFlags,
+    ProcessControlBlock, ProcessControlBlockInner, SignalAction, SignalFlags, WaitEvent,
+    WaitResult, INITPROC, SIG_BLOCK, SIG_SETMASK, SIG_UNBLOCK,
 };
-use crate::timer::get_time_ms;
+use crate::timer::{TimeSpec, TimeVal};
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
@@ -14,104 +22,578 @@ pub fn sys_exit(exit_code: i32) -> ! {
     panic!("Unreachable in sys_exit!");
 }
 
-pub fn sys_yield() -> isize {
+pub fn sys_yield() -> SysResult {
     suspend_current_and_run_next();
-    0
+    Ok(0)
 }
 
-pub fn sys_get_time() -> isize {
-    get_time_ms() as isize
+/// Exit every thread of the process, with `exit_code`.
+pub fn sys_exit_group(exit_code: i32) -> ! {
+    exit_current_group_and_run_next(exit_code);
+    panic!("Unreachable in sys_exit_group!");
 }
 
-pub fn sys_getpid() -> isize {
-    current_task().unwrap().process.upgrade().unwrap().getpid() as isize
+/// The time since boot, there is no real time clock. A time zone is never
+/// reported.
+pub fn sys_gettimeofday(tv: *mut TimeVal, tz: *mut [i32; 2]) -> SysResult {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let (tv, tz) = (UserPtr::from(tv), UserPtr::from(tz));
+    if !tv.is_null() {
+        tv.write(&mut inner.memory_set, &TimeVal::now())?;
+    }
+    if !tz.is_null() {
+        tz.write(&mut inner.memory_set, &[0; 2])?;
+    }
+    Ok(0)
 }
 
-pub fn sys_fork() -> isize {
-    let current_process = current_process();
-    let new_process = current_process.fork();
-    let new_pid = new_process.getpid();
-    // modify trap context of new_task, because it returns immediately after switching
-    let new_process_inner = new_process.inner_exclusive_access();
-    let task = new_process_inner.tasks[0].as_ref().unwrap();
-    let trap_cx = task.inner_exclusive_access().get_trap_cx();
-    // we do not have to move to next instruction since we have done it before
-    // for child process, fork returns 0
-    trap_cx.x[10] = 0;
-    new_pid as isize
+/// Clocks of `sys_clock_gettime` which are all the time since boot here:
+/// the real time, monotonic, raw, coarse and boot time ones.
+const WALL_CLOCKS: [usize; 6] = [0, 1, 4, 5, 6, 7];
+
+/// Fail with EINVAL for the CPU time clocks, which aren't kept track of.
+pub fn sys_clock_gettime(clock_id: usize, tp: *mut TimeSpec) -> SysResult {
+    if !WALL_CLOCKS.contains(&clock_id) {
+        return Err(SysError::EINVAL);
+    }
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    UserPtr::from(tp).write(&mut inner.memory_set, &TimeSpec::now())?;
+    Ok(0)
 }
 
-pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
-    let token = current_user_token();
-    let path = translated_str(token, path);
-    let mut args_vec: Vec<String> = Vec::new();
-    loop {
-        let arg_str_ptr = *translated_ref(token, args);
-        if arg_str_ptr == 0 {
+pub fn sys_getpid() -> SysResult {
+    Ok(current_task().unwrap().process.upgrade().unwrap().getpid())
+}
+
+/// Return the pid of the parent, 0 for initproc.
+pub fn sys_getppid() -> SysResult {
+    let parent = current_process()
+        .inner_exclusive_access()
+        .parent
+        .as_ref()
+        .and_then(|parent| parent.upgrade());
+    Ok(parent.map_or(0, |parent| parent.getpid()))
+}
+
+/// Every process runs as root, this stands for getuid, geteuid, getgid and
+/// getegid alike.
+pub fn sys_getuid() -> SysResult {
+    Ok(0)
+}
+
+/// `struct utsname` of Linux.
+#[repr(C)]
+pub struct UtsName {
+    sysname: [u8; 65],
+    nodename: [u8; 65],
+    release: [u8; 65],
+    version: [u8; 65],
+    machine: [u8; 65],
+    domainname: [u8; 65],
+}
+
+/// A `\0`-terminated field of `UtsName`.
+fn uts_field(value: &str) -> [u8; 65] {
+    let mut field = [0; 65];
+    field[..value.len()].copy_from_slice(value.as_bytes());
+    field
+}
+
+pub fn sys_uname(buf: *mut UtsName) -> SysResult {
+    let uts = UtsName {
+        sysname: uts_field("rCore"),
+        nodename: uts_field("rcore"),
+        release: uts_field(env!("CARGO_PKG_VERSION")),
+        version: uts_field("rCore-Tutorial-v3"),
+        machine: uts_field("riscv64"),
+        domainname: uts_field("(none)"),
+    };
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    UserPtr::from(buf).write(&mut inner.memory_set, &uts)?;
+    Ok(0)
+}
+
+/// Clear the `u32` at `tidptr` and wake it up as a futex when the current
+/// thread exits, as CLONE_CHILD_CLEARTID does. Return the pid for the main
+/// thread, whose tid it is on Linux, and the tid otherwise, see `sys_clone`.
+pub fn sys_set_tid_address(tidptr: usize) -> SysResult {
+    let task = current_task().unwrap();
+    let process = task.process.upgrade().unwrap();
+    let main_tid = process.inner_exclusive_access().main_tid;
+    let mut task_inner = task.inner_exclusive_access();
+    task_inner.clear_child_tid = tidptr;
+    let tid = task_inner.res.as_ref().unwrap().tid;
+    Ok(if tid == main_tid {
+        process.getpid()
+    } else {
+        tid
+    })
+}
+
+/// Store `id` to the word at `uaddr` in `process` for CLONE_PARENT_SETTID and
+/// CLONE_CHILD_SETTID, a bad address is ignored as on Linux.
+fn put_tid(process: &ProcessControlBlock, uaddr: usize, id: usize) {
+    let mut inner = process.inner_exclusive_access();
+    if uaddr % core::mem::size_of::<u32>() == 0 {
+        let _ = UserPtr::<u32>::new(uaddr).write(&mut inner.memory_set, &(id as u32));
+    }
+}
+
+/// Create a process, or a thread of this one with CLONE_THREAD, which
+/// returns 0 from the call, on `stack` unless it is 0. Return the pid of the
+/// process or the tid of the thread, which unlike on Linux only means
+/// something within this process. A thread has a stack and a TLS block of
+/// its own in any case, they are used when `stack` is 0 and without
+/// CLONE_SETTLS. Fail with EINVAL on CLONE_VM or CLONE_SIGHAND without
+/// CLONE_THREAD, so there is no vfork.
+pub fn sys_clone(flags: usize, stack: usize, ptid: usize, tls: usize, ctid: usize) -> SysResult {
+    let flags = CloneFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    // threads share the memory, the handlers and the files of the process,
+    // and only threads share the memory, thus the handlers
+    let thread = flags.contains(CloneFlags::THREAD);
+    let thread_flags = CloneFlags::VM | CloneFlags::SIGHAND | CloneFlags::FILES;
+    let vm_flags = CloneFlags::VM | CloneFlags::SIGHAND;
+    if (thread && !flags.contains(thread_flags)) || (!thread && flags.intersects(vm_flags)) {
+        return Err(SysError::EINVAL);
+    }
+    // page tables, trap contexts and kernel stacks come from the frames kept
+    // for the kernel, make up for them by swapping user pages out, and for
+    // the thread-local storage of a thread
+    reclaim_frames(USER_TLS_SIZE / PAGE_SIZE);
+    let task = current_task().unwrap();
+    let process = task.process.upgrade().unwrap();
+    let (new_task, id) = if thread {
+        // copied before another TCB is locked
+        let parent_trap_cx = current_trap_cx().clone();
+        let new_task = new_thread(&task)?;
+        let new_task_inner = new_task.inner_exclusive_access();
+        let new_task_res = new_task_inner.res.as_ref().unwrap();
+        let tid = new_task_res.tid;
+        let trap_cx = new_task_inner.get_trap_cx();
+        *trap_cx = parent_trap_cx;
+        trap_cx.kernel_sp = new_task.kstack.get_top();
+        trap_cx.x[10] = 0;
+        trap_cx.set_sp(new_task_res.ustack_top());
+        trap_cx.set_tp(new_task_res.tls_base());
+        drop(new_task_inner);
+        (new_task, tid)
+    } else {
+        let new_task = process.fork(flags);
+        let pid = new_task.process.upgrade().unwrap().getpid();
+        (new_task, pid)
+    };
+    let mut new_task_inner = new_task.inner_exclusive_access();
+    let trap_cx = new_task_inner.get_trap_cx();
+    if stack != 0 {
+        trap_cx.set_sp(stack);
+    }
+    if flags.contains(CloneFlags::SETTLS) {
+        trap_cx.set_tp(tls);
+    }
+    if flags.contains(CloneFlags::CHILD_CLEARTID) {
+        new_task_inner.clear_child_tid = ctid;
+    }
+    drop(new_task_inner);
+    if flags.contains(CloneFlags::PARENT_SETTID) {
+        put_tid(&process, ptid, id);
+    }
+    if flags.contains(CloneFlags::CHILD_SETTID) {
+        put_tid(&new_task.process.upgrade().unwrap(), ctid, id);
+    }
+    // the child runs once it is set up
+    add_task(new_task);
+    Ok(id)
+}
+
+/// The most bytes of arguments exec puts on the new user stack, pointers
+/// included, the rest of the stack is left to the program.
+const ARG_MAX: usize = USER_STACK_SIZE / 2;
+
+/// Read the null-terminated array of strings at `array`, a null `array`
+/// standing for none. `size` counts the bytes they take on the new stack,
+/// fail with E2BIG if it goes over `ARG_MAX`.
+fn read_str_array(
+    inner: &mut ProcessControlBlockInner,
+    array: *const usize,
+    size: &mut usize,
+) -> SysResult<Vec<String>> {
+    let array = UserPtr::from(array);
+    let mut strings: Vec<String> = Vec::new();
+    // the pointer ending the array takes room as well
+    *size += core::mem::size_of::<usize>();
+    while !array.is_null() {
+        let str_ptr = array.add(strings.len()).read(&mut inner.memory_set)?;
+        if str_ptr == 0 {
             break;
         }
-        args_vec.push(translated_str(token, arg_str_ptr as *const u8));
-        unsafe {
-            args = args.add(1);
+        // its pointer and its `\0`
+        *size += core::mem::size_of::<usize>() + 1;
+        if *size > ARG_MAX {
+            return Err(SysError::E2BIG);
         }
+        let string = UserPtr::<u8>::new(str_ptr)
+            .read_str(&mut inner.memory_set, ARG_MAX - *size)
+            .map_err(|err| match err {
+                SysError::ENAMETOOLONG => SysError::E2BIG,
+                err => err,
+            })?;
+        *size += string.len();
+        strings.push(string);
+    }
+    Ok(strings)
+}
+
+/// `args` and `envs` are null-terminated arrays of strings, a null array
+/// stands for an empty one. Fail with E2BIG if they take more than `ARG_MAX`
+/// bytes together.
+pub fn sys_execve(path: *const u8, args: *const usize, envs: *const usize) -> SysResult {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let path = UserPtr::from(path).read_str(&mut inner.memory_set, PATH_MAX)?;
+    let mut size = 0;
+    let args_vec = read_str_array(&mut inner, args, &mut size)?;
+    let envs_vec = read_str_array(&mut inner, envs, &mut size)?;
+    drop(inner);
+    let app_inode = open_file(path.as_str(), OpenFlags::RDONLY)?;
+    if app_inode.is_dir() {
+        return Err(SysError::EACCES);
+    }
+    let all_data = app_inode.read_all();
+    // checked while the program can still be returned to
+    if !MemorySet::elf_is_valid(all_data.as_slice()) {
+        return Err(SysError::ENOEXEC);
+    }
+    let argc = args_vec.len();
+    // if killed, the thread exits instead of returning
+    process.exec(all_data.as_slice(), args_vec, envs_vec)?;
+    // return argc because cx.x[10] will be covered with it later
+    Ok(argc)
+}
+
+bitflags! {
+    pub struct WaitFlags: u32 {
+        /// return 0 at once if no child has exited yet
+        const WNOHANG = 1 << 0;
+        /// report children which have stopped as well
+        const WUNTRACED = 1 << 1;
+        /// report stopped children which have continued as well
+        const WCONTINUED = 1 << 3;
+    }
+}
+
+/// Status of a child reported as stopped, with the signal number shifted by
+/// 8, and as continued, the same values as on Linux. `sys_waitpid` reports
+/// exit codes as they are, see `sys_wait4` for how Linux does.
+const WAIT_STOPPED: i32 = 0x7f;
+const WAIT_CONTINUED: i32 = 0xffff;
+/// The size of `struct rusage`.
+const RUSAGE_SIZE: usize = 144;
+
+/// Whether a child is one of those `pid` stands for in `sys_waitpid`.
+fn waited(pid: isize, pgid: usize, child_pid: usize, child_pgid: usize) -> bool {
+    match pid {
+        -1 => true,
+        0 => child_pgid == pgid,
+        pid if pid < 0 => child_pgid == (-pid) as usize,
+        pid => child_pid == pid as usize,
     }
-    if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
-        let all_data = app_inode.read_all();
+}
+
+/// Wait for a child process to exit: the one with pid `pid`, or any child if
+/// `pid` is -1, any child of the group of the caller if it is 0 and any
+/// child of group `-pid` if it is below -1. If there is no such child, fail
+/// with ECHILD. Else if none has exited, block until one does or the wait is
+/// interrupted with EINTR, or return 0 with WNOHANG. Stops and continues are
+/// reported too with WUNTRACED and WCONTINUED, stops of traced children
+/// always are. The exit code is stored as it is, negated signal numbers
+/// standing for the signals which have killed the child.
+pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32, options: u32) -> SysResult {
+    wait_child(pid, exit_code_ptr, options, |child_inner| {
+        child_inner.exit_code
+    })
+}
+
+/// `sys_waitpid` with the status of Linux: the low byte of the exit code
+/// shifted by 8, or the number of the signal which has killed the child.
+/// Resource usage isn't kept track of, `rusage` is zeroed.
+pub fn sys_wait4(pid: isize, wstatus: *mut i32, options: u32, rusage: usize) -> SysResult {
+    if rusage != 0 {
         let process = current_process();
-        let argc = args_vec.len();
-        process.exec(all_data.as_slice(), args_vec);
-        // return argc because cx.x[10] will be covered with it later
-        argc as isize
+        let mut inner = process.inner_exclusive_access();
+        copy_to_user(&mut inner.memory_set, rusage, &[0; RUSAGE_SIZE])?;
+    }
+    wait_child(pid, wstatus, options, |child_inner| {
+        match child_inner.killed_by {
+            Some(signum) => signum as i32,
+            None => (child_inner.exit_code & 0xff) << 8,
+        }
+    })
+}
+
+/// Wait as `sys_waitpid` says, `exit_status` tells how a zombie child is
+/// reported.
+fn wait_child(
+    pid: isize,
+    exit_code_ptr: *mut i32,
+    options: u32,
+    exit_status: impl Fn(&ProcessControlBlockInner) -> i32,
+) -> SysResult {
+    let options = WaitFlags::from_bits(options).ok_or(SysError::EINVAL)?;
+    let process = current_process();
+    loop {
+        // find a child process
+        let mut inner = process.inner_exclusive_access();
+        let pgid = inner.pgid;
+        let mut found = false;
+        // index, status and whether it has exited
+        let mut report = None;
+        for (idx, child) in inner.children.iter().enumerate() {
+            // ++++ access child PCB exclusively until the next one
+            let mut child_inner = child.inner_exclusive_access();
+            if !waited(pid, pgid, child.getpid(), child_inner.pgid) {
+                continue;
+            }
+            found = true;
+            if child_inner.is_zombie {
+                report = Some((idx, exit_status(&child_inner), true));
+                break;
+            }
+            let status = match child_inner.wait_event {
+                // a tracer is told about stops without asking
+                Some(WaitEvent::Stopped(signum))
+                    if options.contains(WaitFlags::WUNTRACED) || child_inner.ptrace.is_some() =>
+                {
+                    ((signum as i32) << 8) | WAIT_STOPPED
+                }
+                Some(WaitEvent::Continued) if options.contains(WaitFlags::WCONTINUED) => {
+                    WAIT_CONTINUED
+                }
+                _ => continue,
+            };
+            child_inner.wait_event = None;
+            report = Some((idx, status, false));
+            break;
+        }
+        if !found {
+            return Err(SysError::ECHILD);
+            // ---- release current PCB
+        }
+        if let Some((idx, status, exited)) = report {
+            let found_pid = inner.children[idx].getpid();
+            let exit_code_ptr = UserPtr::from(exit_code_ptr);
+            if !exit_code_ptr.is_null() {
+                // the child is left to be waited for again on failure
+                exit_code_ptr.write(&mut inner.memory_set, &status)?;
+            }
+            if exited {
+                // the child is deallocated once the hart it exited on has
+                // dropped its last reference as well
+                inner.children.remove(idx);
+            }
+            return Ok(found_pid);
+        }
+        if options.contains(WaitFlags::WNOHANG) {
+            return Ok(0);
+        }
+        // the child wakes us up when it becomes a zombie, stops or continues
+        inner.wait_queue.push_back(current_task().unwrap());
+        drop(inner);
+        if process.wait_for_waiters() == WaitResult::Interrupted {
+            return Err(SysError::EINTR);
+        }
+    }
+}
+
+/// Send a signal to process `pid` if it is positive, otherwise to group
+/// `-pid`, to the group of the caller if it is 0, and to every process but
+/// initproc if it is -1. Signal 0 only checks that there is such a process.
+pub fn sys_kill(pid: isize, signum: usize) -> SysResult {
+    let signal = match signum {
+        0 => None,
+        signum => Some(SignalFlags::from_signum(signum).ok_or(SysError::EINVAL)?),
+    };
+    let pgid = current_process().inner_exclusive_access().pgid;
+    let targets: Vec<_> = match pid {
+        pid if pid > 0 => pid2process(pid as usize).into_iter().collect(),
+        0 => process_group(pgid),
+        -1 => processes()
+            .into_iter()
+            .filter(|process| !Arc::ptr_eq(process, &INITPROC))
+            .collect(),
+        pid => process_group((-pid) as usize),
+    };
+    if targets.is_empty() {
+        return Err(SysError::ESRCH);
+    }
+    if let Some(signal) = signal {
+        for process in targets.iter() {
+            send_signal(process, signal);
+        }
+    }
+    Ok(0)
+}
+
+/// Whether there is a group `pgid` in session `sid`. A zombie child of
+/// `process` may be the last member of the group.
+fn group_in_session(process: &ProcessControlBlock, pgid: usize, sid: usize) -> bool {
+    let in_session = |other: &ProcessControlBlock| {
+        let other_inner = other.inner_exclusive_access();
+        other_inner.pgid == pgid && other_inner.sid == sid
+    };
+    let children = process.inner_exclusive_access().children.clone();
+    children.iter().any(|child| in_session(child))
+        || process_group(pgid).iter().any(|other| in_session(other))
+}
+
+/// Move process `pid`, the caller or one of its children, to group `pgid`
+/// of its session, or to a new group if `pgid` is `pid`. 0 stands for the
+/// caller as `pid` and for `pid` as `pgid`. A session leader can't move.
+pub fn sys_setpgid(pid: usize, pgid: usize) -> SysResult {
+    let process = current_process();
+    let pid = if pid == 0 { process.getpid() } else { pid };
+    let pgid = if pgid == 0 { pid } else { pgid };
+    let target = if pid == process.getpid() {
+        Arc::clone(&process)
     } else {
-        -1
+        let inner = process.inner_exclusive_access();
+        let child = inner.children.iter().find(|child| child.getpid() == pid);
+        Arc::clone(child.ok_or(SysError::ESRCH)?)
+    };
+    let sid = process.inner_exclusive_access().sid;
+    if pgid != pid && !group_in_session(&process, pgid, sid) {
+        return Err(SysError::EPERM);
+    }
+    let mut target_inner = target.inner_exclusive_access();
+    if target_inner.is_zombie {
+        return Err(SysError::ESRCH);
+    }
+    if target_inner.sid != sid || target_inner.sid == pid {
+        return Err(SysError::EPERM);
     }
+    target_inner.pgid = pgid;
+    Ok(0)
 }
 
-/// If there is not a child process whose pid is same as given, return -1.
-/// Else if there is a child process but it is still running, return -2.
-pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
+/// Return the group of process `pid`, or of the caller if it is 0.
+pub fn sys_getpgid(pid: usize) -> SysResult {
+    let process = match pid {
+        0 => current_process(),
+        pid => pid2process(pid).ok_or(SysError::ESRCH)?,
+    };
+    let pgid = process.inner_exclusive_access().pgid;
+    Ok(pgid)
+}
+
+/// Make the caller the leader of a new session, without a controlling
+/// terminal, and of a new group in it. Return the new session, or fail with
+/// EPERM if the caller leads a group already, since a group can't span
+/// sessions.
+pub fn sys_setsid() -> SysResult {
     let process = current_process();
-    // find a child process
+    let pid = process.getpid();
+    if !process_group(pid).is_empty() {
+        return Err(SysError::EPERM);
+    }
+    let mut inner = process.inner_exclusive_access();
+    inner.pgid = pid;
+    inner.sid = pid;
+    Ok(pid)
+}
 
+/// Return the session of process `pid`, or of the caller if it is 0.
+pub fn sys_getsid(pid: usize) -> SysResult {
+    let process = match pid {
+        0 => current_process(),
+        pid => pid2process(pid).ok_or(SysError::ESRCH)?,
+    };
+    let sid = process.inner_exclusive_access().sid;
+    Ok(sid)
+}
+
+/// Either `action` or `old_action` may be null. SIGKILL and SIGSTOP can't
+/// be given an action. `sigsetsize` is the size of the masks, which has to
+/// be 8.
+pub fn sys_rt_sigaction(
+    signum: usize,
+    action: *const SignalAction,
+    old_action: *mut SignalAction,
+    sigsetsize: usize,
+) -> SysResult {
+    if sigsetsize != core::mem::size_of::<SignalFlags>() {
+        return Err(SysError::EINVAL);
+    }
+    let signal = SignalFlags::from_signum(signum).ok_or(SysError::EINVAL)?;
+    if !action.is_null() && SignalFlags::unblockable().contains(signal) {
+        return Err(SysError::EINVAL);
+    }
+    let process = current_process();
     let mut inner = process.inner_exclusive_access();
-    if !inner
-        .children
-        .iter()
-        .any(|p| pid == -1 || pid as usize == p.getpid())
-    {
-        return -1;
-        // ---- release current PCB
-    }
-    let pair = inner.children.iter().enumerate().find(|(_, p)| {
-        // ++++ temporarily access child PCB exclusively
-        p.inner_exclusive_access().is_zombie && (pid == -1 || pid as usize == p.getpid())
-        // ++++ release child PCB
-    });
-    if let Some((idx, _)) = pair {
-        let child = inner.children.remove(idx);
-        // confirm that child will be deallocated after being removed from children list
-        assert_eq!(Arc::strong_count(&child), 1);
-        let found_pid = child.getpid();
-        // ++++ temporarily access child PCB exclusively
-        let exit_code = child.inner_exclusive_access().exit_code;
-        // ++++ release child PCB
-        *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
-        found_pid as isize
+    let (action, old_action) = (UserPtr::from(action), UserPtr::from(old_action));
+    let new_action = if action.is_null() {
+        None
     } else {
-        -2
+        let mut new_action = action.read(&mut inner.memory_set)?;
+        new_action.mask =
+            SignalFlags::from_bits_truncate(new_action.mask.bits()) - SignalFlags::unblockable();
+        Some(new_action)
+    };
+    if !old_action.is_null() {
+        let old = inner.signal_actions[signum];
+        old_action.write(&mut inner.memory_set, &old)?;
+    }
+    if let Some(new_action) = new_action {
+        inner.signal_actions[signum] = new_action;
     }
-    // ---- release current PCB automatically
+    Ok(0)
 }
 
-pub fn sys_kill(pid: usize, signal: u32) -> isize {
-    if let Some(process) = pid2process(pid) {
-        if let Some(flag) = SignalFlags::from_bits(signal) {
-            process.inner_exclusive_access().signals |= flag;
-            0
-        } else {
-            -1
-        }
+/// Change the signal mask of the current thread as `how` says with the mask
+/// at `set`, unless it is null, and store the old mask at `old_set`, unless
+/// it is null. SIGKILL and SIGSTOP can't be blocked. `sigsetsize` is the
+/// size of the masks, which has to be 8.
+pub fn sys_rt_sigprocmask(
+    how: usize,
+    set: *const SignalFlags,
+    old_set: *mut SignalFlags,
+    sigsetsize: usize,
+) -> SysResult {
+    if sigsetsize != core::mem::size_of::<SignalFlags>() {
+        return Err(SysError::EINVAL);
+    }
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let (set, old_set) = (UserPtr::from(set), UserPtr::from(old_set));
+    let task = current_task().unwrap();
+    let old_mask = task.inner_exclusive_access().signal_mask;
+    let new_mask = if set.is_null() {
+        old_mask
     } else {
-        -1
+        // read as bits, since unknown ones are dropped
+        let bits = UserPtr::<u64>::new(set.addr()).read(&mut process_inner.memory_set)?;
+        let mask = SignalFlags::from_bits_truncate(bits) - SignalFlags::unblockable();
+        match how {
+            SIG_BLOCK => old_mask | mask,
+            SIG_UNBLOCK => old_mask - mask,
+            SIG_SETMASK => mask,
+            _ => return Err(SysError::EINVAL),
+        }
+    };
+    if !old_set.is_null() {
+        old_set.write(&mut process_inner.memory_set, &old_mask)?;
+    }
+    task.inner_exclusive_access().signal_mask = new_mask;
+    Ok(0)
+}
+
+/// Return from a handler through the sigreturn trampoline. The registers of
+/// the interrupted context are restored, including a0.
+pub fn sys_sigreturn() -> SysResult {
+    if !restore_signal_frame() {
+        current_add_signal(SignalFlags::SIGSEGV);
+        return Err(SysError::EFAULT);
     }
+    Ok(current_trap_cx().x[10])
 }
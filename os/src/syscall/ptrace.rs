@@ -0,0 +1,187 @@
+use crate::error::{SysError, SysResult};
+use crate::mm::UserPtr;
+use crate::task::{
+    current_process, ptrace_detach, send_signal, ProcessControlBlock, ProcessControlBlockInner,
+    Ptrace, PtraceResume, SignalFlags, TaskControlBlock,
+};
+use alloc::sync::Arc;
+
+/// Requests of `sys_ptrace`, numbered as on Linux.
+const PTRACE_TRACEME: usize = 0;
+const PTRACE_PEEKTEXT: usize = 1;
+const PTRACE_PEEKDATA: usize = 2;
+const PTRACE_POKETEXT: usize = 4;
+const PTRACE_POKEDATA: usize = 5;
+const PTRACE_CONT: usize = 7;
+const PTRACE_KILL: usize = 8;
+const PTRACE_SINGLESTEP: usize = 9;
+const PTRACE_GETREGS: usize = 12;
+const PTRACE_SETREGS: usize = 13;
+const PTRACE_ATTACH: usize = 16;
+const PTRACE_DETACH: usize = 17;
+const PTRACE_SYSCALL: usize = 24;
+
+/// `struct user_regs_struct` of Linux on RISC-V: the pc, then x1 to x31.
+type UserRegs = [usize; 32];
+
+/// Child `pid` of `process`, which may have exited.
+fn child_of(process: &ProcessControlBlock, pid: usize) -> SysResult<Arc<ProcessControlBlock>> {
+    let inner = process.inner_exclusive_access();
+    let child = inner.children.iter().find(|child| child.getpid() == pid);
+    child.cloned().ok_or(SysError::ESRCH)
+}
+
+/// The thread which has stopped the traced process, or ESRCH if it isn't
+/// stopped.
+fn stopped_thread(child_inner: &ProcessControlBlockInner) -> SysResult<Arc<TaskControlBlock>> {
+    match child_inner.ptrace.as_ref() {
+        Some(ptrace) if ptrace.stopped => child_inner
+            .tasks
+            .get(ptrace.tid)
+            .cloned()
+            .flatten()
+            .ok_or(SysError::ESRCH),
+        _ => Err(SysError::ESRCH),
+    }
+}
+
+/// The signal `data` lets a resumed process take, 0 for none.
+fn resume_signal(data: usize) -> SysResult<Option<usize>> {
+    match data {
+        0 => Ok(None),
+        signum => SignalFlags::from_signum(signum)
+            .map(|_| Some(signum))
+            .ok_or(SysError::EIO),
+    }
+}
+
+/// Trace a child process, as ptrace of Linux does with these requests:
+/// - PTRACE_TRACEME: the caller is traced by its parent from now on.
+/// - PTRACE_ATTACH: trace child `pid`, which is stopped with SIGSTOP.
+/// - PTRACE_PEEKTEXT and PTRACE_PEEKDATA: store the word at `addr` of the
+///   child at `data`.
+/// - PTRACE_POKETEXT and PTRACE_POKEDATA: write word `data` at `addr` of the
+///   child, its code included.
+/// - PTRACE_GETREGS and PTRACE_SETREGS: read or write the registers of the
+///   stopped thread of the child from or to the `UserRegs` at `data`.
+/// - PTRACE_CONT, PTRACE_SYSCALL and PTRACE_SINGLESTEP: resume the child,
+///   until its next syscall entry or exit with PTRACE_SYSCALL, or for one
+///   instruction with PTRACE_SINGLESTEP. It takes signal `data` unless it
+///   is 0.
+/// - PTRACE_DETACH: stop tracing the child, and resume it as PTRACE_CONT.
+/// - PTRACE_KILL: kill the child.
+///
+/// A traced process stops when it is about to take a signal, SIGKILL
+/// aside, and after exec with SIGTRAP. The stops are reported to the
+/// tracer by `sys_waitpid` even without WUNTRACED. Requests other than the
+/// first two and PTRACE_KILL fail with ESRCH unless the child is traced and
+/// stopped.
+pub fn sys_ptrace(request: usize, pid: usize, addr: usize, data: usize) -> SysResult {
+    let process = current_process();
+    if request == PTRACE_TRACEME {
+        let mut inner = process.inner_exclusive_access();
+        if inner.ptrace.is_some() {
+            return Err(SysError::EPERM);
+        }
+        inner.ptrace = Some(Ptrace::new());
+        return Ok(0);
+    }
+    // what the caller passes in is read before the child is locked
+    let regs = if request == PTRACE_SETREGS {
+        let mut inner = process.inner_exclusive_access();
+        Some(UserPtr::<UserRegs>::new(data).read(&mut inner.memory_set)?)
+    } else {
+        None
+    };
+    let child = child_of(&process, pid)?;
+    let mut child_inner = child.inner_exclusive_access();
+    if child_inner.is_zombie {
+        return Err(SysError::ESRCH);
+    }
+    match request {
+        PTRACE_ATTACH => {
+            if child_inner.ptrace.is_some() {
+                return Err(SysError::EPERM);
+            }
+            child_inner.ptrace = Some(Ptrace::new());
+            drop(child_inner);
+            // it stops as it is about to take the signal
+            send_signal(&child, SignalFlags::SIGSTOP);
+            return Ok(0);
+        }
+        PTRACE_KILL => {
+            if child_inner.ptrace.is_none() {
+                return Err(SysError::ESRCH);
+            }
+            drop(child_inner);
+            send_signal(&child, SignalFlags::SIGKILL);
+            return Ok(0);
+        }
+        _ => {}
+    }
+    let task = stopped_thread(&child_inner)?;
+    match request {
+        PTRACE_PEEKTEXT | PTRACE_PEEKDATA => {
+            let word = UserPtr::<usize>::new(addr)
+                .read(&mut child_inner.memory_set)
+                .map_err(|_| SysError::EIO)?;
+            drop(child_inner);
+            let mut inner = process.inner_exclusive_access();
+            UserPtr::<usize>::new(data).write(&mut inner.memory_set, &word)?;
+        }
+        PTRACE_POKETEXT | PTRACE_POKEDATA => {
+            if !child_inner
+                .memory_set
+                .write_forced(addr, &data.to_ne_bytes())
+            {
+                return Err(SysError::EIO);
+            }
+        }
+        PTRACE_GETREGS => {
+            let trap_cx = task.inner_exclusive_access().get_trap_cx();
+            let mut regs: UserRegs = trap_cx.x;
+            regs[0] = trap_cx.sepc;
+            drop(child_inner);
+            let mut inner = process.inner_exclusive_access();
+            UserPtr::<UserRegs>::new(data).write(&mut inner.memory_set, &regs)?;
+        }
+        PTRACE_SETREGS => {
+            let regs = regs.unwrap();
+            // the rest of the trap context is the kernel's
+            let trap_cx = task.inner_exclusive_access().get_trap_cx();
+            trap_cx.sepc = regs[0];
+            trap_cx.x[1..].copy_from_slice(&regs[1..]);
+        }
+        PTRACE_CONT | PTRACE_SYSCALL | PTRACE_SINGLESTEP => {
+            let signal = resume_signal(data)?;
+            let child_inner = &mut *child_inner;
+            let ptrace = child_inner.ptrace.as_mut().unwrap();
+            if request == PTRACE_SINGLESTEP {
+                let trap_cx = task.inner_exclusive_access().get_trap_cx();
+                if !ptrace.insert_step_breakpoints(&mut child_inner.memory_set, trap_cx) {
+                    return Err(SysError::EIO);
+                }
+            }
+            ptrace.resume = match request {
+                PTRACE_CONT => PtraceResume::Continue,
+                PTRACE_SYSCALL => PtraceResume::Syscall,
+                _ => PtraceResume::Step,
+            };
+            ptrace.signal = signal;
+            ptrace.stopped = false;
+            child_inner.wait_event = None;
+            child_inner.wake_stopped();
+        }
+        PTRACE_DETACH => {
+            let signal = resume_signal(data)?;
+            ptrace_detach(&mut child_inner);
+            child_inner.wait_event = None;
+            drop(child_inner);
+            if let Some(signum) = signal {
+                send_signal(&child, SignalFlags::from_signum(signum).unwrap());
+            }
+        }
+        _ => return Err(SysError::EIO),
+    }
+    Ok(0)
+}
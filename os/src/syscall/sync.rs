@@ -1,17 +1,60 @@
-use crate::sync::{Condvar, Mutex, MutexBlocking, MutexSpin, Semaphore};
-use crate::task::{block_current_and_run_next, current_process, current_task};
-use crate::timer::{add_timer, get_time_ms};
+use crate::error::{SysError, SysResult};
+use crate::mm::UserPtr;
+use crate::sync::{
+    futex_cancel, futex_enqueue, futex_wake, futex_word, semaphore_open, Barrier, Condvar, Mutex,
+    MutexBlocking, MutexSpin, Resource, RwLock, RwLockPolicy, Semaphore,
+};
+use crate::task::ProcessControlBlockInner;
+use crate::task::{block_current, current_process, current_task, leave_no_queue, WaitResult};
+use crate::timer::{get_time_ms, TimeSpec, NSEC_PER_SEC};
 use alloc::sync::Arc;
 
-pub fn sys_sleep(ms: usize) -> isize {
-    let expire_ms = get_time_ms() + ms;
-    let task = current_task().unwrap();
-    add_timer(expire_ms, task);
-    block_current_and_run_next();
-    0
+const FUTEX_WAIT: usize = 0;
+const FUTEX_WAKE: usize = 1;
+const FUTEX_REQUEUE: usize = 3;
+/// Accepted and ignored, futexes are keyed on physical addresses anyway.
+const FUTEX_PRIVATE_FLAG: usize = 128;
+
+/// The object with `id` in one of the lists of the process, or EINVAL.
+fn get_by_id<T: ?Sized>(list: &[Option<Arc<T>>], id: usize) -> SysResult<Arc<T>> {
+    list.get(id).cloned().flatten().ok_or(SysError::EINVAL)
 }
 
-pub fn sys_mutex_create(blocking: bool) -> isize {
+/// Fail with ETIMEDOUT or EINTR unless the wait has ended as it should.
+fn wait_result(result: WaitResult) -> SysResult {
+    match result {
+        WaitResult::Woken => Ok(0),
+        WaitResult::TimedOut => Err(SysError::ETIMEDOUT),
+        WaitResult::Interrupted => Err(SysError::EINTR),
+    }
+}
+
+fn current_tid() -> usize {
+    current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .res
+        .as_ref()
+        .unwrap()
+        .tid
+}
+
+/// Sleep for the time at `req`, rounded up to milliseconds, or fail with
+/// EINTR if interrupted. `rem` is never written.
+pub fn sys_nanosleep(req: *const TimeSpec, _rem: *mut TimeSpec) -> SysResult {
+    let process = current_process();
+    let req = UserPtr::from(req).read(&mut process.inner_exclusive_access().memory_set)?;
+    if req.tv_nsec >= NSEC_PER_SEC || (req.tv_sec as isize) < 0 {
+        return Err(SysError::EINVAL);
+    }
+    let expire_ms = get_time_ms().saturating_add(req.as_ms());
+    match block_current(Some(expire_ms), leave_no_queue) {
+        WaitResult::Interrupted => Err(SysError::EINTR),
+        _ => Ok(0),
+    }
+}
+
+pub fn sys_mutex_create(blocking: bool) -> SysResult {
     let process = current_process();
     let mutex: Option<Arc<dyn Mutex>> = if !blocking {
         Some(Arc::new(MutexSpin::new()))
@@ -19,7 +62,7 @@ pub fn sys_mutex_create(blocking: bool) -> isize {
         Some(Arc::new(MutexBlocking::new()))
     };
     let mut process_inner = process.inner_exclusive_access();
-    if let Some(id) = process_inner
+    let id = if let Some(id) = process_inner
         .mutex_list
         .iter()
         .enumerate()
@@ -27,108 +70,357 @@ pub fn sys_mutex_create(blocking: bool) -> isize {
         .map(|(id, _)| id)
     {
         process_inner.mutex_list[id] = mutex;
-        id as isize
+        id
     } else {
         process_inner.mutex_list.push(mutex);
-        process_inner.mutex_list.len() as isize - 1
+        process_inner.mutex_list.len() - 1
+    };
+    process_inner
+        .deadlock_detector
+        .add_resource(Resource::Mutex(id), 1);
+    Ok(id)
+}
+
+/// Lock the mutex, giving up at `expire_ms` if there is one.
+fn lock_mutex(mutex_id: usize, expire_ms: Option<usize>) -> SysResult {
+    let tid = current_tid();
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let mutex = get_by_id(&process_inner.mutex_list, mutex_id)?;
+    let resource = Resource::Mutex(mutex_id);
+    let live = process_inner.live_tids();
+    if !process_inner
+        .deadlock_detector
+        .request(tid, resource, &live)
+    {
+        return Err(SysError::EDEADLK);
+    }
+    drop(process_inner);
+    let result = mutex.lock(expire_ms);
+    let mut process_inner = process.inner_exclusive_access();
+    if result != WaitResult::Woken {
+        process_inner.deadlock_detector.cancel(tid, resource);
+        return wait_result(result);
+    }
+    process_inner.deadlock_detector.acquire(tid, resource);
+    Ok(0)
+}
+
+pub fn sys_mutex_lock(mutex_id: usize) -> SysResult {
+    lock_mutex(mutex_id, None)
+}
+
+pub fn sys_mutex_lock_timeout(mutex_id: usize, timeout_ms: usize) -> SysResult {
+    lock_mutex(mutex_id, Some(get_time_ms().saturating_add(timeout_ms)))
+}
+
+pub fn sys_mutex_unlock(mutex_id: usize) -> SysResult {
+    let tid = current_tid();
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let mutex = get_by_id(&process_inner.mutex_list, mutex_id)?;
+    // the PCB is held until the release is recorded, before a waiter handed
+    // the mutex records its acquisition
+    if !mutex.unlock() {
+        return Err(SysError::EPERM);
+    }
+    process_inner
+        .deadlock_detector
+        .release(tid, Resource::Mutex(mutex_id));
+    Ok(0)
+}
+
+/// Put `sem` in the first free slot of the semaphore list, return its id.
+fn insert_semaphore(process_inner: &mut ProcessControlBlockInner, sem: Arc<Semaphore>) -> usize {
+    if let Some(id) = process_inner
+        .semaphore_list
+        .iter()
+        .enumerate()
+        .find(|(_, item)| item.is_none())
+        .map(|(id, _)| id)
+    {
+        process_inner.semaphore_list[id] = Some(sem);
+        id
+    } else {
+        process_inner.semaphore_list.push(Some(sem));
+        process_inner.semaphore_list.len() - 1
+    }
+}
+
+pub fn sys_semaphore_create(res_count: usize) -> SysResult {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let id = insert_semaphore(&mut process_inner, Arc::new(Semaphore::new(res_count)));
+    process_inner
+        .deadlock_detector
+        .add_resource(Resource::Semaphore(id), res_count);
+    Ok(id)
+}
+
+/// Return the id of the semaphore with `key`, which processes share, it is
+/// created with `res_count` units if no process has it open. A forked child
+/// has to open it again. Key 0 is invalid. These semaphores are left out of
+/// deadlock detection, which only knows the threads of one process.
+pub fn sys_semaphore_open(key: usize, res_count: usize) -> SysResult {
+    if key == 0 {
+        return Err(SysError::EINVAL);
     }
+    let sem = semaphore_open(key, res_count);
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    Ok(insert_semaphore(&mut process_inner, sem))
 }
 
-pub fn sys_mutex_lock(mutex_id: usize) -> isize {
+pub fn sys_semaphore_up(sem_id: usize) -> SysResult {
+    let tid = current_tid();
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let sem = get_by_id(&process_inner.semaphore_list, sem_id)?;
+    if sem.key == 0 {
+        process_inner
+            .deadlock_detector
+            .release(tid, Resource::Semaphore(sem_id));
+    }
+    drop(process_inner);
+    sem.up();
+    Ok(0)
+}
+
+/// Down the semaphore, giving up at `expire_ms` if there is one.
+fn down_semaphore(sem_id: usize, expire_ms: Option<usize>) -> SysResult {
+    let tid = current_tid();
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let sem = get_by_id(&process_inner.semaphore_list, sem_id)?;
+    if sem.key != 0 {
+        drop(process_inner);
+        return wait_result(sem.down(expire_ms));
+    }
+    let resource = Resource::Semaphore(sem_id);
+    let live = process_inner.live_tids();
+    if !process_inner
+        .deadlock_detector
+        .request(tid, resource, &live)
+    {
+        return Err(SysError::EDEADLK);
+    }
+    drop(process_inner);
+    let result = sem.down(expire_ms);
+    let mut process_inner = process.inner_exclusive_access();
+    if result != WaitResult::Woken {
+        process_inner.deadlock_detector.cancel(tid, resource);
+        return wait_result(result);
+    }
+    process_inner.deadlock_detector.acquire(tid, resource);
+    Ok(0)
+}
+
+pub fn sys_semaphore_down(sem_id: usize) -> SysResult {
+    down_semaphore(sem_id, None)
+}
+
+pub fn sys_semaphore_down_timeout(sem_id: usize, timeout_ms: usize) -> SysResult {
+    down_semaphore(sem_id, Some(get_time_ms().saturating_add(timeout_ms)))
+}
+
+/// With detection enabled, locking a mutex or downing a semaphore fails
+/// with EDEADLK instead of blocking when the threads of the process could
+/// no longer all finish. Downing a semaphore is only refused once all the
+/// other threads are waiting as well, since any of them may raise it.
+pub fn sys_enable_deadlock_detect(enabled: usize) -> SysResult {
+    if enabled > 1 {
+        return Err(SysError::EINVAL);
+    }
+    let process = current_process();
+    process.inner_exclusive_access().deadlock_detector.enabled = enabled == 1;
+    Ok(0)
+}
+
+pub fn sys_condvar_create(_arg: usize) -> SysResult {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let id = if let Some(id) = process_inner
+        .condvar_list
+        .iter()
+        .enumerate()
+        .find(|(_, item)| item.is_none())
+        .map(|(id, _)| id)
+    {
+        process_inner.condvar_list[id] = Some(Arc::new(Condvar::new()));
+        id
+    } else {
+        process_inner
+            .condvar_list
+            .push(Some(Arc::new(Condvar::new())));
+        process_inner.condvar_list.len() - 1
+    };
+    Ok(id)
+}
+
+pub fn sys_condvar_signal(condvar_id: usize) -> SysResult {
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
-    let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
+    let condvar = get_by_id(&process_inner.condvar_list, condvar_id)?;
+    drop(process_inner);
+    condvar.signal();
+    Ok(0)
+}
+
+pub fn sys_condvar_broadcast(condvar_id: usize) -> SysResult {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let condvar = get_by_id(&process_inner.condvar_list, condvar_id)?;
+    drop(process_inner);
+    condvar.broadcast();
+    Ok(0)
+}
+
+pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> SysResult {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let condvar = get_by_id(&process_inner.condvar_list, condvar_id)?;
+    let mutex = get_by_id(&process_inner.mutex_list, mutex_id)?;
     drop(process_inner);
     drop(process);
-    mutex.lock();
-    0
+    wait_result(condvar.wait(mutex, None))
 }
 
-pub fn sys_mutex_unlock(mutex_id: usize) -> isize {
+/// The mutex is locked again when this returns, timed out or not, unless it
+/// fails with EINTR.
+pub fn sys_condvar_wait_timeout(
+    condvar_id: usize,
+    mutex_id: usize,
+    timeout_ms: usize,
+) -> SysResult {
+    let expire_ms = get_time_ms().saturating_add(timeout_ms);
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
-    let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
+    let condvar = get_by_id(&process_inner.condvar_list, condvar_id)?;
+    let mutex = get_by_id(&process_inner.mutex_list, mutex_id)?;
     drop(process_inner);
     drop(process);
-    mutex.unlock();
-    0
+    wait_result(condvar.wait(mutex, Some(expire_ms)))
 }
 
-pub fn sys_semaphore_create(res_count: usize) -> isize {
+/// `policy` is 0 to prefer readers or 1 to prefer writers.
+pub fn sys_rwlock_create(policy: usize) -> SysResult {
+    let policy = match policy {
+        0 => RwLockPolicy::PreferReader,
+        1 => RwLockPolicy::PreferWriter,
+        _ => return Err(SysError::EINVAL),
+    };
     let process = current_process();
     let mut process_inner = process.inner_exclusive_access();
     let id = if let Some(id) = process_inner
-        .semaphore_list
+        .rwlock_list
         .iter()
         .enumerate()
         .find(|(_, item)| item.is_none())
         .map(|(id, _)| id)
     {
-        process_inner.semaphore_list[id] = Some(Arc::new(Semaphore::new(res_count)));
+        process_inner.rwlock_list[id] = Some(Arc::new(RwLock::new(policy)));
         id
     } else {
         process_inner
-            .semaphore_list
-            .push(Some(Arc::new(Semaphore::new(res_count))));
-        process_inner.semaphore_list.len() - 1
+            .rwlock_list
+            .push(Some(Arc::new(RwLock::new(policy))));
+        process_inner.rwlock_list.len() - 1
     };
-    id as isize
+    Ok(id)
 }
 
-pub fn sys_semaphore_up(sem_id: usize) -> isize {
+pub fn sys_rwlock_read_lock(rwlock_id: usize) -> SysResult {
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
-    let sem = Arc::clone(process_inner.semaphore_list[sem_id].as_ref().unwrap());
+    let rwlock = get_by_id(&process_inner.rwlock_list, rwlock_id)?;
     drop(process_inner);
-    sem.up();
-    0
+    drop(process);
+    wait_result(rwlock.read_lock())
 }
 
-pub fn sys_semaphore_down(sem_id: usize) -> isize {
+pub fn sys_rwlock_write_lock(rwlock_id: usize) -> SysResult {
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
-    let sem = Arc::clone(process_inner.semaphore_list[sem_id].as_ref().unwrap());
+    let rwlock = get_by_id(&process_inner.rwlock_list, rwlock_id)?;
     drop(process_inner);
-    sem.down();
-    0
+    drop(process);
+    wait_result(rwlock.write_lock())
 }
 
-pub fn sys_condvar_create(_arg: usize) -> isize {
+pub fn sys_rwlock_unlock(rwlock_id: usize) -> SysResult {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let rwlock = get_by_id(&process_inner.rwlock_list, rwlock_id)?;
+    drop(process_inner);
+    drop(process);
+    if !rwlock.unlock() {
+        return Err(SysError::EPERM);
+    }
+    Ok(0)
+}
+
+pub fn sys_barrier_create(count: usize) -> SysResult {
+    if count == 0 {
+        return Err(SysError::EINVAL);
+    }
     let process = current_process();
     let mut process_inner = process.inner_exclusive_access();
     let id = if let Some(id) = process_inner
-        .condvar_list
+        .barrier_list
         .iter()
         .enumerate()
         .find(|(_, item)| item.is_none())
         .map(|(id, _)| id)
     {
-        process_inner.condvar_list[id] = Some(Arc::new(Condvar::new()));
+        process_inner.barrier_list[id] = Some(Arc::new(Barrier::new(count)));
         id
     } else {
         process_inner
-            .condvar_list
-            .push(Some(Arc::new(Condvar::new())));
-        process_inner.condvar_list.len() - 1
+            .barrier_list
+            .push(Some(Arc::new(Barrier::new(count))));
+        process_inner.barrier_list.len() - 1
     };
-    id as isize
+    Ok(id)
 }
 
-pub fn sys_condvar_signal(condvar_id: usize) -> isize {
+/// Return 1 for the last thread to arrive, which releases the others, and
+/// 0 for the others, or fail with EINTR if interrupted.
+pub fn sys_barrier_wait(barrier_id: usize) -> SysResult {
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
-    let condvar = Arc::clone(process_inner.condvar_list[condvar_id].as_ref().unwrap());
+    let barrier = get_by_id(&process_inner.barrier_list, barrier_id)?;
     drop(process_inner);
-    condvar.signal();
-    0
+    drop(process);
+    barrier
+        .wait()
+        .map(|last| last as usize)
+        .ok_or(SysError::EINTR)
 }
 
-pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
+/// WAIT blocks until woken up if the word at `uaddr` still holds `val`, and
+/// fails with EAGAIN at once otherwise, or with EINTR if interrupted. WAKE
+/// wakes up at most `val` waiters and REQUEUE moves at most `val2` of the
+/// remaining ones to the word at `uaddr2`, both return how many were woken
+/// up.
+pub fn sys_futex(uaddr: usize, op: usize, val: usize, val2: usize, uaddr2: usize) -> SysResult {
     let process = current_process();
-    let process_inner = process.inner_exclusive_access();
-    let condvar = Arc::clone(process_inner.condvar_list[condvar_id].as_ref().unwrap());
-    let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
-    drop(process_inner);
-    condvar.wait(mutex);
-    0
+    let mut process_inner = process.inner_exclusive_access();
+    let pa = futex_word(&mut process_inner.memory_set, uaddr)?;
+    match op & !FUTEX_PRIVATE_FLAG {
+        FUTEX_WAIT => {
+            // the PCB keeps the page from being swapped out until queued
+            if !futex_enqueue(pa, val as u32, current_task().unwrap()) {
+                return Err(SysError::EAGAIN);
+            }
+            drop(process_inner);
+            drop(process);
+            wait_result(block_current(None, futex_cancel))
+        }
+        FUTEX_WAKE => Ok(futex_wake(pa, val, 0, pa)),
+        FUTEX_REQUEUE => {
+            let target = futex_word(&mut process_inner.memory_set, uaddr2)?;
+            Ok(futex_wake(pa, val, val2, target))
+        }
+        _ => Err(SysError::ENOSYS),
+    }
 }
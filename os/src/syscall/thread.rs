@@ -1,28 +1,37 @@
 use crate::{
-    mm::kernel_token,
-    task::{add_task, current_task, TaskControlBlock},
+    config::{PAGE_SIZE, USER_TLS_SIZE},
+    error::{SysError, SysResult},
+    mm::{kernel_token, reclaim_frames, UserPtr},
+    task::{
+        add_task, current_process, current_task, exit_current_thread_and_run_next, interrupt_task,
+        SchedStat, TaskControlBlock, WaitResult,
+    },
     trap::{trap_handler, TrapContext},
 };
 use alloc::sync::Arc;
 
-pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
-    let task = current_task().unwrap();
+/// Create a thread in the process of `task`, which inherits its signal mask.
+/// The trap context is left for the caller to set up before adding the
+/// thread to the scheduler. Fail with ENOMEM if there is no frame left for
+/// its thread-local storage.
+pub fn new_thread(task: &Arc<TaskControlBlock>) -> SysResult<Arc<TaskControlBlock>> {
     let process = task.process.upgrade().unwrap();
+    // the TCB is released before the PCB is locked to create the thread
+    let task_inner = task.inner_exclusive_access();
+    let ustack_base = task_inner.res.as_ref().unwrap().ustack_base;
+    let signal_mask = task_inner.signal_mask;
+    drop(task_inner);
     // create a new thread
     let new_task = Arc::new(TaskControlBlock::new(
         Arc::clone(&process),
-        task.inner_exclusive_access()
-            .res
-            .as_ref()
-            .unwrap()
-            .ustack_base,
+        ustack_base,
         true,
-    ));
-    // add new task to scheduler
-    add_task(Arc::clone(&new_task));
-    let new_task_inner = new_task.inner_exclusive_access();
-    let new_task_res = new_task_inner.res.as_ref().unwrap();
-    let new_task_tid = new_task_res.tid;
+    )?);
+    let mut new_task_inner = new_task.inner_exclusive_access();
+    // the new thread inherits the signal mask
+    new_task_inner.signal_mask = signal_mask;
+    let new_task_tid = new_task_inner.res.as_ref().unwrap().tid;
+    drop(new_task_inner);
     let mut process_inner = process.inner_exclusive_access();
     // add new thread to current process
     let tasks = &mut process_inner.tasks;
@@ -30,6 +39,18 @@ pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
         tasks.push(None);
     }
     tasks[new_task_tid] = Some(Arc::clone(&new_task));
+    Ok(new_task)
+}
+
+pub fn sys_thread_create(entry: usize, arg: usize) -> SysResult {
+    // the trap context and the kernel stack come from the frames kept for
+    // the kernel, make up for them by swapping user pages out, and for the
+    // thread-local storage
+    reclaim_frames(USER_TLS_SIZE / PAGE_SIZE);
+    let new_task = new_thread(&current_task().unwrap())?;
+    let new_task_inner = new_task.inner_exclusive_access();
+    let new_task_res = new_task_inner.res.as_ref().unwrap();
+    let new_task_tid = new_task_res.tid;
     let new_task_trap_cx = new_task_inner.get_trap_cx();
     *new_task_trap_cx = TrapContext::app_init_context(
         entry,
@@ -38,48 +59,147 @@ pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
         new_task.kstack.get_top(),
         trap_handler as usize,
     );
+    new_task_trap_cx.set_tp(new_task_res.tls_base());
     (*new_task_trap_cx).x[10] = arg;
-    new_task_tid as isize
+    drop(new_task_inner);
+    // add new task to scheduler once another hart is able to run it
+    add_task(new_task);
+    Ok(new_task_tid)
 }
 
-pub fn sys_gettid() -> isize {
-    current_task()
+pub fn sys_gettid() -> SysResult {
+    Ok(current_task()
         .unwrap()
         .inner_exclusive_access()
         .res
         .as_ref()
         .unwrap()
-        .tid as isize
+        .tid)
 }
 
-/// thread does not exist, return -1
-/// thread has not exited yet, return -2
-/// otherwise, return thread's exit code
-pub fn sys_waittid(tid: usize) -> i32 {
+/// thread does not exist, fail with ESRCH
+/// thread is the caller or detached, fail with EDEADLK or EINVAL
+/// thread has not exited yet, block until it exits, or fail with EINTR if
+/// the wait is interrupted
+/// otherwise, return thread's exit code, which may look like an error
+pub fn sys_waittid(tid: usize) -> SysResult {
     let task = current_task().unwrap();
     let process = task.process.upgrade().unwrap();
-    let task_inner = task.inner_exclusive_access();
-    let mut process_inner = process.inner_exclusive_access();
     // a thread cannot wait for itself
-    if task_inner.res.as_ref().unwrap().tid == tid {
-        return -1;
+    if task.inner_exclusive_access().res.as_ref().unwrap().tid == tid {
+        return Err(SysError::EDEADLK);
     }
-    let mut exit_code: Option<i32> = None;
-    let waited_task = process_inner.tasks[tid].as_ref();
-    if let Some(waited_task) = waited_task {
-        if let Some(waited_exit_code) = waited_task.inner_exclusive_access().exit_code {
-            exit_code = Some(waited_exit_code);
+    loop {
+        let mut process_inner = process.inner_exclusive_access();
+        let waited_task = match process_inner.tasks.get(tid) {
+            Some(Some(waited_task)) => waited_task,
+            // waited thread does not exist
+            _ => return Err(SysError::ESRCH),
+        };
+        let waited_task_inner = waited_task.inner_exclusive_access();
+        if waited_task_inner.detached {
+            return Err(SysError::EINVAL);
+        }
+        let exit_code = waited_task_inner.exit_code;
+        drop(waited_task_inner);
+        if let Some(exit_code) = exit_code {
+            // dealloc the exited thread
+            process_inner.reap_task(tid);
+            return Ok(exit_code as isize as usize);
         }
-    } else {
-        // waited thread does not exist
-        return -1;
+        // the waited thread wakes us up when it exits
+        process_inner.wait_queue.push_back(Arc::clone(&task));
+        drop(process_inner);
+        if process.wait_for_waiters() == WaitResult::Interrupted {
+            return Err(SysError::EINTR);
+        }
+    }
+}
+
+/// Exit the current thread only. The process exits with its last thread,
+/// with the exit code of that thread.
+pub fn sys_thread_exit(exit_code: i32) -> ! {
+    exit_current_thread_and_run_next(exit_code);
+    panic!("Unreachable in sys_thread_exit!");
+}
+
+/// Let the thread be reaped as soon as it exits, it can't be waited for
+/// any longer. Fail with ESRCH if it doesn't exist, and with EINVAL if it is
+/// the main thread or detached already.
+pub fn sys_thread_detach(tid: usize) -> SysResult {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let task = match process_inner.tasks.get(tid) {
+        Some(Some(task)) => task,
+        _ => return Err(SysError::ESRCH),
+    };
+    if tid == process_inner.main_tid {
+        return Err(SysError::EINVAL);
+    }
+    let mut task_inner = task.inner_exclusive_access();
+    if task_inner.detached {
+        return Err(SysError::EINVAL);
+    }
+    task_inner.detached = true;
+    let exited = task_inner.exit_code.is_some();
+    drop(task_inner);
+    if exited {
+        process_inner.reap_task(tid);
+    }
+    Ok(0)
+}
+
+/// Make a thread other than the main one exit with the exit code of SIGKILL,
+/// without disturbing the rest of the process. It exits the next time it is
+/// about to return to user mode, a wait it is blocked in is interrupted
+/// first. Fail with ESRCH if it doesn't exist or has exited already, and
+/// with EINVAL if it is the main thread.
+pub fn sys_thread_kill(tid: usize) -> SysResult {
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let task = match process_inner.tasks.get(tid) {
+        Some(Some(task)) => task,
+        _ => return Err(SysError::ESRCH),
+    };
+    if tid == process_inner.main_tid {
+        return Err(SysError::EINVAL);
+    }
+    let mut task_inner = task.inner_exclusive_access();
+    if task_inner.exit_code.is_some() {
+        return Err(SysError::ESRCH);
     }
-    if let Some(exit_code) = exit_code {
-        // dealloc the exited thread
-        process_inner.tasks[tid] = None;
-        exit_code
-    } else {
-        // waited thread has not exited
-        -2
+    task_inner.killed = true;
+    drop(task_inner);
+    let task = Arc::clone(task);
+    drop(process_inner);
+    interrupt_task(&task);
+    Ok(0)
+}
+
+/// Set the priority of the current thread, a larger one gets more CPU time.
+/// Return the priority, or fail with EINVAL if it is less than 1. Inherited
+/// priorities stay in effect until the mutexes are unlocked.
+pub fn sys_set_priority(priority: isize) -> SysResult {
+    if priority < 1 {
+        return Err(SysError::EINVAL);
     }
+    current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .sched
+        .set_base_priority(priority as usize);
+    Ok(priority as usize)
+}
+
+pub fn sys_sched_stat(stat: *mut SchedStat) -> SysResult {
+    let value = current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .sched
+        .stat();
+    UserPtr::from(stat).write(
+        &mut current_process().inner_exclusive_access().memory_set,
+        &value,
+    )?;
+    Ok(0)
 }
@@ -0,0 +1,190 @@
+use super::{
+    SYSCALL_EXECVE, SYSCALL_EXIT, SYSCALL_EXIT_GROUP, SYSCALL_NEWFSTATAT, SYSCALL_OPENAT,
+    SYSCALL_READ, SYSCALL_THREAD_EXIT, SYSCALL_WRITE,
+};
+use crate::error::{SysError, SysResult};
+use crate::mm::{copy_from_user, MemorySet, UserPtr, UserSlice};
+use crate::task::{
+    current_process, current_task, ProcessControlBlock, TraceLog, TraceRecord, TRACE_DATA_LEN,
+    TRACE_NORETURN, TRACE_TRUNCATED,
+};
+use crate::timer::get_time_us;
+use alloc::sync::Arc;
+
+/// `flags` of `sys_trace`: start tracing, or stop without it.
+const TRACE_ON: usize = 1;
+
+/// A syscall of a traced process, recorded once it returns.
+pub struct TracedCall {
+    log: Arc<TraceLog>,
+    record: TraceRecord,
+}
+
+/// The string or buffer a syscall takes, kept in its record.
+enum TraceData {
+    None,
+    /// a path, in the argument with this index
+    Str(usize),
+    /// a buffer read by the syscall, and the argument with its length
+    In(usize, usize),
+    /// a buffer filled by the syscall, as long as it returns
+    Out(usize),
+}
+
+fn trace_data(id: usize) -> TraceData {
+    match id {
+        SYSCALL_OPENAT | SYSCALL_NEWFSTATAT => TraceData::Str(1),
+        SYSCALL_EXECVE => TraceData::Str(0),
+        SYSCALL_WRITE => TraceData::In(1, 2),
+        SYSCALL_READ => TraceData::Out(1),
+        _ => TraceData::None,
+    }
+}
+
+/// Keep the start of the `len` bytes at `addr`, or nothing if they can't be
+/// read.
+fn record_bytes(memory_set: &mut MemorySet, record: &mut TraceRecord, addr: usize, len: usize) {
+    let kept = len.min(TRACE_DATA_LEN);
+    if copy_from_user(memory_set, addr, &mut record.data[..kept]).is_ok() {
+        record.data_len = kept;
+        if len > kept {
+            record.flags |= TRACE_TRUNCATED;
+        }
+    }
+}
+
+/// Keep the start of the string at `addr`, up to where it can be read.
+fn record_str(memory_set: &mut MemorySet, record: &mut TraceRecord, addr: usize) {
+    let ptr = UserPtr::<u8>::new(addr);
+    for i in 0..TRACE_DATA_LEN {
+        match ptr.add(i).read(memory_set) {
+            Ok(0) | Err(_) => return,
+            Ok(byte) => {
+                record.data[i] = byte;
+                record.data_len += 1;
+            }
+        }
+    }
+    if !matches!(ptr.add(TRACE_DATA_LEN).read(memory_set), Ok(0)) {
+        record.flags |= TRACE_TRUNCATED;
+    }
+}
+
+/// Start recording a syscall if the current process is traced. The
+/// syscalls which don't return are recorded at once.
+pub fn trace_enter(id: usize, args: &[usize; 6]) -> Option<TracedCall> {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let log = inner.trace.clone()?;
+    let task = current_task().unwrap();
+    let tid = task.inner_exclusive_access().res.as_ref().unwrap().tid;
+    let mut record = TraceRecord::new(tid, id, *args, get_time_us());
+    // taken before the syscall, exec replaces the memory they are in
+    match trace_data(id) {
+        TraceData::Str(arg) => record_str(&mut inner.memory_set, &mut record, args[arg]),
+        TraceData::In(arg, len_arg) => {
+            record_bytes(&mut inner.memory_set, &mut record, args[arg], args[len_arg])
+        }
+        TraceData::None | TraceData::Out(_) => {}
+    }
+    drop(inner);
+    if [SYSCALL_EXIT, SYSCALL_EXIT_GROUP, SYSCALL_THREAD_EXIT].contains(&id) {
+        record.flags |= TRACE_NORETURN;
+        log.push(record);
+        return None;
+    }
+    Some(TracedCall { log, record })
+}
+
+/// Record a syscall started by `trace_enter` which has returned `ret`.
+pub fn trace_exit(call: TracedCall, ret: isize) {
+    let TracedCall { log, mut record } = call;
+    record.ret = ret;
+    record.duration_us = get_time_us() - record.start_us;
+    if let TraceData::Out(arg) = trace_data(record.id) {
+        if ret > 0 {
+            let process = current_process();
+            let mut inner = process.inner_exclusive_access();
+            let addr = record.args[arg];
+            record_bytes(&mut inner.memory_set, &mut record, addr, ret as usize);
+        }
+    }
+    log.push(record);
+}
+
+/// The caller if `pid` is 0 or its own, else its child with `pid`, which
+/// may have exited.
+fn self_or_child(
+    process: &Arc<ProcessControlBlock>,
+    pid: usize,
+) -> SysResult<Arc<ProcessControlBlock>> {
+    if pid == 0 || pid == process.getpid() {
+        return Ok(Arc::clone(process));
+    }
+    let inner = process.inner_exclusive_access();
+    let child = inner.children.iter().find(|child| child.getpid() == pid);
+    child.cloned().ok_or(SysError::ESRCH)
+}
+
+/// Start recording the syscalls of process `pid`, the caller if it is 0 or
+/// one of its children, with TRACE_ON, or stop and drop the records without
+/// it. Tracing goes on across exec, so that a process can start it right
+/// before it execs the program to trace.
+pub fn sys_trace(pid: usize, flags: usize) -> SysResult {
+    if flags & !TRACE_ON != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let process = current_process();
+    let target = self_or_child(&process, pid)?;
+    let mut target_inner = target.inner_exclusive_access();
+    if target_inner.is_zombie {
+        return Err(SysError::ESRCH);
+    }
+    if flags & TRACE_ON == 0 {
+        if let Some(log) = target_inner.trace.take() {
+            log.close();
+        }
+    } else if target_inner.trace.is_none() {
+        target_inner.trace = Some(Arc::new(TraceLog::new()));
+    }
+    Ok(0)
+}
+
+/// Move at most `count` records of the syscalls of child `pid` to `buf`,
+/// the oldest first, and return how many. Block until there is one, or
+/// return 0 once the child has exited or tracing has stopped and all of
+/// them have been read. Fail with EINVAL if the child isn't traced, and
+/// with EINTR if the wait is interrupted.
+pub fn sys_trace_read(pid: usize, buf: *mut TraceRecord, count: usize) -> SysResult {
+    let process = current_process();
+    let child = self_or_child(&process, pid)?;
+    if Arc::ptr_eq(&child, &process) {
+        // it would wait for itself
+        return Err(SysError::EINVAL);
+    }
+    let log = child
+        .inner_exclusive_access()
+        .trace
+        .clone()
+        .ok_or(SysError::EINVAL)?;
+    // checked first, so that records aren't lost
+    let size = count
+        .checked_mul(core::mem::size_of::<TraceRecord>())
+        .ok_or(SysError::EFAULT)?;
+    UserSlice::new(buf as usize, size)
+        .check(&mut process.inner_exclusive_access().memory_set, true)?;
+    let buf = UserPtr::from(buf);
+    let mut read = 0;
+    while read < count {
+        // only the first record is waited for
+        match log.pop(read == 0)? {
+            Some(record) => {
+                let mut inner = process.inner_exclusive_access();
+                buf.add(read).write(&mut inner.memory_set, &record)?;
+                read += 1;
+            }
+            None => break,
+        }
+    }
+    Ok(read)
+}
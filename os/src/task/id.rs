@@ -1,7 +1,10 @@
 use super::ProcessControlBlock;
-use crate::config::{KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT_BASE, USER_STACK_SIZE};
-use crate::mm::{MapPermission, PhysPageNum, VirtAddr, KERNEL_SPACE};
-use crate::sync::UPSafeCell;
+use crate::config::{
+    KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT_BASE, USER_STACK_SIZE, USER_TLS_SIZE,
+};
+use crate::error::SysResult;
+use crate::mm::{MapPermission, MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use crate::sync::SpinLock;
 use alloc::{
     sync::{Arc, Weak},
     vec::Vec,
@@ -20,6 +23,13 @@ impl RecycleAllocator {
             recycled: Vec::new(),
         }
     }
+    /// An allocator in which only `id` is in use.
+    pub fn with_allocated(id: usize) -> Self {
+        RecycleAllocator {
+            current: id + 1,
+            recycled: (0..id).rev().collect(),
+        }
+    }
     pub fn alloc(&mut self) -> usize {
         if let Some(id) = self.recycled.pop() {
             id
@@ -40,21 +50,20 @@ impl RecycleAllocator {
 }
 
 lazy_static! {
-    static ref PID_ALLOCATOR: UPSafeCell<RecycleAllocator> =
-        unsafe { UPSafeCell::new(RecycleAllocator::new()) };
-    static ref KSTACK_ALLOCATOR: UPSafeCell<RecycleAllocator> =
-        unsafe { UPSafeCell::new(RecycleAllocator::new()) };
+    static ref PID_ALLOCATOR: SpinLock<RecycleAllocator> = SpinLock::new(RecycleAllocator::new());
+    static ref KSTACK_ALLOCATOR: SpinLock<RecycleAllocator> =
+        SpinLock::new(RecycleAllocator::new());
 }
 
 pub struct PidHandle(pub usize);
 
 pub fn pid_alloc() -> PidHandle {
-    PidHandle(PID_ALLOCATOR.exclusive_access().alloc())
+    PidHandle(PID_ALLOCATOR.lock().alloc())
 }
 
 impl Drop for PidHandle {
     fn drop(&mut self) {
-        PID_ALLOCATOR.exclusive_access().dealloc(self.0);
+        PID_ALLOCATOR.lock().dealloc(self.0);
     }
 }
 
@@ -68,9 +77,9 @@ pub fn kernel_stack_position(kstack_id: usize) -> (usize, usize) {
 pub struct KernelStack(pub usize);
 
 pub fn kstack_alloc() -> KernelStack {
-    let kstack_id = KSTACK_ALLOCATOR.exclusive_access().alloc();
+    let kstack_id = KSTACK_ALLOCATOR.lock().alloc();
     let (kstack_bottom, kstack_top) = kernel_stack_position(kstack_id);
-    KERNEL_SPACE.exclusive_access().insert_framed_area(
+    KERNEL_SPACE.lock().insert_framed_area(
         kstack_bottom.into(),
         kstack_top.into(),
         MapPermission::R | MapPermission::W,
@@ -83,7 +92,7 @@ impl Drop for KernelStack {
         let (kernel_stack_bottom, _) = kernel_stack_position(self.0);
         let kernel_stack_bottom_va: VirtAddr = kernel_stack_bottom.into();
         KERNEL_SPACE
-            .exclusive_access()
+            .lock()
             .remove_area_with_start_vpn(kernel_stack_bottom_va.into());
     }
 }
@@ -117,16 +126,20 @@ fn trap_cx_bottom_from_tid(tid: usize) -> usize {
     TRAP_CONTEXT_BASE - tid * PAGE_SIZE
 }
 
-fn ustack_bottom_from_tid(ustack_base: usize, tid: usize) -> usize {
-    ustack_base + tid * (PAGE_SIZE + USER_STACK_SIZE)
+/// Each thread has its user stack, then its thread-local storage and a
+/// guard page.
+pub fn ustack_bottom_from_tid(ustack_base: usize, tid: usize) -> usize {
+    ustack_base + tid * (USER_STACK_SIZE + USER_TLS_SIZE + PAGE_SIZE)
 }
 
 impl TaskUserRes {
+    /// Fail with ENOMEM if there is no frame left for the thread-local
+    /// storage, the tid and the areas are released then.
     pub fn new(
         process: Arc<ProcessControlBlock>,
         ustack_base: usize,
         alloc_user_res: bool,
-    ) -> Self {
+    ) -> SysResult<Self> {
         let tid = process.inner_exclusive_access().alloc_tid();
         let task_user_res = Self {
             tid,
@@ -134,34 +147,67 @@ impl TaskUserRes {
             process: Arc::downgrade(&process),
         };
         if alloc_user_res {
-            task_user_res.alloc_user_res();
+            if let Err(err) = task_user_res.alloc_user_res() {
+                drop(task_user_res);
+                process.inner_exclusive_access().dealloc_tid(tid);
+                return Err(err);
+            }
         }
-        task_user_res
+        Ok(task_user_res)
     }
 
-    pub fn alloc_user_res(&self) {
+    /// User res of thread `tid` in the address space copied by fork, which
+    /// are in place already.
+    pub fn forked(process: &Arc<ProcessControlBlock>, ustack_base: usize, tid: usize) -> Self {
+        Self {
+            tid,
+            ustack_base,
+            process: Arc::downgrade(process),
+        }
+    }
+
+    pub fn alloc_user_res(&self) -> SysResult<()> {
         let process = self.process.upgrade().unwrap();
         let mut process_inner = process.inner_exclusive_access();
-        // alloc user stack
-        let ustack_bottom = ustack_bottom_from_tid(self.ustack_base, self.tid);
+        Self::map_user_res(&mut process_inner.memory_set, self.ustack_base, self.tid)
+    }
+
+    /// Map the user stack, the thread-local storage and the trap context of
+    /// thread `tid` into `memory_set`. Fail with ENOMEM if there is no frame
+    /// left for the thread-local storage, the areas are all mapped then.
+    pub fn map_user_res(
+        memory_set: &mut MemorySet,
+        ustack_base: usize,
+        tid: usize,
+    ) -> SysResult<()> {
+        // alloc user stack, backed on demand
+        let ustack_bottom = ustack_bottom_from_tid(ustack_base, tid);
         let ustack_top = ustack_bottom + USER_STACK_SIZE;
-        process_inner.memory_set.insert_framed_area(
+        memory_set.insert_lazy_area(
             ustack_bottom.into(),
             ustack_top.into(),
             MapPermission::R | MapPermission::W | MapPermission::U,
         );
+        // alloc thread-local storage
+        let tls_base = ustack_top;
+        memory_set.insert_lazy_area(
+            tls_base.into(),
+            (tls_base + USER_TLS_SIZE).into(),
+            MapPermission::R | MapPermission::W | MapPermission::U,
+        );
         // alloc trap_cx
-        let trap_cx_bottom = trap_cx_bottom_from_tid(self.tid);
+        let trap_cx_bottom = trap_cx_bottom_from_tid(tid);
         let trap_cx_top = trap_cx_bottom + PAGE_SIZE;
-        process_inner.memory_set.insert_framed_area(
+        memory_set.insert_framed_area(
             trap_cx_bottom.into(),
             trap_cx_top.into(),
             MapPermission::R | MapPermission::W,
         );
+        // only the template is backed now
+        memory_set.init_tls(tls_base)
     }
 
     fn dealloc_user_res(&self) {
-        // dealloc tid
         let process = self.process.upgrade().unwrap();
         let mut process_inner = process.inner_exclusive_access();
         // dealloc ustack manually
@@ -169,6 +215,11 @@ impl TaskUserRes {
         process_inner
             .memory_set
             .remove_area_with_start_vpn(ustack_bottom_va.into());
+        // dealloc thread-local storage manually
+        let tls_base_va: VirtAddr = self.tls_base().into();
+        process_inner
+            .memory_set
+            .remove_area_with_start_vpn(tls_base_va.into());
         // dealloc trap_cx manually
         let trap_cx_bottom_va: VirtAddr = trap_cx_bottom_from_tid(self.tid).into();
         process_inner
@@ -186,12 +237,6 @@ impl TaskUserRes {
             .alloc_tid();
     }
 
-    pub fn dealloc_tid(&self) {
-        let process = self.process.upgrade().unwrap();
-        let mut process_inner = process.inner_exclusive_access();
-        process_inner.dealloc_tid(self.tid);
-    }
-
     pub fn trap_cx_user_va(&self) -> usize {
         trap_cx_bottom_from_tid(self.tid)
     }
@@ -213,11 +258,16 @@ impl TaskUserRes {
     pub fn ustack_top(&self) -> usize {
         ustack_bottom_from_tid(self.ustack_base, self.tid) + USER_STACK_SIZE
     }
+    /// The thread-local storage block, which tp points to.
+    pub fn tls_base(&self) -> usize {
+        self.ustack_top()
+    }
 }
 
+/// The tid is not deallocated with the rest, it is still in use until the
+/// thread is reaped.
 impl Drop for TaskUserRes {
     fn drop(&mut self) {
-        self.dealloc_tid();
         self.dealloc_user_res();
     }
 }
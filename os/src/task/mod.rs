@@ -3,27 +3,49 @@ mod id;
 mod manager;
 mod process;
 mod processor;
+mod ptrace;
+mod sched;
 mod signal;
 mod switch;
 #[allow(clippy::module_inception)]
 mod task;
+mod trace;
+mod wait;
 
-use crate::fs::{open_file, OpenFlags};
+use crate::fs::{console_hangup, open_file, OpenFlags};
+use crate::sync::{futex_clear_and_wake, futex_remove_task, SpinLock};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use lazy_static::*;
 use manager::fetch_task;
-use process::ProcessControlBlock;
+use ptrace::ptrace_thread_exit;
 use switch::__switch;
 
 pub use context::TaskContext;
 pub use id::{kstack_alloc, pid_alloc, KernelStack, PidHandle};
-pub use manager::{add_task, pid2process, remove_from_pid2process};
+pub use manager::{add_task, pid2process, process_group, processes, remove_from_pid2process};
+pub use process::{
+    alloc_fd, CloneFlags, FdTable, ProcessControlBlock, ProcessControlBlockInner, WaitEvent,
+};
 pub use processor::{
     current_kstack_top, current_process, current_task, current_trap_cx, current_trap_cx_user_va,
     current_user_token, run_tasks, schedule, take_current_task,
 };
-pub use signal::SignalFlags;
+pub use ptrace::{
+    ptrace_breakpoint_trap, ptrace_detach, ptrace_syscall_stop, Ptrace, PtraceResume,
+};
+pub use sched::{scheduler_name, SchedStat};
+pub use signal::{
+    current_add_signal, current_blocks_or_ignores, handle_signals, restore_signal_frame,
+    send_group_signal, send_signal, SignalAction, SignalFlags, MAX_SIG, SIG_BLOCK, SIG_IGN,
+    SIG_SETMASK, SIG_UNBLOCK,
+};
 pub use task::{TaskControlBlock, TaskStatus};
+pub use trace::{TraceLog, TraceRecord, TRACE_DATA_LEN, TRACE_NORETURN, TRACE_TRUNCATED};
+pub use wait::{
+    block_current, block_current_killable, current_interrupted, interrupt_for_signal,
+    interrupt_task, leave_no_queue, remove_waiter, Wait, WaitResult,
+};
 
 pub fn suspend_current_and_run_next() {
     // There must be an application running.
@@ -34,6 +56,7 @@ pub fn suspend_current_and_run_next() {
     let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
     // Change status to Ready
     task_inner.task_status = TaskStatus::Ready;
+    task_inner.sched.on_leave();
     drop(task_inner);
     // ---- release current TCB
 
@@ -43,65 +66,176 @@ pub fn suspend_current_and_run_next() {
     schedule(task_cx_ptr);
 }
 
+/// The current task has used up its time slice.
+pub fn preempt_current_and_run_next() {
+    current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .sched
+        .on_preempt();
+    suspend_current_and_run_next();
+}
+
 pub fn block_current_and_run_next() {
     let task = take_current_task().unwrap();
     let mut task_inner = task.inner_exclusive_access();
     let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
     task_inner.task_status = TaskStatus::Blocking;
+    task_inner.sched.on_leave();
     drop(task_inner);
     schedule(task_cx_ptr);
 }
 
+/// Exit the current thread, the main thread takes the whole process with it.
 pub fn exit_current_and_run_next(exit_code: i32) {
+    exit_current(exit_code, true);
+}
+
+/// Exit the current thread only, the process exits with the last thread.
+pub fn exit_current_thread_and_run_next(exit_code: i32) {
+    exit_current(exit_code, false);
+}
+
+/// Exit the whole process with `exit_code`, from any of its threads. The
+/// others exit as soon as they are about to return to user mode, those
+/// blocked in the kernel are interrupted.
+pub fn exit_current_group_and_run_next(exit_code: i32) {
+    if let Some(process) = current_task().unwrap().process.upgrade() {
+        let mut process_inner = process.inner_exclusive_access();
+        process_inner.terminating.get_or_insert(exit_code);
+    }
+    exit_current(exit_code, false);
+}
+
+fn exit_current(exit_code: i32, main_exits_process: bool) {
     let task = take_current_task().unwrap();
     let mut task_inner = task.inner_exclusive_access();
-    let process = task.process.upgrade().unwrap();
-    let tid = task_inner.res.as_ref().unwrap().tid;
-    // record exit code
-    task_inner.exit_code = Some(exit_code);
-    task_inner.res = None;
-    // here we do not remove the thread since we are still using the kstack
-    // it will be deallocated when sys_waittid is called
+    task_inner.sched.on_leave();
+    let res = task_inner.res.take();
     drop(task_inner);
+    let tid = res.as_ref().map(|res| res.tid);
+    // user res are deallocated without holding the TCB, since the PCB is
+    // locked by then and the PCB has to be locked before any TCB
+    drop(res);
+    // nothing is left to clean up if the process is gone
+    if let Some(process) = task.process.upgrade() {
+        exit_thread(&process, &task, tid, exit_code, main_exits_process);
+    }
+    // the kernel stack is in use until we have switched away, `run_tasks`
+    // holds the last reference of a reaped thread until then
     drop(task);
-    // however, if this is the main thread of current process
-    // the process should terminate at once
-    if tid == 0 {
-        remove_from_pid2process(process.getpid());
-        let mut process_inner = process.inner_exclusive_access();
-        // mark this process as a zombie process
-        process_inner.is_zombie = true;
-        // record exit code of main process
-        process_inner.exit_code = exit_code;
-
-        {
-            // move all child processes under init process
-            let mut initproc_inner = INITPROC.inner_exclusive_access();
-            for child in process_inner.children.iter() {
-                child.inner_exclusive_access().parent = Some(Arc::downgrade(&INITPROC));
-                initproc_inner.children.push(child.clone());
-            }
-        }
+    // we do not have to save task context
+    let mut _unused = TaskContext::zero_init();
+    schedule(&mut _unused as *mut _);
+}
+
+/// Record the exit of `task`, and tear down the process if it is the last
+/// thread to leave.
+fn exit_thread(
+    process: &Arc<ProcessControlBlock>,
+    task: &Arc<TaskControlBlock>,
+    tid: Option<usize>,
+    exit_code: i32,
+    main_exits_process: bool,
+) {
+    let mut process_inner = process.inner_exclusive_access();
+    if main_exits_process && tid == Some(process_inner.main_tid) {
+        // the other threads exit as soon as they return to user mode
+        process_inner.terminating.get_or_insert(exit_code);
+    }
+    // it can't be woken up any more
+    futex_remove_task(task);
+    let mut task_inner = task.inner_exclusive_access();
+    task_inner.exit_code = Some(exit_code);
+    let detached = task_inner.detached;
+    let clear_child_tid = core::mem::take(&mut task_inner.clear_child_tid);
+    drop(task_inner);
+    if clear_child_tid != 0 && !process_inner.is_zombie {
+        // lets a thread library join this thread with a futex
+        futex_clear_and_wake(&mut process_inner.memory_set, clear_child_tid);
+    }
+    if let Some(tid) = tid {
+        ptrace_thread_exit(&mut process_inner, tid);
+    }
+    if let Some(tid) = tid.filter(|_| detached) {
+        // nobody is going to wait for it
+        process_inner.reap_task(tid);
+    }
+    // the others give up their waits to exit too
+    let others: Vec<_> = match process_inner.terminating {
+        Some(_) => process_inner.tasks.iter().flatten().cloned().collect(),
+        None => Vec::new(),
+    };
+    drop(process_inner);
+    for other in others.iter() {
+        interrupt_task(other);
+    }
+    // threads of the process may be waiting for this one
+    process.wakeup_waiters();
+    exit_process_if_last(process, exit_code);
+}
 
-        // deallocate user res (including tid/trap_cx/ustack) of all threads
-        // it has to be done before we dealloc the whole memory_set
-        // otherwise they will be deallocated twice
-        for task in process_inner.tasks.iter().filter(|t| t.is_some()) {
-            let task = task.as_ref().unwrap();
-            let mut task_inner = task.inner_exclusive_access();
-            task_inner.res = None;
+/// Tear down the process once all of its threads have exited, with
+/// `exit_code` unless it is terminating.
+fn exit_process_if_last(process: &Arc<ProcessControlBlock>, exit_code: i32) {
+    let mut process_inner = process.inner_exclusive_access();
+    // exit codes are set before this is checked with the PCB locked, so that
+    // only the last of them tears the process down
+    let last = !process_inner.is_zombie
+        && process_inner
+            .tasks
+            .iter()
+            .flatten()
+            .all(|task| task.inner_exclusive_access().exit_code.is_some());
+    if !last {
+        return;
+    }
+    // the parent may see the zombie from now on
+    process_inner.is_zombie = true;
+    process_inner.exit_code = process_inner.terminating.unwrap_or(exit_code);
+    let trace = process_inner.trace.clone();
+    drop(process_inner);
+    // all of its syscalls have been recorded
+    if let Some(log) = trace {
+        log.close();
+    }
+    remove_from_pid2process(process.getpid());
+    let mut process_inner = process.inner_exclusive_access();
+    // the children are moved under init process after the PCB is
+    // released, since init process may be waiting with its PCB locked
+    let children = core::mem::take(&mut process_inner.children);
+
+    // deallocate other data in user space i.e. program code/data section
+    process_inner.memory_set.recycle_data_pages();
+    // drop file descriptors, unless processes cloned with CLONE_FILES still
+    // use them
+    process_inner.fd_table = Arc::new(SpinLock::new(Vec::new()));
+    let parent = process_inner.parent.as_ref().and_then(|p| p.upgrade());
+    let sid = process_inner.sid;
+    drop(process_inner);
+
+    // the parent may be waiting for this process
+    signal::notify_parent(parent);
+    // the foreground group loses the console with the session leader
+    if sid == process.getpid() {
+        if let Some(pgid) = console_hangup(sid) {
+            send_group_signal(pgid, SignalFlags::SIGHUP);
+            send_group_signal(pgid, SignalFlags::SIGCONT);
         }
+    }
 
-        process_inner.children.clear();
-        // deallocate other data in user space i.e. program code/data section
-        process_inner.memory_set.recycle_data_pages();
-        // drop file descriptors
-        process_inner.fd_table.clear();
+    // move all child processes under init process
+    for child in children.iter() {
+        let mut child_inner = child.inner_exclusive_access();
+        child_inner.parent = Some(Arc::downgrade(&INITPROC));
+        // init doesn't trace them
+        ptrace_detach(&mut child_inner);
+    }
+    if !children.is_empty() {
+        INITPROC.inner_exclusive_access().children.extend(children);
+        // some of them may be zombies already
+        INITPROC.wakeup_waiters();
     }
-    drop(process);
-    // we do not have to save task context
-    let mut _unused = TaskContext::zero_init();
-    schedule(&mut _unused as *mut _);
 }
 
 lazy_static! {
@@ -113,17 +247,6 @@ lazy_static! {
 }
 
 pub fn add_initproc() {
+    println!("[kernel] scheduler: {}", scheduler_name());
     let _initproc = INITPROC.clone();
 }
-
-pub fn check_signals_of_current() -> Option<(i32, &'static str)> {
-    let process = current_process();
-    let process_inner = process.inner_exclusive_access();
-    process_inner.signals.check_error()
-}
-
-pub fn current_add_signal(signal: SignalFlags) {
-    let process = current_process();
-    let mut process_inner = process.inner_exclusive_access();
-    process_inner.signals |= signal;
-}
@@ -1,23 +1,67 @@
-use super::id::RecycleAllocator;
+use super::id::{ustack_bottom_from_tid, RecycleAllocator, TaskUserRes};
 use super::manager::insert_into_pid2process;
-use super::TaskControlBlock;
-use super::{add_task, SignalFlags};
-use super::{pid_alloc, PidHandle};
+use super::{add_task, SignalAction, SignalFlags, MAX_SIG, SIG_IGN};
+use super::{block_current, block_current_killable, interrupt_task, remove_waiter, WaitResult};
+use super::{current_task, TaskControlBlock};
+use super::{pid_alloc, send_signal, PidHandle, Ptrace, TraceLog};
+use crate::config::{PAGE_SIZE, USER_STACK_SIZE, USER_TLS_SIZE};
+use crate::error::{SysError, SysResult};
 use crate::fs::{File, Stdin, Stdout};
-use crate::mm::{translated_refmut, MemorySet, KERNEL_SPACE};
-use crate::sync::{Condvar, Mutex, Semaphore, UPSafeCell};
+use crate::mm::{copy_to_user, reclaim_frames, MemorySet, VirtAddr, KERNEL_SPACE};
+use crate::sync::{
+    Barrier, Condvar, DeadlockDetector, Mutex, RwLock, Semaphore, SpinLock, SpinLockGuard,
+};
+use crate::timer::get_time;
 use crate::trap::{trap_handler, TrapContext};
+use alloc::collections::VecDeque;
 use alloc::string::String;
 use alloc::sync::{Arc, Weak};
 use alloc::vec;
 use alloc::vec::Vec;
-use core::cell::RefMut;
+use bitflags::*;
+
+/// Open files of a process.
+pub type FdTable = Vec<Option<Arc<dyn File + Send + Sync>>>;
+/// Signal handlers of a process.
+pub type SignalActions = [SignalAction; MAX_SIG + 1];
+
+bitflags! {
+    /// Flags of `sys_clone`, as in Linux.
+    pub struct CloneFlags: usize {
+        /// the signal sent to the parent on exit, always SIGCHLD here
+        const CSIGNAL = 0xff;
+        /// only with THREAD, processes never share their memory
+        const VM = 1 << 8;
+        /// ignored, there is no working directory
+        const FS = 1 << 9;
+        const FILES = 1 << 10;
+        /// only with THREAD, as the handlers go with the memory
+        const SIGHAND = 1 << 11;
+        const THREAD = 1 << 16;
+        /// ignored, semaphores of `sys_semaphore_open` are always shared
+        const SYSVSEM = 1 << 18;
+        const SETTLS = 1 << 19;
+        const PARENT_SETTID = 1 << 20;
+        const CHILD_CLEARTID = 1 << 21;
+        /// ignored, as in Linux
+        const DETACHED = 1 << 22;
+        const CHILD_SETTID = 1 << 24;
+    }
+}
+
+/// A change of a child process reported by `sys_waitpid` besides its exit.
+#[derive(Copy, Clone, PartialEq)]
+pub enum WaitEvent {
+    /// stopped by the signal with this number
+    Stopped(usize),
+    Continued,
+}
 
 pub struct ProcessControlBlock {
     // immutable
     pub pid: PidHandle,
     // mutable
-    inner: UPSafeCell<ProcessControlBlockInner>,
+    inner: SpinLock<ProcessControlBlockInner>,
 }
 
 pub struct ProcessControlBlockInner {
@@ -26,13 +70,147 @@ pub struct ProcessControlBlockInner {
     pub parent: Option<Weak<ProcessControlBlock>>,
     pub children: Vec<Arc<ProcessControlBlock>>,
     pub exit_code: i32,
-    pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    /// shared with the processes cloned with CLONE_FILES
+    pub fd_table: Arc<SpinLock<FdTable>>,
+    /// pending signals sent to the process
     pub signals: SignalFlags,
+    /// copied by fork, threads share them as part of the process
+    pub signal_actions: SignalActions,
+    /// stopped by a signal until SIGCONT or SIGKILL
+    pub stopped: bool,
+    /// the last stop or continue, until the parent waits for it
+    pub wait_event: Option<WaitEvent>,
+    /// process group, see `sys_setpgid`
+    pub pgid: usize,
+    /// session, see `sys_setsid`
+    pub sid: usize,
+    /// killed by a signal or exiting, threads exit with this code
+    pub terminating: Option<i32>,
+    /// the signal which has killed the process, reported by `sys_wait4`
+    pub killed_by: Option<usize>,
+    /// the syscalls of the process while it is traced, see `sys_trace`
+    pub trace: Option<Arc<TraceLog>>,
+    /// set while the process is traced by its parent, see `sys_ptrace`
+    pub ptrace: Option<Ptrace>,
     pub tasks: Vec<Option<Arc<TaskControlBlock>>>,
     pub task_res_allocator: RecycleAllocator,
+    /// the thread whose exit takes the process with it, the forking thread
+    /// of a forked process keeps its tid
+    pub main_tid: usize,
     pub mutex_list: Vec<Option<Arc<dyn Mutex>>>,
     pub semaphore_list: Vec<Option<Arc<Semaphore>>>,
     pub condvar_list: Vec<Option<Arc<Condvar>>>,
+    pub rwlock_list: Vec<Option<Arc<RwLock>>>,
+    pub barrier_list: Vec<Option<Arc<Barrier>>>,
+    pub deadlock_detector: DeadlockDetector,
+    /// threads waiting for a child process or another thread to exit
+    pub wait_queue: VecDeque<Arc<TaskControlBlock>>,
+    /// threads blocked while the process is stopped by a signal or its tracer
+    pub stop_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+/// Types of auxiliary vector entries, besides those describing the ELF file.
+const AT_NULL: usize = 0;
+const AT_PAGESZ: usize = 6;
+const AT_UID: usize = 11;
+const AT_EUID: usize = 12;
+const AT_GID: usize = 13;
+const AT_EGID: usize = 14;
+const AT_HWCAP: usize = 16;
+const AT_CLKTCK: usize = 17;
+const AT_SECURE: usize = 23;
+const AT_RANDOM: usize = 25;
+/// The ISA extensions of rv64imafdc, bit n standing for letter 'a' + n.
+const HWCAP: usize = 1 << 0 | 1 << 2 | 1 << 3 | 1 << 5 | 1 << 8 | 1 << 12;
+/// Clock ticks per second as `times` counts them, fixed on Linux.
+const CLOCK_TICKS: usize = 100;
+
+/// Copy `bytes` to the user stack below `user_sp`, return where they start.
+fn push_bytes(memory_set: &mut MemorySet, user_sp: &mut usize, bytes: &[u8]) -> SysResult<usize> {
+    *user_sp -= bytes.len();
+    copy_to_user(memory_set, *user_sp, bytes)?;
+    Ok(*user_sp)
+}
+
+/// Push the arguments, the environment and the auxiliary vector of a new
+/// program to its stack below `user_sp` as on Linux, return the sp which
+/// points at argc. Fail with ENOMEM if there is no frame left for them.
+fn push_initial_stack(
+    memory_set: &mut MemorySet,
+    mut user_sp: usize,
+    args: &[String],
+    envs: &[String],
+    elf_data: &[u8],
+) -> SysResult<usize> {
+    // sys_execve has made sure that they fit in the stack, the strings go
+    // at the top
+    let mut push_str = |user_sp: &mut usize, string: &String| {
+        push_bytes(memory_set, user_sp, &[0])?;
+        push_bytes(memory_set, user_sp, string.as_bytes())
+    };
+    let arg_ptrs = args
+        .iter()
+        .map(|arg| push_str(&mut user_sp, arg))
+        .collect::<SysResult<Vec<usize>>>()?;
+    let env_ptrs = envs
+        .iter()
+        .map(|env| push_str(&mut user_sp, env))
+        .collect::<SysResult<Vec<usize>>>()?;
+    let random = push_bytes(memory_set, &mut user_sp, &random_bytes())?;
+    let mut auxv = MemorySet::elf_aux_entries(elf_data);
+    auxv.extend_from_slice(&[
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_CLKTCK, CLOCK_TICKS),
+        (AT_HWCAP, HWCAP),
+        (AT_UID, 0),
+        (AT_EUID, 0),
+        (AT_GID, 0),
+        (AT_EGID, 0),
+        (AT_SECURE, 0),
+        (AT_RANDOM, random),
+        (AT_NULL, 0),
+    ]);
+    // then argc, argv, envp and auxv, sp points at argc
+    let mut vector = vec![args.len()];
+    vector.extend(arg_ptrs);
+    vector.push(0);
+    vector.extend(env_ptrs);
+    vector.push(0);
+    for (key, value) in auxv {
+        vector.push(key);
+        vector.push(value);
+    }
+    let bytes: Vec<u8> = vector.iter().flat_map(|word| word.to_ne_bytes()).collect();
+    // the ABI wants sp aligned to 16B
+    user_sp = (user_sp - bytes.len()) & !0xf;
+    copy_to_user(memory_set, user_sp, &bytes)?;
+    Ok(user_sp)
+}
+
+/// The 16 bytes AT_RANDOM points to, which seed the stack protector of a
+/// program. They are only as unpredictable as the time it is started at.
+fn random_bytes() -> [u8; 16] {
+    let mut state = get_time() as u64;
+    let mut bytes = [0u8; 16];
+    for chunk in bytes.chunks_mut(8) {
+        // splitmix64
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        chunk.copy_from_slice(&(z ^ (z >> 31)).to_ne_bytes());
+    }
+    bytes
+}
+
+/// Return the lowest free fd, which stays free until the file is put there.
+pub fn alloc_fd(fd_table: &mut FdTable) -> usize {
+    if let Some(fd) = (0..fd_table.len()).find(|fd| fd_table[*fd].is_none()) {
+        fd
+    } else {
+        fd_table.push(None);
+        fd_table.len() - 1
+    }
 }
 
 impl ProcessControlBlockInner {
@@ -41,15 +219,6 @@ impl ProcessControlBlockInner {
         self.memory_set.token()
     }
 
-    pub fn alloc_fd(&mut self) -> usize {
-        if let Some(fd) = (0..self.fd_table.len()).find(|fd| self.fd_table[*fd].is_none()) {
-            fd
-        } else {
-            self.fd_table.push(None);
-            self.fd_table.len() - 1
-        }
-    }
-
     pub fn alloc_tid(&mut self) -> usize {
         self.task_res_allocator.alloc()
     }
@@ -58,70 +227,114 @@ impl ProcessControlBlockInner {
         self.task_res_allocator.dealloc(tid)
     }
 
-    pub fn thread_count(&self) -> usize {
-        self.tasks.len()
+    /// Remove an exited thread, its tid may be reused from now on. The tid
+    /// of the main thread is never reused, it stands for the process.
+    pub fn reap_task(&mut self, tid: usize) {
+        self.tasks[tid] = None;
+        if tid != self.main_tid {
+            self.dealloc_tid(tid);
+        }
     }
 
-    pub fn get_task(&self, tid: usize) -> Arc<TaskControlBlock> {
-        self.tasks[tid].as_ref().unwrap().clone()
+    /// The tids of the threads which have not exited yet.
+    pub fn live_tids(&self) -> Vec<usize> {
+        self.tasks
+            .iter()
+            .enumerate()
+            .filter(|(_, task)| {
+                task.as_ref().map_or(false, |task| {
+                    task.inner_exclusive_access().exit_code.is_none()
+                })
+            })
+            .map(|(tid, _)| tid)
+            .collect()
+    }
+
+    /// Wake up the threads blocked while the process was stopped, they check
+    /// again whether it still is.
+    pub fn wake_stopped(&mut self) {
+        for task in self.stop_queue.drain(..) {
+            add_task(task);
+        }
     }
 }
 
 impl ProcessControlBlock {
-    pub fn inner_exclusive_access(&self) -> RefMut<'_, ProcessControlBlockInner> {
-        self.inner.exclusive_access()
+    pub fn inner_exclusive_access(&self) -> SpinLockGuard<'_, ProcessControlBlockInner> {
+        self.inner.lock()
     }
 
     pub fn new(elf_data: &[u8]) -> Arc<Self> {
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, ustack_base, entry_point) =
+            MemorySet::from_elf(elf_data).expect("out of frames for initproc");
         // allocate a pid
         let pid_handle = pid_alloc();
+        let pid = pid_handle.0;
         let process = Arc::new(Self {
             pid: pid_handle,
-            inner: unsafe {
-                UPSafeCell::new(ProcessControlBlockInner {
-                    is_zombie: false,
-                    memory_set,
-                    parent: None,
-                    children: Vec::new(),
-                    exit_code: 0,
-                    fd_table: vec![
-                        // 0 -> stdin
-                        Some(Arc::new(Stdin)),
-                        // 1 -> stdout
-                        Some(Arc::new(Stdout)),
-                        // 2 -> stderr
-                        Some(Arc::new(Stdout)),
-                    ],
-                    signals: SignalFlags::empty(),
-                    tasks: Vec::new(),
-                    task_res_allocator: RecycleAllocator::new(),
-                    mutex_list: Vec::new(),
-                    semaphore_list: Vec::new(),
-                    condvar_list: Vec::new(),
-                })
-            },
+            inner: SpinLock::new(ProcessControlBlockInner {
+                is_zombie: false,
+                memory_set,
+                parent: None,
+                children: Vec::new(),
+                exit_code: 0,
+                fd_table: Arc::new(SpinLock::new(vec![
+                    // 0 -> stdin
+                    Some(Arc::new(Stdin)),
+                    // 1 -> stdout
+                    Some(Arc::new(Stdout)),
+                    // 2 -> stderr
+                    Some(Arc::new(Stdout)),
+                ])),
+                signals: SignalFlags::empty(),
+                signal_actions: [SignalAction::default(); MAX_SIG + 1],
+                stopped: false,
+                wait_event: None,
+                // the leader of a session and a group of its own
+                pgid: pid,
+                sid: pid,
+                terminating: None,
+                killed_by: None,
+                trace: None,
+                ptrace: None,
+                tasks: Vec::new(),
+                task_res_allocator: RecycleAllocator::new(),
+                main_tid: 0,
+                mutex_list: Vec::new(),
+                semaphore_list: Vec::new(),
+                condvar_list: Vec::new(),
+                rwlock_list: Vec::new(),
+                barrier_list: Vec::new(),
+                deadlock_detector: DeadlockDetector::new(),
+                wait_queue: VecDeque::new(),
+                stop_queue: VecDeque::new(),
+            }),
         });
+        process
+            .inner_exclusive_access()
+            .memory_set
+            .set_owner(&process);
         // create a main thread, we should allocate ustack and trap_cx here
-        let task = Arc::new(TaskControlBlock::new(
-            Arc::clone(&process),
-            ustack_base,
-            true,
-        ));
+        let task = Arc::new(
+            TaskControlBlock::new(Arc::clone(&process), ustack_base, true)
+                .expect("out of frames for initproc"),
+        );
         // prepare trap_cx of main thread
         let task_inner = task.inner_exclusive_access();
         let trap_cx = task_inner.get_trap_cx();
         let ustack_top = task_inner.res.as_ref().unwrap().ustack_top();
+        let tls_base = task_inner.res.as_ref().unwrap().tls_base();
         let kstack_top = task.kstack.get_top();
         drop(task_inner);
         *trap_cx = TrapContext::app_init_context(
             entry_point,
             ustack_top,
-            KERNEL_SPACE.exclusive_access().token(),
+            KERNEL_SPACE.lock().token(),
             kstack_top,
             trap_handler as usize,
         );
+        trap_cx.set_tp(tls_base);
         // add main thread to the process
         let mut process_inner = process.inner_exclusive_access();
         process_inner.tasks.push(Some(Arc::clone(&task)));
@@ -132,125 +345,279 @@ impl ProcessControlBlock {
         process
     }
 
-    /// Only support processes with a single thread.
-    pub fn exec(self: &Arc<Self>, elf_data: &[u8], args: Vec<String>) {
-        assert_eq!(self.inner_exclusive_access().thread_count(), 1);
-        // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
-        let new_token = memory_set.token();
+    /// Replace the program of the process. Only the calling thread goes on,
+    /// with the same tid, and finds the arguments, the environment and the
+    /// auxiliary vector on its stack. Fail with EINTR if it has been killed
+    /// meanwhile, and with ENOMEM if there are not enough frames for the
+    /// program, which is kept then.
+    pub fn exec(
+        self: &Arc<Self>,
+        elf_data: &[u8],
+        args: Vec<String>,
+        envs: Vec<String>,
+    ) -> SysResult<()> {
+        let task = current_task().unwrap();
+        if !self.exit_other_threads(&task) {
+            return Err(SysError::EINTR);
+        }
+        // memory_set with elf program headers/trampoline/trap context/user stack,
+        // no PCB is borrowed so that pages can be swapped out for it
+        let (mut memory_set, ustack_base, entry_point) = match MemorySet::from_elf(elf_data) {
+            Some(built) => built,
+            None if reclaim_frames(MemorySet::elf_frames(elf_data)) => {
+                MemorySet::from_elf(elf_data).ok_or(SysError::ENOMEM)?
+            }
+            None => return Err(SysError::ENOMEM),
+        };
+        // then the user resource of the thread, with the same tid, so that
+        // the old program is kept if there is no frame for it
+        let (tid, trap_cx_user_va) = {
+            let task_inner = task.inner_exclusive_access();
+            let res = task_inner.res.as_ref().unwrap();
+            (res.tid, VirtAddr::from(res.trap_cx_user_va()))
+        };
+        // and the stack of the new program, with its arguments, environment
+        // and auxiliary vector, which take a page more than their strings at
+        // most
+        let arg_bytes: usize = args
+            .iter()
+            .chain(envs.iter())
+            .map(|string| string.len() + 1)
+            .sum();
+        reclaim_frames(USER_TLS_SIZE / PAGE_SIZE + arg_bytes / PAGE_SIZE + 2);
+        TaskUserRes::map_user_res(&mut memory_set, ustack_base, tid)?;
+        let trap_cx_ppn = memory_set.translate(trap_cx_user_va.into()).unwrap().ppn();
+        let ustack_top = ustack_bottom_from_tid(ustack_base, tid) + USER_STACK_SIZE;
+        let user_sp = push_initial_stack(&mut memory_set, ustack_top, &args, &envs, elf_data)?;
         // substitute memory_set
-        self.inner_exclusive_access().memory_set = memory_set;
-        // then we alloc user resource for main thread again
-        // since memory_set has been changed
-        let task = self.inner_exclusive_access().get_task(0);
-        let mut task_inner = task.inner_exclusive_access();
-        task_inner.res.as_mut().unwrap().ustack_base = ustack_base;
-        task_inner.res.as_mut().unwrap().alloc_user_res();
-        task_inner.trap_cx_ppn = task_inner.res.as_mut().unwrap().trap_cx_ppn();
-        // push arguments on user stack
-        let mut user_sp = task_inner.res.as_mut().unwrap().ustack_top();
-        user_sp -= (args.len() + 1) * core::mem::size_of::<usize>();
-        let argv_base = user_sp;
-        let mut argv: Vec<_> = (0..=args.len())
-            .map(|arg| {
-                translated_refmut(
-                    new_token,
-                    (argv_base + arg * core::mem::size_of::<usize>()) as *mut usize,
-                )
-            })
-            .collect();
-        *argv[args.len()] = 0;
-        for i in 0..args.len() {
-            user_sp -= args[i].len() + 1;
-            *argv[i] = user_sp;
-            let mut p = user_sp;
-            for c in args[i].as_bytes() {
-                *translated_refmut(new_token, p as *mut u8) = *c;
-                p += 1;
+        let mut inner = self.inner_exclusive_access();
+        inner.memory_set = memory_set;
+        inner.memory_set.set_owner(self);
+        if let Some(ptrace) = inner.ptrace.as_mut() {
+            ptrace.clear_step_breakpoints();
+        }
+        // handlers are gone with the old program, ignored signals stay ignored,
+        // and files are not shared with other processes any longer
+        for action in inner.signal_actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SignalAction::default();
             }
-            *translated_refmut(new_token, p as *mut u8) = 0;
         }
-        // make the user_sp aligned to 8B for k210 platform
-        user_sp -= user_sp % core::mem::size_of::<usize>();
+        let fd_table = inner.fd_table.lock().clone();
+        inner.fd_table = Arc::new(SpinLock::new(fd_table));
+        drop(inner);
+        // the user resource has been mapped already, it only moves
+        let mut task_inner = task.inner_exclusive_access();
+        let res = task_inner.res.as_mut().unwrap();
+        res.ustack_base = ustack_base;
+        let tls_base = res.tls_base();
+        task_inner.trap_cx_ppn = trap_cx_ppn;
+        // the word belonged to the old program
+        task_inner.clear_child_tid = 0;
+        drop(task_inner);
         // initialize trap_cx
         let mut trap_cx = TrapContext::app_init_context(
             entry_point,
             user_sp,
-            KERNEL_SPACE.exclusive_access().token(),
+            KERNEL_SPACE.lock().token(),
             task.kstack.get_top(),
             trap_handler as usize,
         );
+        trap_cx.set_tp(tls_base);
+        // for programs which take them as arguments of their entry point
         trap_cx.x[10] = args.len();
-        trap_cx.x[11] = argv_base;
-        *task_inner.get_trap_cx() = trap_cx;
+        trap_cx.x[11] = user_sp + core::mem::size_of::<usize>();
+        *task.inner_exclusive_access().get_trap_cx() = trap_cx;
+        // the tracer gets to see the new program before it starts
+        if self.inner_exclusive_access().ptrace.is_some() {
+            send_signal(self, SignalFlags::SIGTRAP);
+        }
+        Ok(())
+    }
+
+    /// Make every thread but `task` exit, and reclaim their user res. Those
+    /// blocked in the kernel are interrupted, and waited for until they have
+    /// exited. Return false if `task` is killed meanwhile.
+    fn exit_other_threads(self: &Arc<Self>, task: &Arc<TaskControlBlock>) -> bool {
+        loop {
+            let mut inner = self.inner_exclusive_access();
+            if inner.terminating.is_some() || task.inner_exclusive_access().killed {
+                return false;
+            }
+            // threads may still be created, or exec called by another one
+            let mut others = Vec::new();
+            for other in inner.tasks.iter().flatten() {
+                if Arc::ptr_eq(other, task) {
+                    continue;
+                }
+                let mut other_inner = other.inner_exclusive_access();
+                if other_inner.exit_code.is_none() {
+                    other_inner.killed = true;
+                    others.push(Arc::clone(other));
+                }
+            }
+            if others.is_empty() {
+                break;
+            }
+            // each of them wakes us up as it exits
+            inner.wait_queue.push_back(Arc::clone(task));
+            drop(inner);
+            for other in others.iter() {
+                interrupt_task(other);
+            }
+            let process = Arc::clone(self);
+            block_current_killable(move |task, waiting| {
+                let mut inner = process.inner_exclusive_access();
+                remove_waiter(&mut inner.wait_queue, task, waiting)
+            });
+        }
+        let mut inner = self.inner_exclusive_access();
+        // the exited ones can't be waited for any longer
+        let mut recycle_res = Vec::new();
+        for slot in inner.tasks.iter_mut() {
+            if matches!(slot, Some(other) if !Arc::ptr_eq(other, task)) {
+                let other = slot.take().unwrap();
+                recycle_res.extend(other.inner_exclusive_access().res.take());
+            }
+        }
+        let tid = task.inner_exclusive_access().res.as_ref().unwrap().tid;
+        inner.task_res_allocator = RecycleAllocator::with_allocated(tid);
+        inner.main_tid = tid;
+        // deallocating them needs the PCB, and the old memory_set which
+        // they are part of
+        drop(inner);
+        recycle_res.clear();
+        true
     }
 
-    /// Only support processes with a single thread.
-    pub fn fork(self: &Arc<Self>) -> Arc<Self> {
+    /// Only the calling thread is duplicated, with its tid, stack and trap
+    /// context in the copied address space. The child shares the open files
+    /// with CLONE_FILES and gets a copy of them otherwise, the signal handlers
+    /// are always copied. The thread of the child is returned, for the
+    /// caller to add it to the scheduler.
+    pub fn fork(self: &Arc<Self>, flags: CloneFlags) -> Arc<TaskControlBlock> {
+        let task = current_task().unwrap();
         let mut parent = self.inner_exclusive_access();
-        assert_eq!(parent.thread_count(), 1);
-        // clone parent's memory_set completely including trampoline/ustacks/trap_cxs
-        let memory_set = MemorySet::from_existed_user(&parent.memory_set);
+        // share parent's memory_set copy-on-write, trap_cxs are still copied
+        let memory_set = MemorySet::from_existed_user(&mut parent.memory_set);
         // alloc a pid
         let pid = pid_alloc();
         // copy fd table
-        let mut new_fd_table: Vec<Option<Arc<dyn File + Send + Sync>>> = Vec::new();
-        for fd in parent.fd_table.iter() {
-            if let Some(file) = fd {
-                new_fd_table.push(Some(file.clone()));
-            } else {
-                new_fd_table.push(None);
-            }
-        }
+        let fd_table = if flags.contains(CloneFlags::FILES) {
+            Arc::clone(&parent.fd_table)
+        } else {
+            Arc::new(SpinLock::new(parent.fd_table.lock().clone()))
+        };
+        let task_inner = task.inner_exclusive_access();
+        let res = task_inner.res.as_ref().unwrap();
+        let (tid, ustack_base) = (res.tid, res.ustack_base());
+        // the new thread inherits the mask
+        let signal_mask = task_inner.signal_mask;
+        drop(task_inner);
+        // tids of the other threads, whose user res are not part of the child
+        let other_tids: Vec<usize> = parent
+            .tasks
+            .iter()
+            .flatten()
+            .filter_map(|other| {
+                other
+                    .inner_exclusive_access()
+                    .res
+                    .as_ref()
+                    .map(|res| res.tid)
+            })
+            .filter(|other_tid| *other_tid != tid)
+            .collect();
         // create child process pcb
         let child = Arc::new(Self {
             pid,
-            inner: unsafe {
-                UPSafeCell::new(ProcessControlBlockInner {
-                    is_zombie: false,
-                    memory_set,
-                    parent: Some(Arc::downgrade(self)),
-                    children: Vec::new(),
-                    exit_code: 0,
-                    fd_table: new_fd_table,
-                    signals: SignalFlags::empty(),
-                    tasks: Vec::new(),
-                    task_res_allocator: RecycleAllocator::new(),
-                    mutex_list: Vec::new(),
-                    semaphore_list: Vec::new(),
-                    condvar_list: Vec::new(),
-                })
-            },
+            inner: SpinLock::new(ProcessControlBlockInner {
+                is_zombie: false,
+                memory_set,
+                parent: Some(Arc::downgrade(self)),
+                children: Vec::new(),
+                exit_code: 0,
+                fd_table,
+                signals: SignalFlags::empty(),
+                signal_actions: parent.signal_actions,
+                stopped: false,
+                wait_event: None,
+                pgid: parent.pgid,
+                sid: parent.sid,
+                terminating: None,
+                killed_by: None,
+                trace: None,
+                ptrace: None,
+                tasks: vec![None; tid],
+                task_res_allocator: RecycleAllocator::with_allocated(tid),
+                main_tid: tid,
+                mutex_list: Vec::new(),
+                semaphore_list: Vec::new(),
+                condvar_list: Vec::new(),
+                rwlock_list: Vec::new(),
+                barrier_list: Vec::new(),
+                deadlock_detector: parent.deadlock_detector.fork(),
+                wait_queue: VecDeque::new(),
+                stop_queue: VecDeque::new(),
+            }),
         });
         // add child
         parent.children.push(Arc::clone(&child));
-        // create main thread of child process
-        let task = Arc::new(TaskControlBlock::new(
-            Arc::clone(&child),
-            parent
-                .get_task(0)
-                .inner_exclusive_access()
-                .res
-                .as_ref()
-                .unwrap()
-                .ustack_base(),
-            // here we do not allocate trap_cx or ustack again
-            // but mention that we allocate a new kstack here
-            false,
-        ));
+        drop(parent);
+        // unmap the stacks and trap contexts of the other threads, which
+        // needs the child PCB
+        for other_tid in other_tids {
+            drop(TaskUserRes::forked(&child, ustack_base, other_tid));
+        }
+        // here we do not allocate trap_cx or ustack again
+        // but mention that we allocate a new kstack here
+        let task = Arc::new(TaskControlBlock::forked(&child, ustack_base, tid));
         // attach task to child process
         let mut child_inner = child.inner_exclusive_access();
         child_inner.tasks.push(Some(Arc::clone(&task)));
+        child_inner.memory_set.set_owner(&child);
         drop(child_inner);
         // modify kstack_top in trap_cx of this thread
-        let task_inner = task.inner_exclusive_access();
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.signal_mask = signal_mask;
         let trap_cx = task_inner.get_trap_cx();
         trap_cx.kernel_sp = task.kstack.get_top();
+        // we do not have to move to next instruction since we have done it
+        // before, for child process fork returns 0, which has to be set
+        // before another hart may run it
+        trap_cx.x[10] = 0;
         drop(task_inner);
         insert_into_pid2process(child.getpid(), Arc::clone(&child));
-        // add this thread to scheduler
-        add_task(task);
-        child
+        task
+    }
+
+    /// Block the current thread, which has just joined the wait queue,
+    /// until `wakeup_waiters` or an interruption.
+    pub fn wait_for_waiters(self: &Arc<Self>) -> WaitResult {
+        let process = Arc::clone(self);
+        block_current(None, move |task, waiting| {
+            let mut inner = process.inner_exclusive_access();
+            remove_waiter(&mut inner.wait_queue, task, waiting)
+        })
+    }
+
+    /// Block the current thread, which has just joined the stop queue, until
+    /// `wake_stopped` or it is killed.
+    pub fn wait_stopped(self: &Arc<Self>) {
+        let process = Arc::clone(self);
+        block_current_killable(move |task, waiting| {
+            let mut inner = process.inner_exclusive_access();
+            remove_waiter(&mut inner.stop_queue, task, waiting)
+        });
+    }
+
+    /// Wake up the threads waiting for a child process or a thread to exit,
+    /// they check again whether the one they wait for has exited.
+    pub fn wakeup_waiters(&self) {
+        let waiters = core::mem::take(&mut self.inner_exclusive_access().wait_queue);
+        for task in waiters {
+            add_task(task);
+        }
     }
 
     pub fn getpid(&self) -> usize {
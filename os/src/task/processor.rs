@@ -1,9 +1,16 @@
 use super::__switch;
 use super::{fetch_task, TaskStatus};
 use super::{ProcessControlBlock, TaskContext, TaskControlBlock};
+use crate::config::MAX_HARTS;
+use crate::hart::hart_id;
 use crate::sync::UPSafeCell;
+use crate::timer::check_timer;
 use crate::trap::TrapContext;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cell::RefMut;
+use core::hint::spin_loop;
+use core::sync::atomic::Ordering;
 use lazy_static::*;
 
 pub struct Processor {
@@ -30,38 +37,58 @@ impl Processor {
 }
 
 lazy_static! {
-    pub static ref PROCESSOR: UPSafeCell<Processor> = unsafe { UPSafeCell::new(Processor::new()) };
+    /// One processor for each hart, which is only accessed by that hart.
+    static ref PROCESSORS: Vec<UPSafeCell<Processor>> = (0..MAX_HARTS)
+        .map(|_| unsafe { UPSafeCell::new(Processor::new()) })
+        .collect();
+}
+
+fn current_processor() -> RefMut<'static, Processor> {
+    PROCESSORS[hart_id()].exclusive_access()
 }
 
 pub fn run_tasks() {
     loop {
-        let mut processor = PROCESSOR.exclusive_access();
         if let Some(task) = fetch_task() {
+            // a task woken up by another hart may still be switching away
+            // from its last hart, wait until its context has been saved
+            while task.on_cpu.load(Ordering::Acquire) {
+                spin_loop();
+            }
+            task.on_cpu.store(true, Ordering::Relaxed);
+            let mut processor = current_processor();
             let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
             // access coming task TCB exclusively
             let mut task_inner = task.inner_exclusive_access();
             let next_task_cx_ptr = &task_inner.task_cx as *const TaskContext;
             task_inner.task_status = TaskStatus::Running;
+            task_inner.sched.on_dispatch();
             drop(task_inner);
             // release coming task TCB manually
-            processor.current = Some(task);
+            processor.current = Some(Arc::clone(&task));
             // release processor manually
             drop(processor);
             unsafe {
                 __switch(idle_task_cx_ptr, next_task_cx_ptr);
             }
+            // the task has left this hart, the last reference of an exited
+            // task is dropped here since its kernel stack was in use so far
+            task.on_cpu.store(false, Ordering::Release);
         } else {
-            println!("no tasks available in run_tasks");
+            // timer interrupts are not taken in the kernel, so idle harts
+            // wake up sleeping tasks themselves
+            check_timer();
+            spin_loop();
         }
     }
 }
 
 pub fn take_current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.exclusive_access().take_current()
+    current_processor().take_current()
 }
 
 pub fn current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.exclusive_access().current()
+    current_processor().current()
 }
 
 pub fn current_process() -> Arc<ProcessControlBlock> {
@@ -95,7 +122,7 @@ pub fn current_kstack_top() -> usize {
 }
 
 pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
-    let mut processor = PROCESSOR.exclusive_access();
+    let mut processor = current_processor();
     let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
     drop(processor);
     unsafe {
@@ -0,0 +1,284 @@
+//! Tracing of a process by its parent for debuggers, see `sys_ptrace`. The
+//! tracee stops at the signals it gets, at exec and, if the tracer asks for
+//! it, at syscalls or after an instruction. Stops are reported to the tracer
+//! by `sys_waitpid` as stops by a signal, SIGTRAP for those which aren't
+//! caused by one.
+
+use super::signal::notify_parent;
+use super::{current_task, ProcessControlBlock, ProcessControlBlockInner, SignalFlags, WaitEvent};
+use crate::mm::{copy_from_user, MemorySet};
+use crate::trap::TrapContext;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+/// `c.ebreak`, which fits in place of any instruction.
+const C_EBREAK: [u8; 2] = [0x02, 0x90];
+/// `ebreak`, which user code may have as well.
+const EBREAK: [u8; 4] = [0x73, 0x00, 0x10, 0x00];
+
+/// How a traced process goes on once it is resumed.
+#[derive(Copy, Clone, PartialEq)]
+pub enum PtraceResume {
+    /// until it gets a signal
+    Continue,
+    /// until the next syscall entry or exit as well
+    Syscall,
+    /// for one instruction
+    Step,
+}
+
+pub struct Ptrace {
+    /// stopped until the tracer resumes the process
+    pub stopped: bool,
+    /// the thread which has stopped, whose registers the tracer accesses
+    pub tid: usize,
+    pub resume: PtraceResume,
+    /// a signal the tracer has let through, taken without stopping again
+    pub signal: Option<usize>,
+    /// the breakpoints put where a stepped instruction may go next, with the
+    /// bytes they replaced
+    step_breakpoints: Vec<(usize, [u8; 2])>,
+}
+
+impl Ptrace {
+    pub fn new() -> Self {
+        Self {
+            stopped: false,
+            tid: 0,
+            resume: PtraceResume::Continue,
+            signal: None,
+            step_breakpoints: Vec::new(),
+        }
+    }
+
+    /// Step the instruction at the pc of `trap_cx`, the process stops at the
+    /// breakpoints put after it. Return false if the instruction can't be
+    /// read or a breakpoint can't be put.
+    pub fn insert_step_breakpoints(
+        &mut self,
+        memory_set: &mut MemorySet,
+        trap_cx: &TrapContext,
+    ) -> bool {
+        let next_pcs = match next_pcs(memory_set, trap_cx) {
+            Some(next_pcs) => next_pcs,
+            None => return false,
+        };
+        for pc in next_pcs.iter().flatten() {
+            let mut saved = [0u8; 2];
+            if copy_from_user(memory_set, *pc, &mut saved).is_err()
+                || !memory_set.write_forced(*pc, &C_EBREAK)
+            {
+                self.remove_step_breakpoints(memory_set);
+                return false;
+            }
+            self.step_breakpoints.push((*pc, saved));
+        }
+        true
+    }
+
+    /// Put back what the step breakpoints replaced, the last ones first in
+    /// case they are at the same place.
+    pub fn remove_step_breakpoints(&mut self, memory_set: &mut MemorySet) {
+        while let Some((pc, saved)) = self.step_breakpoints.pop() {
+            // the code may have been unmapped meanwhile
+            memory_set.write_forced(pc, &saved);
+        }
+    }
+
+    /// Forget the step breakpoints of a program which has been replaced.
+    pub fn clear_step_breakpoints(&mut self) {
+        self.step_breakpoints.clear();
+    }
+
+    /// Whether thread `tid` is to stay stopped: the process is stopped, or
+    /// another thread is stepped, whose breakpoints the code shared with it
+    /// has.
+    pub fn holds(&self, tid: usize) -> bool {
+        self.stopped || (self.resume == PtraceResume::Step && self.tid != tid)
+    }
+}
+
+/// Bits `hi` to `lo` of `inst`, shifted to bit `to`.
+fn bits(inst: u32, hi: u32, lo: u32, to: u32) -> u32 {
+    ((inst >> lo) & ((1 << (hi - lo + 1)) - 1)) << to
+}
+
+/// `value` as a signed number of `width` bits.
+fn sign_extend(value: u32, width: u32) -> usize {
+    (((value << (32 - width)) as i32) >> (32 - width)) as isize as usize
+}
+
+/// Where the instruction at the pc of `trap_cx` may go: after itself, and
+/// to the target of a jump or a taken branch. Return None if it can't be
+/// read.
+fn next_pcs(memory_set: &mut MemorySet, trap_cx: &TrapContext) -> Option<[Option<usize>; 2]> {
+    let pc = trap_cx.sepc;
+    let mut half = [0u8; 2];
+    copy_from_user(memory_set, pc, &mut half).ok()?;
+    let low = u16::from_le_bytes(half) as u32;
+    let reg = |index: u32| trap_cx.x[index as usize];
+    if low & 0b11 != 0b11 {
+        // compressed
+        let (quadrant, funct3) = (low & 0b11, low >> 13);
+        let rs1 = bits(low, 11, 7, 0);
+        let next = pc + 2;
+        let targets = match (quadrant, funct3) {
+            // c.j
+            (1, 0b101) => {
+                let imm = bits(low, 12, 12, 11)
+                    | bits(low, 11, 11, 4)
+                    | bits(low, 10, 9, 8)
+                    | bits(low, 8, 8, 10)
+                    | bits(low, 7, 7, 6)
+                    | bits(low, 6, 6, 7)
+                    | bits(low, 5, 3, 1)
+                    | bits(low, 2, 2, 5);
+                [Some(pc.wrapping_add(sign_extend(imm, 12))), None]
+            }
+            // c.beqz and c.bnez
+            (1, 0b110) | (1, 0b111) => {
+                let imm = bits(low, 12, 12, 8)
+                    | bits(low, 11, 10, 3)
+                    | bits(low, 6, 5, 6)
+                    | bits(low, 4, 3, 1)
+                    | bits(low, 2, 2, 5);
+                [Some(next), Some(pc.wrapping_add(sign_extend(imm, 9)))]
+            }
+            // c.jr and c.jalr
+            (2, 0b100) if bits(low, 6, 2, 0) == 0 && rs1 != 0 => [Some(reg(rs1)), None],
+            _ => [Some(next), None],
+        };
+        return Some(targets);
+    }
+    let mut word = [0u8; 4];
+    copy_from_user(memory_set, pc, &mut word).ok()?;
+    let inst = u32::from_le_bytes(word);
+    let next = pc + 4;
+    let targets = match inst & 0x7f {
+        // jal
+        0x6f => {
+            let imm = bits(inst, 31, 31, 20)
+                | bits(inst, 30, 21, 1)
+                | bits(inst, 20, 20, 11)
+                | bits(inst, 19, 12, 12);
+            [Some(pc.wrapping_add(sign_extend(imm, 21))), None]
+        }
+        // jalr
+        0x67 => {
+            let rs1 = bits(inst, 19, 15, 0);
+            let target = reg(rs1).wrapping_add(sign_extend(bits(inst, 31, 20, 0), 12));
+            [Some(target & !1), None]
+        }
+        // branches
+        0x63 => {
+            let imm = bits(inst, 31, 31, 12)
+                | bits(inst, 30, 25, 5)
+                | bits(inst, 11, 8, 1)
+                | bits(inst, 7, 7, 11);
+            [Some(next), Some(pc.wrapping_add(sign_extend(imm, 13)))]
+        }
+        _ => [Some(next), None],
+    };
+    Some(targets)
+}
+
+/// Stop the traced process at an event of thread `tid`, reported as a stop
+/// by signal `signum`. Return the tracer, to be told once the PCB is
+/// released.
+pub fn ptrace_stop(
+    process_inner: &mut ProcessControlBlockInner,
+    tid: usize,
+    signum: usize,
+) -> Option<Arc<ProcessControlBlock>> {
+    let ptrace = process_inner.ptrace.as_mut().unwrap();
+    ptrace.stopped = true;
+    ptrace.tid = tid;
+    // the tracer sees the code as it is
+    ptrace.remove_step_breakpoints(&mut process_inner.memory_set);
+    process_inner.wait_event = Some(WaitEvent::Stopped(signum));
+    process_inner.parent.as_ref().and_then(Weak::upgrade)
+}
+
+/// Stop tracing the process, when the tracer detaches or exits. A stopped
+/// process goes on as if it had never been traced.
+pub fn ptrace_detach(process_inner: &mut ProcessControlBlockInner) {
+    if let Some(mut ptrace) = process_inner.ptrace.take() {
+        ptrace.remove_step_breakpoints(&mut process_inner.memory_set);
+        process_inner.wake_stopped();
+    }
+}
+
+/// Let the other threads go on if thread `tid`, which exits, is stepped.
+pub fn ptrace_thread_exit(process_inner: &mut ProcessControlBlockInner, tid: usize) {
+    let ptrace = match process_inner.ptrace.as_mut() {
+        Some(ptrace) if ptrace.resume == PtraceResume::Step && ptrace.tid == tid => ptrace,
+        _ => return,
+    };
+    ptrace.resume = PtraceResume::Continue;
+    ptrace.remove_step_breakpoints(&mut process_inner.memory_set);
+    process_inner.wake_stopped();
+}
+
+/// Whether the ebreak at `pc` the current thread has trapped at raises
+/// SIGTRAP. A step breakpoint does only for the stepped thread, the others
+/// are held until it is gone and then run the instruction it replaced, as
+/// does a thread whose breakpoint the tracer has put back meanwhile.
+pub fn ptrace_breakpoint_trap(pc: usize) -> bool {
+    let task = current_task().unwrap();
+    let process = task.process.upgrade().unwrap();
+    let mut process_inner = process.inner_exclusive_access();
+    let tid = task.inner_exclusive_access().res.as_ref().unwrap().tid;
+    if let Some(ptrace) = process_inner.ptrace.as_ref() {
+        let step_breakpoint = ptrace.step_breakpoints.iter().any(|(at, _)| *at == pc);
+        if step_breakpoint && ptrace.tid != tid {
+            return false;
+        }
+    }
+    let mut inst = [0u8; 4];
+    // an ebreak which can't be read any longer faults again
+    if copy_from_user(&mut process_inner.memory_set, pc, &mut inst[..2]).is_err() {
+        return true;
+    }
+    if inst[..2] == C_EBREAK {
+        return true;
+    }
+    copy_from_user(&mut process_inner.memory_set, pc, &mut inst).map_or(true, |_| inst == EBREAK)
+}
+
+/// Stop at the entry or exit of a syscall of the current thread if the
+/// tracer has asked for it with PTRACE_SYSCALL, and wait until the tracer
+/// resumes the process. The tracer may change the syscall, its arguments
+/// and its result meanwhile.
+pub fn ptrace_syscall_stop() {
+    let task = current_task().unwrap();
+    let process = task.process.upgrade().unwrap();
+    let mut process_inner = process.inner_exclusive_access();
+    let tid = task.inner_exclusive_access().res.as_ref().unwrap().tid;
+    match process_inner.ptrace.as_mut() {
+        Some(ptrace) if ptrace.resume == PtraceResume::Syscall && !ptrace.stopped => {
+            ptrace.resume = PtraceResume::Continue;
+        }
+        _ => return,
+    }
+    if process_inner.terminating.is_some() {
+        return;
+    }
+    let signum = SignalFlags::SIGTRAP.first_signum().unwrap();
+    let tracer = ptrace_stop(&mut process_inner, tid, signum);
+    drop(process_inner);
+    notify_parent(tracer);
+    // until the tracer resumes it, detaches or kills it
+    loop {
+        let mut process_inner = process.inner_exclusive_access();
+        let held = process_inner
+            .ptrace
+            .as_ref()
+            .map_or(false, |ptrace| ptrace.holds(tid));
+        if !held || process_inner.terminating.is_some() || task.inner_exclusive_access().killed {
+            return;
+        }
+        process_inner.stop_queue.push_back(Arc::clone(&task));
+        drop(process_inner);
+        process.wait_stopped();
+    }
+}
@@ -0,0 +1,57 @@
+use super::{Scheduler, TaskControlBlock};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Every task holds as many tickets as its priority and a random ticket
+/// picks the next task to run.
+pub struct LotteryScheduler {
+    ready_queue: Vec<Arc<TaskControlBlock>>,
+    /// state of a xorshift generator, fixed so that runs are reproducible
+    seed: u64,
+}
+
+impl LotteryScheduler {
+    fn rand(&mut self) -> usize {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed as usize
+    }
+}
+
+impl Scheduler for LotteryScheduler {
+    const NAME: &'static str = "lottery";
+    fn new() -> Self {
+        Self {
+            ready_queue: Vec::new(),
+            seed: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.ready_queue.push(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let tickets: Vec<usize> = self
+            .ready_queue
+            .iter()
+            .map(|task| task.inner_exclusive_access().sched.priority)
+            .collect();
+        let total: usize = tickets.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let mut winner = self.rand() % total;
+        let idx = tickets
+            .iter()
+            .position(|count| {
+                if winner < *count {
+                    true
+                } else {
+                    winner -= count;
+                    false
+                }
+            })
+            .unwrap();
+        Some(self.ready_queue.remove(idx))
+    }
+}
@@ -0,0 +1,54 @@
+use super::{Scheduler, TaskControlBlock};
+use crate::timer::get_time_ms;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+const LEVELS: usize = 3;
+/// All tasks go back to the highest level this often, so that none starves.
+const BOOST_INTERVAL_MS: usize = 1000;
+
+/// Multilevel feedback queue: a task using up its time slice moves one level
+/// down, tasks giving up the CPU earlier keep their level, and higher levels
+/// always run first.
+pub struct MlfqScheduler {
+    queues: [VecDeque<Arc<TaskControlBlock>>; LEVELS],
+    last_boost_ms: usize,
+}
+
+impl MlfqScheduler {
+    fn boost(&mut self) {
+        for level in 1..LEVELS {
+            while let Some(task) = self.queues[level].pop_front() {
+                task.inner_exclusive_access().sched.level = 0;
+                self.queues[0].push_back(task);
+            }
+        }
+    }
+}
+
+impl Scheduler for MlfqScheduler {
+    const NAME: &'static str = "MLFQ";
+    fn new() -> Self {
+        Self {
+            queues: Default::default(),
+            last_boost_ms: 0,
+        }
+    }
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let mut task_inner = task.inner_exclusive_access();
+        if task_inner.sched.preempted && task_inner.sched.level + 1 < LEVELS {
+            task_inner.sched.level += 1;
+        }
+        let level = task_inner.sched.level;
+        drop(task_inner);
+        self.queues[level].push_back(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let current_ms = get_time_ms();
+        if current_ms - self.last_boost_ms >= BOOST_INTERVAL_MS {
+            self.boost();
+            self.last_boost_ms = current_ms;
+        }
+        self.queues.iter_mut().find_map(|queue| queue.pop_front())
+    }
+}
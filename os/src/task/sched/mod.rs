@@ -0,0 +1,191 @@
+mod lottery;
+mod mlfq;
+mod priority;
+mod rr;
+mod stride;
+
+use super::TaskControlBlock;
+use crate::timer::get_time_us;
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+
+#[allow(unused)]
+use lottery::LotteryScheduler;
+#[allow(unused)]
+use mlfq::MlfqScheduler;
+#[allow(unused)]
+use priority::PriorityScheduler;
+#[allow(unused)]
+use rr::RoundRobinScheduler;
+#[allow(unused)]
+use stride::StrideScheduler;
+
+pub const DEFAULT_PRIORITY: usize = 16;
+
+/// Scheduling statistics of a task, times are in microseconds.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct SchedStat {
+    pub priority: usize,
+    /// times the task has been picked to run
+    pub dispatches: usize,
+    /// times the task has used up its time slice
+    pub preemptions: usize,
+    /// time spent running
+    pub run_time: usize,
+    /// time spent in the ready queue
+    pub wait_time: usize,
+}
+
+/// Per-task state kept for the schedulers.
+pub struct SchedEntity {
+    /// effective priority, a larger one gets more CPU time
+    pub priority: usize,
+    /// priority set by the task itself, `priority` is raised above it while
+    /// a higher priority task waits for a mutex held by this one
+    base_priority: usize,
+    /// for each mutex held, the highest priority among its waiters, or 0
+    held_mutexes: BTreeMap<usize, usize>,
+    /// the mutex this task is blocked on and its owner
+    pub blocked_on: Option<(usize, Weak<TaskControlBlock>)>,
+    /// virtual time of stride scheduling
+    pub pass: usize,
+    /// queue level of MLFQ, 0 is the highest
+    pub level: usize,
+    /// the task used up its time slice the last time it ran
+    pub preempted: bool,
+    stat: SchedStat,
+    ready_since: usize,
+    running_since: usize,
+}
+
+impl SchedEntity {
+    pub fn new() -> Self {
+        Self {
+            priority: DEFAULT_PRIORITY,
+            base_priority: DEFAULT_PRIORITY,
+            held_mutexes: BTreeMap::new(),
+            blocked_on: None,
+            pass: 0,
+            level: 0,
+            preempted: false,
+            stat: SchedStat::default(),
+            ready_since: 0,
+            running_since: 0,
+        }
+    }
+    fn update_priority(&mut self) {
+        self.priority = self
+            .held_mutexes
+            .values()
+            .copied()
+            .fold(self.base_priority, usize::max);
+    }
+    pub fn set_base_priority(&mut self, priority: usize) {
+        self.base_priority = priority;
+        self.update_priority();
+    }
+    /// The task takes `mutex`, whose waiters have at most `inherited`
+    /// priority.
+    pub fn hold_mutex(&mut self, mutex: usize, inherited: usize) {
+        self.held_mutexes.insert(mutex, inherited);
+        self.update_priority();
+    }
+    /// The waiters of `mutex` now have at most `inherited` priority, after
+    /// one of them has given up waiting.
+    pub fn update_inherited(&mut self, mutex: usize, inherited: usize) {
+        if let Some(old) = self.held_mutexes.get_mut(&mutex) {
+            *old = inherited;
+            self.update_priority();
+        }
+    }
+    pub fn release_mutex(&mut self, mutex: usize) {
+        self.held_mutexes.remove(&mutex);
+        self.update_priority();
+    }
+    /// A waiter of `mutex` with `priority` lends it to this task. Return
+    /// whether the priority has been raised, which is not the case if the
+    /// task no longer holds the mutex.
+    pub fn inherit(&mut self, mutex: usize, priority: usize) -> bool {
+        match self.held_mutexes.get_mut(&mutex) {
+            Some(inherited) if *inherited < priority => *inherited = priority,
+            _ => return false,
+        }
+        let old_priority = self.priority;
+        self.update_priority();
+        self.priority > old_priority
+    }
+    /// The task is put into the ready queue.
+    pub fn on_ready(&mut self) {
+        self.ready_since = get_time_us();
+    }
+    /// The task is picked to run.
+    pub fn on_dispatch(&mut self) {
+        let now = get_time_us();
+        self.stat.dispatches += 1;
+        self.stat.wait_time += now - self.ready_since;
+        self.running_since = now;
+        self.preempted = false;
+    }
+    /// The task has used up its time slice.
+    pub fn on_preempt(&mut self) {
+        self.stat.preemptions += 1;
+        self.preempted = true;
+    }
+    /// The task stops running.
+    pub fn on_leave(&mut self) {
+        self.stat.run_time += get_time_us() - self.running_since;
+    }
+    /// Statistics of the running task, including its current time slice.
+    pub fn stat(&self) -> SchedStat {
+        SchedStat {
+            priority: self.priority,
+            run_time: self.stat.run_time + get_time_us() - self.running_since,
+            ..self.stat
+        }
+    }
+}
+
+/// Ready tasks are handed to a scheduler by `add` and taken back by `fetch`.
+/// A scheduler may inspect the `SchedEntity` of the tasks it holds, so no
+/// TCB may be borrowed by the callers.
+pub trait Scheduler {
+    const NAME: &'static str;
+    fn new() -> Self;
+    fn add(&mut self, task: Arc<TaskControlBlock>);
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>>;
+}
+
+#[cfg(any(
+    all(feature = "sched_rr", feature = "sched_stride"),
+    all(feature = "sched_rr", feature = "sched_prio"),
+    all(feature = "sched_rr", feature = "sched_mlfq"),
+    all(feature = "sched_rr", feature = "sched_lottery"),
+    all(feature = "sched_stride", feature = "sched_prio"),
+    all(feature = "sched_stride", feature = "sched_mlfq"),
+    all(feature = "sched_stride", feature = "sched_lottery"),
+    all(feature = "sched_prio", feature = "sched_mlfq"),
+    all(feature = "sched_prio", feature = "sched_lottery"),
+    all(feature = "sched_mlfq", feature = "sched_lottery"),
+))]
+compile_error!("at most one of the sched_* features can be enabled");
+
+#[cfg(feature = "sched_stride")]
+pub type SchedulerImpl = StrideScheduler;
+#[cfg(feature = "sched_prio")]
+pub type SchedulerImpl = PriorityScheduler;
+#[cfg(feature = "sched_mlfq")]
+pub type SchedulerImpl = MlfqScheduler;
+#[cfg(feature = "sched_lottery")]
+pub type SchedulerImpl = LotteryScheduler;
+#[cfg(not(any(
+    feature = "sched_stride",
+    feature = "sched_prio",
+    feature = "sched_mlfq",
+    feature = "sched_lottery"
+)))]
+pub type SchedulerImpl = RoundRobinScheduler;
+
+pub fn scheduler_name() -> &'static str {
+    SchedulerImpl::NAME
+}
@@ -0,0 +1,32 @@
+use super::{Scheduler, TaskControlBlock};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+/// The ready task with the highest effective priority runs, tasks of the
+/// same priority take turns. Mutex owners inherit the priorities of their
+/// waiters, so a low priority owner is not starved by medium priority tasks.
+pub struct PriorityScheduler {
+    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl Scheduler for PriorityScheduler {
+    const NAME: &'static str = "priority";
+    fn new() -> Self {
+        Self {
+            ready_queue: VecDeque::new(),
+        }
+    }
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.ready_queue.push_back(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let priority = |task: &Arc<TaskControlBlock>| task.inner_exclusive_access().sched.priority;
+        let highest = self.ready_queue.iter().map(priority).max()?;
+        let idx = self
+            .ready_queue
+            .iter()
+            .position(|task| priority(task) == highest)
+            .unwrap();
+        self.ready_queue.remove(idx)
+    }
+}
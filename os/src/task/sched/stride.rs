@@ -0,0 +1,41 @@
+use super::{Scheduler, TaskControlBlock};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+const BIG_STRIDE: usize = 0x10_0000;
+
+/// The task with the smallest pass runs, and its pass advances by
+/// `BIG_STRIDE / priority`, so CPU time is proportional to priority.
+pub struct StrideScheduler {
+    ready_queue: Vec<Arc<TaskControlBlock>>,
+    /// pass of the task picked last
+    min_pass: usize,
+}
+
+impl Scheduler for StrideScheduler {
+    const NAME: &'static str = "stride";
+    fn new() -> Self {
+        Self {
+            ready_queue: Vec::new(),
+            min_pass: 0,
+        }
+    }
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let mut task_inner = task.inner_exclusive_access();
+        // neither new tasks nor tasks waking up get credit for the time
+        // they were not ready
+        task_inner.sched.pass = task_inner.sched.pass.max(self.min_pass);
+        drop(task_inner);
+        self.ready_queue.push(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let idx = (0..self.ready_queue.len())
+            .min_by_key(|idx| self.ready_queue[*idx].inner_exclusive_access().sched.pass)?;
+        let task = self.ready_queue.remove(idx);
+        let mut task_inner = task.inner_exclusive_access();
+        self.min_pass = task_inner.sched.pass;
+        task_inner.sched.pass += BIG_STRIDE / task_inner.sched.priority;
+        drop(task_inner);
+        Some(task)
+    }
+}
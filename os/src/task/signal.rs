@@ -1,29 +1,476 @@
+use super::ptrace::ptrace_stop;
+use super::task::TaskControlBlockInner;
+use super::{
+    current_process, current_task, exit_current_and_run_next, interrupt_for_signal, process_group,
+    ProcessControlBlock, ProcessControlBlockInner, WaitEvent,
+};
+use crate::config::SIGRETURN_TRAMPOLINE;
+use crate::mm::UserPtr;
+use alloc::sync::{Arc, Weak};
 use bitflags::*;
+use core::mem::size_of;
+
+pub const MAX_SIG: usize = 31;
+
+/// `handler` of a `SignalAction` taking the default action.
+pub const SIG_DFL: usize = 0;
+/// `handler` of a `SignalAction` ignoring the signal.
+pub const SIG_IGN: usize = 1;
+
+/// `how` of `sys_rt_sigprocmask`.
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
 
 bitflags! {
-    pub struct SignalFlags: u32 {
-        const SIGINT    = 1 << 2;
-        const SIGILL    = 1 << 4;
-        const SIGABRT   = 1 << 6;
-        const SIGFPE    = 1 << 8;
-        const SIGSEGV   = 1 << 11;
+    /// Signal n is bit n - 1, as in the `sigset_t` of Linux.
+    pub struct SignalFlags: u64 {
+        const SIGHUP    = 1 << 0;
+        const SIGINT    = 1 << 1;
+        const SIGQUIT   = 1 << 2;
+        const SIGILL    = 1 << 3;
+        const SIGTRAP   = 1 << 4;
+        const SIGABRT   = 1 << 5;
+        const SIGBUS    = 1 << 6;
+        const SIGFPE    = 1 << 7;
+        const SIGKILL   = 1 << 8;
+        const SIGUSR1   = 1 << 9;
+        const SIGSEGV   = 1 << 10;
+        const SIGUSR2   = 1 << 11;
+        const SIGPIPE   = 1 << 12;
+        const SIGALRM   = 1 << 13;
+        const SIGTERM   = 1 << 14;
+        const SIGSTKFLT = 1 << 15;
+        const SIGCHLD   = 1 << 16;
+        const SIGCONT   = 1 << 17;
+        const SIGSTOP   = 1 << 18;
+        const SIGTSTP   = 1 << 19;
+        const SIGTTIN   = 1 << 20;
+        const SIGTTOU   = 1 << 21;
+        const SIGURG    = 1 << 22;
+        const SIGXCPU   = 1 << 23;
+        const SIGXFSZ   = 1 << 24;
+        const SIGVTALRM = 1 << 25;
+        const SIGPROF   = 1 << 26;
+        const SIGWINCH  = 1 << 27;
+        const SIGIO     = 1 << 28;
+        const SIGPWR    = 1 << 29;
+        const SIGSYS    = 1 << 30;
     }
 }
 
+/// Name and description of each signal, printed when it kills a process.
+const SIGNAL_INFO: [(&str, &str); MAX_SIG + 1] = [
+    ("", ""),
+    ("SIGHUP", "Hangup"),
+    ("SIGINT", "Killed"),
+    ("SIGQUIT", "Quit"),
+    ("SIGILL", "Illegal Instruction"),
+    ("SIGTRAP", "Trace/Breakpoint Trap"),
+    ("SIGABRT", "Aborted"),
+    ("SIGBUS", "Bus Error"),
+    ("SIGFPE", "Erroneous Arithmetic Operation"),
+    ("SIGKILL", "Killed"),
+    ("SIGUSR1", "User Defined Signal 1"),
+    ("SIGSEGV", "Segmentation Fault"),
+    ("SIGUSR2", "User Defined Signal 2"),
+    ("SIGPIPE", "Broken Pipe"),
+    ("SIGALRM", "Alarm Clock"),
+    ("SIGTERM", "Terminated"),
+    ("SIGSTKFLT", "Stack Fault"),
+    ("SIGCHLD", "Child Status Changed"),
+    ("SIGCONT", "Continued"),
+    ("SIGSTOP", "Stopped"),
+    ("SIGTSTP", "Stopped"),
+    ("SIGTTIN", "Stopped"),
+    ("SIGTTOU", "Stopped"),
+    ("SIGURG", "Urgent I/O Condition"),
+    ("SIGXCPU", "CPU Time Limit Exceeded"),
+    ("SIGXFSZ", "File Size Limit Exceeded"),
+    ("SIGVTALRM", "Virtual Timer Expired"),
+    ("SIGPROF", "Profiling Timer Expired"),
+    ("SIGWINCH", "Window Changed"),
+    ("SIGIO", "I/O Possible"),
+    ("SIGPWR", "Power Failure"),
+    ("SIGSYS", "Bad System Call"),
+];
+
+#[derive(Copy, Clone, PartialEq)]
+enum DefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+    Continue,
+}
+
 impl SignalFlags {
-    pub fn check_error(&self) -> Option<(i32, &'static str)> {
-        if self.contains(Self::SIGINT) {
-            Some((-2, "Killed, SIGINT=2"))
-        } else if self.contains(Self::SIGILL) {
-            Some((-4, "Illegal Instruction, SIGILL=4"))
-        } else if self.contains(Self::SIGABRT) {
-            Some((-6, "Aborted, SIGABRT=6"))
-        } else if self.contains(Self::SIGFPE) {
-            Some((-8, "Erroneous Arithmetic Operation, SIGFPE=8"))
-        } else if self.contains(Self::SIGSEGV) {
-            Some((-11, "Segmentation Fault, SIGSEGV=11"))
+    pub fn from_signum(signum: usize) -> Option<Self> {
+        if (1..=MAX_SIG).contains(&signum) {
+            Self::from_bits(1 << (signum - 1))
         } else {
             None
         }
     }
+
+    /// Signals which can't be caught, ignored or blocked.
+    pub fn unblockable() -> Self {
+        Self::SIGKILL | Self::SIGSTOP
+    }
+
+    fn stop_signals() -> Self {
+        Self::SIGSTOP | Self::SIGTSTP | Self::SIGTTIN | Self::SIGTTOU
+    }
+
+    /// The lowest signal in the set.
+    pub(super) fn first_signum(&self) -> Option<usize> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.bits().trailing_zeros() as usize + 1)
+        }
+    }
+
+    fn default_action(&self) -> DefaultAction {
+        if self.intersects(Self::SIGCHLD | Self::SIGURG | Self::SIGWINCH) {
+            DefaultAction::Ignore
+        } else if self.intersects(Self::stop_signals()) {
+            DefaultAction::Stop
+        } else if self.contains(Self::SIGCONT) {
+            DefaultAction::Continue
+        } else {
+            DefaultAction::Terminate
+        }
+    }
+}
+
+/// `flags` of a `SignalAction`: the handler is passed a `siginfo_t` in a1.
+pub const SA_SIGINFO: usize = 0x4;
+/// the signal isn't blocked while its handler runs
+pub const SA_NODEFER: usize = 0x4000_0000;
+/// the action goes back to the default once the handler is entered
+pub const SA_RESETHAND: usize = 0x8000_0000;
+
+/// `struct sigaction` of Linux on RISC-V, which has no restorer: handlers
+/// return to the sigreturn trampoline.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct SignalAction {
+    /// `SIG_DFL`, `SIG_IGN` or the address of a user handler
+    pub handler: usize,
+    /// `SA_*`, those not listed above are accepted and ignored
+    pub flags: usize,
+    /// signals blocked while the handler runs, besides the signal itself
+    pub mask: SignalFlags,
+}
+
+impl Default for SignalAction {
+    fn default() -> Self {
+        Self {
+            handler: SIG_DFL,
+            flags: 0,
+            mask: SignalFlags::empty(),
+        }
+    }
+}
+
+/// `siginfo_t` of Linux, of which only the signal number is filled in.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct SignalInfo {
+    signo: i32,
+    errno: i32,
+    code: i32,
+    _rest: [i32; 29],
+}
+
+/// Pushed on the user stack when a handler is entered and popped by
+/// `sys_sigreturn`. Only the user part of the trap context is saved, so that
+/// a handler can't change what the kernel relies on, such as `sstatus`. The
+/// `siginfo_t` comes first, a1 points to the frame with SA_SIGINFO.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct SignalFrame {
+    info: SignalInfo,
+    x: [usize; 32],
+    sepc: usize,
+    /// mask of the thread before the handler was entered
+    mask: SignalFlags,
+}
+
+/// Generate a signal for a process, it is taken by whichever of its threads
+/// doesn't block it first, a blocked thread is interrupted for it if the
+/// signal is caught or kills the process. Stop and continue signals discard
+/// each other, and SIGCONT resumes a stopped process even if it is caught or
+/// blocked.
+pub fn send_signal(process: &Arc<ProcessControlBlock>, signal: SignalFlags) {
+    let mut inner = process.inner_exclusive_access();
+    if signal.intersects(SignalFlags::stop_signals()) {
+        inner.signals.remove(SignalFlags::SIGCONT);
+    }
+    let continued = inner.stopped && signal.contains(SignalFlags::SIGCONT);
+    if signal.intersects(SignalFlags::SIGCONT | SignalFlags::SIGKILL) {
+        inner.signals.remove(SignalFlags::stop_signals());
+        if inner.stopped {
+            // a killed process is only reported when it exits
+            inner.wait_event = Some(WaitEvent::Continued).filter(|_| continued);
+        }
+        inner.stopped = false;
+        // they check again, those stopped by the tracer stay stopped
+        inner.wake_stopped();
+    }
+    if signal.contains(SignalFlags::SIGKILL) {
+        // only SIGKILL ends a stop of a traced process without the tracer
+        if let Some(ptrace) = inner.ptrace.as_mut() {
+            ptrace.stopped = false;
+        }
+    }
+    inner.signals |= signal;
+    let parent = inner.parent.as_ref().and_then(Weak::upgrade);
+    drop(inner);
+    interrupt_for_signal(process);
+    if continued {
+        notify_parent(parent);
+    }
+}
+
+/// Send a signal to every process of group `pgid`, return false if there is
+/// none.
+pub fn send_group_signal(pgid: usize, signal: SignalFlags) -> bool {
+    let group = process_group(pgid);
+    for process in group.iter() {
+        send_signal(process, signal);
+    }
+    !group.is_empty()
+}
+
+/// Tell the parent of a process which has exited, stopped or continued,
+/// with SIGCHLD and by waking up its threads waiting for children.
+pub fn notify_parent(parent: Option<Arc<ProcessControlBlock>>) {
+    // the parent ignores SIGCHLD unless it has a handler
+    if let Some(parent) = parent {
+        parent.wakeup_waiters();
+        send_signal(&parent, SignalFlags::SIGCHLD);
+    }
+}
+
+/// Whether the current thread blocks `signal` or its process ignores it.
+pub fn current_blocks_or_ignores(signal: SignalFlags) -> bool {
+    let process = current_process();
+    let task = current_task().unwrap();
+    let process_inner = process.inner_exclusive_access();
+    let task_inner = task.inner_exclusive_access();
+    let signum = signal.first_signum().unwrap();
+    task_inner.signal_mask.contains(signal)
+        || process_inner.signal_actions[signum].handler == SIG_IGN
+}
+
+/// Generate a signal for the current thread because of a fault. Retrying the
+/// faulting instruction would fault again, so the signal can't be ignored or
+/// blocked: the default action is taken instead.
+pub fn current_add_signal(signal: SignalFlags) {
+    let process = current_process();
+    let task = current_task().unwrap();
+    let mut process_inner = process.inner_exclusive_access();
+    let mut task_inner = task.inner_exclusive_access();
+    let signum = signal.first_signum().unwrap();
+    if process_inner.signal_actions[signum].handler == SIG_IGN {
+        process_inner.signal_actions[signum].handler = SIG_DFL;
+    }
+    task_inner.signal_mask.remove(signal);
+    task_inner.signals |= signal;
+}
+
+/// Whether a pending signal which the thread doesn't block is to interrupt
+/// a wait of the thread: one caught by a handler, one killing the process,
+/// or any of them if it is traced since it stops then.
+pub(super) fn signal_interrupts(
+    process_inner: &ProcessControlBlockInner,
+    task_inner: &TaskControlBlockInner,
+) -> bool {
+    let pending = (process_inner.signals | task_inner.signals) - task_inner.signal_mask;
+    if process_inner.ptrace.is_some() {
+        return !pending.is_empty();
+    }
+    let signal_actions = &process_inner.signal_actions;
+    (1..=MAX_SIG).any(|signum| {
+        let signal = SignalFlags::from_signum(signum).unwrap();
+        pending.contains(signal)
+            && match signal_actions[signum].handler {
+                SIG_IGN => false,
+                SIG_DFL => signal.default_action() == DefaultAction::Terminate,
+                _ => true,
+            }
+    })
+}
+
+enum SignalStep {
+    Return,
+    /// the thread has joined the stop queue of its process
+    Stopped(Arc<ProcessControlBlock>),
+    /// the process has just stopped, its parent is told before going on
+    NotifyParent(Option<Arc<ProcessControlBlock>>),
+    Exit(i32),
+}
+
+/// Act on the pending signals before the current thread returns to user
+/// mode. A stopped process doesn't return to user mode until it is
+/// continued or killed.
+pub fn handle_signals() {
+    loop {
+        match next_signal_step() {
+            SignalStep::Return => return,
+            SignalStep::Stopped(process) => process.wait_stopped(),
+            SignalStep::NotifyParent(parent) => notify_parent(parent),
+            SignalStep::Exit(exit_code) => exit_current_and_run_next(exit_code),
+        }
+    }
+}
+
+/// Take the default actions of pending signals until one enters a handler,
+/// kills the process or none is left.
+fn next_signal_step() -> SignalStep {
+    let task = current_task().unwrap();
+    let process = task.process.upgrade().unwrap();
+    let mut process_inner = process.inner_exclusive_access();
+    let mut task_inner = task.inner_exclusive_access();
+    if let Some(exit_code) = process_inner.terminating {
+        return SignalStep::Exit(exit_code);
+    }
+    if task_inner.killed {
+        // only this thread, see sys_thread_kill
+        return SignalStep::Exit(-(SignalFlags::SIGKILL.first_signum().unwrap() as i32));
+    }
+    let tid = task_inner.res.as_ref().unwrap().tid;
+    if process_inner
+        .ptrace
+        .as_ref()
+        .map_or(false, |ptrace| ptrace.holds(tid))
+    {
+        process_inner.stop_queue.push_back(Arc::clone(&task));
+        return SignalStep::Stopped(Arc::clone(&process));
+    }
+    loop {
+        // a signal let through by the tracer is taken without stopping again
+        let injected = process_inner
+            .ptrace
+            .as_mut()
+            .and_then(|ptrace| ptrace.signal.take());
+        let signum = match injected {
+            Some(signum) => signum,
+            None => {
+                let pending = (process_inner.signals | task_inner.signals) - task_inner.signal_mask;
+                let signum = match pending.first_signum() {
+                    Some(signum) => signum,
+                    None if process_inner.stopped => {
+                        process_inner.stop_queue.push_back(Arc::clone(&task));
+                        return SignalStep::Stopped(Arc::clone(&process));
+                    }
+                    None => return SignalStep::Return,
+                };
+                let signal = SignalFlags::from_signum(signum).unwrap();
+                // signals of the thread first, those are faults
+                if task_inner.signals.contains(signal) {
+                    task_inner.signals.remove(signal);
+                } else {
+                    process_inner.signals.remove(signal);
+                }
+                if process_inner.ptrace.is_some() && signal != SignalFlags::SIGKILL {
+                    // the tracer decides whether the signal is taken
+                    let tracer = ptrace_stop(&mut process_inner, tid, signum);
+                    return SignalStep::NotifyParent(tracer);
+                }
+                signum
+            }
+        };
+        let signal = SignalFlags::from_signum(signum).unwrap();
+        let action = process_inner.signal_actions[signum];
+        if action.handler == SIG_IGN {
+            continue;
+        }
+        if action.handler != SIG_DFL {
+            // enter the handler, which returns to the sigreturn trampoline
+            let trap_cx = task_inner.get_trap_cx();
+            let frame = SignalFrame {
+                info: SignalInfo {
+                    signo: signum as i32,
+                    errno: 0,
+                    code: 0,
+                    _rest: [0; 29],
+                },
+                x: trap_cx.x,
+                sepc: trap_cx.sepc,
+                mask: task_inner.signal_mask,
+            };
+            let frame_addr = (trap_cx.x[2].wrapping_sub(size_of::<SignalFrame>())) & !0xf;
+            let frame_ptr = UserPtr::<SignalFrame>::new(frame_addr);
+            if frame_ptr
+                .write(&mut process_inner.memory_set, &frame)
+                .is_ok()
+            {
+                trap_cx.x[1] = SIGRETURN_TRAMPOLINE;
+                trap_cx.x[2] = frame_addr;
+                trap_cx.x[10] = signum;
+                if action.flags & SA_SIGINFO != 0 {
+                    // there is no ucontext_t
+                    trap_cx.x[11] = frame_addr;
+                    trap_cx.x[12] = 0;
+                }
+                trap_cx.sepc = action.handler;
+                let mut mask = action.mask;
+                if action.flags & SA_NODEFER == 0 {
+                    mask |= signal;
+                }
+                task_inner.signal_mask |= mask - SignalFlags::unblockable();
+                if action.flags & SA_RESETHAND != 0 {
+                    process_inner.signal_actions[signum] = SignalAction::default();
+                }
+                return SignalStep::Return;
+            }
+            // the user stack is broken, there is nothing to do but kill it
+            return terminate(&mut process_inner, SignalFlags::SIGSEGV);
+        }
+        match signal.default_action() {
+            DefaultAction::Terminate => return terminate(&mut process_inner, signal),
+            DefaultAction::Stop if !process_inner.stopped => {
+                process_inner.stopped = true;
+                process_inner.wait_event = Some(WaitEvent::Stopped(signum));
+                let parent = process_inner.parent.as_ref().and_then(Weak::upgrade);
+                return SignalStep::NotifyParent(parent);
+            }
+            DefaultAction::Stop | DefaultAction::Ignore | DefaultAction::Continue => {}
+        }
+    }
+}
+
+/// Kill the process of the current thread. The other threads exit when they
+/// are about to return to user mode.
+fn terminate(process_inner: &mut ProcessControlBlockInner, signal: SignalFlags) -> SignalStep {
+    let signum = signal.first_signum().unwrap();
+    let (name, description) = SIGNAL_INFO[signum];
+    println!("[kernel] {}, {}={}", description, name, signum);
+    let exit_code = -(signum as i32);
+    process_inner.terminating = Some(exit_code);
+    process_inner.killed_by = Some(signum);
+    SignalStep::Exit(exit_code)
+}
+
+/// Restore the context and mask saved when the current thread entered a
+/// handler, the frame is on top of its user stack. Return false if the frame
+/// can't be read.
+pub fn restore_signal_frame() -> bool {
+    let process = current_process();
+    let task = current_task().unwrap();
+    let mut process_inner = process.inner_exclusive_access();
+    let mut task_inner = task.inner_exclusive_access();
+    let trap_cx = task_inner.get_trap_cx();
+    let frame = match UserPtr::<SignalFrame>::new(trap_cx.x[2]).read(&mut process_inner.memory_set)
+    {
+        Ok(frame) => frame,
+        Err(_) => return false,
+    };
+    trap_cx.x = frame.x;
+    trap_cx.sepc = frame.sepc;
+    task_inner.signal_mask =
+        SignalFlags::from_bits_truncate(frame.mask.bits()) - SignalFlags::unblockable();
+    true
 }
@@ -1,21 +1,28 @@
 use super::id::TaskUserRes;
-use super::{kstack_alloc, KernelStack, ProcessControlBlock, TaskContext};
+use super::sched::SchedEntity;
+use super::{kstack_alloc, KernelStack, ProcessControlBlock, SignalFlags, TaskContext, Wait};
 use crate::trap::TrapContext;
-use crate::{mm::PhysPageNum, sync::UPSafeCell};
+use crate::{
+    error::SysResult,
+    mm::PhysPageNum,
+    sync::{SpinLock, SpinLockGuard},
+};
 use alloc::sync::{Arc, Weak};
-use core::cell::RefMut;
+use core::sync::atomic::AtomicBool;
 
 pub struct TaskControlBlock {
     // immutable
     pub process: Weak<ProcessControlBlock>,
     pub kstack: KernelStack,
+    /// set while the task runs on a hart, until its context is saved
+    pub on_cpu: AtomicBool,
     // mutable
-    inner: UPSafeCell<TaskControlBlockInner>,
+    inner: SpinLock<TaskControlBlockInner>,
 }
 
 impl TaskControlBlock {
-    pub fn inner_exclusive_access(&self) -> RefMut<'_, TaskControlBlockInner> {
-        self.inner.exclusive_access()
+    pub fn inner_exclusive_access(&self) -> SpinLockGuard<'_, TaskControlBlockInner> {
+        self.inner.lock()
     }
 
     pub fn get_user_token(&self) -> usize {
@@ -31,6 +38,19 @@ pub struct TaskControlBlockInner {
     pub task_cx: TaskContext,
     pub task_status: TaskStatus,
     pub exit_code: Option<i32>,
+    pub sched: SchedEntity,
+    /// pending signals sent to this thread
+    pub signals: SignalFlags,
+    pub signal_mask: SignalFlags,
+    /// reaped as soon as it exits instead of by `sys_waittid`
+    pub detached: bool,
+    /// exits instead of returning to user mode, see `sys_thread_kill`
+    pub killed: bool,
+    /// the wait the thread is blocked in, which an interruption ends
+    pub wait: Option<Arc<Wait>>,
+    /// address of a futex word cleared and woken up when the thread exits,
+    /// set by CLONE_CHILD_CLEARTID
+    pub clear_child_tid: usize,
 }
 
 impl TaskControlBlockInner {
@@ -45,27 +65,45 @@ impl TaskControlBlockInner {
 }
 
 impl TaskControlBlock {
+    /// Fail with ENOMEM if there is no frame left for the thread-local
+    /// storage of the thread.
     pub fn new(
         process: Arc<ProcessControlBlock>,
         ustack_base: usize,
         alloc_user_res: bool,
-    ) -> Self {
-        let res = TaskUserRes::new(Arc::clone(&process), ustack_base, alloc_user_res);
+    ) -> SysResult<Self> {
+        let res = TaskUserRes::new(Arc::clone(&process), ustack_base, alloc_user_res)?;
+        Ok(Self::with_res(&process, res))
+    }
+
+    /// The thread of a forked process, which keeps the tid of the thread
+    /// calling fork.
+    pub fn forked(process: &Arc<ProcessControlBlock>, ustack_base: usize, tid: usize) -> Self {
+        Self::with_res(process, TaskUserRes::forked(process, ustack_base, tid))
+    }
+
+    fn with_res(process: &Arc<ProcessControlBlock>, res: TaskUserRes) -> Self {
         let trap_cx_ppn = res.trap_cx_ppn();
         let kstack = kstack_alloc();
         let kstack_top = kstack.get_top();
         Self {
-            process: Arc::downgrade(&process),
+            process: Arc::downgrade(process),
             kstack,
-            inner: unsafe {
-                UPSafeCell::new(TaskControlBlockInner {
-                    res: Some(res),
-                    trap_cx_ppn,
-                    task_cx: TaskContext::goto_trap_return(kstack_top),
-                    task_status: TaskStatus::Ready,
-                    exit_code: None,
-                })
-            },
+            on_cpu: AtomicBool::new(false),
+            inner: SpinLock::new(TaskControlBlockInner {
+                res: Some(res),
+                trap_cx_ppn,
+                task_cx: TaskContext::goto_trap_return(kstack_top),
+                task_status: TaskStatus::Ready,
+                exit_code: None,
+                sched: SchedEntity::new(),
+                signals: SignalFlags::empty(),
+                signal_mask: SignalFlags::empty(),
+                detached: false,
+                killed: false,
+                wait: None,
+                clear_child_tid: 0,
+            }),
         }
     }
 }
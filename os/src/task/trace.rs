@@ -0,0 +1,131 @@
+//! Syscall tracing: each syscall of a traced process is recorded in a ring
+//! buffer, which its parent reads with `sys_trace_read`.
+
+use super::{add_task, block_current, current_task, remove_waiter, TaskControlBlock, WaitResult};
+use crate::error::{SysError, SysResult};
+use crate::sync::SpinLock;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+/// Records kept before the oldest ones are dropped.
+const TRACE_CAPACITY: usize = 128;
+/// Bytes of a string or buffer argument kept in a record.
+pub const TRACE_DATA_LEN: usize = 32;
+/// `flags` of a `TraceRecord`: the syscall didn't return, `ret` and
+/// `duration_us` mean nothing
+pub const TRACE_NORETURN: usize = 1 << 0;
+/// `data` was cut to `TRACE_DATA_LEN` bytes
+pub const TRACE_TRUNCATED: usize = 1 << 1;
+
+/// A syscall as read by `sys_trace_read`, times are in microseconds.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct TraceRecord {
+    /// counted from 0 when tracing starts, a gap means records were dropped
+    pub seq: usize,
+    pub tid: usize,
+    pub id: usize,
+    pub args: [usize; 6],
+    pub ret: isize,
+    /// since boot
+    pub start_us: usize,
+    pub duration_us: usize,
+    /// `TRACE_*`
+    pub flags: usize,
+    /// the start of the string or buffer the syscall takes, if any
+    pub data_len: usize,
+    pub data: [u8; TRACE_DATA_LEN],
+}
+
+impl TraceRecord {
+    pub fn new(tid: usize, id: usize, args: [usize; 6], start_us: usize) -> Self {
+        Self {
+            seq: 0,
+            tid,
+            id,
+            args,
+            ret: 0,
+            start_us,
+            duration_us: 0,
+            flags: 0,
+            data_len: 0,
+            data: [0; TRACE_DATA_LEN],
+        }
+    }
+}
+
+struct TraceLogInner {
+    records: VecDeque<TraceRecord>,
+    next_seq: usize,
+    /// no record is pushed any longer, see `close`
+    closed: bool,
+    /// threads of the tracer blocked in `pop` until there is a record
+    readers: VecDeque<Arc<TaskControlBlock>>,
+}
+
+/// The syscalls of a traced process, the oldest first.
+pub struct TraceLog {
+    inner: SpinLock<TraceLogInner>,
+}
+
+impl TraceLog {
+    pub fn new() -> Self {
+        Self {
+            inner: SpinLock::new(TraceLogInner {
+                records: VecDeque::with_capacity(TRACE_CAPACITY),
+                next_seq: 0,
+                closed: false,
+                readers: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Number `record` and append it, dropping the oldest record if the log
+    /// is full.
+    pub fn push(&self, mut record: TraceRecord) {
+        let mut inner = self.inner.lock();
+        record.seq = inner.next_seq;
+        inner.next_seq += 1;
+        if inner.records.len() == TRACE_CAPACITY {
+            inner.records.pop_front();
+        }
+        inner.records.push_back(record);
+        for reader in inner.readers.drain(..) {
+            add_task(reader);
+        }
+    }
+
+    /// Stop the log once the traced process has exited or tracing stops,
+    /// the readers which wait for a record get none.
+    pub fn close(&self) {
+        let mut inner = self.inner.lock();
+        inner.closed = true;
+        for reader in inner.readers.drain(..) {
+            add_task(reader);
+        }
+    }
+
+    /// Take the oldest record. If there is none, block until one is pushed
+    /// if `wait` is set, and return None once the log is closed. Fail with
+    /// EINTR if the wait is interrupted.
+    pub fn pop(self: &Arc<Self>, wait: bool) -> SysResult<Option<TraceRecord>> {
+        loop {
+            let mut inner = self.inner.lock();
+            if let Some(record) = inner.records.pop_front() {
+                return Ok(Some(record));
+            }
+            if inner.closed || !wait {
+                return Ok(None);
+            }
+            inner.readers.push_back(current_task().unwrap());
+            drop(inner);
+            let log = Arc::clone(self);
+            let result = block_current(None, move |task, waiting| {
+                remove_waiter(&mut log.inner.lock().readers, task, waiting)
+            });
+            if result == WaitResult::Interrupted {
+                return Err(SysError::EINTR);
+            }
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Waits of blocked tasks, which end when the task is woken up by what it
+//! waits for, or earlier when it times out or is interrupted. A task is
+//! interrupted when it is killed, see `sys_thread_kill`, or its process is
+//! terminating, calls exec or gets SIGKILL. Unless the wait is killable, it
+//! is also interrupted by a signal the thread is to take, see
+//! `signal_interrupts`.
+
+use super::signal::signal_interrupts;
+use super::task::TaskControlBlockInner;
+use super::{add_task, block_current_and_run_next, current_task, TaskControlBlock};
+use super::{ProcessControlBlock, ProcessControlBlockInner, SignalFlags};
+use crate::sync::SpinLock;
+use crate::timer::{add_timer, remove_timer};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// How a wait has ended.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum WaitResult {
+    /// by what the task waited for
+    Woken,
+    TimedOut,
+    Interrupted,
+}
+
+type Cancel = Box<dyn FnOnce(&Arc<TaskControlBlock>, &AtomicBool) -> bool + Send>;
+
+/// A wait a task is blocked in, which its timer or an interruption may end
+/// before the task is woken up.
+pub struct Wait {
+    /// set while the task is in the wait, see `remove_waiter`
+    waiting: AtomicBool,
+    /// taken by whichever tries to end the wait first
+    cancel: SpinLock<Option<Cancel>>,
+    /// how the wait has ended if it has been ended early
+    result: SpinLock<WaitResult>,
+    /// interrupted by signals, i.e. not killable
+    by_signals: bool,
+}
+
+impl Wait {
+    /// End the wait of `task` with `result` and wake the task up, unless it
+    /// has been woken up already.
+    pub fn end(&self, task: &Arc<TaskControlBlock>, result: WaitResult) {
+        let cancel = self.cancel.lock().take();
+        if let Some(cancel) = cancel {
+            if cancel(task, &self.waiting) {
+                *self.result.lock() = result;
+                add_task(Arc::clone(task));
+            }
+        }
+    }
+}
+
+/// Whether a wait of the thread is to be interrupted, by signals as well if
+/// `by_signals`.
+fn interrupt_pending(
+    process_inner: &ProcessControlBlockInner,
+    task_inner: &TaskControlBlockInner,
+    by_signals: bool,
+) -> bool {
+    task_inner.killed
+        || process_inner.terminating.is_some()
+        || process_inner.signals.contains(SignalFlags::SIGKILL)
+        || (by_signals && signal_interrupts(process_inner, task_inner))
+}
+
+/// Block the current task, which has just joined a wait queue, until it is
+/// woken up, `expire_ms` is reached if there is one, or it is interrupted.
+/// In the latter cases `cancel` is called with the task and a flag which is
+/// set while the task is in this wait, it should take the task out with
+/// `remove_waiter` and undo the effects of the wait on the primitive, and
+/// return false if the task has been woken up already.
+pub fn block_current<F>(expire_ms: Option<usize>, cancel: F) -> WaitResult
+where
+    F: FnOnce(&Arc<TaskControlBlock>, &AtomicBool) -> bool + Send + 'static,
+{
+    block(expire_ms, true, Box::new(cancel))
+}
+
+/// Like `block_current`, but signals don't interrupt the wait, which has no
+/// timeout either.
+pub fn block_current_killable<F>(cancel: F) -> WaitResult
+where
+    F: FnOnce(&Arc<TaskControlBlock>, &AtomicBool) -> bool + Send + 'static,
+{
+    block(None, false, Box::new(cancel))
+}
+
+fn block(expire_ms: Option<usize>, by_signals: bool, cancel: Cancel) -> WaitResult {
+    let task = current_task().unwrap();
+    let wait = Arc::new(Wait {
+        waiting: AtomicBool::new(true),
+        cancel: SpinLock::new(Some(cancel)),
+        result: SpinLock::new(WaitResult::Woken),
+        by_signals,
+    });
+    let timer = expire_ms.map(|expire_ms| add_timer(expire_ms, &task, &wait));
+    // interruptions from now on find the wait, those before are seen here
+    let interrupted = match task.process.upgrade() {
+        Some(process) => {
+            let process_inner = process.inner_exclusive_access();
+            let mut task_inner = task.inner_exclusive_access();
+            task_inner.wait = Some(Arc::clone(&wait));
+            interrupt_pending(&process_inner, &task_inner, by_signals)
+        }
+        None => true,
+    };
+    if interrupted {
+        wait.end(&task, WaitResult::Interrupted);
+    }
+    block_current_and_run_next();
+    // whatever tries to end the wait later finds the flag cleared
+    let woken = wait.waiting.swap(false, Ordering::AcqRel);
+    task.inner_exclusive_access().wait = None;
+    if let Some(timer) = timer {
+        remove_timer(timer);
+    }
+    if woken {
+        WaitResult::Woken
+    } else {
+        *wait.result.lock()
+    }
+}
+
+/// End the wait `task` is blocked in, if any, as interrupted.
+pub fn interrupt_task(task: &Arc<TaskControlBlock>) {
+    let wait = task.inner_exclusive_access().wait.clone();
+    if let Some(wait) = wait {
+        wait.end(task, WaitResult::Interrupted);
+    }
+}
+
+/// Interrupt the wait of a thread of `process` which is to take one of its
+/// pending signals. One is enough: the others are interrupted in turn if the
+/// signal kills the process.
+pub fn interrupt_for_signal(process: &Arc<ProcessControlBlock>) {
+    let process_inner = process.inner_exclusive_access();
+    let waiter = process_inner.tasks.iter().flatten().find_map(|task| {
+        let task_inner = task.inner_exclusive_access();
+        let wait = task_inner.wait.as_ref()?;
+        if interrupt_pending(&process_inner, &task_inner, wait.by_signals) {
+            Some((Arc::clone(task), Arc::clone(wait)))
+        } else {
+            None
+        }
+    });
+    drop(process_inner);
+    if let Some((task, wait)) = waiter {
+        wait.end(&task, WaitResult::Interrupted);
+    }
+}
+
+/// Whether the current task is to give up waiting, for those which wait by
+/// yielding in a loop rather than blocking.
+pub fn current_interrupted() -> bool {
+    let task = current_task().unwrap();
+    match task.process.upgrade() {
+        Some(process) => {
+            let process_inner = process.inner_exclusive_access();
+            let task_inner = task.inner_exclusive_access();
+            interrupt_pending(&process_inner, &task_inner, true)
+        }
+        None => true,
+    }
+}
+
+/// Take `task` out of `wait_queue` if it is still in the wait `waiting`
+/// belongs to, to be called with the lock of the queue held. Return false
+/// if it has been woken up already.
+pub fn remove_waiter(
+    wait_queue: &mut VecDeque<Arc<TaskControlBlock>>,
+    task: &Arc<TaskControlBlock>,
+    waiting: &AtomicBool,
+) -> bool {
+    if !waiting.load(Ordering::Acquire) {
+        return false;
+    }
+    let idx = wait_queue
+        .iter()
+        .position(|waiter| Arc::ptr_eq(waiter, task));
+    match idx {
+        Some(idx) => {
+            wait_queue.remove(idx);
+            waiting.store(false, Ordering::Release);
+            true
+        }
+        None => false,
+    }
+}
+
+/// The `cancel` of a wait in no queue, such as a sleep.
+pub fn leave_no_queue(_task: &Arc<TaskControlBlock>, waiting: &AtomicBool) -> bool {
+    waiting.swap(false, Ordering::AcqRel)
+}
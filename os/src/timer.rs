@@ -1,16 +1,17 @@
-use core::cmp::Ordering;
-
 use crate::config::CLOCK_FREQ;
 use crate::sbi::set_timer;
-use crate::sync::UPSafeCell;
-use crate::task::{add_task, TaskControlBlock};
-use alloc::collections::BinaryHeap;
+use crate::sync::SpinLock;
+use crate::task::{TaskControlBlock, Wait, WaitResult};
+use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use lazy_static::*;
 use riscv::register::time;
 
 const TICKS_PER_SEC: usize = 100;
 const MSEC_PER_SEC: usize = 1000;
+const USEC_PER_SEC: usize = 1_000_000;
+pub const NSEC_PER_SEC: usize = 1_000_000_000;
+const NSEC_PER_MSEC: usize = 1_000_000;
 
 pub fn get_time() -> usize {
     time::read()
@@ -20,54 +21,111 @@ pub fn get_time_ms() -> usize {
     time::read() / (CLOCK_FREQ / MSEC_PER_SEC)
 }
 
-pub fn set_next_trigger() {
-    set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
+pub fn get_time_us() -> usize {
+    time::read() / (CLOCK_FREQ / USEC_PER_SEC)
 }
 
-pub struct TimerCondVar {
-    pub expire_ms: usize,
-    pub task: Arc<TaskControlBlock>,
+/// `struct timespec` of Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeSpec {
+    pub tv_sec: usize,
+    pub tv_nsec: usize,
 }
 
-impl PartialEq for TimerCondVar {
-    fn eq(&self, other: &Self) -> bool {
-        self.expire_ms == other.expire_ms
-    }
+/// `struct timeval` of Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeVal {
+    pub tv_sec: usize,
+    pub tv_usec: usize,
 }
-impl Eq for TimerCondVar {}
-impl PartialOrd for TimerCondVar {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        let a = -(self.expire_ms as isize);
-        let b = -(other.expire_ms as isize);
-        Some(a.cmp(&b))
+
+impl TimeSpec {
+    /// The time since boot, the only clock there is.
+    pub fn now() -> Self {
+        let time = get_time();
+        Self {
+            tv_sec: time / CLOCK_FREQ,
+            tv_nsec: time % CLOCK_FREQ * NSEC_PER_SEC / CLOCK_FREQ,
+        }
+    }
+
+    /// Whole milliseconds, rounded up so that a sleep is never cut short.
+    pub fn as_ms(&self) -> usize {
+        self.tv_sec
+            .saturating_mul(MSEC_PER_SEC)
+            .saturating_add((self.tv_nsec + NSEC_PER_MSEC - 1) / NSEC_PER_MSEC)
     }
 }
 
-impl Ord for TimerCondVar {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap()
+impl TimeVal {
+    pub fn now() -> Self {
+        let time = get_time();
+        Self {
+            tv_sec: time / CLOCK_FREQ,
+            tv_usec: time % CLOCK_FREQ * USEC_PER_SEC / CLOCK_FREQ,
+        }
     }
 }
 
+pub fn set_next_trigger() {
+    set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
+}
+
+pub struct TimerCondVar {
+    pub task: Arc<TaskControlBlock>,
+    /// the wait the task times out of
+    pub wait: Arc<Wait>,
+}
+
+/// Timers are ordered by their expiry, then by the order they were added
+/// in, which also tells apart those expiring at the same time.
+pub type TimerKey = (usize, usize);
+
+struct Timers {
+    timers: BTreeMap<TimerKey, TimerCondVar>,
+    next_id: usize,
+}
+
 lazy_static! {
-    static ref TIMERS: UPSafeCell<BinaryHeap<TimerCondVar>> =
-        unsafe { UPSafeCell::new(BinaryHeap::<TimerCondVar>::new()) };
+    static ref TIMERS: SpinLock<Timers> = SpinLock::new(Timers {
+        timers: BTreeMap::new(),
+        next_id: 0,
+    });
 }
 
-pub fn add_timer(expire_ms: usize, task: Arc<TaskControlBlock>) {
-    let mut timers = TIMERS.exclusive_access();
-    timers.push(TimerCondVar { expire_ms, task });
+/// Time `wait` of `task` out at `expire_ms`, unless the timer is removed
+/// before.
+pub fn add_timer(expire_ms: usize, task: &Arc<TaskControlBlock>, wait: &Arc<Wait>) -> TimerKey {
+    let mut timers = TIMERS.lock();
+    let key = (expire_ms, timers.next_id);
+    timers.next_id += 1;
+    let timer = TimerCondVar {
+        task: Arc::clone(task),
+        wait: Arc::clone(wait),
+    };
+    timers.timers.insert(key, timer);
+    key
+}
+
+/// Remove a timer which may have fired already.
+pub fn remove_timer(key: TimerKey) {
+    let timer = TIMERS.lock().timers.remove(&key);
+    // it may hold the last reference of the task
+    drop(timer);
 }
 
 pub fn check_timer() {
     let current_ms = get_time_ms();
-    let mut timers = TIMERS.exclusive_access();
-    while let Some(timer) = timers.peek() {
-        if timer.expire_ms <= current_ms {
-            add_task(Arc::clone(&timer.task));
-            timers.pop();
-        } else {
-            break;
-        }
+    let mut timers = TIMERS.lock();
+    // the timers expiring later are left in place
+    let later = timers.timers.split_off(&(current_ms + 1, 0));
+    let expired = core::mem::replace(&mut timers.timers, later);
+    // waits are cancelled with the lock of the primitive, which may be
+    // held while timers are added
+    drop(timers);
+    for timer in expired.into_values() {
+        timer.wait.end(&timer.task, WaitResult::TimedOut);
     }
 }
@@ -1,10 +1,13 @@
 mod context;
 
 use crate::config::TRAMPOLINE;
+use crate::fs::console_poll;
+use crate::mm::{handle_page_fault, MapPermission, VirtAddr};
 use crate::syscall::syscall;
 use crate::task::{
-    check_signals_of_current, current_add_signal, current_trap_cx, current_trap_cx_user_va,
-    current_user_token, exit_current_and_run_next, suspend_current_and_run_next, SignalFlags,
+    current_add_signal, current_process, current_trap_cx, current_trap_cx_user_va,
+    current_user_token, handle_signals, preempt_current_and_run_next, ptrace_breakpoint_trap,
+    ptrace_syscall_stop, SignalFlags,
 };
 use crate::timer::{check_timer, set_next_trigger};
 use core::arch::{asm, global_asm};
@@ -15,6 +18,7 @@ use riscv::register::{
 };
 
 global_asm!(include_str!("trap.S"));
+global_asm!(include_str!("sigreturn.S"));
 
 pub fn init() {
     set_kernel_trap_entry();
@@ -48,35 +52,63 @@ pub fn trap_handler() -> ! {
             // jump to next instruction anyway
             let mut cx = current_trap_cx();
             cx.sepc += 4;
+            // a tracer may change the syscall before it is made
+            ptrace_syscall_stop();
+            cx = current_trap_cx();
             // get system call return value
-            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]);
+            let result = syscall(
+                cx.x[17],
+                [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]],
+            );
             // cx is changed during sys_exec, so we have to call it again
             cx = current_trap_cx();
             cx.x[10] = result as usize;
+            // and see its result
+            ptrace_syscall_stop();
+        }
+        Trap::Exception(Exception::StorePageFault)
+        | Trap::Exception(Exception::LoadPageFault)
+        | Trap::Exception(Exception::InstructionPageFault) => {
+            // lazy, swapped and copy-on-write pages are resolved here
+            let access = match scause.cause() {
+                Trap::Exception(Exception::StorePageFault) => MapPermission::W,
+                Trap::Exception(Exception::InstructionPageFault) => MapPermission::X,
+                _ => MapPermission::R,
+            };
+            // an invalid access, or no frame left for the page
+            if handle_page_fault(&current_process(), VirtAddr::from(stval).floor(), access).is_err()
+            {
+                /*
+                println!(
+                    "[kernel] {:?} in application, bad addr = {:#x}, bad instruction = {:#x}, kernel killed it.",
+                    scause.cause(),
+                    stval,
+                    current_trap_cx().sepc,
+                );
+                */
+                current_add_signal(SignalFlags::SIGSEGV);
+            }
         }
         Trap::Exception(Exception::StoreFault)
-        | Trap::Exception(Exception::StorePageFault)
         | Trap::Exception(Exception::InstructionFault)
-        | Trap::Exception(Exception::InstructionPageFault)
-        | Trap::Exception(Exception::LoadFault)
-        | Trap::Exception(Exception::LoadPageFault) => {
-            /*
-            println!(
-                "[kernel] {:?} in application, bad addr = {:#x}, bad instruction = {:#x}, kernel killed it.",
-                scause.cause(),
-                stval,
-                current_trap_cx().sepc,
-            );
-            */
+        | Trap::Exception(Exception::LoadFault) => {
             current_add_signal(SignalFlags::SIGSEGV);
         }
         Trap::Exception(Exception::IllegalInstruction) => {
             current_add_signal(SignalFlags::SIGILL);
         }
+        Trap::Exception(Exception::Breakpoint) => {
+            // sepc stays at the ebreak, for a debugger to put back the
+            // instruction it has replaced and go on from there
+            if ptrace_breakpoint_trap(current_trap_cx().sepc) {
+                current_add_signal(SignalFlags::SIGTRAP);
+            }
+        }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             set_next_trigger();
             check_timer();
-            suspend_current_and_run_next();
+            console_poll();
+            preempt_current_and_run_next();
         }
         _ => {
             panic!(
@@ -86,16 +118,13 @@ pub fn trap_handler() -> ! {
             );
         }
     }
-    // check signals
-    if let Some((errno, msg)) = check_signals_of_current() {
-        println!("[kernel] {}", msg);
-        exit_current_and_run_next(errno);
-    }
     trap_return();
 }
 
 #[no_mangle]
 pub fn trap_return() -> ! {
+    // pending signals may terminate or stop the thread, or enter a handler
+    handle_signals();
     set_user_trap_entry();
     let trap_cx_user_va = current_trap_cx_user_va();
     let user_satp = current_user_token();
@@ -0,0 +1,9 @@
+/* A static musl program, run by linux_abi_test. */
+#include <stdio.h>
+#include <stdlib.h>
+
+int main(int argc, char *argv[]) {
+    printf("Hello from musl! argc = %d, argv[0] = %s, HOME = %s\n", argc, argv[0],
+           getenv("HOME") ? getenv("HOME") : "(unset)");
+    return 0;
+}
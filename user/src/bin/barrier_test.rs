@@ -0,0 +1,52 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{barrier_create, barrier_wait, exit, thread_create, waittid, Errno, IntoResult};
+
+const THREADS: usize = 8;
+const ROUNDS: usize = 50;
+
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+static mut BARRIER: usize = 0;
+/// Threads which have arrived in each round.
+static ARRIVED: [AtomicUsize; ROUNDS] = [ZERO; ROUNDS];
+/// Threads told to be the last one in each round.
+static LEADERS: [AtomicUsize; ROUNDS] = [ZERO; ROUNDS];
+
+unsafe fn worker() -> ! {
+    for round in 0..ROUNDS {
+        ARRIVED[round].fetch_add(1, Ordering::SeqCst);
+        if barrier_wait(BARRIER) == 1 {
+            LEADERS[round].fetch_add(1, Ordering::SeqCst);
+        }
+        // nobody passes before everyone has arrived
+        assert_eq!(ARRIVED[round].load(Ordering::SeqCst), THREADS);
+    }
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(barrier_create(0).into_result(), Err(Errno::EINVAL));
+    unsafe {
+        BARRIER = barrier_create(THREADS) as usize;
+    }
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| thread_create(worker as usize, 0))
+        .collect();
+    for tid in threads {
+        assert_eq!(waittid(tid as usize), 0);
+    }
+    for round in 0..ROUNDS {
+        assert_eq!(ARRIVED[round].load(Ordering::SeqCst), THREADS);
+        assert_eq!(LEADERS[round].load(Ordering::SeqCst), 1);
+    }
+    println!("barrier_test passed!");
+    0
+}
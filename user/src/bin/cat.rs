@@ -5,16 +5,15 @@
 extern crate user_lib;
 extern crate alloc;
 
-use user_lib::{close, open, read, OpenFlags};
+use user_lib::{close, open, read, IntoResult, OpenFlags};
 
 #[no_mangle]
 pub fn main(argc: usize, argv: &[&str]) -> i32 {
     assert!(argc == 2);
-    let fd = open(argv[1], OpenFlags::RDONLY);
-    if fd == -1 {
-        panic!("Error occured when opening file");
-    }
-    let fd = fd as usize;
+    let fd = match open(argv[1], OpenFlags::RDONLY).into_result() {
+        Ok(fd) => fd,
+        Err(err) => panic!("Error occured when opening file: {}", err),
+    };
     let mut buf = [0u8; 256];
     loop {
         let size = read(fd, &mut buf) as usize;
@@ -0,0 +1,177 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use user_lib::{
+    clone, close, dup, futex_wait, gettid, run_tests, sigaction, waitpid, waittid, CloneFlags,
+    Errno, IntoResult, SignalAction, SIGUSR1,
+};
+
+const STACK_SIZE: usize = 0x4000;
+const ARG: usize = 0x1234;
+
+#[repr(align(16))]
+struct Stack([u8; STACK_SIZE]);
+
+static mut STACK: Stack = Stack([0; STACK_SIZE]);
+/// Stands for the thread control block of a thread library.
+static TLS_BLOCK: [usize; 4] = [0; 4];
+static PTID: AtomicU32 = AtomicU32::new(0);
+static CTID: AtomicU32 = AtomicU32::new(0);
+static SEEN_TID: AtomicUsize = AtomicUsize::new(0);
+
+fn stack_range() -> (usize, usize) {
+    let bottom = unsafe { STACK.0.as_ptr() as usize };
+    (bottom, bottom + STACK_SIZE)
+}
+
+fn tp() -> usize {
+    let tp: usize;
+    unsafe {
+        core::arch::asm!("mv {}, tp", out(reg) tp);
+    }
+    tp
+}
+
+fn sp() -> usize {
+    let sp: usize;
+    unsafe {
+        core::arch::asm!("mv {}, sp", out(reg) sp);
+    }
+    sp
+}
+
+fn library_thread(arg: usize) -> i32 {
+    let (bottom, top) = stack_range();
+    SEEN_TID.store(CTID.load(Ordering::Acquire) as usize, Ordering::Relaxed);
+    let ok = arg == ARG
+        && tp() == TLS_BLOCK.as_ptr() as usize
+        && (bottom..top).contains(&sp())
+        && CTID.load(Ordering::Relaxed) as isize == gettid();
+    ok as i32
+}
+
+/// What a libc thread library does: the thread runs on a stack and with a
+/// thread pointer set up by the caller, and is joined through the futex
+/// word cleared when it exits.
+fn library_threads() {
+    let flags = CloneFlags::VM
+        | CloneFlags::FILES
+        | CloneFlags::SIGHAND
+        | CloneFlags::THREAD
+        | CloneFlags::SETTLS
+        | CloneFlags::PARENT_SETTID
+        | CloneFlags::CHILD_SETTID
+        | CloneFlags::CHILD_CLEARTID;
+    let tid = clone(
+        library_thread,
+        ARG,
+        flags,
+        stack_range().1,
+        TLS_BLOCK.as_ptr() as usize,
+        Some(&PTID),
+        Some(&CTID),
+    );
+    assert!(tid > 0);
+    assert_eq!(PTID.load(Ordering::Relaxed) as isize, tid);
+    loop {
+        let word = CTID.load(Ordering::Acquire);
+        if word == 0 {
+            break;
+        }
+        futex_wait(&CTID, word);
+    }
+    assert_eq!(SEEN_TID.load(Ordering::Relaxed) as isize, tid);
+    assert_eq!(waittid(tid as usize), 1);
+}
+
+fn returning(arg: usize) -> i32 {
+    arg as i32
+}
+
+/// Without a stack, a thread runs on its own one.
+fn own_stack() {
+    let flags = CloneFlags::VM | CloneFlags::FILES | CloneFlags::SIGHAND | CloneFlags::THREAD;
+    let tid = clone(returning, 5, flags, 0, 0, None, None);
+    assert!(tid > 0);
+    assert_eq!(waittid(tid as usize), 5);
+}
+
+fn dup_stdout(_: usize) -> i32 {
+    dup(1) as i32
+}
+
+/// A process cloned with `FILES` opens files in the table of its parent.
+fn shared_files() {
+    let mut exit_code = 0;
+    let pid = clone(dup_stdout, 0, CloneFlags::FILES, 0, 0, None, None);
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert!(exit_code > 2);
+    assert_eq!(close(exit_code as usize), 0);
+    // a copy otherwise
+    let pid = clone(dup_stdout, 0, CloneFlags::empty(), 0, 0, None, None);
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert!(exit_code > 2);
+    assert_eq!(close(exit_code as usize).into_result(), Err(Errno::EBADF));
+}
+
+extern "C" fn usr1_handler(_signum: i32) {}
+
+fn set_usr1_handler(_: usize) -> i32 {
+    let action = SignalAction {
+        handler: usr1_handler as usize,
+        ..SignalAction::default()
+    };
+    sigaction(SIGUSR1, Some(&action), None) as i32
+}
+
+fn usr1_handler_set() -> bool {
+    let mut old = SignalAction::default();
+    assert_eq!(sigaction(SIGUSR1, None, Some(&mut old)), 0);
+    old.handler == usr1_handler as usize
+}
+
+/// A process changes a copy of the handlers of its parent.
+fn copied_handlers() {
+    let mut exit_code = -1;
+    let pid = clone(set_usr1_handler, 0, CloneFlags::empty(), 0, 0, None, None);
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert!(!usr1_handler_set());
+}
+
+/// Only threads share the memory and the handlers, and they share
+/// everything else too.
+fn invalid_flags() {
+    let thread = CloneFlags::VM | CloneFlags::FILES | CloneFlags::SIGHAND | CloneFlags::THREAD;
+    for flags in [
+        CloneFlags::VM,
+        CloneFlags::SIGHAND,
+        CloneFlags::THREAD,
+        thread - CloneFlags::FILES,
+        thread - CloneFlags::SIGHAND,
+    ]
+    .iter()
+    {
+        assert_eq!(
+            clone(returning, 0, *flags, 0, 0, None, None).into_result(),
+            Err(Errno::EINVAL)
+        );
+    }
+}
+
+static TESTS: &[(fn(), &str)] = &[
+    (library_threads, "library_threads"),
+    (own_stack, "own_stack"),
+    (shared_files, "shared_files"),
+    (copied_handlers, "copied_handlers"),
+    (invalid_flags, "invalid_flags"),
+];
+
+#[no_mangle]
+pub fn main() -> i32 {
+    run_tests("clone_test", TESTS);
+    0
+}
@@ -0,0 +1,70 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{
+    condvar_broadcast, condvar_create, condvar_wait, exit, mutex_blocking_create, mutex_lock,
+    mutex_unlock, sleep, thread_create, waittid,
+};
+
+const THREADS: usize = 8;
+const ROUNDS: usize = 20;
+
+static mut MUTEX: usize = 0;
+static mut CONDVAR: usize = 0;
+/// Bumped for every round, all the waiters have to see each one.
+static mut ROUND: usize = 0;
+static mut WAITING: usize = 0;
+
+unsafe fn waiter() -> ! {
+    for round in 1..=ROUNDS {
+        mutex_lock(MUTEX);
+        WAITING += 1;
+        while ROUND < round {
+            condvar_wait(CONDVAR, MUTEX);
+        }
+        assert_eq!(ROUND, round);
+        mutex_unlock(MUTEX);
+    }
+    exit(0)
+}
+
+/// Wait until all the threads are waiting for the next round.
+unsafe fn all_waiting(round: usize) -> bool {
+    mutex_lock(MUTEX);
+    let waiting = WAITING == THREADS * round;
+    mutex_unlock(MUTEX);
+    waiting
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    unsafe {
+        MUTEX = mutex_blocking_create() as usize;
+        CONDVAR = condvar_create() as usize;
+    }
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| thread_create(waiter as usize, 0))
+        .collect();
+    for round in 1..=ROUNDS {
+        unsafe {
+            while !all_waiting(round) {
+                sleep(1);
+            }
+            mutex_lock(MUTEX);
+            ROUND = round;
+            // a signal would wake up only one of them
+            condvar_broadcast(CONDVAR);
+            mutex_unlock(MUTEX);
+        }
+    }
+    for tid in threads {
+        assert_eq!(waittid(tid as usize), 0);
+    }
+    println!("condvar_broadcast passed!");
+    0
+}
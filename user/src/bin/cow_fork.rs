@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec;
+use user_lib::{close, exit, fork, pipe, read, wait, write};
+
+const LEN: usize = 4096 * 4;
+const HEAP_LEN: usize = 4096 * 2;
+
+static mut DATA: [u8; LEN] = [1; LEN];
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut stack_value: usize = 0x1234;
+    let mut heap = vec![5u8; HEAP_LEN];
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    let pid = fork();
+    if pid == 0 {
+        // child writes first, then lets the parent write
+        close(pipe_fd[0]);
+        unsafe {
+            for byte in DATA.iter_mut() {
+                *byte = 2;
+            }
+        }
+        stack_value = 0x5678;
+        for byte in heap.iter_mut() {
+            *byte = 6;
+        }
+        write(pipe_fd[1], &[0u8]);
+        close(pipe_fd[1]);
+        assert!(unsafe { DATA.iter().all(|b| *b == 2) });
+        assert_eq!(stack_value, 0x5678);
+        assert!(heap.iter().all(|b| *b == 6));
+        println!("cow_fork: child sees its own copy.");
+        exit(0);
+    }
+    assert!(pid > 0);
+    close(pipe_fd[1]);
+    let mut buf = [0u8; 1];
+    // the child has written its copy once this read returns
+    assert_eq!(read(pipe_fd[0], &mut buf), 1);
+    close(pipe_fd[0]);
+    assert!(unsafe { DATA.iter().all(|b| *b == 1) });
+    assert_eq!(stack_value, 0x1234);
+    assert!(heap.iter().all(|b| *b == 5));
+    unsafe {
+        for byte in DATA.iter_mut() {
+            *byte = 3;
+        }
+    }
+    for byte in heap.iter_mut() {
+        *byte = 7;
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert!(unsafe { DATA.iter().all(|b| *b == 3) });
+    assert!(heap.iter().all(|b| *b == 7));
+    println!("cow_fork passed!");
+    0
+}
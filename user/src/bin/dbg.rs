@@ -0,0 +1,160 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{
+    close, exec, exit, fork, open, ptrace, ptrace_getregs, ptrace_peek, ptrace_poke, read,
+    stop_signal, waitpid, IntoResult, OpenFlags, UserRegs, PTRACE_CONT, PTRACE_SINGLESTEP,
+    PTRACE_TRACEME, SIGTRAP,
+};
+
+/// ABI names of the registers in `UserRegs`.
+const REG_NAMES: [&str; 32] = [
+    "pc", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5",
+    "t6",
+];
+/// `c.ebreak`, which fits in place of any instruction.
+const C_EBREAK: usize = 0x9002;
+/// The type of the section holding the symbol table of an ELF file.
+const SHT_SYMTAB: usize = 2;
+
+fn read_file(path: &str) -> Option<Vec<u8>> {
+    let fd = open(path, OpenFlags::RDONLY).into_result().ok()?;
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let len = read(fd, &mut buf);
+        if len <= 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..len as usize]);
+    }
+    close(fd);
+    Some(data)
+}
+
+/// The little-endian number of `len` bytes at `offset`.
+fn field(data: &[u8], offset: usize, len: usize) -> Option<usize> {
+    let bytes = data.get(offset..offset.checked_add(len)?)?;
+    Some(
+        bytes
+            .iter()
+            .rev()
+            .fold(0, |value, byte| (value << 8) | *byte as usize),
+    )
+}
+
+/// The address of symbol `name` in ELF64 file `elf`, from its symbol table.
+fn symbol_addr(elf: &[u8], name: &str) -> Option<usize> {
+    let shoff = field(elf, 0x28, 8)?;
+    let shentsize = field(elf, 0x3a, 2)?;
+    let shnum = field(elf, 0x3c, 2)?;
+    for i in 0..shnum {
+        let section = shoff + i * shentsize;
+        if field(elf, section + 0x4, 4)? != SHT_SYMTAB {
+            continue;
+        }
+        let (offset, size) = (
+            field(elf, section + 0x18, 8)?,
+            field(elf, section + 0x20, 8)?,
+        );
+        let entsize = field(elf, section + 0x38, 8)?;
+        // the names are in the string table the symbol table links to
+        let strtab = shoff + field(elf, section + 0x28, 4)? * shentsize;
+        let names = field(elf, strtab + 0x18, 8)?;
+        for symbol in (offset..offset + size).step_by(entsize.max(1)) {
+            let start = names + field(elf, symbol, 4)?;
+            let len = elf.get(start..)?.iter().position(|byte| *byte == 0)?;
+            if &elf[start..start + len] == name.as_bytes() {
+                return field(elf, symbol + 0x8, 8);
+            }
+        }
+    }
+    None
+}
+
+fn dump_regs(regs: &UserRegs) {
+    for (i, (name, value)) in REG_NAMES.iter().zip(regs.iter()).enumerate() {
+        print!("{:>4} {:#018x}", name, value);
+        if i % 4 == 3 {
+            println!("");
+        } else {
+            print!("  ");
+        }
+    }
+}
+
+/// Wait for traced child `pid` to stop with SIGTRAP, exit if it doesn't.
+fn wait_trap(pid: usize) {
+    let mut status = 0;
+    waitpid(pid, &mut status);
+    match stop_signal(status) {
+        Some(SIGTRAP) => {}
+        Some(signum) => {
+            println!("dbg: stopped by signal {}", signum);
+            exit(-1);
+        }
+        None => {
+            println!("dbg: exited with {}", status);
+            exit(-1);
+        }
+    }
+}
+
+/// Run a program until it reaches a symbol, `main` unless another one is
+/// given, dump its registers there, and step one instruction.
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc < 2 {
+        println!("usage: dbg <program> [symbol]");
+        return -1;
+    }
+    let path = argv[1];
+    let symbol = if argc > 2 { argv[2] } else { "main" };
+    let addr = match read_file(path).and_then(|elf| symbol_addr(&elf, symbol)) {
+        Some(addr) => addr,
+        None => {
+            println!("dbg: no symbol {} in {}", symbol, path);
+            return -1;
+        }
+    };
+    let pid = fork();
+    if pid == 0 {
+        ptrace(PTRACE_TRACEME, 0, 0, 0);
+        // the strings are followed by their '\0's on the stack
+        let err = exec(path, &[path.as_ptr(), core::ptr::null()])
+            .into_result()
+            .unwrap_err();
+        println!("dbg: can't run {}: {}", path, err);
+        exit(-1);
+    }
+    let pid = pid as usize;
+    // stopped right after exec
+    wait_trap(pid);
+    let mut word = 0;
+    assert_eq!(ptrace_peek(pid, addr, &mut word), 0);
+    assert_eq!(ptrace_poke(pid, addr, (word & !0xffff) | C_EBREAK), 0);
+    println!("dbg: breakpoint at {} ({:#x})", symbol, addr);
+    assert_eq!(ptrace(PTRACE_CONT, pid, 0, 0), 0);
+    wait_trap(pid);
+    let mut regs: UserRegs = [0; 32];
+    assert_eq!(ptrace_getregs(pid, &mut regs), 0);
+    println!("dbg: stopped at {:#x}", regs[0]);
+    dump_regs(&regs);
+    // the instruction is back, and runs when the program goes on
+    assert_eq!(ptrace_poke(pid, addr, word), 0);
+    assert_eq!(ptrace(PTRACE_SINGLESTEP, pid, 0, 0), 0);
+    wait_trap(pid);
+    assert_eq!(ptrace_getregs(pid, &mut regs), 0);
+    println!("dbg: stepped to {:#x}", regs[0]);
+    assert_eq!(ptrace(PTRACE_CONT, pid, 0, 0), 0);
+    let mut exit_code = 0;
+    waitpid(pid, &mut exit_code);
+    println!("dbg: {} exited with {}", path, exit_code);
+    0
+}
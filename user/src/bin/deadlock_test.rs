@@ -0,0 +1,105 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use user_lib::{
+    enable_deadlock_detect, exit, mutex_blocking_create, mutex_lock, mutex_unlock, run_tests,
+    semaphore_create, semaphore_down, semaphore_up, sleep, thread_create, waittid, Errno,
+};
+
+const ITEMS: usize = 20;
+
+static mut MUTEXES: [usize; 2] = [0; 2];
+static mut SEMAPHORES: [usize; 2] = [0; 2];
+
+/// Only one thread, nobody could ever raise the semaphore again.
+fn semaphore() {
+    let sem = semaphore_create(1) as usize;
+    assert_eq!(semaphore_down(sem), 0);
+    assert_eq!(semaphore_down(sem), Errno::EDEADLK.as_ret());
+    semaphore_up(sem);
+    assert_eq!(semaphore_down(sem), 0);
+    semaphore_up(sem);
+}
+
+/// Lock `first`, then `second` after `delay_ms`, exit with 1 if that was
+/// refused.
+fn lock_both(first: usize, second: usize, delay_ms: usize) -> ! {
+    assert_eq!(mutex_lock(first), 0);
+    sleep(delay_ms);
+    let result = mutex_lock(second);
+    if result == 0 {
+        mutex_unlock(second);
+    }
+    mutex_unlock(first);
+    exit((result == Errno::EDEADLK.as_ret()) as i32)
+}
+
+fn first_thread() -> ! {
+    unsafe { lock_both(MUTEXES[0], MUTEXES[1], 50) }
+}
+
+fn second_thread() -> ! {
+    unsafe { lock_both(MUTEXES[1], MUTEXES[0], 100) }
+}
+
+/// The first thread waits for the second one, which can still finish. The
+/// second thread would then wait for the first one, and is refused.
+fn crossed_mutexes() {
+    unsafe {
+        MUTEXES = [
+            mutex_blocking_create() as usize,
+            mutex_blocking_create() as usize,
+        ];
+    }
+    let first = thread_create(first_thread as usize, 0);
+    let second = thread_create(second_thread as usize, 0);
+    assert_eq!(waittid(first as usize), 0);
+    assert_eq!(waittid(second as usize), 1);
+}
+
+/// Waits for the empty slot, then fills it, exits with the number of
+/// refused downs.
+fn producer() -> ! {
+    let [empty, full] = unsafe { SEMAPHORES };
+    let mut refused = 0;
+    for _ in 0..ITEMS {
+        if semaphore_down(empty) != 0 {
+            refused += 1;
+        }
+        semaphore_up(full);
+    }
+    exit(refused)
+}
+
+/// The consumer waits on a semaphore it never holds, which only the producer
+/// raises. That is no deadlock as long as the producer is running.
+fn producer_consumer() {
+    unsafe {
+        SEMAPHORES = [semaphore_create(1) as usize, semaphore_create(0) as usize];
+    }
+    let [empty, full] = unsafe { SEMAPHORES };
+    let producer = thread_create(producer as usize, 0);
+    for _ in 0..ITEMS {
+        assert_eq!(semaphore_down(full), 0);
+        semaphore_up(empty);
+    }
+    assert_eq!(waittid(producer as usize), 0);
+    // nobody is left to raise it
+    assert_eq!(semaphore_down(full), Errno::EDEADLK.as_ret());
+}
+
+static TESTS: &[(fn(), &str)] = &[
+    (semaphore, "semaphore"),
+    (crossed_mutexes, "crossed_mutexes"),
+    (producer_consumer, "producer_consumer"),
+];
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(enable_deadlock_detect(true), 0);
+    run_tests("deadlock_test", TESTS);
+    assert_eq!(enable_deadlock_detect(false), 0);
+    0
+}
@@ -0,0 +1,193 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use core::sync::atomic::AtomicU32;
+use user_lib::{
+    close, dup, exec, mutex_blocking_create, mutex_create, mutex_lock, mutex_unlock, open, pipe,
+    raw_syscall, read, run_tests, rwlock_create, rwlock_unlock, semaphore_down, thread_create,
+    thread_exit, wait, waittid, write, Errno, IntoResult, OpenFlags, AT_FDCWD,
+    RWLOCK_PREFER_READER,
+};
+
+const SYSCALL_OPENAT: usize = 56;
+const SYSCALL_WRITE: usize = 64;
+const SYSCALL_FUTEX: usize = 98;
+const SYSCALL_WAIT4: usize = 260;
+const NOT_ELF: &str = "errno_not_elf\0";
+const BAD_ELF: &str = "errno_bad_elf\0";
+const PAGE_SIZE: u64 = 4096;
+const SIGRETURN_TRAMPOLINE: u64 = 0x40_0000_0000 - PAGE_SIZE;
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const NO_FD: usize = 100;
+const NO_ID: usize = 1000;
+
+/// Ids the kernel doesn't know fail instead of taking it down.
+fn unknown_syscalls() {
+    for id in [0, 500, 4242, usize::MAX].iter() {
+        assert_eq!(raw_syscall(*id, [0; 6]).into_result(), Err(Errno::ENOSYS));
+    }
+    let word = AtomicU32::new(0);
+    let futex = [&word as *const _ as usize, 99, 0, 0, 0, 0];
+    assert_eq!(
+        raw_syscall(SYSCALL_FUTEX, futex).into_result(),
+        Err(Errno::ENOSYS)
+    );
+}
+
+/// Closed fds, and open ones in the wrong direction.
+fn bad_fds() {
+    let mut buf = [0u8; 4];
+    assert_eq!(read(NO_FD, &mut buf).into_result(), Err(Errno::EBADF));
+    assert_eq!(write(NO_FD, &buf).into_result(), Err(Errno::EBADF));
+    assert_eq!(close(NO_FD).into_result(), Err(Errno::EBADF));
+    assert_eq!(dup(NO_FD).into_result(), Err(Errno::EBADF));
+    assert_eq!(write(0, &buf).into_result(), Err(Errno::EBADF));
+    assert_eq!(read(1, &mut buf).into_result(), Err(Errno::EBADF));
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    assert_eq!(read(pipe_fd[1], &mut buf).into_result(), Err(Errno::EBADF));
+    assert_eq!(write(pipe_fd[0], &buf).into_result(), Err(Errno::EBADF));
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+}
+
+fn bad_arguments() {
+    let path = "errno_test\0".as_ptr() as usize;
+    let open_args = [AT_FDCWD as usize, path, 1 << 30, 0, 0, 0];
+    assert_eq!(
+        raw_syscall(SYSCALL_OPENAT, open_args).into_result(),
+        Err(Errno::EINVAL)
+    );
+    assert_eq!(
+        open("errno_no_such_file\0", OpenFlags::RDONLY).into_result(),
+        Err(Errno::ENOENT)
+    );
+    let mut status = 0i32;
+    let waitpid_args = [usize::MAX, &mut status as *mut _ as usize, 1 << 7, 0, 0, 0];
+    assert_eq!(
+        raw_syscall(SYSCALL_WAIT4, waitpid_args).into_result(),
+        Err(Errno::EINVAL)
+    );
+    assert_eq!(wait(&mut status).into_result(), Err(Errno::ECHILD));
+    // memory which isn't mapped
+    let write_args = [1, 0x10, 4, 0, 0, 0];
+    assert_eq!(
+        raw_syscall(SYSCALL_WRITE, write_args).into_result(),
+        Err(Errno::EFAULT)
+    );
+}
+
+/// Unlock a mutex held by another thread.
+fn foreign_unlock(mutex: usize) -> ! {
+    thread_exit((mutex_unlock(mutex).into_result() == Err(Errno::EPERM)) as i32)
+}
+
+/// Ids which were never handed out, and unlocks of locks the caller
+/// doesn't hold.
+fn sync_objects() {
+    assert_eq!(mutex_lock(NO_ID).into_result(), Err(Errno::EINVAL));
+    assert_eq!(mutex_unlock(NO_ID).into_result(), Err(Errno::EINVAL));
+    assert_eq!(semaphore_down(NO_ID).into_result(), Err(Errno::EINVAL));
+    assert_eq!(rwlock_unlock(NO_ID).into_result(), Err(Errno::EINVAL));
+    for mutex in [mutex_create(), mutex_blocking_create()].iter() {
+        let mutex = *mutex as usize;
+        assert_eq!(mutex_unlock(mutex).into_result(), Err(Errno::EPERM));
+        assert_eq!(mutex_lock(mutex), 0);
+        let tid = thread_create(foreign_unlock as usize, mutex);
+        assert_eq!(waittid(tid as usize), 1);
+        assert_eq!(mutex_unlock(mutex), 0);
+        assert_eq!(mutex_unlock(mutex).into_result(), Err(Errno::EPERM));
+    }
+    let rwlock = rwlock_create(RWLOCK_PREFER_READER) as usize;
+    assert_eq!(rwlock_unlock(rwlock).into_result(), Err(Errno::EPERM));
+}
+
+/// A file which isn't a program can't be run, and the caller goes on.
+fn exec_not_elf() {
+    let fd = open(
+        NOT_ELF,
+        OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::WRONLY,
+    )
+    .into_result()
+    .unwrap();
+    assert_eq!(write(fd, b"#!/bin/sh\n"), 10);
+    close(fd);
+    let args = [NOT_ELF.as_ptr(), core::ptr::null::<u8>()];
+    assert_eq!(exec(NOT_ELF, &args).into_result(), Err(Errno::ENOEXEC));
+    assert_eq!(
+        exec("errno_no_such_file\0", &args).into_result(),
+        Err(Errno::ENOENT)
+    );
+}
+
+/// An ELF header for RISC-V with two LOAD segments of `[start, end)`, which
+/// take no bytes of the file.
+fn elf_with_loads(loads: [(u64, u64); 2]) -> [u8; EHDR_SIZE + 2 * PHDR_SIZE] {
+    let mut elf = [0u8; EHDR_SIZE + 2 * PHDR_SIZE];
+    // 64-bit, little endian, version 1
+    elf[..7].copy_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1]);
+    // executable for RISC-V
+    elf[16..20].copy_from_slice(&[2, 0, 0xf3, 0]);
+    elf[20..24].copy_from_slice(&1u32.to_le_bytes());
+    elf[24..32].copy_from_slice(&loads[0].0.to_le_bytes());
+    elf[32..40].copy_from_slice(&(EHDR_SIZE as u64).to_le_bytes());
+    elf[52..54].copy_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+    elf[54..56].copy_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+    elf[56..58].copy_from_slice(&2u16.to_le_bytes());
+    for (i, (start, end)) in loads.iter().enumerate() {
+        let ph = &mut elf[EHDR_SIZE + i * PHDR_SIZE..EHDR_SIZE + (i + 1) * PHDR_SIZE];
+        // PT_LOAD, readable and executable
+        ph[0..4].copy_from_slice(&1u32.to_le_bytes());
+        ph[4..8].copy_from_slice(&5u32.to_le_bytes());
+        ph[16..24].copy_from_slice(&start.to_le_bytes());
+        ph[24..32].copy_from_slice(&start.to_le_bytes());
+        ph[40..48].copy_from_slice(&(end - start).to_le_bytes());
+        ph[48..56].copy_from_slice(&PAGE_SIZE.to_le_bytes());
+    }
+    elf
+}
+
+/// Segments which would be mapped over each other or over the trampolines
+/// are refused before the program is replaced.
+fn exec_bad_segments() {
+    let args = [BAD_ELF.as_ptr(), core::ptr::null::<u8>()];
+    let overlapping = [(0x10000, 0x11800), (0x11400, 0x12000)];
+    let trampoline = [(0x10000, 0x11000), (0x20000, SIGRETURN_TRAMPOLINE + 8)];
+    for loads in [overlapping, trampoline].iter() {
+        let fd = open(
+            BAD_ELF,
+            OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::WRONLY,
+        )
+        .into_result()
+        .unwrap();
+        let elf = elf_with_loads(*loads);
+        assert_eq!(write(fd, &elf), elf.len() as isize);
+        close(fd);
+        assert_eq!(exec(BAD_ELF, &args).into_result(), Err(Errno::ENOEXEC));
+    }
+}
+
+/// The console takes any bytes, not only UTF-8.
+fn console_bytes() {
+    let bytes = [0xff, 0xfe, b'\n'];
+    assert_eq!(write(1, &bytes), bytes.len() as isize);
+}
+
+static TESTS: &[(fn(), &str)] = &[
+    (unknown_syscalls, "unknown_syscalls"),
+    (bad_fds, "bad_fds"),
+    (bad_arguments, "bad_arguments"),
+    (sync_objects, "sync_objects"),
+    (exec_not_elf, "exec_not_elf"),
+    (exec_bad_segments, "exec_bad_segments"),
+    (console_bytes, "console_bytes"),
+];
+
+#[no_mangle]
+pub fn main() -> i32 {
+    run_tests("errno_test", TESTS);
+    0
+}
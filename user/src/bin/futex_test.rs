@@ -0,0 +1,162 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+extern crate user_lib;
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+use user_lib::sync::{Condvar, Mutex};
+use user_lib::{
+    exit, fork, futex_wait, futex_wake, mmap, munmap, run_tests, shm_attach, shm_create,
+    shm_detach, shm_remove, sleep, thread_create, waitpid, waittid, Errno, IntoResult, MmapFlags,
+    MmapProt,
+};
+
+const SHM_KEY: usize = 0x4655_5400;
+const PAGE_SIZE: usize = 4096;
+const THREAD_COUNT: usize = 8;
+const ITEMS: usize = 1000;
+const QUEUE_LEN: usize = 4;
+
+static MUTEX: Mutex = Mutex::new();
+static NOT_EMPTY: Condvar = Condvar::new();
+static NOT_FULL: Condvar = Condvar::new();
+static GATE: Condvar = Condvar::new();
+static mut QUEUE: [usize; QUEUE_LEN] = [0; QUEUE_LEN];
+static mut HEAD: usize = 0;
+static mut TAIL: usize = 0;
+static mut OPEN: bool = false;
+
+fn syscalls() {
+    let word = AtomicU32::new(1);
+    // the word doesn't hold the expected value
+    assert_eq!(futex_wait(&word, 0).into_result(), Err(Errno::EAGAIN));
+    // nobody to wake up
+    assert_eq!(futex_wake(&word, 1), 0);
+}
+
+unsafe fn producer() -> ! {
+    for i in 0..ITEMS {
+        MUTEX.lock();
+        while TAIL - HEAD == QUEUE_LEN {
+            NOT_FULL.wait(&MUTEX);
+        }
+        QUEUE[TAIL % QUEUE_LEN] = i;
+        TAIL += 1;
+        NOT_EMPTY.signal();
+        MUTEX.unlock();
+    }
+    exit(0)
+}
+
+unsafe fn consumer() -> ! {
+    let mut sum = 0;
+    for i in 0..ITEMS {
+        MUTEX.lock();
+        while TAIL == HEAD {
+            NOT_EMPTY.wait(&MUTEX);
+        }
+        let item = QUEUE[HEAD % QUEUE_LEN];
+        assert_eq!(item, i);
+        sum += item;
+        HEAD += 1;
+        NOT_FULL.signal();
+        MUTEX.unlock();
+    }
+    exit((sum == ITEMS * (ITEMS - 1) / 2) as i32)
+}
+
+fn prodcons() {
+    let threads = [
+        thread_create(producer as usize, 0),
+        thread_create(consumer as usize, 0),
+    ];
+    assert_eq!(waittid(threads[0] as usize), 0);
+    assert_eq!(waittid(threads[1] as usize), 1);
+}
+
+unsafe fn gate_waiter() -> ! {
+    MUTEX.lock();
+    while !OPEN {
+        GATE.wait(&MUTEX);
+    }
+    MUTEX.unlock();
+    exit(0)
+}
+
+/// Waiters are requeued to the mutex by broadcast and must all get it.
+fn broadcast() {
+    let threads: Vec<_> = (0..THREAD_COUNT)
+        .map(|_| thread_create(gate_waiter as usize, 0))
+        .collect();
+    sleep(20);
+    MUTEX.lock();
+    unsafe {
+        OPEN = true;
+    }
+    GATE.broadcast();
+    MUTEX.unlock();
+    for tid in threads {
+        assert_eq!(waittid(tid as usize), 0);
+    }
+}
+
+/// The queue is keyed on the physical address, so a word in shared memory
+/// works across processes.
+fn shared_memory() {
+    let id = shm_create(SHM_KEY, core::mem::size_of::<AtomicU32>());
+    assert!(id >= 0);
+    let addr = shm_attach(id as usize);
+    assert!(addr > 0);
+    let word = unsafe { &*(addr as usize as *const AtomicU32) };
+    word.store(0, Ordering::Relaxed);
+    let pid = fork();
+    if pid == 0 {
+        while word.load(Ordering::Acquire) == 0 {
+            futex_wait(word, 0);
+        }
+        exit(0);
+    }
+    sleep(20);
+    word.store(1, Ordering::Release);
+    futex_wake(word, 1);
+    let mut exit_code = -1;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(shm_detach(addr as usize), 0);
+    assert_eq!(shm_remove(id as usize), 0);
+}
+
+fn unmapped_waiter(addr: usize) -> ! {
+    let word = unsafe { &*(addr as *const AtomicU32) };
+    futex_wait(word, 0);
+    exit(0)
+}
+
+/// Waiters on a word which is unmapped are woken up, the frame it was in
+/// may be reused for another word.
+fn unmapped() {
+    let prot = MmapProt::READ | MmapProt::WRITE;
+    let flags = MmapFlags::PRIVATE | MmapFlags::ANONYMOUS;
+    let addr = mmap(0, PAGE_SIZE, prot, flags, -1, 0);
+    assert!(addr > 0);
+    let tid = thread_create(unmapped_waiter as usize, addr as usize);
+    sleep(20);
+    assert_eq!(munmap(addr as usize, PAGE_SIZE), 0);
+    assert_eq!(waittid(tid as usize), 0);
+}
+
+static TESTS: &[(fn(), &str)] = &[
+    (syscalls, "syscalls"),
+    (prodcons, "prodcons"),
+    (broadcast, "broadcast"),
+    (shared_memory, "shared_memory"),
+    (unmapped, "unmapped"),
+];
+
+#[no_mangle]
+pub fn main() -> i32 {
+    run_tests("futex_test", TESTS);
+    0
+}
@@ -0,0 +1,180 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{
+    exit, fork, getpgid, getpid, getsid, is_continued, kill, killpg, read, run_tests, setpgid,
+    setsid, sigaction, sleep, stop_signal, tcgetpgrp, tcsetpgrp, waitpid, waitpid_flags, Errno,
+    IntoResult, SignalAction, WaitFlags, SIGCHLD, SIGCONT, SIGKILL, SIGSTOP, SIGTTIN,
+};
+
+const NO_PROCESS: usize = 10000;
+
+static CHLD_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn chld_handler(signum: i32) {
+    assert_eq!(signum, SIGCHLD);
+    CHLD_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+fn forever() -> ! {
+    loop {
+        sleep(10);
+    }
+}
+
+fn spawn_forever() -> usize {
+    let pid = fork();
+    if pid == 0 {
+        forever();
+    }
+    pid as usize
+}
+
+fn kill_and_wait(pid: usize) {
+    let mut exit_code = 0;
+    assert_eq!(kill(pid, SIGKILL), 0);
+    assert_eq!(waitpid(pid, &mut exit_code), pid as isize);
+    assert_eq!(exit_code, -SIGKILL);
+}
+
+/// A child starts in the group of its parent, and can be moved to a group
+/// of its own or to another group of the session.
+fn groups() {
+    let pgid = getpgid(0);
+    let sid = getsid(0);
+    assert_eq!(getpgid(getpid() as usize), pgid);
+    let first = spawn_forever();
+    assert_eq!(getpgid(first), pgid);
+    assert_eq!(getsid(first), sid);
+    assert_eq!(setpgid(first, 0), 0);
+    assert_eq!(getpgid(first), first as isize);
+    let second = spawn_forever();
+    assert_eq!(setpgid(second, NO_PROCESS).into_result(), Err(Errno::EPERM));
+    assert_eq!(setpgid(second, first), 0);
+    assert_eq!(getpgid(second), first as isize);
+    // only the caller and its children
+    assert_eq!(setpgid(NO_PROCESS, 0).into_result(), Err(Errno::ESRCH));
+    // the whole group is killed and waited for
+    assert_eq!(killpg(first, SIGKILL), 0);
+    let mut exit_code = 0;
+    for _ in 0..2 {
+        let pid = waitpid_flags(-(first as isize), &mut exit_code, WaitFlags::empty());
+        assert!(pid == first as isize || pid == second as isize);
+        assert_eq!(exit_code, -SIGKILL);
+    }
+    assert_eq!(
+        waitpid_flags(-(first as isize), &mut exit_code, WaitFlags::empty()).into_result(),
+        Err(Errno::ECHILD)
+    );
+    assert_eq!(killpg(first, SIGKILL).into_result(), Err(Errno::ESRCH));
+}
+
+/// A new session is led by a process which doesn't lead a group yet, and
+/// its groups can't be joined from other sessions.
+fn sessions() {
+    let pid = fork();
+    if pid == 0 {
+        let pid = getpid();
+        let new = setsid() == pid && getsid(0) == pid && getpgid(0) == pid;
+        let again = setsid() == Errno::EPERM.as_ret();
+        // a session leader stays in its group
+        let fixed = setpgid(0, 0) == Errno::EPERM.as_ret();
+        exit((new && again && fixed) as i32);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 1);
+    let pid = fork();
+    if pid == 0 {
+        setpgid(0, 0);
+        exit((setsid() == Errno::EPERM.as_ret()) as i32);
+    }
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 1);
+    let leader = fork();
+    if leader == 0 {
+        setsid();
+        forever();
+    }
+    let leader = leader as usize;
+    while getsid(leader) != leader as isize {
+        sleep(1);
+    }
+    let other = spawn_forever();
+    assert_eq!(setpgid(other, leader).into_result(), Err(Errno::EPERM));
+    assert_eq!(setpgid(leader, 0).into_result(), Err(Errno::EPERM));
+    kill_and_wait(other);
+    kill_and_wait(leader);
+}
+
+/// Stops and continues are reported once each, only when asked for, and
+/// stops raise SIGCHLD in the parent.
+fn stop_and_continue() {
+    let action = SignalAction {
+        handler: chld_handler as usize,
+        ..SignalAction::default()
+    };
+    assert_eq!(sigaction(SIGCHLD, Some(&action), None), 0);
+    CHLD_COUNT.store(0, Ordering::Relaxed);
+    let pid = spawn_forever();
+    let mut status = 0;
+    assert_eq!(kill(pid, SIGSTOP), 0);
+    sleep(50);
+    assert_eq!(
+        waitpid_flags(pid as isize, &mut status, WaitFlags::WNOHANG),
+        0
+    );
+    assert_eq!(
+        waitpid_flags(pid as isize, &mut status, WaitFlags::WUNTRACED),
+        pid as isize
+    );
+    assert_eq!(stop_signal(status), Some(SIGSTOP));
+    assert_eq!(CHLD_COUNT.load(Ordering::Relaxed), 1);
+    let flags = WaitFlags::WUNTRACED | WaitFlags::WNOHANG;
+    assert_eq!(waitpid_flags(pid as isize, &mut status, flags), 0);
+    assert_eq!(kill(pid, SIGCONT), 0);
+    assert_eq!(
+        waitpid_flags(pid as isize, &mut status, WaitFlags::WCONTINUED),
+        pid as isize
+    );
+    assert!(is_continued(status));
+    kill_and_wait(pid);
+    assert_eq!(sigaction(SIGCHLD, Some(&SignalAction::default()), None), 0);
+}
+
+/// A process outside the foreground group of the console is stopped by
+/// SIGTTIN when it reads from it.
+fn background_read() {
+    let pgid = getpgid(0);
+    assert_eq!(tcgetpgrp(0), pgid);
+    assert_eq!(tcsetpgrp(0, NO_PROCESS).into_result(), Err(Errno::EPERM));
+    let pid = fork();
+    if pid == 0 {
+        setpgid(0, 0);
+        let mut buf = [0u8; 1];
+        read(0, &mut buf);
+        exit(0);
+    }
+    setpgid(pid as usize, 0);
+    let mut status = 0;
+    assert_eq!(waitpid_flags(pid, &mut status, WaitFlags::WUNTRACED), pid);
+    assert_eq!(stop_signal(status), Some(SIGTTIN));
+    assert_eq!(tcgetpgrp(0), pgid);
+    kill_and_wait(pid as usize);
+}
+
+static TESTS: &[(fn(), &str)] = &[
+    (groups, "groups"),
+    (sessions, "sessions"),
+    (stop_and_continue, "stop_and_continue"),
+    (background_read, "background_read"),
+];
+
+#[no_mangle]
+pub fn main() -> i32 {
+    run_tests("job_control", TESTS);
+    0
+}
@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, get_time, swap_stat, wait, SwapStat};
+
+const PAGE_SIZE: usize = 4096;
+const PAGES: usize = 4096;
+const STEP: usize = 128;
+const TOUCHED: usize = PAGES / STEP;
+
+// 16MiB in .bss, twice the physical memory, so that the program can only be
+// loaded if the pages get a frame when they are touched
+static mut BIG: [u8; PAGE_SIZE * PAGES] = [0; PAGE_SIZE * PAGES];
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let start = get_time();
+    let mut before = SwapStat::default();
+    swap_stat(&mut before);
+    unsafe {
+        for page in (0..PAGES).step_by(STEP) {
+            let p = &mut BIG[page * PAGE_SIZE] as *mut u8;
+            assert_eq!(p.read_volatile(), 0);
+            p.write_volatile(page as u8 + 1);
+        }
+        // each page touched has been faulted in, and only those took frames,
+        // a few page tables aside
+        let mut after = SwapStat::default();
+        swap_stat(&mut after);
+        assert!(after.page_faults - before.page_faults >= TOUCHED);
+        assert!(before.free_frames.saturating_sub(after.free_frames) < 2 * TOUCHED);
+        let pid = fork();
+        if pid == 0 {
+            // untouched pages are still lazy in the child
+            for page in (0..PAGES).step_by(STEP) {
+                let p = &mut BIG[page * PAGE_SIZE + 1] as *mut u8;
+                assert_eq!(p.read_volatile(), 0);
+                assert_eq!(BIG[page * PAGE_SIZE], page as u8 + 1);
+            }
+            exit(0);
+        }
+        let mut exit_code: i32 = 0;
+        assert_eq!(wait(&mut exit_code), pid);
+        assert_eq!(exit_code, 0);
+        for page in (0..PAGES).step_by(STEP) {
+            assert_eq!(BIG[page * PAGE_SIZE], page as u8 + 1);
+        }
+    }
+    println!("lazy_alloc passed, time cost = {}ms", get_time() - start);
+    0
+}
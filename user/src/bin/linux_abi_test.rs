@@ -0,0 +1,300 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::ptr::null;
+use user_lib::{
+    close, execve, exit, fork, getpid, kill, open, pipe, raw_syscall, read, run_tests, waitpid,
+    Errno, IntoResult, OpenFlags, AT_FDCWD, SIGKILL,
+};
+
+const SYSCALL_GETCWD: usize = 17;
+const SYSCALL_DUP3: usize = 24;
+const SYSCALL_FCNTL: usize = 25;
+const SYSCALL_GETDENTS64: usize = 61;
+const SYSCALL_READV: usize = 65;
+const SYSCALL_WRITEV: usize = 66;
+const SYSCALL_NEWFSTATAT: usize = 79;
+const SYSCALL_FSTAT: usize = 80;
+const SYSCALL_EXIT_GROUP: usize = 94;
+const SYSCALL_SET_TID_ADDRESS: usize = 96;
+const SYSCALL_CLOCK_GETTIME: usize = 113;
+const SYSCALL_UNAME: usize = 160;
+const SYSCALL_GETPPID: usize = 173;
+const SYSCALL_GETUID: usize = 174;
+const SYSCALL_WAIT4: usize = 260;
+const F_DUPFD: usize = 0;
+const CLOCK_MONOTONIC: usize = 1;
+const CLOCK_PROCESS_CPUTIME_ID: usize = 2;
+const S_IFMT: u32 = 0o170000;
+const S_IFREG: u32 = 0o100000;
+const S_IFDIR: u32 = 0o040000;
+const UTS_FIELD: usize = 65;
+
+fn syscall(id: usize, args: &[usize]) -> Result<usize, Errno> {
+    let mut all = [0; 6];
+    all[..args.len()].copy_from_slice(args);
+    raw_syscall(id, all).into_result()
+}
+
+/// `st_mode` and `st_size` of a `struct stat`.
+fn mode_and_size(stat: &[u8; 128]) -> (u32, u64) {
+    let mut mode = [0; 4];
+    mode.copy_from_slice(&stat[16..20]);
+    let mut size = [0; 8];
+    size.copy_from_slice(&stat[48..56]);
+    (u32::from_ne_bytes(mode), u64::from_ne_bytes(size))
+}
+
+/// The `\0`-terminated string at the start of `bytes`.
+fn c_str(bytes: &[u8]) -> &[u8] {
+    &bytes[..bytes.iter().position(|b| *b == 0).unwrap()]
+}
+
+fn uname() {
+    let mut uts = [0u8; 6 * UTS_FIELD];
+    assert_eq!(syscall(SYSCALL_UNAME, &[uts.as_mut_ptr() as usize]), Ok(0));
+    assert_eq!(c_str(&uts), b"rCore");
+    assert_eq!(c_str(&uts[4 * UTS_FIELD..]), b"riscv64");
+}
+
+fn ids() {
+    for id in SYSCALL_GETUID..SYSCALL_GETUID + 4 {
+        assert_eq!(syscall(id, &[]), Ok(0));
+    }
+    // the main thread has the tid of the process
+    let mut word = 0u32;
+    let tidptr = &mut word as *mut _ as usize;
+    assert_eq!(
+        syscall(SYSCALL_SET_TID_ADDRESS, &[tidptr]),
+        Ok(getpid() as usize)
+    );
+    let pid = fork();
+    if pid == 0 {
+        let ppid = syscall(SYSCALL_GETPPID, &[]).unwrap();
+        exit(ppid as i32);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, getpid() as i32);
+}
+
+fn clocks() {
+    let mut before = [0usize; 2];
+    let mut after = [0usize; 2];
+    for time in [&mut before, &mut after].iter_mut() {
+        let tp = time.as_mut_ptr() as usize;
+        assert_eq!(
+            syscall(SYSCALL_CLOCK_GETTIME, &[CLOCK_MONOTONIC, tp]),
+            Ok(0)
+        );
+        assert!(time[1] < 1_000_000_000);
+    }
+    assert!(after >= before);
+    let tp = before.as_mut_ptr() as usize;
+    assert_eq!(
+        syscall(SYSCALL_CLOCK_GETTIME, &[CLOCK_PROCESS_CPUTIME_ID, tp]),
+        Err(Errno::EINVAL)
+    );
+}
+
+/// Vectored I/O, and fds duplicated to a number of the caller's choosing.
+fn fds() {
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    let (head, tail) = (b"Hello, ", b"world!");
+    let iov = [
+        [head.as_ptr() as usize, head.len()],
+        [tail.as_ptr() as usize, tail.len()],
+    ];
+    let iov_ptr = iov.as_ptr() as usize;
+    assert_eq!(syscall(SYSCALL_WRITEV, &[pipe_fd[1], iov_ptr, 2]), Ok(13));
+    let dup_fd = 50;
+    assert_eq!(syscall(SYSCALL_DUP3, &[pipe_fd[0], dup_fd, 0]), Ok(dup_fd));
+    assert_eq!(
+        syscall(SYSCALL_DUP3, &[dup_fd, dup_fd, 0]),
+        Err(Errno::EINVAL)
+    );
+    let low_fd = syscall(SYSCALL_FCNTL, &[dup_fd, F_DUPFD, 60]).unwrap();
+    assert!(low_fd >= 60);
+    let (mut first, mut second) = ([0u8; 4], [0u8; 9]);
+    let iov = [
+        [first.as_mut_ptr() as usize, first.len()],
+        [second.as_mut_ptr() as usize, second.len()],
+    ];
+    let iov_ptr = iov.as_ptr() as usize;
+    assert_eq!(syscall(SYSCALL_READV, &[low_fd, iov_ptr, 2]), Ok(13));
+    assert_eq!(&first, b"Hell");
+    assert_eq!(&second, b"o, world!");
+    for fd in [pipe_fd[0], pipe_fd[1], dup_fd, low_fd].iter() {
+        close(*fd);
+    }
+}
+
+/// Files and the root directory, which is the working directory as well.
+fn files() {
+    let mut stat = [0u8; 128];
+    let stat_ptr = stat.as_mut_ptr() as usize;
+    let fd = open("linux_abi_test\0", OpenFlags::RDONLY)
+        .into_result()
+        .unwrap();
+    assert_eq!(syscall(SYSCALL_FSTAT, &[fd, stat_ptr]), Ok(0));
+    let (mode, size) = mode_and_size(&stat);
+    assert_eq!(mode & S_IFMT, S_IFREG);
+    assert!(size > 0);
+    close(fd);
+    let dot = ".\0".as_ptr() as usize;
+    let args = [AT_FDCWD as usize, dot, stat_ptr, 0];
+    assert_eq!(syscall(SYSCALL_NEWFSTATAT, &args), Ok(0));
+    assert_eq!(mode_and_size(&stat).0 & S_IFMT, S_IFDIR);
+    let mut cwd = [0u8; 16];
+    let cwd_ptr = cwd.as_mut_ptr() as usize;
+    assert_eq!(syscall(SYSCALL_GETCWD, &[cwd_ptr, cwd.len()]), Ok(2));
+    assert_eq!(c_str(&cwd), b"/");
+    assert_eq!(syscall(SYSCALL_GETCWD, &[cwd_ptr, 1]), Err(Errno::ERANGE));
+    assert_eq!(
+        open("linux_abi_test\0", OpenFlags::DIRECTORY).into_result(),
+        Err(Errno::ENOTDIR)
+    );
+    let fd = open(".\0", OpenFlags::DIRECTORY).into_result().unwrap();
+    let mut names: Vec<Vec<u8>> = Vec::new();
+    let mut buf = [0u8; 512];
+    loop {
+        let buf_ptr = buf.as_mut_ptr() as usize;
+        let len = syscall(SYSCALL_GETDENTS64, &[fd, buf_ptr, buf.len()]).unwrap();
+        if len == 0 {
+            break;
+        }
+        // d_ino, d_off, d_reclen, d_type and d_name
+        let mut offset = 0;
+        while offset < len {
+            let record = &buf[offset..len];
+            let reclen = u16::from_ne_bytes([record[16], record[17]]) as usize;
+            names.push(c_str(&record[19..reclen]).to_vec());
+            offset += reclen;
+        }
+    }
+    close(fd);
+    assert_eq!(names[0], b".");
+    assert_eq!(names[1], b"..");
+    assert!(names.iter().any(|name| name == b"linux_abi_test"));
+}
+
+/// Statuses of `wait4` are encoded as on Linux, those of the native
+/// waitpid are not.
+fn wait_status() {
+    let pid = fork();
+    if pid == 0 {
+        syscall(SYSCALL_EXIT_GROUP, &[0x1234]).unwrap();
+        unreachable!();
+    }
+    let mut status = 0i32;
+    let status_ptr = &mut status as *mut _ as usize;
+    let mut rusage = [0xffu8; 144];
+    let args = [pid as usize, status_ptr, 0, rusage.as_mut_ptr() as usize];
+    assert_eq!(syscall(SYSCALL_WAIT4, &args), Ok(pid as usize));
+    assert_eq!(status, 0x34 << 8);
+    assert!(rusage.iter().all(|b| *b == 0));
+    for native in [false, true].iter() {
+        let pid = fork();
+        if pid == 0 {
+            loop {}
+        }
+        kill(pid as usize, SIGKILL);
+        if *native {
+            assert_eq!(waitpid(pid as usize, &mut status), pid);
+            assert_eq!(status, -SIGKILL);
+        } else {
+            let args = [pid as usize, status_ptr, 0, 0];
+            assert_eq!(syscall(SYSCALL_WAIT4, &args), Ok(pid as usize));
+            assert_eq!(status, SIGKILL);
+        }
+    }
+}
+
+/// Run `path` with its output going to a pipe, return the output and the
+/// exit code, or None if the program wasn't packed into the image.
+fn run_captured(path: &str, args: &[&str], envs: &[&str]) -> Option<(Vec<u8>, i32)> {
+    let fd = open(path, OpenFlags::RDONLY).into_result().ok()?;
+    close(fd);
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    let pid = fork();
+    if pid == 0 {
+        syscall(SYSCALL_DUP3, &[pipe_fd[1], 1, 0]).unwrap();
+        close(pipe_fd[0]);
+        close(pipe_fd[1]);
+        let mut argv: Vec<*const u8> = args.iter().map(|arg| arg.as_ptr()).collect();
+        argv.push(null());
+        let mut envp: Vec<*const u8> = envs.iter().map(|env| env.as_ptr()).collect();
+        envp.push(null());
+        let err = execve(path, &argv, &envp).into_result().unwrap_err();
+        println!("linux_abi_test: can't run {}: {}", path, err);
+        exit(-1);
+    }
+    close(pipe_fd[1]);
+    let mut output = Vec::new();
+    let mut buf = [0u8; 64];
+    loop {
+        let len = read(pipe_fd[0], &mut buf);
+        assert!(len >= 0);
+        if len == 0 {
+            break;
+        }
+        output.extend_from_slice(&buf[..len as usize]);
+    }
+    close(pipe_fd[0]);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    Some((output, exit_code))
+}
+
+/// The programs of user/linux, skipped unless they were packed.
+fn static_programs() {
+    let hello = run_captured(
+        "hello_musl\0",
+        &["hello_musl\0", "arg\0"],
+        &["HOME=/root\0"],
+    );
+    match hello {
+        Some((output, exit_code)) => {
+            let expected = b"Hello from musl! argc = 2, argv[0] = hello_musl, HOME = /root\n";
+            assert_eq!(output.as_slice(), &expected[..]);
+            assert_eq!(exit_code, 0);
+        }
+        None => println!("linux_abi_test: no hello_musl, skipped"),
+    }
+    let sh = run_captured(
+        "busybox\0",
+        &["sh\0", "-c\0", "echo $((6 * 7)); exit 3\0"],
+        &["PATH=/\0"],
+    );
+    match sh {
+        Some((output, exit_code)) => {
+            assert_eq!(output.as_slice(), b"42\n");
+            assert_eq!(exit_code, 3);
+        }
+        None => println!("linux_abi_test: no busybox, skipped"),
+    }
+}
+
+static TESTS: &[(fn(), &str)] = &[
+    (uname, "uname"),
+    (ids, "ids"),
+    (clocks, "clocks"),
+    (fds, "fds"),
+    (files, "files"),
+    (wait_status, "wait_status"),
+    (static_programs, "static_programs"),
+];
+
+#[no_mangle]
+pub fn main() -> i32 {
+    run_tests("linux_abi_test", TESTS);
+    0
+}
@@ -0,0 +1,183 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec;
+use user_lib::{
+    close, exit, fork, mmap, mprotect, munmap, open, read, wait, write, Errno, IntoResult,
+    MmapFlags, MmapProt, OpenFlags,
+};
+
+const PAGE_SIZE: usize = 4096;
+const FILE_NAME: &str = "mmap_file\0";
+const FILE_LEN: usize = PAGE_SIZE + 100;
+/// Far more than physical memory, only the pages touched get frames.
+const LARGE_PAGES: usize = 16384;
+
+fn page(addr: usize, idx: usize) -> &'static mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut((addr + idx * PAGE_SIZE) as *mut u8, PAGE_SIZE) }
+}
+
+fn anonymous_private() {
+    let rw = MmapProt::READ | MmapProt::WRITE;
+    let flags = MmapFlags::PRIVATE | MmapFlags::ANONYMOUS;
+    let addr = mmap(0, 3 * PAGE_SIZE, rw, flags, -1, 0);
+    assert!(addr > 0);
+    let addr = addr as usize;
+    for idx in 0..3 {
+        assert!(page(addr, idx).iter().all(|b| *b == 0));
+        page(addr, idx).fill(idx as u8 + 1);
+    }
+    let pid = fork();
+    if pid == 0 {
+        page(addr, 0).fill(9);
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert!(page(addr, 0).iter().all(|b| *b == 1));
+    // punch a hole in the middle, the rest of the area stays mapped
+    assert_eq!(munmap(addr + PAGE_SIZE, PAGE_SIZE), 0);
+    assert!(page(addr, 0).iter().all(|b| *b == 1));
+    assert!(page(addr, 2).iter().all(|b| *b == 3));
+    // the hole can be mapped again at a fixed address
+    let fixed = MmapFlags::PRIVATE | MmapFlags::ANONYMOUS | MmapFlags::FIXED;
+    assert_eq!(
+        mmap(addr + PAGE_SIZE, PAGE_SIZE, rw, fixed, -1, 0),
+        (addr + PAGE_SIZE) as isize
+    );
+    assert!(page(addr, 1).iter().all(|b| *b == 0));
+    // a read-only page can still be read, but a store is fatal
+    assert_eq!(mprotect(addr, PAGE_SIZE, MmapProt::READ), 0);
+    assert!(page(addr, 0).iter().all(|b| *b == 1));
+    let pid = fork();
+    if pid == 0 {
+        page(addr, 0)[0] = 0;
+        exit(0);
+    }
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, -11);
+    assert_eq!(mprotect(addr, PAGE_SIZE, rw), 0);
+    page(addr, 0)[0] = 0;
+    assert_eq!(munmap(addr, 3 * PAGE_SIZE), 0);
+    // the range is no longer mapped
+    assert_eq!(
+        mprotect(addr, PAGE_SIZE, MmapProt::READ).into_result(),
+        Err(Errno::ENOMEM)
+    );
+    println!("mmap_test: anonymous private mappings ok.");
+}
+
+fn anonymous_shared() {
+    let rw = MmapProt::READ | MmapProt::WRITE;
+    let flags = MmapFlags::SHARED | MmapFlags::ANONYMOUS;
+    let addr = mmap(0, LARGE_PAGES * PAGE_SIZE, rw, flags, -1, 0);
+    assert!(addr > 0);
+    let addr = addr as usize;
+    // no page has been touched before the fork
+    let pid = fork();
+    if pid == 0 {
+        page(addr, 0).fill(0x5a);
+        page(addr, LARGE_PAGES - 1).fill(0xa5);
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    // the pages outlive the child
+    assert!(page(addr, 0).iter().all(|b| *b == 0x5a));
+    assert!(page(addr, LARGE_PAGES - 1).iter().all(|b| *b == 0xa5));
+    assert_eq!(munmap(addr, LARGE_PAGES * PAGE_SIZE), 0);
+    println!("mmap_test: anonymous shared mappings ok.");
+}
+
+fn read_file(buf: &mut [u8]) -> usize {
+    let fd = open(FILE_NAME, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let len = read(fd as usize, buf);
+    close(fd as usize);
+    len as usize
+}
+
+fn file_backed() {
+    let fd = open(
+        FILE_NAME,
+        OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::WRONLY,
+    );
+    assert!(fd > 0);
+    let content = vec![b'a'; FILE_LEN];
+    assert_eq!(write(fd as usize, &content), FILE_LEN as isize);
+    close(fd as usize);
+
+    let fd = open(FILE_NAME, OpenFlags::RDWR);
+    assert!(fd > 0);
+    let rw = MmapProt::READ | MmapProt::WRITE;
+    // private: the changes never reach the file
+    let addr = mmap(0, 2 * PAGE_SIZE, rw, MmapFlags::PRIVATE, fd, 0);
+    assert!(addr > 0);
+    let addr = addr as usize;
+    assert!(page(addr, 0).iter().all(|b| *b == b'a'));
+    assert!(page(addr, 1)[..100].iter().all(|b| *b == b'a'));
+    assert!(page(addr, 1)[100..].iter().all(|b| *b == 0));
+    page(addr, 0).fill(b'p');
+    assert_eq!(munmap(addr, 2 * PAGE_SIZE), 0);
+    // shared: the changes are written back, without growing the file
+    let addr = mmap(0, 2 * PAGE_SIZE, rw, MmapFlags::SHARED, fd, 0);
+    assert!(addr > 0);
+    let addr = addr as usize;
+    page(addr, 0)[..10].fill(b's');
+    page(addr, 1).fill(b's');
+    // another shared mapping of the file has the same pages
+    let other = mmap(0, PAGE_SIZE, rw, MmapFlags::SHARED, fd, PAGE_SIZE);
+    assert!(other > 0);
+    let other = other as usize;
+    assert!(page(other, 0).iter().all(|b| *b == b's'));
+    let pid = fork();
+    if pid == 0 {
+        page(other, 0)[0] = b'c';
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(page(addr, 1)[0], b'c');
+    page(addr, 1)[0] = b's';
+    assert_eq!(munmap(other, PAGE_SIZE), 0);
+    close(fd as usize);
+    assert_eq!(munmap(addr, 2 * PAGE_SIZE), 0);
+
+    let mut buf = vec![0u8; 2 * PAGE_SIZE];
+    assert_eq!(read_file(&mut buf), FILE_LEN);
+    assert!(buf[..10].iter().all(|b| *b == b's'));
+    assert!(buf[10..PAGE_SIZE].iter().all(|b| *b == b'a'));
+    assert!(buf[PAGE_SIZE..FILE_LEN].iter().all(|b| *b == b's'));
+
+    // a shared writable mapping needs a writable file
+    let fd = open(FILE_NAME, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    assert_eq!(
+        mmap(0, PAGE_SIZE, rw, MmapFlags::SHARED, fd, 0).into_result(),
+        Err(Errno::EACCES)
+    );
+    let addr = mmap(0, PAGE_SIZE, MmapProt::READ, MmapFlags::SHARED, fd, 0);
+    assert!(addr > 0);
+    assert_eq!(
+        mprotect(addr as usize, PAGE_SIZE, rw).into_result(),
+        Err(Errno::EACCES)
+    );
+    assert_eq!(munmap(addr as usize, PAGE_SIZE), 0);
+    close(fd as usize);
+    println!("mmap_test: file-backed mappings ok.");
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    anonymous_private();
+    anonymous_shared();
+    file_backed();
+    println!("mmap_test passed!");
+    0
+}
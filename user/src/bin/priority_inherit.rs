@@ -0,0 +1,162 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+extern crate user_lib;
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use user_lib::{
+    exit, get_time, mutex_blocking_create, mutex_lock, mutex_unlock, run_tests, sched_stat,
+    set_priority, sleep, thread_create, waittid, SchedStat,
+};
+
+const LOW: usize = 2;
+const MEDIUM: usize = 4;
+const HIGH: usize = 8;
+/// Enough medium priority threads to keep every hart busy.
+const MEDIUM_THREADS: usize = 4;
+const MEDIUM_MS: isize = 1000;
+const TIMEOUT_MS: isize = 3000;
+
+static mut MUTEXES: [usize; 2] = [0; 2];
+static LOCKED: AtomicBool = AtomicBool::new(false);
+static MEDIUM_DONE: AtomicUsize = AtomicUsize::new(0);
+
+fn priority() -> usize {
+    let mut stat = SchedStat::default();
+    assert_eq!(sched_stat(&mut stat), 0);
+    stat.priority
+}
+
+fn set(priority: usize) {
+    assert_eq!(set_priority(priority as isize), priority as isize);
+}
+
+fn mutex(idx: usize) -> usize {
+    unsafe { MUTEXES[idx] }
+}
+
+/// Wait for a waiter of a mutex held by this thread to lend its priority.
+fn wait_for_priority(expected: usize) -> bool {
+    let start = get_time();
+    while priority() != expected {
+        if get_time() - start > TIMEOUT_MS {
+            return false;
+        }
+    }
+    true
+}
+
+fn wait_until_locked() {
+    while !LOCKED.load(Ordering::Acquire) {
+        sleep(1);
+    }
+}
+
+/// Holds the mutex wanted by the high priority thread, and only gets to
+/// release it by inheriting the high priority.
+fn low() -> ! {
+    set(LOW);
+    mutex_lock(mutex(0));
+    LOCKED.store(true, Ordering::Release);
+    let inherited = wait_for_priority(HIGH);
+    mutex_unlock(mutex(0));
+    exit((inherited && priority() == LOW) as i32)
+}
+
+fn medium() -> ! {
+    set(MEDIUM);
+    let start = get_time();
+    while get_time() - start < MEDIUM_MS {
+        core::hint::spin_loop();
+    }
+    MEDIUM_DONE.fetch_add(1, Ordering::Release);
+    exit(1)
+}
+
+fn high() -> ! {
+    set(HIGH);
+    mutex_lock(mutex(0));
+    mutex_unlock(mutex(0));
+    exit((MEDIUM_DONE.load(Ordering::Acquire) == 0) as i32)
+}
+
+/// The classic case: with the priority scheduler, the medium priority
+/// threads would keep the owner of the mutex from running, and so the high
+/// priority thread from getting it, until they are done.
+fn inversion() {
+    unsafe {
+        MUTEXES[0] = mutex_blocking_create() as usize;
+    }
+    LOCKED.store(false, Ordering::Relaxed);
+    let mut threads = Vec::new();
+    threads.push(thread_create(low as usize, 0));
+    wait_until_locked();
+    for _ in 0..MEDIUM_THREADS {
+        threads.push(thread_create(medium as usize, 0));
+    }
+    threads.push(thread_create(high as usize, 0));
+    for tid in threads {
+        assert_eq!(waittid(tid as usize), 1);
+    }
+}
+
+/// Holds mutex 0, which the middle thread waits for.
+fn chain_low() -> ! {
+    set(LOW);
+    mutex_lock(mutex(0));
+    LOCKED.store(true, Ordering::Release);
+    // inherited from the high priority thread through the middle one
+    let inherited = wait_for_priority(HIGH);
+    mutex_unlock(mutex(0));
+    exit((inherited && priority() == LOW) as i32)
+}
+
+/// Holds mutex 1, which the high priority thread waits for.
+fn chain_middle() -> ! {
+    set(MEDIUM);
+    mutex_lock(mutex(1));
+    mutex_lock(mutex(0));
+    // the high priority thread still waits for mutex 1
+    let inherited = priority() == HIGH;
+    mutex_unlock(mutex(0));
+    let kept = priority() == HIGH;
+    mutex_unlock(mutex(1));
+    exit((inherited && kept && priority() == MEDIUM) as i32)
+}
+
+fn chain_high() -> ! {
+    set(HIGH);
+    mutex_lock(mutex(1));
+    mutex_unlock(mutex(1));
+    exit(1)
+}
+
+/// Priorities are passed along a chain of mutex owners.
+fn chain() {
+    unsafe {
+        MUTEXES = [
+            mutex_blocking_create() as usize,
+            mutex_blocking_create() as usize,
+        ];
+    }
+    LOCKED.store(false, Ordering::Relaxed);
+    let low = thread_create(chain_low as usize, 0);
+    wait_until_locked();
+    let middle = thread_create(chain_middle as usize, 0);
+    // let the middle thread take mutex 1 and block on mutex 0
+    sleep(20);
+    let high = thread_create(chain_high as usize, 0);
+    for tid in [low, middle, high].iter() {
+        assert_eq!(waittid(*tid as usize), 1);
+    }
+}
+
+static TESTS: &[(fn(), &str)] = &[(inversion, "inversion"), (chain, "chain")];
+
+#[no_mangle]
+pub fn main() -> i32 {
+    run_tests("priority_inherit", TESTS);
+    0
+}
@@ -0,0 +1,234 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use core::arch::asm;
+use user_lib::{
+    exec, exit, fork, getpid, kill, ptrace, ptrace_getregs, ptrace_peek, ptrace_poke,
+    ptrace_setregs, run_tests, stop_signal, waitpid, yield_, Errno, IntoResult, UserRegs,
+    PTRACE_ATTACH, PTRACE_CONT, PTRACE_DETACH, PTRACE_KILL, PTRACE_PEEKDATA, PTRACE_SINGLESTEP,
+    PTRACE_SYSCALL, PTRACE_TRACEME, SIGKILL, SIGSTOP, SIGTRAP, SIGUSR1, SIGUSR2,
+};
+
+const SYSCALL_GETPID: usize = 172;
+const REG_A0: usize = 10;
+const REG_A7: usize = 17;
+
+static mut VALUE: usize = 7;
+
+/// Stop at an `ebreak` with `value` in a0, which is incremented three
+/// times after it and returned.
+fn breakpoint(value: usize) -> usize {
+    let mut a0 = value;
+    unsafe {
+        asm!(
+            "ebreak",
+            "addi a0, a0, 1",
+            "addi a0, a0, 1",
+            "addi a0, a0, 1",
+            inout("a0") a0,
+        );
+    }
+    a0
+}
+
+/// Fork a child which is traced and runs `f` once it has stopped with
+/// SIGUSR1, then exits with what it returns.
+fn traced_child(f: fn() -> i32) -> usize {
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(ptrace(PTRACE_TRACEME, 0, 0, 0), 0);
+        kill(getpid() as usize, SIGUSR1);
+        exit(f());
+    }
+    let pid = pid as usize;
+    assert_eq!(wait_stop(pid), Some(SIGUSR1));
+    pid
+}
+
+/// The signal which has stopped child `pid`, or None if it has exited.
+fn wait_stop(pid: usize) -> Option<i32> {
+    let mut status = 0;
+    assert_eq!(waitpid(pid, &mut status), pid as isize);
+    stop_signal(status)
+}
+
+fn wait_exit(pid: usize, expected: i32) {
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), pid as isize);
+    assert_eq!(exit_code, expected);
+}
+
+/// The size of the instruction at `pc` of stopped child `pid`.
+fn inst_len(pid: usize, pc: usize) -> usize {
+    let mut word = 0;
+    assert_eq!(ptrace_peek(pid, pc, &mut word), 0);
+    if word & 0b11 == 0b11 {
+        4
+    } else {
+        2
+    }
+}
+
+/// The signals of a traced child stop it, and are only taken if the tracer
+/// lets them through.
+fn signals() {
+    // SIGUSR1 would kill it
+    let pid = traced_child(|| 0);
+    assert_eq!(ptrace(PTRACE_CONT, pid, 0, 0), 0);
+    wait_exit(pid, 0);
+    let pid = traced_child(|| 0);
+    assert_eq!(ptrace(PTRACE_CONT, pid, 0, SIGUSR2 as usize), 0);
+    wait_exit(pid, -SIGUSR2);
+}
+
+/// Memory is written in the child only, even where it is shared
+/// copy-on-write.
+fn memory() {
+    let pid = traced_child(|| unsafe { core::ptr::read_volatile(&VALUE) as i32 });
+    let addr = unsafe { &VALUE as *const usize as usize };
+    let mut word = 0;
+    assert_eq!(ptrace_peek(pid, addr, &mut word), 0);
+    assert_eq!(word, 7);
+    assert_eq!(ptrace_poke(pid, addr, 42), 0);
+    assert_eq!(ptrace(PTRACE_CONT, pid, 0, 0), 0);
+    wait_exit(pid, 42);
+    assert_eq!(unsafe { core::ptr::read_volatile(&VALUE) }, 7);
+    // code can be written, but nothing out of the address space
+    let code = breakpoint as usize;
+    let pid = traced_child(|| 0);
+    assert_eq!(ptrace_peek(pid, code, &mut word), 0);
+    assert_eq!(ptrace_poke(pid, code, word), 0);
+    assert_eq!(
+        ptrace(PTRACE_PEEKDATA, pid, 0, &mut word as *mut _ as usize).into_result(),
+        Err(Errno::EIO)
+    );
+    assert_eq!(ptrace_poke(pid, 0, 0).into_result(), Err(Errno::EIO));
+    assert_eq!(ptrace(PTRACE_KILL, pid, 0, 0), 0);
+    wait_exit(pid, -SIGKILL);
+}
+
+/// An `ebreak` stops the child with SIGTRAP at the `ebreak`, and the
+/// registers it goes on with can be changed.
+fn registers() {
+    let pid = traced_child(|| breakpoint(5) as i32);
+    assert_eq!(ptrace(PTRACE_CONT, pid, 0, 0), 0);
+    assert_eq!(wait_stop(pid), Some(SIGTRAP));
+    let mut regs: UserRegs = [0; 32];
+    assert_eq!(ptrace_getregs(pid, &mut regs), 0);
+    assert_eq!(regs[REG_A0], 5);
+    regs[0] += inst_len(pid, regs[0]);
+    regs[REG_A0] = 100;
+    assert_eq!(ptrace_setregs(pid, &regs), 0);
+    assert_eq!(ptrace(PTRACE_CONT, pid, 0, 0), 0);
+    wait_exit(pid, 103);
+}
+
+/// Each step runs one instruction.
+fn single_step() {
+    let pid = traced_child(|| breakpoint(5) as i32);
+    assert_eq!(ptrace(PTRACE_CONT, pid, 0, 0), 0);
+    assert_eq!(wait_stop(pid), Some(SIGTRAP));
+    let mut regs: UserRegs = [0; 32];
+    assert_eq!(ptrace_getregs(pid, &mut regs), 0);
+    regs[0] += inst_len(pid, regs[0]);
+    assert_eq!(ptrace_setregs(pid, &regs), 0);
+    for i in 1..=3 {
+        let pc = regs[0];
+        let expected = pc + inst_len(pid, pc);
+        assert_eq!(ptrace(PTRACE_SINGLESTEP, pid, 0, 0), 0);
+        assert_eq!(wait_stop(pid), Some(SIGTRAP));
+        assert_eq!(ptrace_getregs(pid, &mut regs), 0);
+        assert_eq!(regs[0], expected);
+        assert_eq!(regs[REG_A0], 5 + i);
+    }
+    // the code is as it was
+    assert_eq!(ptrace(PTRACE_CONT, pid, 0, 0), 0);
+    wait_exit(pid, 8);
+}
+
+/// With PTRACE_SYSCALL the child stops before and after its next syscall.
+fn syscalls() {
+    let pid = traced_child(|| (getpid() == 1234) as i32);
+    assert_eq!(ptrace(PTRACE_SYSCALL, pid, 0, 0), 0);
+    assert_eq!(wait_stop(pid), Some(SIGTRAP));
+    let mut regs: UserRegs = [0; 32];
+    assert_eq!(ptrace_getregs(pid, &mut regs), 0);
+    assert_eq!(regs[REG_A7], SYSCALL_GETPID);
+    assert_eq!(ptrace(PTRACE_SYSCALL, pid, 0, 0), 0);
+    assert_eq!(wait_stop(pid), Some(SIGTRAP));
+    assert_eq!(ptrace_getregs(pid, &mut regs), 0);
+    assert_eq!(regs[REG_A0], pid);
+    regs[REG_A0] = 1234;
+    assert_eq!(ptrace_setregs(pid, &regs), 0);
+    assert_eq!(ptrace(PTRACE_CONT, pid, 0, 0), 0);
+    wait_exit(pid, 1);
+}
+
+/// A traced child stops after exec, before the new program starts.
+fn exec_stop() {
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(ptrace(PTRACE_TRACEME, 0, 0, 0), 0);
+        assert_eq!(
+            ptrace(PTRACE_TRACEME, 0, 0, 0).into_result(),
+            Err(Errno::EPERM)
+        );
+        exec(
+            "hello_world\0",
+            &["hello_world\0".as_ptr(), core::ptr::null()],
+        );
+        exit(-1);
+    }
+    let pid = pid as usize;
+    assert_eq!(wait_stop(pid), Some(SIGTRAP));
+    assert_eq!(ptrace(PTRACE_DETACH, pid, 0, 0), 0);
+    wait_exit(pid, 0);
+}
+
+/// A running child is stopped with SIGSTOP when it is attached to.
+fn attach() {
+    let pid = fork();
+    if pid == 0 {
+        loop {
+            yield_();
+        }
+    }
+    let pid = pid as usize;
+    let mut word = 0;
+    // it isn't traced yet, and only children can be
+    assert_eq!(
+        ptrace(PTRACE_PEEKDATA, pid, 0, &mut word as *mut _ as usize).into_result(),
+        Err(Errno::ESRCH)
+    );
+    assert_eq!(
+        ptrace(PTRACE_ATTACH, getpid() as usize, 0, 0).into_result(),
+        Err(Errno::ESRCH)
+    );
+    assert_eq!(ptrace(PTRACE_ATTACH, pid, 0, 0), 0);
+    assert_eq!(
+        ptrace(PTRACE_ATTACH, pid, 0, 0).into_result(),
+        Err(Errno::EPERM)
+    );
+    assert_eq!(wait_stop(pid), Some(SIGSTOP));
+    assert_eq!(ptrace(1000, pid, 0, 0).into_result(), Err(Errno::EIO));
+    assert_eq!(ptrace(PTRACE_KILL, pid, 0, 0), 0);
+    wait_exit(pid, -SIGKILL);
+}
+
+static TESTS: &[(fn(), &str)] = &[
+    (signals, "signals"),
+    (memory, "memory"),
+    (registers, "registers"),
+    (single_step, "single_step"),
+    (syscalls, "syscalls"),
+    (exec_stop, "exec_stop"),
+    (attach, "attach"),
+];
+
+#[no_mangle]
+pub fn main() -> i32 {
+    run_tests("ptrace_test", TESTS);
+    0
+}
@@ -18,7 +18,7 @@ unsafe fn f() -> ! {
     let mut t = 2usize;
     for _ in 0..PER_THREAD {
         while OCCUPIED
-            .compare_exchange(false, true, Ordering::Relaxed, Ordering::Relaxed)
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_err()
         {
             yield_();
@@ -29,7 +29,7 @@ unsafe fn f() -> ! {
             t = t * t % 10007;
         }
         a.write_volatile(cur + 1);
-        OCCUPIED.store(false, Ordering::Relaxed);
+        OCCUPIED.store(false, Ordering::Release);
     }
     exit(t as i32)
 }
@@ -0,0 +1,128 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+extern crate user_lib;
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{
+    exit, run_tests, rwlock_create, rwlock_read_lock, rwlock_unlock, rwlock_write_lock, sleep,
+    thread_create, waittid, yield_, Errno, IntoResult, RWLOCK_PREFER_READER, RWLOCK_PREFER_WRITER,
+};
+
+const READERS: usize = 6;
+const WRITERS: usize = 2;
+const ROUNDS: usize = 200;
+
+static mut RWLOCK: usize = 0;
+/// Writers bump both, readers must never see them differ.
+static mut A: usize = 0;
+static mut B: usize = 0;
+static READING: AtomicUsize = AtomicUsize::new(0);
+static WRITING: AtomicUsize = AtomicUsize::new(0);
+/// Order in which the threads of `preference` got the lock.
+static TICKET: AtomicUsize = AtomicUsize::new(0);
+
+unsafe fn reader() -> ! {
+    for _ in 0..ROUNDS {
+        rwlock_read_lock(RWLOCK);
+        READING.fetch_add(1, Ordering::SeqCst);
+        assert_eq!(WRITING.load(Ordering::SeqCst), 0);
+        let a = A;
+        yield_();
+        assert_eq!(a, B);
+        READING.fetch_sub(1, Ordering::SeqCst);
+        rwlock_unlock(RWLOCK);
+    }
+    exit(0)
+}
+
+unsafe fn writer() -> ! {
+    for _ in 0..ROUNDS {
+        rwlock_write_lock(RWLOCK);
+        assert_eq!(WRITING.fetch_add(1, Ordering::SeqCst), 0);
+        assert_eq!(READING.load(Ordering::SeqCst), 0);
+        A += 1;
+        yield_();
+        B += 1;
+        WRITING.fetch_sub(1, Ordering::SeqCst);
+        rwlock_unlock(RWLOCK);
+    }
+    exit(0)
+}
+
+fn stress(policy: usize) {
+    unsafe {
+        RWLOCK = rwlock_create(policy) as usize;
+        A = 0;
+        B = 0;
+    }
+    let mut threads: Vec<_> = (0..WRITERS)
+        .map(|_| thread_create(writer as usize, 0))
+        .collect();
+    threads.extend((0..READERS).map(|_| thread_create(reader as usize, 0)));
+    for tid in threads {
+        assert_eq!(waittid(tid as usize), 0);
+    }
+    unsafe {
+        assert_eq!(A, WRITERS * ROUNDS);
+        assert_eq!(B, WRITERS * ROUNDS);
+    }
+}
+
+unsafe fn ticket_reader() -> ! {
+    rwlock_read_lock(RWLOCK);
+    let ticket = TICKET.fetch_add(1, Ordering::SeqCst);
+    rwlock_unlock(RWLOCK);
+    exit(ticket as i32)
+}
+
+unsafe fn ticket_writer() -> ! {
+    rwlock_write_lock(RWLOCK);
+    let ticket = TICKET.fetch_add(1, Ordering::SeqCst);
+    rwlock_unlock(RWLOCK);
+    exit(ticket as i32)
+}
+
+/// While a reader holds the lock, a writer and then another reader come
+/// along. Return the order in which they get the lock.
+fn preference(policy: usize) -> (isize, isize) {
+    unsafe {
+        RWLOCK = rwlock_create(policy) as usize;
+        rwlock_read_lock(RWLOCK);
+    }
+    TICKET.store(0, Ordering::SeqCst);
+    let writer = thread_create(ticket_writer as usize, 0);
+    sleep(20);
+    let reader = thread_create(ticket_reader as usize, 0);
+    sleep(20);
+    unsafe {
+        rwlock_unlock(RWLOCK);
+    }
+    (waittid(writer as usize), waittid(reader as usize))
+}
+
+fn prefer_reader() {
+    stress(RWLOCK_PREFER_READER);
+    // the reader joins the one holding the lock
+    assert_eq!(preference(RWLOCK_PREFER_READER), (1, 0));
+}
+
+fn prefer_writer() {
+    stress(RWLOCK_PREFER_WRITER);
+    // the reader waits behind the writer
+    assert_eq!(preference(RWLOCK_PREFER_WRITER), (0, 1));
+}
+
+static TESTS: &[(fn(), &str)] = &[
+    (prefer_reader, "prefer_reader"),
+    (prefer_writer, "prefer_writer"),
+];
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(rwlock_create(2).into_result(), Err(Errno::EINVAL));
+    run_tests("rwlock_test", TESTS);
+    0
+}
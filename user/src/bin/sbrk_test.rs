@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use user_lib::{brk, exit, fork, sbrk, wait, Errno, IntoResult};
+
+const PAGE_SIZE: usize = 4096;
+const VEC_LEN: usize = 0x10_0000;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let start = sbrk(0);
+    assert!(start > 0);
+    // far beyond the old fixed 32 KiB heap
+    let mut v: Vec<usize> = Vec::new();
+    for i in 0..VEC_LEN / core::mem::size_of::<usize>() {
+        v.push(i);
+    }
+    let mut s = String::new();
+    for _ in 0..VEC_LEN / 16 {
+        s.push_str("0123456789abcdef");
+    }
+    assert!(sbrk(0) >= start + 2 * VEC_LEN as isize);
+    println!("sbrk_test: heap grew by {:#x} bytes.", sbrk(0) - start);
+
+    let pid = fork();
+    if pid == 0 {
+        assert!(v.iter().enumerate().all(|(i, x)| i == *x));
+        assert_eq!(s.len(), VEC_LEN);
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+
+    // the break moves both ways
+    let old_brk = sbrk(PAGE_SIZE as isize);
+    assert!(old_brk > 0);
+    let byte = old_brk as usize as *mut u8;
+    unsafe {
+        byte.write_volatile(0x5a);
+        assert_eq!(byte.read_volatile(), 0x5a);
+    }
+    assert_eq!(sbrk(-(PAGE_SIZE as isize)), old_brk + PAGE_SIZE as isize);
+    assert_eq!(sbrk(0), old_brk);
+    // the heap neither shrinks below the program nor grows into the stacks
+    assert_eq!(brk(PAGE_SIZE).into_result(), Err(Errno::ENOMEM));
+    assert_eq!(sbrk(0x1000_0000).into_result(), Err(Errno::ENOMEM));
+    assert_eq!(sbrk(0), old_brk);
+    println!("sbrk_test passed!");
+    0
+}
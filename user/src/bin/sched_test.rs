@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, get_time, sched_stat, set_priority, waitpid, Errno, IntoResult, SchedStat,
+};
+
+const PRIORITIES: [isize; 4] = [2, 4, 8, 16];
+const RUN_MS: isize = 1000;
+
+/// Spin for `RUN_MS` and report how much work has been done.
+fn worker(priority: isize) -> ! {
+    assert_eq!(set_priority(priority), priority);
+    let start = get_time();
+    let mut count: usize = 0;
+    while get_time() - start < RUN_MS {
+        for _ in 0..1000 {
+            count = count.wrapping_add(1);
+            core::hint::spin_loop();
+        }
+    }
+    let mut stat = SchedStat::default();
+    assert_eq!(sched_stat(&mut stat), 0);
+    assert_eq!(stat.priority, priority as usize);
+    assert!(stat.dispatches > 0);
+    println!(
+        "priority {:>2}: count = {:>8}, dispatched {} times, preempted {} times, run {} us, wait {} us",
+        priority,
+        count / 1000,
+        stat.dispatches,
+        stat.preemptions,
+        stat.run_time,
+        stat.wait_time
+    );
+    exit(0);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(set_priority(0).into_result(), Err(Errno::EINVAL));
+    assert_eq!(set_priority(-1).into_result(), Err(Errno::EINVAL));
+    let mut pids = [0isize; PRIORITIES.len()];
+    for (i, priority) in PRIORITIES.iter().enumerate() {
+        let pid = fork();
+        if pid == 0 {
+            worker(*priority);
+        }
+        pids[i] = pid;
+    }
+    for pid in pids.iter() {
+        let mut exit_code: i32 = 0;
+        assert_eq!(waitpid(*pid as usize, &mut exit_code), *pid);
+        assert_eq!(exit_code, 0);
+    }
+    println!("sched_test passed!");
+    0
+}
@@ -0,0 +1,104 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, semaphore_down, semaphore_down_timeout, semaphore_open, semaphore_up, shm_attach,
+    shm_create, shm_detach, shm_remove, wait, Errno, IntoResult,
+};
+
+const SHM_KEY: usize = 0x5348_4d00;
+const EMPTY_KEY: usize = 0x5348_4d01;
+const FULL_KEY: usize = 0x5348_4d02;
+const RING_LEN: usize = 16;
+const ITEMS: usize = 1000;
+
+/// Lives in the shared segment, the producer and the consumer each keep
+/// their own index into it.
+#[repr(C)]
+struct Ring {
+    items: [usize; RING_LEN],
+    consumed_sum: usize,
+}
+
+fn consumer(ring: &mut Ring, parent_full: usize) {
+    // the semaphores of the parent aren't inherited, they are opened again
+    assert_eq!(
+        semaphore_down_timeout(parent_full, 0).into_result(),
+        Err(Errno::EINVAL)
+    );
+    let empty = semaphore_open(EMPTY_KEY, 0) as usize;
+    let full = semaphore_open(FULL_KEY, 0) as usize;
+    let mut sum = 0;
+    for i in 0..ITEMS {
+        semaphore_down(full);
+        let item = unsafe { (&ring.items[i % RING_LEN] as *const usize).read_volatile() };
+        assert_eq!(item, i);
+        sum += item;
+        semaphore_up(empty);
+    }
+    unsafe { (&mut ring.consumed_sum as *mut usize).write_volatile(sum) };
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let id = shm_create(SHM_KEY, core::mem::size_of::<Ring>());
+    assert!(id >= 0);
+    // the same key refers to the same segment
+    assert_eq!(shm_create(SHM_KEY, core::mem::size_of::<Ring>()), id);
+    let addr = shm_attach(id as usize);
+    assert!(addr > 0);
+    let ring = unsafe { &mut *(addr as usize as *mut Ring) };
+    ring.consumed_sum = 0;
+    let empty = semaphore_open(EMPTY_KEY, RING_LEN) as usize;
+    let full = semaphore_open(FULL_KEY, 0) as usize;
+    assert_eq!(semaphore_open(0, 0).into_result(), Err(Errno::EINVAL));
+
+    // the attached segment stays shared after fork
+    let pid = fork();
+    if pid == 0 {
+        consumer(ring, full);
+        exit(0);
+    }
+    for i in 0..ITEMS {
+        semaphore_down(empty);
+        unsafe { (&mut ring.items[i % RING_LEN] as *mut usize).write_volatile(i) };
+        semaphore_up(full);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(
+        unsafe { (&ring.consumed_sum as *const usize).read_volatile() },
+        ITEMS * (ITEMS - 1) / 2
+    );
+
+    // another process can attach the segment by key as well
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(shm_detach(addr as usize), 0);
+        let id = shm_create(SHM_KEY, 1);
+        assert!(id >= 0);
+        let addr = shm_attach(id as usize);
+        assert!(addr > 0);
+        let ring = unsafe { &mut *(addr as usize as *mut Ring) };
+        ring.consumed_sum = 0;
+        // only its creator removes it
+        assert_eq!(shm_remove(id as usize).into_result(), Err(Errno::EPERM));
+        exit(0);
+    }
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(ring.consumed_sum, 0);
+
+    assert_eq!(shm_remove(id as usize), 0);
+    // removed segments stay attached but cannot be attached again
+    assert_eq!(shm_attach(id as usize).into_result(), Err(Errno::EINVAL));
+    ring.consumed_sum = 1;
+    assert_eq!(shm_detach(addr as usize), 0);
+    assert_eq!(shm_detach(addr as usize).into_result(), Err(Errno::EINVAL));
+    println!("shm_prodcons passed!");
+    0
+}
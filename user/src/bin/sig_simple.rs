@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use user_lib::{getpid, kill, sigaction, SignalAction, SIGUSR1};
+
+static HANDLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn func(signum: i32) {
+    assert_eq!(signum, SIGUSR1);
+    println!("user_sig_test success");
+    HANDLED.store(true, Ordering::Relaxed);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let new = SignalAction {
+        handler: func as usize,
+        ..SignalAction::default()
+    };
+    let mut old = SignalAction::default();
+
+    println!("signal_simple: sigaction");
+    assert_eq!(sigaction(SIGUSR1, Some(&new), Some(&mut old)), 0);
+    println!("signal_simple: kill");
+    // the handler runs before kill returns to us
+    assert_eq!(kill(getpid() as usize, SIGUSR1), 0);
+    assert!(HANDLED.load(Ordering::Relaxed));
+    println!("signal_simple: Done");
+    0
+}
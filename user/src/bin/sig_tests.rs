@@ -0,0 +1,258 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use user_lib::{
+    exit, fork, getpid, kill, pipe, read, run_tests, shm_attach, shm_create, shm_detach,
+    shm_remove, sigaction, sigprocmask, sleep, waitpid, yield_, Errno, IntoResult, SignalAction,
+    SignalFlags, SIGCONT, SIGKILL, SIGSEGV, SIGSTOP, SIGTERM, SIGUSR1, SIGUSR2, SIG_BLOCK, SIG_IGN,
+    SIG_SETMASK, SIG_UNBLOCK,
+};
+
+const SHM_KEY: usize = 0x5349_4700;
+const SEGV_EXIT_CODE: i32 = 42;
+
+static USR1_COUNT: AtomicUsize = AtomicUsize::new(0);
+static USR2_COUNT: AtomicUsize = AtomicUsize::new(0);
+static RESEND: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn usr1_handler(signum: i32) {
+    assert_eq!(signum, SIGUSR1);
+    USR1_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+extern "C" fn usr2_handler(signum: i32) {
+    assert_eq!(signum, SIGUSR2);
+    USR2_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Sends SIGUSR2 from inside the handler of SIGUSR1, SIGUSR1 itself is
+/// blocked until this handler returns.
+extern "C" fn nested_handler(signum: i32) {
+    assert_eq!(signum, SIGUSR1);
+    let usr1_count = USR1_COUNT.load(Ordering::Relaxed);
+    if RESEND.swap(false, Ordering::Relaxed) {
+        kill(getpid() as usize, SIGUSR1);
+        assert_eq!(USR1_COUNT.load(Ordering::Relaxed), usr1_count);
+    }
+    let usr2_count = USR2_COUNT.load(Ordering::Relaxed);
+    kill(getpid() as usize, SIGUSR2);
+    assert_eq!(USR2_COUNT.load(Ordering::Relaxed), usr2_count + 1);
+    USR1_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+extern "C" fn segv_handler(signum: i32) {
+    assert_eq!(signum, SIGSEGV);
+    exit(SEGV_EXIT_CODE);
+}
+
+fn set_handler(signum: i32, handler: usize, mask: SignalFlags) {
+    let action = SignalAction {
+        handler,
+        mask,
+        ..SignalAction::default()
+    };
+    assert_eq!(sigaction(signum, Some(&action), None), 0);
+}
+
+fn user_handler() {
+    set_handler(SIGUSR1, usr1_handler as usize, SignalFlags::empty());
+    let count = USR1_COUNT.load(Ordering::Relaxed);
+    // the return value of kill is restored by sigreturn
+    assert_eq!(kill(getpid() as usize, SIGUSR1), 0);
+    assert_eq!(USR1_COUNT.load(Ordering::Relaxed), count + 1);
+    let mut old = SignalAction::default();
+    assert_eq!(sigaction(SIGUSR1, None, Some(&mut old)), 0);
+    assert_eq!(old.handler, usr1_handler as usize);
+}
+
+fn mask() {
+    set_handler(SIGUSR1, usr1_handler as usize, SignalFlags::empty());
+    let count = USR1_COUNT.load(Ordering::Relaxed);
+    assert_eq!(sigprocmask(SIG_BLOCK, SignalFlags::SIGUSR1), 0);
+    kill(getpid() as usize, SIGUSR1);
+    assert_eq!(USR1_COUNT.load(Ordering::Relaxed), count);
+    // SIGKILL and SIGSTOP can't be blocked
+    let old = sigprocmask(SIG_BLOCK, SignalFlags::SIGKILL | SignalFlags::SIGSTOP);
+    assert_eq!(old as u64, SignalFlags::SIGUSR1.bits());
+    let old = sigprocmask(SIG_SETMASK, SignalFlags::SIGUSR1);
+    assert_eq!(old as u64, SignalFlags::SIGUSR1.bits());
+    // the pending signal is delivered once it is unblocked
+    sigprocmask(SIG_UNBLOCK, SignalFlags::SIGUSR1);
+    assert_eq!(USR1_COUNT.load(Ordering::Relaxed), count + 1);
+    assert_eq!(sigprocmask(SIG_BLOCK, SignalFlags::empty()), 0);
+    assert_eq!(
+        sigprocmask(3, SignalFlags::empty()).into_result(),
+        Err(Errno::EINVAL)
+    );
+}
+
+fn nested() {
+    set_handler(SIGUSR1, nested_handler as usize, SignalFlags::empty());
+    set_handler(SIGUSR2, usr2_handler as usize, SignalFlags::empty());
+    let usr1_count = USR1_COUNT.load(Ordering::Relaxed);
+    let usr2_count = USR2_COUNT.load(Ordering::Relaxed);
+    RESEND.store(true, Ordering::Relaxed);
+    kill(getpid() as usize, SIGUSR1);
+    // the SIGUSR1 sent by the handler runs the handler once more
+    assert_eq!(USR1_COUNT.load(Ordering::Relaxed), usr1_count + 2);
+    assert_eq!(USR2_COUNT.load(Ordering::Relaxed), usr2_count + 2);
+}
+
+fn ignore() {
+    set_handler(SIGUSR2, SIG_IGN, SignalFlags::empty());
+    let count = USR2_COUNT.load(Ordering::Relaxed);
+    kill(getpid() as usize, SIGUSR2);
+    assert_eq!(USR2_COUNT.load(Ordering::Relaxed), count);
+}
+
+fn uncatchable() {
+    let action = SignalAction {
+        handler: usr1_handler as usize,
+        ..SignalAction::default()
+    };
+    assert_eq!(
+        sigaction(SIGKILL, Some(&action), None).into_result(),
+        Err(Errno::EINVAL)
+    );
+    assert_eq!(
+        sigaction(SIGSTOP, Some(&action), None).into_result(),
+        Err(Errno::EINVAL)
+    );
+    assert_eq!(
+        sigaction(0, Some(&action), None).into_result(),
+        Err(Errno::EINVAL)
+    );
+    assert_eq!(
+        sigaction(32, Some(&action), None).into_result(),
+        Err(Errno::EINVAL)
+    );
+    assert_eq!(
+        kill(getpid() as usize, 32).into_result(),
+        Err(Errno::EINVAL)
+    );
+    assert_eq!(kill(getpid() as usize, 0), 0);
+}
+
+fn default_terminate() {
+    let pid = fork();
+    if pid == 0 {
+        loop {
+            yield_();
+        }
+    }
+    kill(pid as usize, SIGTERM);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, -SIGTERM);
+}
+
+fn fault_handler() {
+    let pid = fork();
+    if pid == 0 {
+        set_handler(SIGSEGV, segv_handler as usize, SignalFlags::empty());
+        unsafe {
+            core::ptr::null_mut::<u8>().write_volatile(0);
+        }
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, SEGV_EXIT_CODE);
+}
+
+fn stop_and_continue() {
+    let id = shm_create(SHM_KEY, core::mem::size_of::<usize>());
+    assert!(id >= 0);
+    let addr = shm_attach(id as usize);
+    assert!(addr > 0);
+    let counter = unsafe { &*(addr as usize as *const AtomicUsize) };
+    counter.store(0, Ordering::Relaxed);
+    let pid = fork();
+    if pid == 0 {
+        loop {
+            counter.fetch_add(1, Ordering::Relaxed);
+            yield_();
+        }
+    }
+    while counter.load(Ordering::Relaxed) == 0 {
+        yield_();
+    }
+    kill(pid as usize, SIGSTOP);
+    // give the child a chance to take the signal
+    sleep(20);
+    let stopped = counter.load(Ordering::Relaxed);
+    sleep(50);
+    assert_eq!(counter.load(Ordering::Relaxed), stopped);
+    kill(pid as usize, SIGCONT);
+    while counter.load(Ordering::Relaxed) == stopped {
+        yield_();
+    }
+    kill(pid as usize, SIGKILL);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, -SIGKILL);
+    assert_eq!(shm_detach(addr as usize), 0);
+    assert_eq!(shm_remove(id as usize), 0);
+}
+
+/// Fork a child which blocks reading a pipe nobody writes to, after
+/// `prepare`. It exits with 0 if the read fails with EINTR.
+fn spawn_reader(prepare: fn()) -> isize {
+    let pid = fork();
+    if pid == 0 {
+        prepare();
+        let mut pipe_fd = [0usize; 2];
+        assert_eq!(pipe(&mut pipe_fd), 0);
+        let mut buf = [0u8; 1];
+        let interrupted = read(pipe_fd[0], &mut buf).into_result() == Err(Errno::EINTR);
+        exit(!interrupted as i32);
+    }
+    pid
+}
+
+fn reader_handles_usr1() {
+    set_handler(SIGUSR1, usr1_handler as usize, SignalFlags::empty());
+}
+
+fn reader_ignores_usr2() {
+    set_handler(SIGUSR2, SIG_IGN, SignalFlags::empty());
+}
+
+/// A blocked thread is interrupted by a signal it catches or which kills
+/// it, not by one it ignores.
+fn interrupt_wait() {
+    let mut exit_code = 0;
+    let pid = spawn_reader(reader_handles_usr1);
+    sleep(20);
+    kill(pid as usize, SIGUSR1);
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    let pid = spawn_reader(reader_ignores_usr2);
+    sleep(20);
+    kill(pid as usize, SIGUSR2);
+    sleep(20);
+    kill(pid as usize, SIGTERM);
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, -SIGTERM);
+}
+
+static TESTS: &[(fn(), &str)] = &[
+    (user_handler, "user_handler"),
+    (mask, "mask"),
+    (nested, "nested"),
+    (ignore, "ignore"),
+    (uncatchable, "uncatchable"),
+    (default_terminate, "default_terminate"),
+    (fault_handler, "fault_handler"),
+    (stop_and_continue, "stop_and_continue"),
+    (interrupt_wait, "interrupt_wait"),
+];
+
+#[no_mangle]
+pub fn main() -> i32 {
+    run_tests("sig_tests", TESTS);
+    0
+}
@@ -0,0 +1,209 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{
+    exec, exit, fork, trace, trace_read, waitpid, IntoResult, TraceRecord, TRACE_NORETURN,
+    TRACE_TRUNCATED,
+};
+
+/// Name, number of arguments, and the argument whose string or buffer is
+/// kept in the records, of each syscall.
+static SYSCALLS: &[(usize, &str, usize, Option<usize>)] = &[
+    (17, "getcwd", 2, None),
+    (23, "dup", 1, None),
+    (24, "dup3", 3, None),
+    (25, "fcntl", 3, None),
+    (29, "ioctl", 3, None),
+    (56, "openat", 4, Some(1)),
+    (57, "close", 1, None),
+    (59, "pipe2", 2, None),
+    (61, "getdents64", 3, None),
+    (63, "read", 3, Some(1)),
+    (64, "write", 3, Some(1)),
+    (65, "readv", 3, None),
+    (66, "writev", 3, None),
+    (79, "newfstatat", 4, Some(1)),
+    (80, "fstat", 2, None),
+    (93, "exit", 1, None),
+    (94, "exit_group", 1, None),
+    (96, "set_tid_address", 1, None),
+    (98, "futex", 5, None),
+    (101, "nanosleep", 2, None),
+    (113, "clock_gettime", 2, None),
+    (124, "sched_yield", 0, None),
+    (129, "kill", 2, None),
+    (134, "rt_sigaction", 4, None),
+    (135, "rt_sigprocmask", 4, None),
+    (139, "rt_sigreturn", 0, None),
+    (140, "set_priority", 1, None),
+    (154, "setpgid", 2, None),
+    (155, "getpgid", 1, None),
+    (156, "getsid", 1, None),
+    (157, "setsid", 0, None),
+    (160, "uname", 1, None),
+    (169, "gettimeofday", 2, None),
+    (172, "getpid", 0, None),
+    (173, "getppid", 0, None),
+    (174, "getuid", 0, None),
+    (175, "geteuid", 0, None),
+    (176, "getgid", 0, None),
+    (177, "getegid", 0, None),
+    (214, "brk", 1, None),
+    (215, "munmap", 2, None),
+    (220, "clone", 5, None),
+    (221, "execve", 3, Some(0)),
+    (222, "mmap", 6, None),
+    (226, "mprotect", 3, None),
+    (260, "wait4", 4, None),
+    (469, "enable_deadlock_detect", 1, None),
+    (1000, "thread_create", 2, None),
+    (1001, "gettid", 0, None),
+    (1002, "waittid", 1, None),
+    (1003, "thread_exit", 1, None),
+    (1004, "thread_detach", 1, None),
+    (1005, "thread_kill", 1, None),
+    (1010, "mutex_create", 1, None),
+    (1011, "mutex_lock", 1, None),
+    (1012, "mutex_unlock", 1, None),
+    (1013, "mutex_lock_timeout", 2, None),
+    (1020, "semaphore_create", 1, None),
+    (1021, "semaphore_up", 1, None),
+    (1022, "semaphore_down", 1, None),
+    (1023, "semaphore_down_timeout", 2, None),
+    (1024, "semaphore_open", 2, None),
+    (1030, "condvar_create", 1, None),
+    (1031, "condvar_signal", 1, None),
+    (1032, "condvar_wait", 2, None),
+    (1033, "condvar_broadcast", 1, None),
+    (1034, "condvar_wait_timeout", 3, None),
+    (1040, "rwlock_create", 1, None),
+    (1041, "rwlock_read_lock", 1, None),
+    (1042, "rwlock_write_lock", 1, None),
+    (1043, "rwlock_unlock", 1, None),
+    (1050, "barrier_create", 1, None),
+    (1051, "barrier_wait", 1, None),
+    (1100, "swap_stat", 1, None),
+    (1110, "shm_create", 2, None),
+    (1111, "shm_attach", 1, None),
+    (1112, "shm_detach", 1, None),
+    (1113, "shm_remove", 1, None),
+    (1200, "sched_stat", 1, None),
+    (1300, "waitpid", 3, None),
+    (1400, "trace", 2, None),
+    (1401, "trace_read", 3, None),
+];
+
+/// Errors are returned as -4095 to -1, as on Linux.
+const MAX_ERRNO: isize = 4095;
+
+/// Small numbers in decimal, negative ones included, others in hex.
+fn print_value(value: usize) {
+    let signed = value as isize;
+    if value < 0x10000 || (-MAX_ERRNO..0).contains(&signed) {
+        print!("{}", signed);
+    } else {
+        print!("{:#x}", value);
+    }
+}
+
+fn print_data(data: &[u8], truncated: bool) {
+    print!("\"");
+    for byte in data {
+        match *byte {
+            b'\n' => print!("\\n"),
+            b'\t' => print!("\\t"),
+            b'"' => print!("\\\""),
+            b'\\' => print!("\\\\"),
+            0x20..=0x7e => print!("{}", *byte as char),
+            _ => print!("\\x{:02x}", byte),
+        }
+    }
+    print!("\"");
+    if truncated {
+        print!("...");
+    }
+}
+
+fn print_record(record: &TraceRecord) {
+    if record.tid != 0 {
+        print!("[tid {}] ", record.tid);
+    }
+    let syscall = SYSCALLS.iter().find(|syscall| syscall.0 == record.id);
+    let (nargs, data_arg) = match syscall {
+        Some((_, name, nargs, data_arg)) => {
+            print!("{}(", name);
+            (*nargs, *data_arg)
+        }
+        None => {
+            print!("syscall_{}(", record.id);
+            (record.args.len(), None)
+        }
+    };
+    for (i, arg) in record.args[..nargs].iter().enumerate() {
+        if i > 0 {
+            print!(", ");
+        }
+        if data_arg == Some(i) && record.data_len > 0 {
+            let truncated = record.flags & TRACE_TRUNCATED != 0;
+            print_data(&record.data[..record.data_len], truncated);
+        } else {
+            print_value(*arg);
+        }
+    }
+    if record.flags & TRACE_NORETURN != 0 {
+        println!(") = ?");
+        return;
+    }
+    print!(") = ");
+    match record.ret.into_result() {
+        Err(err) if record.ret >= -MAX_ERRNO => print!("-1 {:?} ({})", err, err),
+        _ => print_value(record.ret as usize),
+    }
+    println!(" <{}us>", record.duration_us);
+}
+
+/// Run a program with its syscalls traced, and print them as it makes them.
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc < 2 {
+        println!("usage: strace <program> [args...]");
+        return -1;
+    }
+    // the strings are followed by their '\0's on the stack
+    let mut args: Vec<*const u8> = argv[1..].iter().map(|arg| arg.as_ptr()).collect();
+    args.push(core::ptr::null());
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(trace(0, true), 0);
+        let err = exec(argv[1], &args).into_result().unwrap_err();
+        println!("strace: can't run {}: {}", argv[1], err);
+        exit(-1);
+    }
+    let pid = pid as usize;
+    // the child may not have started tracing yet, and reading would fail
+    trace(pid, true);
+    let mut records = [TraceRecord::default(); 8];
+    let mut next_seq = 0;
+    loop {
+        let count = trace_read(pid, &mut records);
+        if count <= 0 {
+            break;
+        }
+        for record in records[..count as usize].iter() {
+            if record.seq != next_seq {
+                println!("... {} syscalls dropped", record.seq - next_seq);
+            }
+            next_seq = record.seq + 1;
+            print_record(record);
+        }
+    }
+    let mut exit_code = 0;
+    waitpid(pid, &mut exit_code);
+    println!("+++ exited with {} +++", exit_code);
+    0
+}
@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{get_time, open, swap_stat, Errno, IntoResult, OpenFlags, SwapStat};
+
+const PAGE_SIZE: usize = 4096;
+// 6MiB, more than the free physical memory of the kernel
+const PAGES: usize = 1536;
+const ROUNDS: usize = 3;
+
+static mut DATA: [u8; PAGE_SIZE * PAGES] = [0; PAGE_SIZE * PAGES];
+
+fn pattern(page: usize, round: usize) -> u8 {
+    (page * 7 + round * 13) as u8
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let start = get_time();
+    let mut before = SwapStat::default();
+    swap_stat(&mut before);
+    unsafe {
+        for round in 0..ROUNDS {
+            for page in 0..PAGES {
+                let p = &mut DATA[page * PAGE_SIZE] as *mut u8;
+                if round > 0 {
+                    assert_eq!(p.read_volatile(), pattern(page, round - 1));
+                }
+                p.write_volatile(pattern(page, round));
+                p.add(PAGE_SIZE - 1).write_volatile(pattern(page, round));
+            }
+        }
+        for page in 0..PAGES {
+            let p = &DATA[page * PAGE_SIZE] as *const u8;
+            assert_eq!(p.read_volatile(), pattern(page, ROUNDS - 1));
+            assert_eq!(p.add(PAGE_SIZE - 1).read_volatile(), pattern(page, ROUNDS - 1));
+        }
+    }
+    let mut after = SwapStat::default();
+    swap_stat(&mut after);
+    println!(
+        "page faults = {}, swap ins = {}, swap outs = {}",
+        after.page_faults - before.page_faults,
+        after.swap_ins - before.swap_ins,
+        after.swap_outs - before.swap_outs,
+    );
+    // the swap area belongs to the kernel
+    assert_eq!(
+        open("swap\0", OpenFlags::RDONLY).into_result(),
+        Err(Errno::ENOENT)
+    );
+    assert_eq!(
+        open("swap\0", OpenFlags::CREATE | OpenFlags::WRONLY).into_result(),
+        Err(Errno::EACCES)
+    );
+    println!("swap_test passed, time cost = {}ms", get_time() - start);
+    0
+}
@@ -0,0 +1,131 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+extern crate user_lib;
+
+use alloc::format;
+use core::sync::atomic::{AtomicBool, Ordering};
+use user_lib::{
+    exec, exit, fork, gettid, pipe, read, run_tests, sleep, thread_create, thread_exit, waitpid,
+    waittid, yield_, Errno, IntoResult,
+};
+
+const EXECED: i32 = 77;
+const MAX_TID: usize = 8;
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+fn exiting(code: usize) -> ! {
+    thread_exit(code as i32)
+}
+
+fn spinning() -> ! {
+    while !STOP.load(Ordering::Acquire) {
+        yield_();
+    }
+    exit(1)
+}
+
+fn fork_in_thread() -> ! {
+    let tid = gettid();
+    let pid = fork();
+    if pid == 0 {
+        // only this thread has been copied, with its tid
+        let same_tid = gettid() == tid;
+        let alone = (0..MAX_TID)
+            .all(|other| other == tid as usize || waittid(other) == Errno::ESRCH.as_ret());
+        // threads can still be created
+        let thread = thread_create(exiting as usize, 3);
+        let created = waittid(thread as usize) == 3;
+        // and this thread takes the process with it
+        exit((same_tid && alone && created) as i32);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    exit(exit_code)
+}
+
+/// A thread other than the main one forks, while a sibling keeps running.
+fn fork_threads() {
+    STOP.store(false, Ordering::Relaxed);
+    let sibling = thread_create(spinning as usize, 0);
+    let forking = thread_create(fork_in_thread as usize, 0);
+    assert_eq!(waittid(forking as usize), 1);
+    STOP.store(true, Ordering::Release);
+    assert_eq!(waittid(sibling as usize), 1);
+}
+
+fn sleeping() -> ! {
+    sleep(100);
+    exit(1)
+}
+
+/// Blocks for good, the write end stays open in the process.
+fn reading(fd: usize) -> ! {
+    let mut buf = [0u8; 1];
+    read(fd, &mut buf);
+    exit(1)
+}
+
+fn exec_in_thread() -> ! {
+    let tid = format!("{}\0", gettid());
+    exec(
+        "thread_fork_exec\0",
+        &[
+            "thread_fork_exec\0".as_ptr(),
+            "exec\0".as_ptr(),
+            tid.as_ptr(),
+            core::ptr::null::<u8>(),
+        ],
+    );
+    panic!("exec failed");
+}
+
+/// The new program only runs the thread which called exec, the others are
+/// gone whether they were running or blocked.
+fn exec_threads() {
+    let pid = fork();
+    if pid == 0 {
+        let mut pipe_fd = [0usize; 2];
+        assert_eq!(pipe(&mut pipe_fd), 0);
+        STOP.store(false, Ordering::Relaxed);
+        thread_create(spinning as usize, 0);
+        thread_create(sleeping as usize, 0);
+        thread_create(reading as usize, pipe_fd[0]);
+        thread_create(exec_in_thread as usize, 0);
+        // the main thread is running too
+        loop {
+            yield_();
+        }
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, EXECED);
+}
+
+/// The program started by `exec_in_thread`.
+fn execed(tid: &str) -> i32 {
+    let tid: usize = tid.parse().unwrap();
+    assert_eq!(gettid() as usize, tid);
+    for other in 0..MAX_TID {
+        if other != tid {
+            assert_eq!(waittid(other).into_result(), Err(Errno::ESRCH));
+        }
+    }
+    EXECED
+}
+
+static TESTS: &[(fn(), &str)] = &[
+    (fork_threads, "fork_threads"),
+    (exec_threads, "exec_threads"),
+];
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc == 3 && argv[1] == "exec" {
+        return execed(argv[2]);
+    }
+    run_tests("thread_fork_exec", TESTS);
+    0
+}
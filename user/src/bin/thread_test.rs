@@ -0,0 +1,189 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use user_lib::{
+    exit, fork, gettid, pipe, read, run_tests, semaphore_create, semaphore_down, semaphore_up,
+    sleep, thread_create, thread_detach, thread_exit, thread_kill, waitpid, waittid, Errno,
+    IntoResult,
+};
+
+const DETACHED_THREADS: usize = 100;
+const SIBLING_SUM: usize = 1_000_000;
+
+static EXITED: AtomicUsize = AtomicUsize::new(0);
+static DONE: AtomicBool = AtomicBool::new(false);
+
+fn exiting(code: usize) -> ! {
+    thread_exit(code as i32)
+}
+
+fn exit_code() {
+    let tid = thread_create(exiting as usize, 7);
+    assert_eq!(waittid(tid as usize), 7);
+    // reaped already
+    assert_eq!(waittid(tid as usize).into_result(), Err(Errno::ESRCH));
+}
+
+fn counting() -> ! {
+    EXITED.fetch_add(1, Ordering::Release);
+    thread_exit(0)
+}
+
+/// Detached threads are reaped when they exit, so their tids are reused.
+fn detach() {
+    assert_eq!(thread_detach(0).into_result(), Err(Errno::EINVAL));
+    assert_eq!(
+        thread_detach(gettid() as usize + 1000).into_result(),
+        Err(Errno::ESRCH)
+    );
+    EXITED.store(0, Ordering::Relaxed);
+    for i in 0..DETACHED_THREADS {
+        let tid = thread_create(counting as usize, 0);
+        assert!(tid > 0 && (tid as usize) < 8);
+        assert_eq!(thread_detach(tid as usize), 0);
+        // detached, or reaped already if it has exited
+        assert!(matches!(
+            thread_detach(tid as usize).into_result(),
+            Err(Errno::EINVAL) | Err(Errno::ESRCH)
+        ));
+        assert!(matches!(
+            waittid(tid as usize).into_result(),
+            Err(Errno::EINVAL) | Err(Errno::ESRCH)
+        ));
+        while EXITED.load(Ordering::Acquire) <= i {
+            sleep(1);
+        }
+    }
+    // detaching a thread which has exited already reaps it
+    let tid = thread_create(exiting as usize, 3);
+    sleep(20);
+    assert_eq!(thread_detach(tid as usize), 0);
+    assert_eq!(waittid(tid as usize).into_result(), Err(Errno::ESRCH));
+}
+
+fn spinning() -> ! {
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+fn sibling() -> ! {
+    let mut sum = 0;
+    for i in 0..SIBLING_SUM {
+        sum += i % 2;
+    }
+    while !DONE.load(Ordering::Acquire) {
+        sleep(1);
+    }
+    exit((sum == SIBLING_SUM / 2) as i32)
+}
+
+/// A killed thread exits with the exit code of SIGKILL, the others go on.
+fn kill() {
+    assert_eq!(thread_kill(0).into_result(), Err(Errno::EINVAL));
+    DONE.store(false, Ordering::Relaxed);
+    let sibling = thread_create(sibling as usize, 0);
+    let spinning = thread_create(spinning as usize, 0);
+    sleep(20);
+    assert_eq!(thread_kill(spinning as usize), 0);
+    assert_eq!(waittid(spinning as usize), -9);
+    assert_eq!(
+        thread_kill(spinning as usize).into_result(),
+        Err(Errno::ESRCH)
+    );
+    DONE.store(true, Ordering::Release);
+    assert_eq!(waittid(sibling as usize), 1);
+    // an exited thread can't be killed
+    let tid = thread_create(exiting as usize, 0);
+    sleep(20);
+    assert_eq!(thread_kill(tid as usize).into_result(), Err(Errno::ESRCH));
+    assert_eq!(waittid(tid as usize), 0);
+}
+
+fn blocked(sem_id: usize) -> ! {
+    semaphore_down(sem_id);
+    thread_exit(0)
+}
+
+/// A killed thread gives up the wait it is blocked in, which is left as if
+/// it had never waited.
+fn kill_blocked() {
+    let sem_id = semaphore_create(0) as usize;
+    let tid = thread_create(blocked as usize, sem_id);
+    let sleeping = thread_create(forever as usize, 0);
+    sleep(20);
+    assert_eq!(thread_kill(tid as usize), 0);
+    assert_eq!(waittid(tid as usize), -9);
+    assert_eq!(thread_kill(sleeping as usize), 0);
+    assert_eq!(waittid(sleeping as usize), -9);
+    // the count isn't taken by the killed thread
+    let tid = thread_create(blocked as usize, sem_id);
+    semaphore_up(sem_id);
+    assert_eq!(waittid(tid as usize), 0);
+}
+
+fn last_thread() -> ! {
+    sleep(20);
+    exit(42)
+}
+
+/// The process exits with its last thread once the main thread is gone.
+fn main_thread_exit() {
+    let pid = fork();
+    if pid == 0 {
+        thread_create(last_thread as usize, 0);
+        thread_exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 42);
+}
+
+fn forever() -> ! {
+    loop {
+        sleep(1000);
+    }
+}
+
+/// Blocks for good, the write end stays open in the process.
+fn reading(fd: usize) -> ! {
+    let mut buf = [0u8; 1];
+    read(fd, &mut buf);
+    exit(1)
+}
+
+/// The main thread takes the others with it when it calls exit, even those
+/// blocked in the kernel.
+fn main_exit() {
+    let pid = fork();
+    if pid == 0 {
+        let mut pipe_fd = [0usize; 2];
+        assert_eq!(pipe(&mut pipe_fd), 0);
+        thread_create(spinning as usize, 0);
+        thread_create(forever as usize, 0);
+        thread_create(reading as usize, pipe_fd[0]);
+        sleep(20);
+        exit(5);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 5);
+}
+
+static TESTS: &[(fn(), &str)] = &[
+    (exit_code, "exit_code"),
+    (detach, "detach"),
+    (kill, "kill"),
+    (kill_blocked, "kill_blocked"),
+    (main_thread_exit, "main_thread_exit"),
+    (main_exit, "main_exit"),
+];
+
+#[no_mangle]
+pub fn main() -> i32 {
+    run_tests("thread_test", TESTS);
+    0
+}
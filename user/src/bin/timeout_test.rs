@@ -0,0 +1,138 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use user_lib::{
+    condvar_create, condvar_signal, condvar_wait_timeout, exit, get_time, mutex_blocking_create,
+    mutex_create, mutex_lock, mutex_lock_timeout, mutex_unlock, run_tests, semaphore_create,
+    semaphore_down, semaphore_down_timeout, semaphore_up, sleep, thread_create, waittid, TIMEDOUT,
+};
+
+const TIMEOUT_MS: usize = 50;
+const LONG_TIMEOUT_MS: usize = 2000;
+const WAKE_MS: usize = 20;
+
+static mut ID: usize = 0;
+static mut MUTEX: usize = 0;
+
+/// Run `f` and return its result and how long it took.
+fn timed(f: impl FnOnce() -> isize) -> (isize, usize) {
+    let start = get_time();
+    let result = f();
+    (result, (get_time() - start) as usize)
+}
+
+fn up_later() -> ! {
+    sleep(WAKE_MS);
+    unsafe { semaphore_up(ID) };
+    exit(0)
+}
+
+fn up_much_later() -> ! {
+    sleep(10 * TIMEOUT_MS);
+    unsafe { semaphore_up(ID) };
+    exit(0)
+}
+
+fn semaphore() {
+    let sem = semaphore_create(0) as usize;
+    unsafe { ID = sem };
+    let (result, elapsed) = timed(|| semaphore_down_timeout(sem, TIMEOUT_MS));
+    assert_eq!(result, TIMEDOUT);
+    assert!(elapsed >= TIMEOUT_MS);
+    // the count has been given back, so one up makes one down pass
+    semaphore_up(sem);
+    assert_eq!(semaphore_down(sem), 0);
+    assert_eq!(semaphore_down_timeout(sem, 0), TIMEDOUT);
+
+    let tid = thread_create(up_later as usize, 0);
+    let (result, elapsed) = timed(|| semaphore_down_timeout(sem, TIMEOUT_MS * 2));
+    assert_eq!(result, 0);
+    assert!(elapsed < TIMEOUT_MS * 2);
+    assert_eq!(waittid(tid as usize), 0);
+    // a timeout too long to add to the time is as good as none
+    let tid = thread_create(up_later as usize, 0);
+    assert_eq!(semaphore_down_timeout(sem, usize::MAX), 0);
+    assert_eq!(waittid(tid as usize), 0);
+    // the timer of the last wait is still pending, and must not cut this
+    // one short
+    let tid = thread_create(up_much_later as usize, 0);
+    let (result, elapsed) = timed(|| semaphore_down(sem));
+    assert_eq!(result, 0);
+    assert!(elapsed >= 5 * TIMEOUT_MS);
+    assert_eq!(waittid(tid as usize), 0);
+}
+
+fn try_lock() -> ! {
+    let result = unsafe { mutex_lock_timeout(MUTEX, TIMEOUT_MS) };
+    exit((result == TIMEDOUT) as i32)
+}
+
+fn lock_and_unlock() -> ! {
+    let result = unsafe { mutex_lock_timeout(MUTEX, LONG_TIMEOUT_MS) };
+    unsafe { mutex_unlock(MUTEX) };
+    exit((result == 0) as i32)
+}
+
+fn mutex_with(mutex: usize) {
+    unsafe { MUTEX = mutex };
+    mutex_lock(mutex);
+    let tid = thread_create(try_lock as usize, 0);
+    assert_eq!(waittid(tid as usize), 1);
+    // the thread has left the wait queue, so it isn't handed the mutex
+    mutex_unlock(mutex);
+    assert_eq!(mutex_lock_timeout(mutex, 0), 0);
+    let tid = thread_create(lock_and_unlock as usize, 0);
+    sleep(WAKE_MS);
+    mutex_unlock(mutex);
+    assert_eq!(waittid(tid as usize), 1);
+}
+
+fn mutex() {
+    mutex_with(mutex_blocking_create() as usize);
+    mutex_with(mutex_create() as usize);
+}
+
+fn signal_later() -> ! {
+    sleep(WAKE_MS);
+    unsafe {
+        mutex_lock(MUTEX);
+        condvar_signal(ID);
+        mutex_unlock(MUTEX);
+    }
+    exit(0)
+}
+
+fn condvar() {
+    let condvar = condvar_create() as usize;
+    let mutex = mutex_blocking_create() as usize;
+    unsafe {
+        ID = condvar;
+        MUTEX = mutex;
+    }
+    mutex_lock(mutex);
+    let (result, elapsed) = timed(|| condvar_wait_timeout(condvar, mutex, TIMEOUT_MS));
+    assert_eq!(result, TIMEDOUT);
+    assert!(elapsed >= TIMEOUT_MS);
+    // the mutex is held again
+    assert_eq!(mutex_lock_timeout(mutex, 0), TIMEDOUT);
+    let tid = thread_create(signal_later as usize, 0);
+    let (result, elapsed) = timed(|| condvar_wait_timeout(condvar, mutex, LONG_TIMEOUT_MS));
+    assert_eq!(result, 0);
+    assert!(elapsed < LONG_TIMEOUT_MS);
+    mutex_unlock(mutex);
+    assert_eq!(waittid(tid as usize), 0);
+}
+
+static TESTS: &[(fn(), &str)] = &[
+    (semaphore, "semaphore"),
+    (mutex, "mutex"),
+    (condvar, "condvar"),
+];
+
+#[no_mangle]
+pub fn main() -> i32 {
+    run_tests("timeout_test", TESTS);
+    0
+}
@@ -0,0 +1,100 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+extern crate user_lib;
+
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{exit, fork, gettid, run_tests, sleep, thread_create, waitpid, waittid, yield_};
+
+const THREAD_COUNT: usize = 8;
+const ROUNDS: usize = 100;
+const INITIAL: usize = 0x5a5a_a5a5;
+
+thread_local! {
+    /// in `.tdata`
+    static INITIALIZED: Cell<usize> = Cell::new(INITIAL);
+    /// in `.tbss`
+    static ZEROED: Cell<usize> = Cell::new(0);
+    static BUFFER: RefCell<[u8; 256]> = RefCell::new([0; 256]);
+}
+
+static ADDRESSES: [AtomicUsize; THREAD_COUNT] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+/// Every thread starts with the initial values, and only sees its own
+/// changes.
+fn worker(idx: usize) -> ! {
+    assert_eq!(INITIALIZED.with(|v| v.get()), INITIAL);
+    assert_eq!(ZEROED.with(|v| v.get()), 0);
+    assert!(BUFFER.with(|buffer| buffer.borrow().iter().all(|b| *b == 0)));
+    ADDRESSES[idx].store(
+        INITIALIZED.with(|v| v as *const _ as usize),
+        Ordering::Relaxed,
+    );
+    let tid = gettid() as usize;
+    INITIALIZED.with(|v| v.set(tid));
+    BUFFER.with(|buffer| buffer.borrow_mut().fill(idx as u8));
+    for round in 0..ROUNDS {
+        ZEROED.with(|v| v.set(v.get() + 1));
+        yield_();
+        assert_eq!(ZEROED.with(|v| v.get()), round + 1);
+        assert_eq!(INITIALIZED.with(|v| v.get()), tid);
+    }
+    let kept = BUFFER.with(|buffer| buffer.borrow().iter().all(|b| *b == idx as u8));
+    exit(kept as i32)
+}
+
+fn threads() {
+    let threads: Vec<_> = (0..THREAD_COUNT)
+        .map(|idx| thread_create(worker as usize, idx))
+        .collect();
+    for tid in threads {
+        assert_eq!(waittid(tid as usize), 1);
+    }
+    let mut addresses: Vec<_> = ADDRESSES
+        .iter()
+        .map(|address| address.load(Ordering::Relaxed))
+        .collect();
+    addresses.sort_unstable();
+    addresses.dedup();
+    assert_eq!(addresses.len(), THREAD_COUNT);
+    // the main thread has a copy of its own
+    assert_eq!(INITIALIZED.with(|v| v.get()), INITIAL);
+    assert_eq!(ZEROED.with(|v| v.get()), 0);
+}
+
+/// A forked child gets a copy of the storage of the thread calling fork.
+fn fork_copy() {
+    ZEROED.with(|v| v.set(42));
+    let pid = fork();
+    if pid == 0 {
+        let copied = ZEROED.with(|v| v.get()) == 42;
+        ZEROED.with(|v| v.set(0));
+        exit(copied as i32);
+    }
+    sleep(10);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 1);
+    assert_eq!(ZEROED.with(|v| v.get()), 42);
+    ZEROED.with(|v| v.set(0));
+}
+
+static TESTS: &[(fn(), &str)] = &[(threads, "threads"), (fork_copy, "fork_copy")];
+
+#[no_mangle]
+pub fn main() -> i32 {
+    run_tests("tls_test", TESTS);
+    0
+}
@@ -0,0 +1,253 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{
+    close, exit, fork, getpid, kill, open, pipe, read, run_tests, sigaction, sleep, trace,
+    trace_read, waitpid, write, Errno, IntoResult, OpenFlags, SignalAction, TraceRecord, SIGUSR1,
+    TRACE_NORETURN, TRACE_TRUNCATED,
+};
+
+const SYSCALL_OPENAT: usize = 56;
+const SYSCALL_READ: usize = 63;
+const SYSCALL_WRITE: usize = 64;
+const SYSCALL_EXIT: usize = 93;
+const SYSCALL_GETPID: usize = 172;
+
+/// All the records of child `pid`, read until it has exited.
+fn read_all(pid: usize) -> Vec<TraceRecord> {
+    let mut all = Vec::new();
+    let mut records = [TraceRecord::default(); 8];
+    loop {
+        let count = trace_read(pid, &mut records).into_result().unwrap();
+        if count == 0 {
+            return all;
+        }
+        all.extend_from_slice(&records[..count]);
+    }
+}
+
+fn wait_exit(pid: usize, expected: i32) {
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), pid as isize);
+    assert_eq!(exit_code, expected);
+}
+
+/// Arguments, return values and the data of the syscalls are recorded in
+/// order, up to the exit which doesn't return.
+fn records() {
+    let (mut to_parent, mut to_child) = ([0usize; 2], [0usize; 2]);
+    assert_eq!(pipe(&mut to_parent), 0);
+    assert_eq!(pipe(&mut to_child), 0);
+    assert_eq!(write(to_child[1], b"ping"), 4);
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(trace(0, true), 0);
+        let pid = getpid();
+        write(to_parent[1], b"traced");
+        let mut buf = [0u8; 4];
+        read(to_child[0], &mut buf);
+        open("no_such_file\0", OpenFlags::RDONLY);
+        exit(pid as i32);
+    }
+    let pid = pid as usize;
+    // the child is traced once it has written
+    let mut buf = [0u8; 6];
+    assert_eq!(read(to_parent[0], &mut buf), 6);
+    let records = read_all(pid);
+    wait_exit(pid, pid as i32);
+    for fd in to_parent.iter().chain(to_child.iter()) {
+        close(*fd);
+    }
+    let ids: Vec<usize> = records.iter().map(|record| record.id).collect();
+    assert_eq!(
+        ids,
+        [
+            SYSCALL_GETPID,
+            SYSCALL_WRITE,
+            SYSCALL_READ,
+            SYSCALL_OPENAT,
+            SYSCALL_EXIT
+        ]
+    );
+    for (seq, record) in records.iter().enumerate() {
+        assert_eq!(record.seq, seq);
+        assert_eq!(record.tid, 0);
+    }
+    assert_eq!(records[0].ret, pid as isize);
+    let write = &records[1];
+    assert_eq!((write.args[0], write.args[2]), (to_parent[1], 6));
+    assert_eq!(write.ret, 6);
+    assert_eq!(&write.data[..write.data_len], b"traced");
+    let read = &records[2];
+    assert_eq!(read.ret, 4);
+    assert_eq!(&read.data[..read.data_len], b"ping");
+    let open = &records[3];
+    assert_eq!(open.ret.into_result(), Err(Errno::ENOENT));
+    assert_eq!(&open.data[..open.data_len], b"no_such_file");
+    assert_eq!(open.flags & TRACE_TRUNCATED, 0);
+    let exit = &records[4];
+    assert_eq!(exit.args[0], pid);
+    assert_eq!(exit.flags & TRACE_NORETURN, TRACE_NORETURN);
+    assert!(records.windows(2).all(|w| w[0].start_us <= w[1].start_us));
+}
+
+/// Long strings and buffers are cut, and marked so.
+fn truncated() {
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    let pid = fork();
+    if pid == 0 {
+        close(pipe_fd[0]);
+        assert_eq!(trace(0, true), 0);
+        write(pipe_fd[1], &[b'x'; 100]);
+        exit(0);
+    }
+    close(pipe_fd[1]);
+    let mut buf = [0u8; 100];
+    assert_eq!(read(pipe_fd[0], &mut buf), 100);
+    close(pipe_fd[0]);
+    let records = read_all(pid as usize);
+    wait_exit(pid as usize, 0);
+    let write = &records[0];
+    assert_eq!(write.ret, 100);
+    assert!(write.data_len > 0 && write.data_len < 100);
+    assert!(write.data[..write.data_len].iter().all(|b| *b == b'x'));
+    assert_eq!(write.flags & TRACE_TRUNCATED, TRACE_TRUNCATED);
+}
+
+/// The oldest records are dropped once the log is full, which shows as a
+/// gap in their numbers.
+fn overflow() {
+    const CALLS: usize = 300;
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    let pid = fork();
+    if pid == 0 {
+        close(pipe_fd[0]);
+        assert_eq!(trace(0, true), 0);
+        for _ in 0..CALLS {
+            getpid();
+        }
+        exit(0);
+    }
+    close(pipe_fd[1]);
+    // the end of the pipe shows the child has exited
+    let mut buf = [0u8; 1];
+    assert_eq!(read(pipe_fd[0], &mut buf), 0);
+    close(pipe_fd[0]);
+    let records = read_all(pid as usize);
+    wait_exit(pid as usize, 0);
+    assert!(!records.is_empty() && records.len() < CALLS);
+    let last = records.last().unwrap();
+    assert_eq!((last.id, last.seq), (SYSCALL_EXIT, CALLS));
+    assert_eq!(records[0].seq, CALLS + 1 - records.len());
+}
+
+fn errors() {
+    assert_eq!(trace(0, true), 0);
+    // the caller can't wait for its own records
+    let mut records = [TraceRecord::default(); 1];
+    assert_eq!(
+        trace_read(0, &mut records).into_result(),
+        Err(Errno::EINVAL)
+    );
+    assert_eq!(trace(0, false), 0);
+    assert_eq!(
+        trace_read(100_000, &mut records).into_result(),
+        Err(Errno::ESRCH)
+    );
+    assert_eq!(trace(100_000, true).into_result(), Err(Errno::ESRCH));
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    let pid = fork();
+    if pid == 0 {
+        exit(0);
+    }
+    let pid = pid as usize;
+    close(pipe_fd[1]);
+    let mut buf = [0u8; 1];
+    assert_eq!(read(pipe_fd[0], &mut buf), 0);
+    close(pipe_fd[0]);
+    // an exited child can't be traced any more, nor read without a log
+    assert_eq!(trace(pid, true).into_result(), Err(Errno::ESRCH));
+    assert_eq!(
+        trace_read(pid, &mut records).into_result(),
+        Err(Errno::EINVAL)
+    );
+    wait_exit(pid, 0);
+}
+
+extern "C" fn usr1_handler(_signum: i32) {}
+
+/// A read waiting for the next record of a child blocked in a syscall is
+/// interrupted by a signal caught by a handler, and goes on afterwards.
+fn interrupted() {
+    let action = SignalAction {
+        handler: usr1_handler as usize,
+        ..SignalAction::default()
+    };
+    assert_eq!(sigaction(SIGUSR1, Some(&action), None), 0);
+    let (mut to_parent, mut to_child) = ([0usize; 2], [0usize; 2]);
+    assert_eq!(pipe(&mut to_parent), 0);
+    assert_eq!(pipe(&mut to_child), 0);
+    let pid = fork();
+    if pid == 0 {
+        close(to_child[1]);
+        assert_eq!(trace(0, true), 0);
+        write(to_parent[1], b"x");
+        // blocks until the parent closes its end
+        let mut buf = [0u8; 1];
+        read(to_child[0], &mut buf);
+        exit(0);
+    }
+    let pid = pid as usize;
+    close(to_child[0]);
+    let mut buf = [0u8; 1];
+    assert_eq!(read(to_parent[0], &mut buf), 1);
+    let parent = getpid() as usize;
+    let killer = fork();
+    if killer == 0 {
+        sleep(100);
+        kill(parent, SIGUSR1);
+        exit(0);
+    }
+    // the write is read before the wait for the next record
+    let mut records = [TraceRecord::default(); 8];
+    loop {
+        match trace_read(pid, &mut records).into_result() {
+            Ok(count) => assert!(count > 0),
+            Err(errno) => {
+                assert_eq!(errno, Errno::EINTR);
+                break;
+            }
+        }
+    }
+    wait_exit(killer as usize, 0);
+    close(to_child[1]);
+    let records = read_all(pid);
+    wait_exit(pid, 0);
+    close(to_parent[0]);
+    close(to_parent[1]);
+    let last = records.last().unwrap();
+    assert_eq!(last.id, SYSCALL_EXIT);
+    assert_eq!(sigaction(SIGUSR1, Some(&SignalAction::default()), None), 0);
+}
+
+static TESTS: &[(fn(), &str)] = &[
+    (records, "records"),
+    (truncated, "truncated"),
+    (overflow, "overflow"),
+    (errors, "errors"),
+    (interrupted, "interrupted"),
+];
+
+#[no_mangle]
+pub fn main() -> i32 {
+    run_tests("trace_test", TESTS);
+    0
+}
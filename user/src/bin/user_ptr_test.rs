@@ -0,0 +1,298 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use user_lib::{
+    close, dup, exit, fork, mmap, munmap, pipe, raw_syscall, read, run_tests, sigaction, sleep,
+    thread_create, thread_exit, wait, waittid, write, Errno, IntoResult, MmapFlags, MmapProt,
+    SignalAction, SIGUSR1, SIG_IGN,
+};
+
+const SYSCALL_PIPE2: usize = 59;
+const SYSCALL_READ: usize = 63;
+const SYSCALL_WRITE: usize = 64;
+const SYSCALL_RT_SIGACTION: usize = 134;
+const SYSCALL_EXECVE: usize = 221;
+const SYSCALL_WAIT4: usize = 260;
+const SYSCALL_SWAP_STAT: usize = 1100;
+const SYSCALL_SCHED_STAT: usize = 1200;
+const PAGE_SIZE: usize = 4096;
+const USER_SPACE_TOP: usize = 0x40_0000_0000;
+const KERNEL_ADDR: usize = 0xffff_ffc0_8020_0000;
+
+static mut PIPE_FDS: [usize; 2] = [0; 2];
+
+fn map(pages: usize, prot: MmapProt) -> usize {
+    let flags = MmapFlags::PRIVATE | MmapFlags::ANONYMOUS;
+    mmap(0, pages * PAGE_SIZE, prot, flags, -1, 0)
+        .into_result()
+        .unwrap()
+}
+
+fn bytes(addr: usize, len: usize) -> &'static mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, len) }
+}
+
+fn syscall(id: usize, args: &[usize]) -> Result<usize, Errno> {
+    let mut all = [0; 6];
+    all[..args.len()].copy_from_slice(args);
+    raw_syscall(id, all).into_result()
+}
+
+/// Addresses which are null, in the kernel, past the top of user space,
+/// aliasing user pages above the bits Sv39 translates, or wrapping around.
+fn bad_addresses() {
+    let buf = [0u8; 4];
+    let valid = buf.as_ptr() as usize;
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    for addr in [
+        0,
+        KERNEL_ADDR,
+        USER_SPACE_TOP - 2,
+        valid | (1 << 40),
+        usize::MAX - 1,
+    ]
+    .iter()
+    {
+        assert_eq!(
+            syscall(SYSCALL_WRITE, &[pipe_fd[1], *addr, buf.len()]),
+            Err(Errno::EFAULT)
+        );
+        assert_eq!(
+            syscall(SYSCALL_READ, &[pipe_fd[0], *addr, buf.len()]),
+            Err(Errno::EFAULT)
+        );
+    }
+    assert_eq!(
+        syscall(SYSCALL_WRITE, &[pipe_fd[1], valid, usize::MAX]),
+        Err(Errno::EFAULT)
+    );
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+}
+
+/// The kernel only writes where the process could, and only reads where it
+/// could.
+fn page_permissions() {
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    assert_eq!(write(pipe_fd[1], b"data"), 4);
+    let read_only = map(1, MmapProt::READ);
+    assert_eq!(
+        syscall(SYSCALL_READ, &[pipe_fd[0], read_only, 4]),
+        Err(Errno::EFAULT)
+    );
+    assert!(bytes(read_only, PAGE_SIZE).iter().all(|b| *b == 0));
+    let text = main as usize;
+    assert_eq!(
+        syscall(SYSCALL_READ, &[pipe_fd[0], text, 4]),
+        Err(Errno::EFAULT)
+    );
+    // nothing was taken out of the pipe
+    let mut buf = [0u8; 4];
+    assert_eq!(read(pipe_fd[0], &mut buf), 4);
+    assert_eq!(&buf, b"data");
+    let no_access = map(1, MmapProt::NONE);
+    assert_eq!(
+        syscall(SYSCALL_WRITE, &[pipe_fd[1], no_access, 4]),
+        Err(Errno::EFAULT)
+    );
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+    munmap(read_only, PAGE_SIZE);
+    munmap(no_access, PAGE_SIZE);
+}
+
+/// Buffers spanning two pages are copied whole, and fail as a whole if the
+/// second page is missing.
+fn crossing_pages() {
+    let rw = MmapProt::READ | MmapProt::WRITE;
+    let addr = map(2, rw);
+    let boundary = addr + PAGE_SIZE;
+    bytes(boundary - 3, 6).copy_from_slice(b"across");
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    assert_eq!(write(pipe_fd[1], bytes(boundary - 3, 6)), 6);
+    let other = map(2, rw);
+    assert_eq!(read(pipe_fd[0], bytes(other + PAGE_SIZE - 2, 6)), 6);
+    assert_eq!(bytes(other + PAGE_SIZE - 2, 6), b"across");
+    munmap(boundary, PAGE_SIZE);
+    assert_eq!(
+        syscall(SYSCALL_WRITE, &[pipe_fd[1], boundary - 3, 6]),
+        Err(Errno::EFAULT)
+    );
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+    munmap(addr, PAGE_SIZE);
+    munmap(other, 2 * PAGE_SIZE);
+}
+
+/// A status which can't be stored leaves the child to be waited for.
+fn waitpid_status() {
+    let pid = fork();
+    if pid == 0 {
+        exit(7);
+    }
+    let read_only = map(1, MmapProt::READ);
+    assert_eq!(
+        syscall(SYSCALL_WAIT4, &[pid as usize, read_only, 0]),
+        Err(Errno::EFAULT)
+    );
+    assert_eq!(
+        syscall(SYSCALL_WAIT4, &[pid as usize, KERNEL_ADDR, 0]),
+        Err(Errno::EFAULT)
+    );
+    let mut exit_code = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 7);
+    munmap(read_only, PAGE_SIZE);
+}
+
+/// Fds which can't be handed back aren't left open.
+fn pipe_fds() {
+    let next_fd = dup(0);
+    assert!(next_fd > 0);
+    close(next_fd as usize);
+    let read_only = map(1, MmapProt::READ);
+    for addr in [read_only, read_only + PAGE_SIZE - 4, KERNEL_ADDR].iter() {
+        assert_eq!(syscall(SYSCALL_PIPE2, &[*addr]), Err(Errno::EFAULT));
+    }
+    assert_eq!(dup(0), next_fd);
+    close(next_fd as usize);
+    munmap(read_only, PAGE_SIZE);
+}
+
+/// Paths and arguments are checked before the program is replaced.
+fn exec_arguments() {
+    let path = "user_ptr_test\0".as_ptr() as usize;
+    let rw = MmapProt::READ | MmapProt::WRITE;
+    assert_eq!(syscall(SYSCALL_EXECVE, &[0, 0]), Err(Errno::EFAULT));
+    assert_eq!(
+        syscall(SYSCALL_EXECVE, &[KERNEL_ADDR, 0]),
+        Err(Errno::EFAULT)
+    );
+    assert_eq!(
+        syscall(SYSCALL_EXECVE, &[path, KERNEL_ADDR]),
+        Err(Errno::EFAULT)
+    );
+    let bad_arg = [KERNEL_ADDR, 0];
+    assert_eq!(
+        syscall(SYSCALL_EXECVE, &[path, bad_arg.as_ptr() as usize]),
+        Err(Errno::EFAULT)
+    );
+    // a string running into memory which isn't mapped
+    let unterminated = map(2, rw);
+    munmap(unterminated + PAGE_SIZE, PAGE_SIZE);
+    bytes(unterminated, PAGE_SIZE).fill(b'a');
+    let short = unterminated + PAGE_SIZE - 8;
+    assert_eq!(syscall(SYSCALL_EXECVE, &[short, 0]), Err(Errno::EFAULT));
+    let short_arg = [short, 0];
+    assert_eq!(
+        syscall(SYSCALL_EXECVE, &[path, short_arg.as_ptr() as usize]),
+        Err(Errno::EFAULT)
+    );
+    // longer than any path, and longer than the new stack can hold
+    bytes(unterminated + PAGE_SIZE - 1, 1)[0] = 0;
+    assert_eq!(
+        syscall(SYSCALL_EXECVE, &[unterminated, 0]),
+        Err(Errno::ENAMETOOLONG)
+    );
+    let long_arg = [unterminated, 0];
+    assert_eq!(
+        syscall(SYSCALL_EXECVE, &[path, long_arg.as_ptr() as usize]),
+        Err(Errno::E2BIG)
+    );
+    // short ones, but too many of them
+    let many_args = map(3, rw);
+    let argv = unsafe { core::slice::from_raw_parts_mut(many_args as *mut usize, 1024) };
+    argv.fill(path);
+    argv[1023] = 0;
+    assert_eq!(
+        syscall(SYSCALL_EXECVE, &[path, many_args]),
+        Err(Errno::E2BIG)
+    );
+    munmap(unterminated, PAGE_SIZE);
+    munmap(many_args, 3 * PAGE_SIZE);
+}
+
+/// Structs passed in or out of the kernel.
+fn structs() {
+    let read_only = map(1, MmapProt::READ);
+    let signum = SIGUSR1 as usize;
+    let sigsetsize = core::mem::size_of::<u64>();
+    assert_eq!(
+        syscall(SYSCALL_RT_SIGACTION, &[signum, KERNEL_ADDR, 0, sigsetsize]),
+        Err(Errno::EFAULT)
+    );
+    let ignore = SignalAction {
+        handler: SIG_IGN,
+        ..Default::default()
+    };
+    let ignore_ptr = &ignore as *const _ as usize;
+    assert_eq!(
+        syscall(
+            SYSCALL_RT_SIGACTION,
+            &[signum, ignore_ptr, read_only, sigsetsize]
+        ),
+        Err(Errno::EFAULT)
+    );
+    // the action is left as it was
+    let mut old = ignore;
+    assert_eq!(sigaction(SIGUSR1, None, Some(&mut old)), 0);
+    assert_ne!(old.handler, SIG_IGN);
+    for id in [SYSCALL_SWAP_STAT, SYSCALL_SCHED_STAT].iter() {
+        assert_eq!(syscall(*id, &[read_only]), Err(Errno::EFAULT));
+        assert_eq!(syscall(*id, &[KERNEL_ADDR]), Err(Errno::EFAULT));
+    }
+    munmap(read_only, PAGE_SIZE);
+}
+
+/// Reads into the page at `addr` from the pipe at `PIPE_FDS[0]`, exits with
+/// the result.
+fn reading_page(addr: usize) -> ! {
+    let result = read(unsafe { PIPE_FDS[0] }, bytes(addr, PAGE_SIZE));
+    thread_exit(result as i32)
+}
+
+/// The page a blocked read is going to fill is unmapped meanwhile: the read
+/// fails instead of writing to the frame, which may belong to another page
+/// by then.
+fn unmapped_while_blocked() {
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    unsafe {
+        PIPE_FDS = pipe_fd;
+    }
+    let page = map(1, MmapProt::READ | MmapProt::WRITE);
+    let tid = thread_create(reading_page as usize, page);
+    sleep(20);
+    assert_eq!(munmap(page, PAGE_SIZE), 0);
+    // likely to get the frame the read would have written to
+    let other = map(1, MmapProt::READ | MmapProt::WRITE);
+    bytes(other, PAGE_SIZE).fill(0);
+    assert_eq!(write(pipe_fd[1], &[0xaa; 16]), 16);
+    assert_eq!(waittid(tid as usize), Errno::EFAULT.as_ret());
+    assert!(bytes(other, PAGE_SIZE).iter().all(|byte| *byte == 0));
+    munmap(other, PAGE_SIZE);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+}
+
+static TESTS: &[(fn(), &str)] = &[
+    (bad_addresses, "bad_addresses"),
+    (page_permissions, "page_permissions"),
+    (crossing_pages, "crossing_pages"),
+    (waitpid_status, "waitpid_status"),
+    (pipe_fds, "pipe_fds"),
+    (exec_arguments, "exec_arguments"),
+    (structs, "structs"),
+    (unmapped_while_blocked, "unmapped_while_blocked"),
+];
+
+#[no_mangle]
+pub fn main() -> i32 {
+    run_tests("user_ptr_test", TESTS);
+    0
+}
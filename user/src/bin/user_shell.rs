@@ -16,7 +16,15 @@ const LINE_START: &str = ">> ";
 use alloc::string::String;
 use alloc::vec::Vec;
 use user_lib::console::getchar;
-use user_lib::{close, dup, exec, fork, open, pipe, waitpid, OpenFlags};
+use user_lib::{
+    close, dup, exec, fork, getpgid, getpid, ioctl, killpg, open, pipe, setpgid, setsid, sigaction,
+    stop_signal, tcsetpgrp, waitpid_flags, IntoResult, OpenFlags, SignalAction, WaitFlags, SIGCONT,
+    SIGINT, SIGQUIT, SIGTSTP, SIGTTIN, SIGTTOU, SIG_DFL, SIG_IGN, TIOCSCTTY,
+};
+
+/// Signals from the console meant for the foreground job, and those stopping
+/// background jobs, the shell itself ignores them.
+const JOB_CONTROL_SIGNALS: [i32; 5] = [SIGINT, SIGQUIT, SIGTSTP, SIGTTIN, SIGTTOU];
 
 #[derive(Debug)]
 struct ProcessArguments {
@@ -74,9 +82,147 @@ impl ProcessArguments {
     }
 }
 
+/// A pipeline run in a process group of its own.
+struct Job {
+    id: usize,
+    pgid: usize,
+    /// the processes which haven't exited yet
+    pids: Vec<usize>,
+    command: String,
+    stopped: bool,
+}
+
+#[derive(PartialEq)]
+enum JobState {
+    Running,
+    Stopped,
+    Done,
+}
+
+fn set_job_control_signals(handler: usize) {
+    let action = SignalAction {
+        handler,
+        ..SignalAction::default()
+    };
+    for signum in JOB_CONTROL_SIGNALS.iter() {
+        sigaction(*signum, Some(&action), None);
+    }
+}
+
+/// Wait for the processes of `job` until they have all exited or one has
+/// stopped, with WNOHANG only collect what has happened already.
+fn wait_job(job: &mut Job, flags: WaitFlags) -> JobState {
+    while !job.pids.is_empty() {
+        let mut status = 0;
+        let pid = waitpid_flags(
+            -(job.pgid as isize),
+            &mut status,
+            flags | WaitFlags::WUNTRACED,
+        );
+        match pid.into_result() {
+            // nobody left to wait for
+            Err(_) => job.pids.clear(),
+            Ok(0) => return JobState::Running,
+            Ok(_) if stop_signal(status).is_some() => {
+                job.stopped = true;
+                return JobState::Stopped;
+            }
+            Ok(pid) => job.pids.retain(|&job_pid| job_pid != pid),
+        }
+    }
+    JobState::Done
+}
+
+/// Give the console to `job` until it exits or stops.
+fn wait_foreground(job: &mut Job, shell_pgid: usize) -> JobState {
+    tcsetpgrp(0, job.pgid);
+    let state = wait_job(job, WaitFlags::empty());
+    tcsetpgrp(0, shell_pgid);
+    if state == JobState::Stopped {
+        println!("");
+        println!("[{}] Stopped    {}", job.id, job.command);
+    }
+    state
+}
+
+/// Tell which jobs have stopped or exited since the last prompt, and forget
+/// the latter.
+fn report_jobs(jobs: &mut Vec<Job>) {
+    for job in jobs.iter_mut() {
+        let stopped = job.stopped;
+        match wait_job(job, WaitFlags::WNOHANG) {
+            JobState::Done => println!("[{}] Done    {}", job.id, job.command),
+            JobState::Stopped if !stopped => println!("[{}] Stopped    {}", job.id, job.command),
+            _ => {}
+        }
+    }
+    jobs.retain(|job| !job.pids.is_empty());
+}
+
+/// The job with id `arg`, or the latest one if there is no argument.
+fn find_job<'a>(jobs: &'a mut [Job], arg: Option<&str>) -> Option<&'a mut Job> {
+    match arg {
+        Some(arg) => {
+            let id: usize = arg.trim_start_matches('%').parse().ok()?;
+            jobs.iter_mut().find(|job| job.id == id)
+        }
+        None => jobs.last_mut(),
+    }
+}
+
+/// Run `jobs`, `fg` and `bg`, return false if `command` is none of them.
+fn run_builtin(command: &str, jobs: &mut Vec<Job>, shell_pgid: usize) -> bool {
+    let mut args = command.split(' ').filter(|arg| !arg.is_empty());
+    let builtin = args.next();
+    if builtin == Some("jobs") {
+        for job in jobs.iter() {
+            let state = if job.stopped { "Stopped" } else { "Running" };
+            println!("[{}] {}    {}", job.id, state, job.command);
+        }
+        return true;
+    }
+    if builtin != Some("fg") && builtin != Some("bg") {
+        return false;
+    }
+    let job = match find_job(jobs, args.next()) {
+        Some(job) => job,
+        None => {
+            println!("{}: no such job", builtin.unwrap());
+            return true;
+        }
+    };
+    if builtin == Some("fg") {
+        println!("{}", job.command);
+        // give it the console before it can read again
+        tcsetpgrp(0, job.pgid);
+        if job.stopped {
+            job.stopped = false;
+            killpg(job.pgid, SIGCONT);
+        }
+        let id = job.id;
+        if wait_foreground(job, shell_pgid) == JobState::Done {
+            jobs.retain(|job| job.id != id);
+        }
+    } else {
+        if job.stopped {
+            job.stopped = false;
+            killpg(job.pgid, SIGCONT);
+        }
+        println!("[{}] {} &", job.id, job.command);
+    }
+    true
+}
+
 #[no_mangle]
 pub fn main() -> i32 {
     println!("Rust user shell");
+    // lead a session with the console as its terminal, to move jobs in and
+    // out of its foreground
+    setsid();
+    let shell_pgid = getpid() as usize;
+    ioctl(0, TIOCSCTTY, 1);
+    set_job_control_signals(SIG_IGN);
+    let mut jobs: Vec<Job> = Vec::new();
     let mut line: String = String::new();
     print!("{}", LINE_START);
     loop {
@@ -84,8 +230,16 @@ pub fn main() -> i32 {
         match c {
             LF | CR => {
                 println!("");
-                if !line.is_empty() {
-                    let splited: Vec<_> = line.as_str().split('|').collect();
+                let command = line.trim();
+                // a trailing `&` runs the job in the background
+                let (command, background) = match command.strip_suffix('&') {
+                    Some(command) => (String::from(command.trim_end()), true),
+                    None => (String::from(command), false),
+                };
+                line.clear();
+                report_jobs(&mut jobs);
+                if !command.is_empty() && !run_builtin(&command, &mut jobs, shell_pgid) {
+                    let splited: Vec<_> = command.as_str().split('|').collect();
                     let process_arguments_list: Vec<_> = splited
                         .iter()
                         .map(|&cmd| ProcessArguments::new(cmd))
@@ -120,37 +274,51 @@ pub fn main() -> i32 {
                                 pipes_fd.push(pipe_fd);
                             }
                         }
-                        let mut children: Vec<_> = Vec::new();
+                        let mut children: Vec<usize> = Vec::new();
                         for (i, process_argument) in process_arguments_list.iter().enumerate() {
                             let pid = fork();
                             if pid == 0 {
+                                // join the group of the job, led by its first
+                                // process, before anything can signal it
+                                let pgid = children.first().copied().unwrap_or(0);
+                                setpgid(0, pgid);
+                                if !background {
+                                    tcsetpgrp(0, getpgid(0) as usize);
+                                }
+                                set_job_control_signals(SIG_DFL);
                                 let input = &process_argument.input;
                                 let output = &process_argument.output;
                                 let args_copy = &process_argument.args_copy;
                                 let args_addr = &process_argument.args_addr;
                                 // redirect input
                                 if !input.is_empty() {
-                                    let input_fd = open(input.as_str(), OpenFlags::RDONLY);
-                                    if input_fd == -1 {
-                                        println!("Error when opening file {}", input);
-                                        return -4;
-                                    }
-                                    let input_fd = input_fd as usize;
+                                    let input_fd = match open(input.as_str(), OpenFlags::RDONLY)
+                                        .into_result()
+                                    {
+                                        Ok(fd) => fd,
+                                        Err(err) => {
+                                            println!("Error when opening file {}: {}", input, err);
+                                            return -4;
+                                        }
+                                    };
                                     close(0);
                                     assert_eq!(dup(input_fd), 0);
                                     close(input_fd);
                                 }
                                 // redirect output
                                 if !output.is_empty() {
-                                    let output_fd = open(
+                                    let output_fd = match open(
                                         output.as_str(),
-                                        OpenFlags::CREATE | OpenFlags::WRONLY,
-                                    );
-                                    if output_fd == -1 {
-                                        println!("Error when opening file {}", output);
-                                        return -4;
-                                    }
-                                    let output_fd = output_fd as usize;
+                                        OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::WRONLY,
+                                    )
+                                    .into_result()
+                                    {
+                                        Ok(fd) => fd,
+                                        Err(err) => {
+                                            println!("Error when opening file {}: {}", output, err);
+                                            return -4;
+                                        }
+                                    };
                                     close(1);
                                     assert_eq!(dup(output_fd), 1);
                                     close(output_fd);
@@ -172,13 +340,17 @@ pub fn main() -> i32 {
                                     close(pipe_fd[0]);
                                     close(pipe_fd[1]);
                                 }
-                                // execute new application
-                                if exec(args_copy[0].as_str(), args_addr.as_slice()) == -1 {
-                                    println!("Error when executing!");
-                                    return -4;
+                                // execute new application, which only returns
+                                // if it has failed
+                                let ret = exec(args_copy[0].as_str(), args_addr.as_slice());
+                                if let Err(err) = ret.into_result() {
+                                    println!("Error when executing: {}", err);
                                 }
-                                unreachable!();
+                                return -4;
                             } else {
+                                // the same from this side, as either may run first
+                                let pid = pid as usize;
+                                setpgid(pid, children.first().copied().unwrap_or(pid));
                                 children.push(pid);
                             }
                         }
@@ -186,14 +358,20 @@ pub fn main() -> i32 {
                             close(pipe_fd[0]);
                             close(pipe_fd[1]);
                         }
-                        let mut exit_code: i32 = 0;
-                        for pid in children.into_iter() {
-                            let exit_pid = waitpid(pid as usize, &mut exit_code);
-                            assert_eq!(pid, exit_pid);
-                            //println!("Shell: Process {} exited with code {}", pid, exit_code);
+                        let mut job = Job {
+                            id: jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1,
+                            pgid: children[0],
+                            pids: children,
+                            command,
+                            stopped: false,
+                        };
+                        if background {
+                            println!("[{}] {}", job.id, job.pgid);
+                            jobs.push(job);
+                        } else if wait_foreground(&mut job, shell_pgid) == JobState::Stopped {
+                            jobs.push(job);
                         }
                     }
-                    line.clear();
                 }
                 print!("{}", LINE_START);
             }
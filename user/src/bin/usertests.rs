@@ -5,16 +5,47 @@
 extern crate user_lib;
 
 static TESTS: &[&str] = &[
+    "barrier_test\0",
+    "clone_test\0",
+    "condvar_broadcast\0",
+    "cow_fork\0",
+    "deadlock_test\0",
+    "errno_test\0",
     "exit\0",
     "fantastic_text\0",
     "forktest\0",
     "forktest2\0",
     "forktest_simple\0",
+    "futex_test\0",
     "hello_world\0",
+    "job_control\0",
+    "lazy_alloc\0",
+    "linux_abi_test\0",
     "matrix\0",
+    "mmap_test\0",
+    "priority_inherit\0",
+    "ptrace_test\0",
+    "race_adder_atomic\0",
+    "race_adder_futex\0",
+    "race_adder_mutex_blocking\0",
+    "race_adder_mutex_spin\0",
+    "rwlock_test\0",
+    "sbrk_test\0",
+    "sched_test\0",
+    "shm_prodcons\0",
+    "sig_simple\0",
+    "sig_tests\0",
     "sleep\0",
     "sleep_simple\0",
     "stack_overflow\0",
+    "swap_test\0",
+    "thread_fork_exec\0",
+    "thread_test\0",
+    "timeout_test\0",
+    "tls_test\0",
+    "trace_test\0",
+    "user_ptr_test\0",
+    "wait_block\0",
     "yield\0",
 ];
 
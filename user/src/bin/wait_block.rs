@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, sched_stat, sleep, thread_create, wait, waitpid, waitpid_nb, waittid, Errno,
+    IntoResult, SchedStat,
+};
+
+const SLEEP_MS: usize = 200;
+/// A waiting task is dispatched once when it is woken up, a spinning one
+/// on every time slice.
+const MAX_DISPATCHES: usize = 3;
+
+fn dispatches() -> usize {
+    let mut stat = SchedStat::default();
+    assert_eq!(sched_stat(&mut stat), 0);
+    stat.dispatches
+}
+
+fn sleeping_thread() -> ! {
+    sleep(SLEEP_MS);
+    exit(5)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut exit_code: i32 = 0;
+    // no children at all
+    assert_eq!(wait(&mut exit_code).into_result(), Err(Errno::ECHILD));
+    assert_eq!(
+        waitpid_nb(1, &mut exit_code).into_result(),
+        Err(Errno::ECHILD)
+    );
+
+    let pid = fork();
+    if pid == 0 {
+        sleep(SLEEP_MS);
+        exit(7);
+    }
+    // the child is still sleeping
+    assert_eq!(waitpid_nb(pid as usize, &mut exit_code), 0);
+    let before = dispatches();
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 7);
+    let waitpid_dispatches = dispatches() - before;
+    println!("dispatched {} times in waitpid", waitpid_dispatches);
+    assert!(waitpid_dispatches <= MAX_DISPATCHES);
+    // the child has been reaped
+    assert_eq!(
+        waitpid_nb(pid as usize, &mut exit_code).into_result(),
+        Err(Errno::ECHILD)
+    );
+
+    let tid = thread_create(sleeping_thread as usize, 0);
+    let before = dispatches();
+    assert_eq!(waittid(tid as usize), 5);
+    let waittid_dispatches = dispatches() - before;
+    println!("dispatched {} times in waittid", waittid_dispatches);
+    assert!(waittid_dispatches <= MAX_DISPATCHES);
+    assert_eq!(waittid(tid as usize).into_result(), Err(Errno::ESRCH));
+    println!("wait_block passed!");
+    0
+}
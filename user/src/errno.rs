@@ -0,0 +1,141 @@
+//! Errors of syscalls. A failed syscall returns the negated errno, which
+//! `IntoResult` turns into an `Err`.
+
+use core::fmt;
+
+/// Why a syscall has failed, with the errno values of Linux.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Errno(i32);
+
+impl Errno {
+    pub const EPERM: Errno = Errno(1);
+    pub const ENOENT: Errno = Errno(2);
+    pub const ESRCH: Errno = Errno(3);
+    pub const EINTR: Errno = Errno(4);
+    pub const EIO: Errno = Errno(5);
+    pub const E2BIG: Errno = Errno(7);
+    pub const ENOEXEC: Errno = Errno(8);
+    pub const EBADF: Errno = Errno(9);
+    pub const ECHILD: Errno = Errno(10);
+    pub const EAGAIN: Errno = Errno(11);
+    pub const ENOMEM: Errno = Errno(12);
+    pub const EACCES: Errno = Errno(13);
+    pub const EFAULT: Errno = Errno(14);
+    pub const EEXIST: Errno = Errno(17);
+    pub const ENOTDIR: Errno = Errno(20);
+    pub const EISDIR: Errno = Errno(21);
+    pub const EINVAL: Errno = Errno(22);
+    pub const EMFILE: Errno = Errno(24);
+    pub const ENOTTY: Errno = Errno(25);
+    pub const ENOSPC: Errno = Errno(28);
+    pub const ERANGE: Errno = Errno(34);
+    pub const EDEADLK: Errno = Errno(35);
+    pub const ENAMETOOLONG: Errno = Errno(36);
+    pub const ENOSYS: Errno = Errno(38);
+    pub const ETIMEDOUT: Errno = Errno(110);
+
+    /// The errno returned negated by a failed syscall.
+    pub const fn code(self) -> i32 {
+        self.0
+    }
+
+    /// What a failed syscall returns for this error.
+    pub const fn as_ret(self) -> isize {
+        -(self.0 as isize)
+    }
+
+    fn name(self) -> Option<&'static str> {
+        Some(match self {
+            Self::EPERM => "EPERM",
+            Self::ENOENT => "ENOENT",
+            Self::ESRCH => "ESRCH",
+            Self::EINTR => "EINTR",
+            Self::EIO => "EIO",
+            Self::E2BIG => "E2BIG",
+            Self::ENOEXEC => "ENOEXEC",
+            Self::EBADF => "EBADF",
+            Self::ECHILD => "ECHILD",
+            Self::EAGAIN => "EAGAIN",
+            Self::ENOMEM => "ENOMEM",
+            Self::EACCES => "EACCES",
+            Self::EFAULT => "EFAULT",
+            Self::EEXIST => "EEXIST",
+            Self::ENOTDIR => "ENOTDIR",
+            Self::EISDIR => "EISDIR",
+            Self::EINVAL => "EINVAL",
+            Self::EMFILE => "EMFILE",
+            Self::ENOTTY => "ENOTTY",
+            Self::ENOSPC => "ENOSPC",
+            Self::ERANGE => "ERANGE",
+            Self::EDEADLK => "EDEADLK",
+            Self::ENAMETOOLONG => "ENAMETOOLONG",
+            Self::ENOSYS => "ENOSYS",
+            Self::ETIMEDOUT => "ETIMEDOUT",
+            _ => return None,
+        })
+    }
+
+    /// A short description, as printed by `strerror`.
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::EPERM => "Operation not permitted",
+            Self::ENOENT => "No such file or directory",
+            Self::ESRCH => "No such process",
+            Self::EINTR => "Interrupted system call",
+            Self::EIO => "Input/output error",
+            Self::E2BIG => "Argument list too long",
+            Self::ENOEXEC => "Exec format error",
+            Self::EBADF => "Bad file descriptor",
+            Self::ECHILD => "No child processes",
+            Self::EAGAIN => "Resource temporarily unavailable",
+            Self::ENOMEM => "Cannot allocate memory",
+            Self::EACCES => "Permission denied",
+            Self::EFAULT => "Bad address",
+            Self::EEXIST => "File exists",
+            Self::ENOTDIR => "Not a directory",
+            Self::EISDIR => "Is a directory",
+            Self::EINVAL => "Invalid argument",
+            Self::EMFILE => "Too many open files",
+            Self::ENOTTY => "Inappropriate ioctl for device",
+            Self::ENOSPC => "No space left on device",
+            Self::ERANGE => "Numerical result out of range",
+            Self::EDEADLK => "Resource deadlock avoided",
+            Self::ENAMETOOLONG => "File name too long",
+            Self::ENOSYS => "Function not implemented",
+            Self::ETIMEDOUT => "Connection timed out",
+            _ => "Unknown error",
+        }
+    }
+}
+
+impl fmt::Debug for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "Errno({})", self.0),
+        }
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+pub type Result<T = usize> = core::result::Result<T, Errno>;
+
+/// Turn what a syscall returns into a `Result`, with the value on success.
+pub trait IntoResult {
+    fn into_result(self) -> Result;
+}
+
+impl IntoResult for isize {
+    fn into_result(self) -> Result {
+        if self < 0 {
+            Err(Errno(self.wrapping_neg() as i32))
+        } else {
+            Ok(self as usize)
+        }
+    }
+}
@@ -2,11 +2,15 @@
 #![feature(linkage)]
 #![feature(panic_info_message)]
 #![feature(alloc_error_handler)]
+#![feature(allow_internal_unstable)]
 
 #[macro_use]
 pub mod console;
+mod errno;
 mod lang_items;
+pub mod sync;
 mod syscall;
+pub mod tls;
 
 extern crate alloc;
 #[macro_use]
@@ -14,14 +18,48 @@ extern crate bitflags;
 
 use alloc::vec::Vec;
 use buddy_system_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{null, null_mut, NonNull};
+use core::sync::atomic::AtomicU32;
 use syscall::*;
 
-const USER_HEAP_SIZE: usize = 32768;
+pub use errno::{Errno, IntoResult, Result};
 
-static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
+/// The heap grows by at least this many bytes at a time.
+const USER_HEAP_GROW_SIZE: usize = 32768;
+
+/// A buddy system heap which moves the program break forward when it runs
+/// out of memory.
+struct GrowableHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for GrowableHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.0.lock();
+        if let Ok(ptr) = heap.alloc(layout) {
+            return ptr.as_ptr();
+        }
+        // add an aligned block large enough for the request, the padding
+        // before it is added to the heap as well
+        let size = layout
+            .size()
+            .max(layout.align())
+            .max(USER_HEAP_GROW_SIZE)
+            .next_power_of_two();
+        let start = sbrk(0) as usize;
+        let aligned = (start + size - 1) & !(size - 1);
+        if sbrk((aligned + size - start) as isize) < 0 {
+            return null_mut();
+        }
+        heap.add_to_heap(start, aligned + size);
+        heap.alloc(layout).map_or(null_mut(), |ptr| ptr.as_ptr())
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout);
+    }
+}
 
 #[global_allocator]
-static HEAP: LockedHeap = LockedHeap::empty();
+static HEAP: GrowableHeap = GrowableHeap(LockedHeap::empty());
 
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
@@ -31,10 +69,6 @@ pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
 #[no_mangle]
 #[link_section = ".text.entry"]
 pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
-    unsafe {
-        HEAP.lock()
-            .init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
-    }
     let mut v: Vec<&'static str> = Vec::new();
     for i in 0..argc {
         let str_start =
@@ -58,27 +92,97 @@ fn main(_argc: usize, _argv: &[&str]) -> i32 {
     panic!("Cannot find main!");
 }
 
+/// Run the tests of `program` in order, each announced by its name, and
+/// report that they have passed. A failing test panics.
+pub fn run_tests(program: &str, tests: &[(fn(), &str)]) {
+    for (test, name) in tests {
+        println!("{}: {}", program, name);
+        test();
+    }
+    println!("{} passed!", program);
+}
+
 bitflags! {
+    /// The values of Linux.
     pub struct OpenFlags: u32 {
         const RDONLY = 0;
         const WRONLY = 1 << 0;
         const RDWR = 1 << 1;
-        const CREATE = 1 << 9;
-        const TRUNC = 1 << 10;
+        const CREATE = 0o100;
+        /// fail with EEXIST if the file exists, with CREATE
+        const EXCL = 0o200;
+        const TRUNC = 0o1000;
+        const APPEND = 0o2000;
+        /// fail with ENOTDIR unless the file is a directory
+        const DIRECTORY = 0o200000;
     }
 }
 
+/// `dirfd` of the `*at` syscalls for paths which aren't relative to a
+/// directory.
+pub const AT_FDCWD: isize = -100;
+
+/// `struct timespec` of Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeSpec {
+    pub tv_sec: usize,
+    pub tv_nsec: usize,
+}
+
+/// `struct timeval` of Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeVal {
+    pub tv_sec: usize,
+    pub tv_usec: usize,
+}
+
+/// Make syscall `id` without a wrapper, whatever the arguments are.
+pub fn raw_syscall(id: usize, args: [usize; 6]) -> isize {
+    sys_raw(id, args)
+}
+
 pub fn dup(fd: usize) -> isize {
     sys_dup(fd)
 }
+
+/// Terminal requests of `ioctl`, the console is the only terminal.
+pub const TIOCSCTTY: usize = 0x540e;
+pub const TIOCGPGRP: usize = 0x540f;
+pub const TIOCSPGRP: usize = 0x5410;
+
+pub fn ioctl(fd: usize, request: usize, arg: usize) -> isize {
+    sys_ioctl(fd, request, arg)
+}
+/// The foreground process group of the terminal `fd`.
+pub fn tcgetpgrp(fd: usize) -> isize {
+    let mut pgid: i32 = 0;
+    let ret = ioctl(fd, TIOCGPGRP, &mut pgid as *mut _ as usize);
+    if ret < 0 {
+        return ret;
+    }
+    pgid as isize
+}
+/// Put group `pgid` in the foreground of the terminal `fd`.
+pub fn tcsetpgrp(fd: usize, pgid: usize) -> isize {
+    let pgid = pgid as i32;
+    ioctl(fd, TIOCSPGRP, &pgid as *const _ as usize)
+}
 pub fn open(path: &str, flags: OpenFlags) -> isize {
-    sys_open(path, flags.bits)
+    sys_openat(AT_FDCWD, path, flags.bits, 0o644)
 }
 pub fn close(fd: usize) -> isize {
     sys_close(fd)
 }
 pub fn pipe(pipe_fd: &mut [usize]) -> isize {
-    sys_pipe(pipe_fd)
+    let mut fds = [0i32; 2];
+    let ret = sys_pipe2(&mut fds, 0);
+    if ret == 0 {
+        pipe_fd[0] = fds[0] as usize;
+        pipe_fd[1] = fds[1] as usize;
+    }
+    ret
 }
 pub fn read(fd: usize, buf: &mut [u8]) -> isize {
     sys_read(fd, buf)
@@ -92,8 +196,14 @@ pub fn exit(exit_code: i32) -> ! {
 pub fn yield_() -> isize {
     sys_yield()
 }
+/// Milliseconds since boot.
 pub fn get_time() -> isize {
-    sys_get_time()
+    let mut tv = TimeVal::default();
+    let ret = sys_gettimeofday(&mut tv);
+    if ret < 0 {
+        return ret;
+    }
+    (tv.tv_sec * 1000 + tv.tv_usec / 1000) as isize
 }
 pub fn getpid() -> isize {
     sys_getpid()
@@ -101,53 +211,245 @@ pub fn getpid() -> isize {
 pub fn fork() -> isize {
     sys_fork()
 }
+/// Move process `pid` to group `pgid`, 0 stands for the caller as `pid` and
+/// for `pid` as `pgid`.
+pub fn setpgid(pid: usize, pgid: usize) -> isize {
+    sys_setpgid(pid, pgid)
+}
+pub fn getpgid(pid: usize) -> isize {
+    sys_getpgid(pid)
+}
+/// Start a new session and group led by the caller, return its id.
+pub fn setsid() -> isize {
+    sys_setsid()
+}
+pub fn getsid(pid: usize) -> isize {
+    sys_getsid(pid)
+}
 pub fn exec(path: &str, args: &[*const u8]) -> isize {
-    sys_exec(path, args)
+    sys_execve(path, args, null())
 }
-pub fn wait(exit_code: &mut i32) -> isize {
-    loop {
-        match sys_waitpid(-1, exit_code as *mut _) {
-            -2 => {
-                yield_();
-            }
-            // -1 or a real pid
-            exit_pid => return exit_pid,
-        }
+/// `args` and `envs` are null-terminated arrays of `\0`-terminated strings.
+pub fn execve(path: &str, args: &[*const u8], envs: &[*const u8]) -> isize {
+    sys_execve(path, args, envs.as_ptr())
+}
+bitflags! {
+    pub struct WaitFlags: u32 {
+        /// return 0 at once if no child has exited yet
+        const WNOHANG = 1 << 0;
+        /// report children which have stopped as well
+        const WUNTRACED = 1 << 1;
+        /// report stopped children which have continued as well
+        const WCONTINUED = 1 << 3;
     }
 }
 
+/// Block until a child exits, return its pid or fail with ECHILD if there is
+/// no child.
+pub fn wait(exit_code: &mut i32) -> isize {
+    sys_waitpid(-1, exit_code as *mut _, WaitFlags::empty().bits())
+}
+
 pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
-    loop {
-        match sys_waitpid(pid as isize, exit_code as *mut _) {
-            -2 => {
-                yield_();
-            }
-            // -1 or a real pid
-            exit_pid => return exit_pid,
-        }
-    }
+    sys_waitpid(pid as isize, exit_code as *mut _, WaitFlags::empty().bits())
 }
 
+/// Return 0 if the child is still running.
 pub fn waitpid_nb(pid: usize, exit_code: &mut i32) -> isize {
-    sys_waitpid(pid as isize, exit_code as *mut _)
+    sys_waitpid(pid as isize, exit_code as *mut _, WaitFlags::WNOHANG.bits())
+}
+
+/// `pid` may also be 0 for any child in the group of the caller, or below -1
+/// for any child in group `-pid`. With WUNTRACED or WCONTINUED, the status
+/// may be that of a stop or a continue instead of an exit code, see
+/// `stop_signal` and `is_continued`.
+pub fn waitpid_flags(pid: isize, status: &mut i32, flags: WaitFlags) -> isize {
+    sys_waitpid(pid, status as *mut _, flags.bits())
 }
 
+/// The signal which has stopped a child, if `status` reports a stop.
+pub fn stop_signal(status: i32) -> Option<i32> {
+    if status & 0xff == 0x7f && (1..32).contains(&(status >> 8)) {
+        Some(status >> 8)
+    } else {
+        None
+    }
+}
+
+/// Whether `status` reports that a stopped child has continued.
+pub fn is_continued(status: i32) -> bool {
+    status == 0xffff
+}
+
+pub const SIGHUP: i32 = 1;
+pub const SIGINT: i32 = 2;
+pub const SIGQUIT: i32 = 3;
+pub const SIGILL: i32 = 4;
+pub const SIGTRAP: i32 = 5;
+pub const SIGABRT: i32 = 6;
+pub const SIGBUS: i32 = 7;
+pub const SIGFPE: i32 = 8;
+pub const SIGKILL: i32 = 9;
+pub const SIGUSR1: i32 = 10;
+pub const SIGSEGV: i32 = 11;
+pub const SIGUSR2: i32 = 12;
+pub const SIGPIPE: i32 = 13;
+pub const SIGALRM: i32 = 14;
+pub const SIGTERM: i32 = 15;
+pub const SIGSTKFLT: i32 = 16;
+pub const SIGCHLD: i32 = 17;
+pub const SIGCONT: i32 = 18;
+pub const SIGSTOP: i32 = 19;
+pub const SIGTSTP: i32 = 20;
+pub const SIGTTIN: i32 = 21;
+pub const SIGTTOU: i32 = 22;
+pub const SIGURG: i32 = 23;
+pub const SIGXCPU: i32 = 24;
+pub const SIGXFSZ: i32 = 25;
+pub const SIGVTALRM: i32 = 26;
+pub const SIGPROF: i32 = 27;
+pub const SIGWINCH: i32 = 28;
+pub const SIGIO: i32 = 29;
+pub const SIGPWR: i32 = 30;
+pub const SIGSYS: i32 = 31;
+
 bitflags! {
-    pub struct SignalFlags: i32 {
-        const SIGINT    = 1 << 2;
-        const SIGILL    = 1 << 4;
-        const SIGABRT   = 1 << 6;
-        const SIGFPE    = 1 << 8;
-        const SIGSEGV   = 1 << 11;
+    /// Signal n is bit n - 1, as in the `sigset_t` of Linux.
+    pub struct SignalFlags: u64 {
+        const SIGHUP    = 1 << 0;
+        const SIGINT    = 1 << 1;
+        const SIGQUIT   = 1 << 2;
+        const SIGILL    = 1 << 3;
+        const SIGTRAP   = 1 << 4;
+        const SIGABRT   = 1 << 5;
+        const SIGBUS    = 1 << 6;
+        const SIGFPE    = 1 << 7;
+        const SIGKILL   = 1 << 8;
+        const SIGUSR1   = 1 << 9;
+        const SIGSEGV   = 1 << 10;
+        const SIGUSR2   = 1 << 11;
+        const SIGPIPE   = 1 << 12;
+        const SIGALRM   = 1 << 13;
+        const SIGTERM   = 1 << 14;
+        const SIGSTKFLT = 1 << 15;
+        const SIGCHLD   = 1 << 16;
+        const SIGCONT   = 1 << 17;
+        const SIGSTOP   = 1 << 18;
+        const SIGTSTP   = 1 << 19;
+        const SIGTTIN   = 1 << 20;
+        const SIGTTOU   = 1 << 21;
+        const SIGURG    = 1 << 22;
+        const SIGXCPU   = 1 << 23;
+        const SIGXFSZ   = 1 << 24;
+        const SIGVTALRM = 1 << 25;
+        const SIGPROF   = 1 << 26;
+        const SIGWINCH  = 1 << 27;
+        const SIGIO     = 1 << 28;
+        const SIGPWR    = 1 << 29;
+        const SIGSYS    = 1 << 30;
+    }
+}
+
+/// `handler` of a `SignalAction` taking the default action.
+pub const SIG_DFL: usize = 0;
+/// `handler` of a `SignalAction` ignoring the signal.
+pub const SIG_IGN: usize = 1;
+
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+/// `flags` of a `SignalAction`: the handler is passed a `siginfo_t` in a1.
+pub const SA_SIGINFO: usize = 0x4;
+/// the signal isn't blocked while its handler runs
+pub const SA_NODEFER: usize = 0x4000_0000;
+/// the action goes back to the default once the handler is entered
+pub const SA_RESETHAND: usize = 0x8000_0000;
+
+/// A handler is called with the signal number and may simply return.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SignalAction {
+    pub handler: usize,
+    /// `SA_*`
+    pub flags: usize,
+    /// signals blocked while the handler runs, besides the signal itself
+    pub mask: SignalFlags,
+}
+
+impl Default for SignalAction {
+    fn default() -> Self {
+        Self {
+            handler: SIG_DFL,
+            flags: 0,
+            mask: SignalFlags::empty(),
+        }
+    }
+}
+
+pub fn kill(pid: usize, signum: i32) -> isize {
+    sys_kill(pid as isize, signum)
+}
+/// Send a signal to every process of group `pgid`, or of the group of the
+/// caller if it is 0.
+pub fn killpg(pgid: usize, signum: i32) -> isize {
+    sys_kill(-(pgid as isize), signum)
+}
+
+pub fn sigaction(
+    signum: i32,
+    action: Option<&SignalAction>,
+    old_action: Option<&mut SignalAction>,
+) -> isize {
+    sys_rt_sigaction(
+        signum,
+        action.map_or(core::ptr::null(), |a| a as *const _),
+        old_action.map_or(core::ptr::null_mut(), |a| a as *mut _),
+    )
+}
+
+/// Return the old mask, or fail with EINVAL if `how` is invalid.
+pub fn sigprocmask(how: usize, mask: SignalFlags) -> isize {
+    let mut old = 0;
+    let ret = sys_rt_sigprocmask(how, &mask.bits, &mut old);
+    if ret < 0 {
+        return ret;
     }
+    old as isize
 }
 
-pub fn kill(pid: usize, signal: i32) -> isize {
-    sys_kill(pid, signal)
+pub const FUTEX_WAIT: usize = 0;
+pub const FUTEX_WAKE: usize = 1;
+pub const FUTEX_REQUEUE: usize = 3;
+
+/// Block while `word` holds `expected`, fail with EAGAIN at once if it
+/// doesn't.
+/// The caller has to check the word again since wakeups may be spurious.
+pub fn futex_wait(word: &AtomicU32, expected: u32) -> isize {
+    let uaddr = word as *const _ as *const u32;
+    sys_futex(uaddr, FUTEX_WAIT, expected as usize, 0, null())
+}
+
+/// Wake up at most `count` waiters of `word`, return how many were woken up.
+pub fn futex_wake(word: &AtomicU32, count: usize) -> isize {
+    let uaddr = word as *const _ as *const u32;
+    sys_futex(uaddr, FUTEX_WAKE, count, 0, null())
+}
+
+/// Wake up at most `count` waiters of `word` and move at most `requeue` of
+/// the others to `target`, return how many were woken up.
+pub fn futex_requeue(word: &AtomicU32, count: usize, requeue: usize, target: &AtomicU32) -> isize {
+    let uaddr = word as *const _ as *const u32;
+    let uaddr2 = target as *const _ as *const u32;
+    sys_futex(uaddr, FUTEX_REQUEUE, count, requeue, uaddr2)
 }
 
 pub fn sleep(sleep_ms: usize) {
-    sys_sleep(sleep_ms);
+    let req = TimeSpec {
+        tv_sec: sleep_ms / 1000,
+        tv_nsec: sleep_ms % 1000 * 1_000_000,
+    };
+    sys_nanosleep(&req, null_mut());
 }
 
 pub fn thread_create(entry: usize, arg: usize) -> isize {
@@ -156,28 +458,74 @@ pub fn thread_create(entry: usize, arg: usize) -> isize {
 pub fn gettid() -> isize {
     sys_gettid()
 }
+/// Return the exit code of the thread, which can't be told from an error if
+/// it is negative.
 pub fn waittid(tid: usize) -> isize {
-    loop {
-        match sys_waittid(tid) {
-            -2 => {
-                yield_();
-            }
-            exit_code => return exit_code,
-        }
+    sys_waittid(tid)
+}
+/// Exit the current thread only, even the main thread. The process exits
+/// with its last thread.
+pub fn thread_exit(exit_code: i32) -> ! {
+    sys_thread_exit(exit_code)
+}
+/// Let a thread be reaped as soon as it exits instead of by `waittid`.
+pub fn thread_detach(tid: usize) -> isize {
+    sys_thread_detach(tid)
+}
+/// Make a thread other than the main one exit, with exit code -9.
+pub fn thread_kill(tid: usize) -> isize {
+    sys_thread_kill(tid)
+}
+
+bitflags! {
+    pub struct CloneFlags: usize {
+        const VM = 1 << 8;
+        const FILES = 1 << 10;
+        const SIGHAND = 1 << 11;
+        const THREAD = 1 << 16;
+        const SETTLS = 1 << 19;
+        const PARENT_SETTID = 1 << 20;
+        const CHILD_CLEARTID = 1 << 21;
+        const CHILD_SETTID = 1 << 24;
     }
 }
 
+/// Create a process, or a thread with `CloneFlags::THREAD` which also needs
+/// `VM`, `SIGHAND` and `FILES`. The child calls `entry(arg)` on `stack`, or
+/// on a stack of its own if it is 0, then exits with what `entry` returns.
+/// Return the pid or tid of the child, or fail with EINVAL if the flags are
+/// invalid.
+pub fn clone(
+    entry: fn(usize) -> i32,
+    arg: usize,
+    flags: CloneFlags,
+    stack: usize,
+    tls: usize,
+    ptid: Option<&AtomicU32>,
+    ctid: Option<&AtomicU32>,
+) -> isize {
+    let word =
+        |word: Option<&AtomicU32>| word.map_or(null_mut(), |word| word as *const _ as *mut u32);
+    sys_clone(entry, arg, flags.bits, stack, word(ptid), tls, word(ctid))
+}
+
 pub fn mutex_create() -> isize {
     sys_mutex_create(false)
 }
 pub fn mutex_blocking_create() -> isize {
     sys_mutex_create(true)
 }
-pub fn mutex_lock(mutex_id: usize) {
-    sys_mutex_lock(mutex_id);
+pub fn mutex_lock(mutex_id: usize) -> isize {
+    sys_mutex_lock(mutex_id)
+}
+/// Returned by the waits with a timeout when they give up.
+pub const TIMEDOUT: isize = Errno::ETIMEDOUT.as_ret();
+pub fn mutex_lock_timeout(mutex_id: usize, timeout_ms: usize) -> isize {
+    sys_mutex_lock_timeout(mutex_id, timeout_ms)
 }
-pub fn mutex_unlock(mutex_id: usize) {
-    sys_mutex_unlock(mutex_id);
+/// Fail with EPERM if the mutex isn't locked.
+pub fn mutex_unlock(mutex_id: usize) -> isize {
+    sys_mutex_unlock(mutex_id)
 }
 pub fn semaphore_create(res_count: usize) -> isize {
     sys_semaphore_create(res_count)
@@ -185,8 +533,22 @@ pub fn semaphore_create(res_count: usize) -> isize {
 pub fn semaphore_up(sem_id: usize) {
     sys_semaphore_up(sem_id);
 }
-pub fn semaphore_down(sem_id: usize) {
-    sys_semaphore_down(sem_id);
+pub fn semaphore_down(sem_id: usize) -> isize {
+    sys_semaphore_down(sem_id)
+}
+pub fn semaphore_down_timeout(sem_id: usize, timeout_ms: usize) -> isize {
+    sys_semaphore_down_timeout(sem_id, timeout_ms)
+}
+/// Return the id of the semaphore with `key`, shared by the processes which
+/// open it, creating it with `res_count` units if none has it open. Those of
+/// `semaphore_create` belong to a single process, a forked child has none.
+pub fn semaphore_open(key: usize, res_count: usize) -> isize {
+    sys_semaphore_open(key, res_count)
+}
+/// Once enabled, `mutex_lock` and `semaphore_down` fail with EDEADLK instead
+/// of blocking forever.
+pub fn enable_deadlock_detect(enabled: bool) -> isize {
+    sys_enable_deadlock_detect(enabled as usize)
 }
 pub fn condvar_create() -> isize {
     sys_condvar_create(0)
@@ -197,3 +559,221 @@ pub fn condvar_signal(condvar_id: usize) {
 pub fn condvar_wait(condvar_id: usize, mutex_id: usize) {
     sys_condvar_wait(condvar_id, mutex_id);
 }
+/// The mutex is locked again when this returns, timed out or not.
+pub fn condvar_wait_timeout(condvar_id: usize, mutex_id: usize, timeout_ms: usize) -> isize {
+    sys_condvar_wait_timeout(condvar_id, mutex_id, timeout_ms)
+}
+pub fn condvar_broadcast(condvar_id: usize) {
+    sys_condvar_broadcast(condvar_id);
+}
+pub const RWLOCK_PREFER_READER: usize = 0;
+pub const RWLOCK_PREFER_WRITER: usize = 1;
+pub fn rwlock_create(policy: usize) -> isize {
+    sys_rwlock_create(policy)
+}
+pub fn rwlock_read_lock(rwlock_id: usize) {
+    sys_rwlock_read_lock(rwlock_id);
+}
+pub fn rwlock_write_lock(rwlock_id: usize) {
+    sys_rwlock_write_lock(rwlock_id);
+}
+/// Fail with EPERM if the lock isn't held.
+pub fn rwlock_unlock(rwlock_id: usize) -> isize {
+    sys_rwlock_unlock(rwlock_id)
+}
+/// Create a barrier at which `count` threads wait for each other.
+pub fn barrier_create(count: usize) -> isize {
+    sys_barrier_create(count)
+}
+/// Return 1 for the last thread to arrive and 0 for the others.
+pub fn barrier_wait(barrier_id: usize) -> isize {
+    sys_barrier_wait(barrier_id)
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct SwapStat {
+    pub page_faults: usize,
+    pub swap_ins: usize,
+    pub swap_outs: usize,
+    pub free_frames: usize,
+}
+
+pub fn swap_stat(stat: &mut SwapStat) -> isize {
+    sys_swap_stat(stat as *mut _)
+}
+
+bitflags! {
+    pub struct MmapProt: u32 {
+        const NONE = 0;
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXEC = 1 << 2;
+    }
+}
+
+bitflags! {
+    pub struct MmapFlags: u32 {
+        const SHARED = 1 << 0;
+        const PRIVATE = 1 << 1;
+        const FIXED = 1 << 4;
+        const ANONYMOUS = 1 << 5;
+    }
+}
+
+/// Return 0 on success, or fail with ENOMEM if the program break cannot be
+/// moved to `addr`.
+pub fn brk(addr: usize) -> isize {
+    if sys_brk(addr) == addr as isize {
+        0
+    } else {
+        Errno::ENOMEM.as_ret()
+    }
+}
+/// Move the program break by `increment` bytes, return the old one or fail
+/// with ENOMEM.
+pub fn sbrk(increment: isize) -> isize {
+    let old_brk = sys_brk(0);
+    if increment == 0 || sys_brk((old_brk + increment) as usize) == old_brk + increment {
+        old_brk
+    } else {
+        Errno::ENOMEM.as_ret()
+    }
+}
+
+/// Return the start address of the mapping.
+/// `fd` is ignored for anonymous mappings.
+pub fn mmap(
+    addr: usize,
+    len: usize,
+    prot: MmapProt,
+    flags: MmapFlags,
+    fd: isize,
+    offset: usize,
+) -> isize {
+    sys_mmap(addr, len, prot.bits, flags.bits, fd, offset)
+}
+pub fn munmap(addr: usize, len: usize) -> isize {
+    sys_munmap(addr, len)
+}
+pub fn mprotect(addr: usize, len: usize, prot: MmapProt) -> isize {
+    sys_mprotect(addr, len, prot.bits)
+}
+
+/// Return the id of the shared memory segment with `key`, creating it with
+/// `size` bytes if needed. Key 0 always creates a new segment.
+pub fn shm_create(key: usize, size: usize) -> isize {
+    sys_shm_create(key, size)
+}
+/// Return the address the segment is attached at.
+pub fn shm_attach(id: usize) -> isize {
+    sys_shm_attach(id)
+}
+pub fn shm_detach(addr: usize) -> isize {
+    sys_shm_detach(addr)
+}
+/// The segment is released once every process has detached it. Fail with
+/// EPERM unless the caller has created it.
+pub fn shm_remove(id: usize) -> isize {
+    sys_shm_remove(id)
+}
+
+/// Scheduling statistics of a thread, times are in microseconds.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct SchedStat {
+    pub priority: usize,
+    pub dispatches: usize,
+    pub preemptions: usize,
+    pub run_time: usize,
+    pub wait_time: usize,
+}
+
+/// Return the priority, or fail with EINVAL if it is less than 1.
+pub fn set_priority(priority: isize) -> isize {
+    sys_set_priority(priority)
+}
+pub fn sched_stat(stat: &mut SchedStat) -> isize {
+    sys_sched_stat(stat as *mut _)
+}
+
+/// Bytes of a string or buffer argument kept in a `TraceRecord`.
+pub const TRACE_DATA_LEN: usize = 32;
+/// `flags` of a `TraceRecord`: the syscall didn't return, `ret` and
+/// `duration_us` mean nothing
+pub const TRACE_NORETURN: usize = 1 << 0;
+/// `data` was cut to `TRACE_DATA_LEN` bytes
+pub const TRACE_TRUNCATED: usize = 1 << 1;
+
+/// A syscall made by a traced process, times are in microseconds.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TraceRecord {
+    /// counted from 0 when tracing starts, a gap means records were dropped
+    pub seq: usize,
+    pub tid: usize,
+    pub id: usize,
+    pub args: [usize; 6],
+    pub ret: isize,
+    /// since boot
+    pub start_us: usize,
+    pub duration_us: usize,
+    /// `TRACE_*`
+    pub flags: usize,
+    /// the start of the path a syscall opens, the buffer it writes or the
+    /// bytes it has read
+    pub data_len: usize,
+    pub data: [u8; TRACE_DATA_LEN],
+}
+
+/// Start or stop recording the syscalls of process `pid`, the caller if it
+/// is 0 or one of its children. Stopping drops the records. Tracing goes on
+/// across exec.
+pub fn trace(pid: usize, enable: bool) -> isize {
+    sys_trace(pid, enable as usize)
+}
+/// Move the oldest records of child `pid` to `records`, return how many.
+/// Block until there is one, return 0 once the child has exited and all of
+/// them have been read.
+pub fn trace_read(pid: usize, records: &mut [TraceRecord]) -> isize {
+    sys_trace_read(pid, records)
+}
+
+/// Requests of `ptrace`, numbered as on Linux.
+pub const PTRACE_TRACEME: usize = 0;
+pub const PTRACE_PEEKDATA: usize = 2;
+pub const PTRACE_POKEDATA: usize = 5;
+pub const PTRACE_CONT: usize = 7;
+pub const PTRACE_KILL: usize = 8;
+pub const PTRACE_SINGLESTEP: usize = 9;
+pub const PTRACE_GETREGS: usize = 12;
+pub const PTRACE_SETREGS: usize = 13;
+pub const PTRACE_ATTACH: usize = 16;
+pub const PTRACE_DETACH: usize = 17;
+pub const PTRACE_SYSCALL: usize = 24;
+
+/// The registers of a thread as `PTRACE_GETREGS` reads them: the pc, then
+/// x1 to x31.
+pub type UserRegs = [usize; 32];
+
+/// Trace a child for a debugger. A traced process stops when it is about to
+/// take a signal and after exec with SIGTRAP, `waitpid` reports it, see
+/// `stop_signal`. `data` of the requests resuming it is the signal it takes
+/// then, or 0.
+pub fn ptrace(request: usize, pid: usize, addr: usize, data: usize) -> isize {
+    sys_ptrace(request, pid, addr, data)
+}
+/// Read the word at `addr` of stopped child `pid`.
+pub fn ptrace_peek(pid: usize, addr: usize, word: &mut usize) -> isize {
+    sys_ptrace(PTRACE_PEEKDATA, pid, addr, word as *mut _ as usize)
+}
+/// Write `word` at `addr` of stopped child `pid`, its code included.
+pub fn ptrace_poke(pid: usize, addr: usize, word: usize) -> isize {
+    sys_ptrace(PTRACE_POKEDATA, pid, addr, word)
+}
+pub fn ptrace_getregs(pid: usize, regs: &mut UserRegs) -> isize {
+    sys_ptrace(PTRACE_GETREGS, pid, 0, regs.as_mut_ptr() as usize)
+}
+pub fn ptrace_setregs(pid: usize, regs: &UserRegs) -> isize {
+    sys_ptrace(PTRACE_SETREGS, pid, 0, regs.as_ptr() as usize)
+}
@@ -0,0 +1,114 @@
+//! Locks in user space which only enter the kernel when they have to wait
+//! or there is someone to wake up.
+
+use super::{futex_requeue, futex_wait, futex_wake};
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+/// Locked, and some threads may be waiting for it.
+const CONTENDED: u32 = 2;
+
+pub struct Mutex {
+    state: AtomicU32,
+}
+
+impl Mutex {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(UNLOCKED),
+        }
+    }
+
+    pub fn lock(&self) {
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            self.lock_contended();
+        }
+    }
+
+    /// Whoever takes the lock here can't tell whether others are waiting,
+    /// so it leaves the state contended and wakes one up on unlock.
+    fn lock_contended(&self) {
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            futex_wait(&self.state, CONTENDED);
+        }
+    }
+
+    pub fn try_lock(&self) -> bool {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    pub fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            futex_wake(&self.state, 1);
+        }
+    }
+}
+
+impl Default for Mutex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A sequence number which is bumped on every signal, so that a waiter
+/// doesn't sleep through a signal sent after it released the mutex.
+pub struct Condvar {
+    seq: AtomicU32,
+    /// the state of the mutex used by the waiters, broadcast moves them
+    /// there instead of waking them all up to fight for the mutex
+    mutex: AtomicPtr<AtomicU32>,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            seq: AtomicU32::new(0),
+            mutex: AtomicPtr::new(null_mut()),
+        }
+    }
+
+    /// `mutex` must be locked, and is locked again when this returns.
+    /// Wakeups may be spurious.
+    pub fn wait(&self, mutex: &Mutex) {
+        self.mutex.store(
+            &mutex.state as *const _ as *mut AtomicU32,
+            Ordering::Relaxed,
+        );
+        let seq = self.seq.load(Ordering::Relaxed);
+        mutex.unlock();
+        futex_wait(&self.seq, seq);
+        // others may have been requeued to the mutex
+        mutex.lock_contended();
+    }
+
+    pub fn signal(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        futex_wake(&self.seq, 1);
+    }
+
+    pub fn broadcast(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        let mutex = self.mutex.load(Ordering::Relaxed);
+        if mutex.is_null() {
+            futex_wake(&self.seq, usize::MAX);
+        } else {
+            // wake up one, the others follow one by one as the mutex is
+            // unlocked
+            futex_requeue(&self.seq, 1, usize::MAX, unsafe { &*mutex });
+        }
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
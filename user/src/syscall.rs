@@ -1,30 +1,69 @@
-const SYSCALL_DUP: usize = 24;
-const SYSCALL_OPEN: usize = 56;
+use super::{SchedStat, SignalAction, SwapStat, TimeSpec, TimeVal, TraceRecord};
+
+const SYSCALL_DUP: usize = 23;
+const SYSCALL_IOCTL: usize = 29;
+const SYSCALL_OPENAT: usize = 56;
 const SYSCALL_CLOSE: usize = 57;
-const SYSCALL_PIPE: usize = 59;
+const SYSCALL_PIPE2: usize = 59;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
 const SYSCALL_EXIT: usize = 93;
-const SYSCALL_SLEEP: usize = 101;
+const SYSCALL_FUTEX: usize = 98;
+const SYSCALL_NANOSLEEP: usize = 101;
+const SYSCALL_PTRACE: usize = 117;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_KILL: usize = 129;
-const SYSCALL_GET_TIME: usize = 169;
+const SYSCALL_RT_SIGACTION: usize = 134;
+const SYSCALL_RT_SIGPROCMASK: usize = 135;
+const SYSCALL_SET_PRIORITY: usize = 140;
+const SYSCALL_SETPGID: usize = 154;
+const SYSCALL_GETPGID: usize = 155;
+const SYSCALL_GETSID: usize = 156;
+const SYSCALL_SETSID: usize = 157;
+const SYSCALL_GETTIMEOFDAY: usize = 169;
 const SYSCALL_GETPID: usize = 172;
-const SYSCALL_FORK: usize = 220;
-const SYSCALL_EXEC: usize = 221;
-const SYSCALL_WAITPID: usize = 260;
+const SYSCALL_BRK: usize = 214;
+const SYSCALL_MUNMAP: usize = 215;
+const SYSCALL_CLONE: usize = 220;
+const SYSCALL_EXECVE: usize = 221;
+const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MPROTECT: usize = 226;
+const SYSCALL_ENABLE_DEADLOCK_DETECT: usize = 469;
 const SYSCALL_THREAD_CREATE: usize = 1000;
 const SYSCALL_GETTID: usize = 1001;
 const SYSCALL_WAITTID: usize = 1002;
+const SYSCALL_THREAD_EXIT: usize = 1003;
+const SYSCALL_THREAD_DETACH: usize = 1004;
+const SYSCALL_THREAD_KILL: usize = 1005;
 const SYSCALL_MUTEX_CREATE: usize = 1010;
 const SYSCALL_MUTEX_LOCK: usize = 1011;
 const SYSCALL_MUTEX_UNLOCK: usize = 1012;
+const SYSCALL_MUTEX_LOCK_TIMEOUT: usize = 1013;
 const SYSCALL_SEMAPHORE_CREATE: usize = 1020;
 const SYSCALL_SEMAPHORE_UP: usize = 1021;
 const SYSCALL_SEMAPHORE_DOWN: usize = 1022;
+const SYSCALL_SEMAPHORE_DOWN_TIMEOUT: usize = 1023;
+const SYSCALL_SEMAPHORE_OPEN: usize = 1024;
 const SYSCALL_CONDVAR_CREATE: usize = 1030;
 const SYSCALL_CONDVAR_SIGNAL: usize = 1031;
 const SYSCALL_CONDVAR_WAIT: usize = 1032;
+const SYSCALL_CONDVAR_BROADCAST: usize = 1033;
+const SYSCALL_CONDVAR_WAIT_TIMEOUT: usize = 1034;
+const SYSCALL_RWLOCK_CREATE: usize = 1040;
+const SYSCALL_RWLOCK_READ_LOCK: usize = 1041;
+const SYSCALL_RWLOCK_WRITE_LOCK: usize = 1042;
+const SYSCALL_RWLOCK_UNLOCK: usize = 1043;
+const SYSCALL_BARRIER_CREATE: usize = 1050;
+const SYSCALL_BARRIER_WAIT: usize = 1051;
+const SYSCALL_SWAP_STAT: usize = 1100;
+const SYSCALL_SHM_CREATE: usize = 1110;
+const SYSCALL_SHM_ATTACH: usize = 1111;
+const SYSCALL_SHM_DETACH: usize = 1112;
+const SYSCALL_SHM_REMOVE: usize = 1113;
+const SYSCALL_SCHED_STAT: usize = 1200;
+const SYSCALL_WAITPID: usize = 1300;
+const SYSCALL_TRACE: usize = 1400;
+const SYSCALL_TRACE_READ: usize = 1401;
 
 fn syscall(id: usize, args: [usize; 3]) -> isize {
     let mut ret: isize;
@@ -40,20 +79,59 @@ fn syscall(id: usize, args: [usize; 3]) -> isize {
     ret
 }
 
+fn syscall6(id: usize, args: [usize; 6]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("x10") args[0] => ret,
+            in("x11") args[1],
+            in("x12") args[2],
+            in("x13") args[3],
+            in("x14") args[4],
+            in("x15") args[5],
+            in("x17") id
+        );
+    }
+    ret
+}
+
+pub fn sys_raw(id: usize, args: [usize; 6]) -> isize {
+    syscall6(id, args)
+}
+
 pub fn sys_dup(fd: usize) -> isize {
     syscall(SYSCALL_DUP, [fd, 0, 0])
 }
 
-pub fn sys_open(path: &str, flags: u32) -> isize {
-    syscall(SYSCALL_OPEN, [path.as_ptr() as usize, flags as usize, 0])
+pub fn sys_ioctl(fd: usize, request: usize, arg: usize) -> isize {
+    syscall(SYSCALL_IOCTL, [fd, request, arg])
+}
+
+/// `dirfd` is `AT_FDCWD` for paths which aren't relative to a directory.
+pub fn sys_openat(dirfd: isize, path: &str, flags: u32, mode: u32) -> isize {
+    syscall6(
+        SYSCALL_OPENAT,
+        [
+            dirfd as usize,
+            path.as_ptr() as usize,
+            flags as usize,
+            mode as usize,
+            0,
+            0,
+        ],
+    )
 }
 
 pub fn sys_close(fd: usize) -> isize {
     syscall(SYSCALL_CLOSE, [fd, 0, 0])
 }
 
-pub fn sys_pipe(pipe: &mut [usize]) -> isize {
-    syscall(SYSCALL_PIPE, [pipe.as_mut_ptr() as usize, 0, 0])
+pub fn sys_pipe2(pipe: &mut [i32; 2], flags: u32) -> isize {
+    syscall(
+        SYSCALL_PIPE2,
+        [pipe.as_mut_ptr() as usize, flags as usize, 0],
+    )
 }
 
 pub fn sys_read(fd: usize, buffer: &mut [u8]) -> isize {
@@ -72,39 +150,151 @@ pub fn sys_exit(exit_code: i32) -> ! {
     panic!("sys_exit never returns!");
 }
 
-pub fn sys_sleep(sleep_ms: usize) -> isize {
-    syscall(SYSCALL_SLEEP, [sleep_ms, 0, 0])
+pub fn sys_futex(
+    uaddr: *const u32,
+    op: usize,
+    val: usize,
+    val2: usize,
+    uaddr2: *const u32,
+) -> isize {
+    syscall6(
+        SYSCALL_FUTEX,
+        [uaddr as usize, op, val, val2, uaddr2 as usize, 0],
+    )
+}
+
+pub fn sys_nanosleep(req: &TimeSpec, rem: *mut TimeSpec) -> isize {
+    syscall(
+        SYSCALL_NANOSLEEP,
+        [req as *const _ as usize, rem as usize, 0],
+    )
+}
+
+pub fn sys_ptrace(request: usize, pid: usize, addr: usize, data: usize) -> isize {
+    syscall6(SYSCALL_PTRACE, [request, pid, addr, data, 0, 0])
 }
 
 pub fn sys_yield() -> isize {
     syscall(SYSCALL_YIELD, [0, 0, 0])
 }
 
-pub fn sys_kill(pid: usize, signal: i32) -> isize {
-    syscall(SYSCALL_KILL, [pid, signal as usize, 0])
+pub fn sys_kill(pid: isize, signal: i32) -> isize {
+    syscall(SYSCALL_KILL, [pid as usize, signal as usize, 0])
+}
+
+pub fn sys_rt_sigaction(
+    signum: i32,
+    action: *const SignalAction,
+    old_action: *mut SignalAction,
+) -> isize {
+    syscall6(
+        SYSCALL_RT_SIGACTION,
+        [
+            signum as usize,
+            action as usize,
+            old_action as usize,
+            core::mem::size_of::<u64>(),
+            0,
+            0,
+        ],
+    )
+}
+
+pub fn sys_rt_sigprocmask(how: usize, set: *const u64, old_set: *mut u64) -> isize {
+    syscall6(
+        SYSCALL_RT_SIGPROCMASK,
+        [
+            how,
+            set as usize,
+            old_set as usize,
+            core::mem::size_of::<u64>(),
+            0,
+            0,
+        ],
+    )
 }
 
-pub fn sys_get_time() -> isize {
-    syscall(SYSCALL_GET_TIME, [0, 0, 0])
+pub fn sys_gettimeofday(tv: &mut TimeVal) -> isize {
+    syscall(SYSCALL_GETTIMEOFDAY, [tv as *mut _ as usize, 0, 0])
 }
 
 pub fn sys_getpid() -> isize {
     syscall(SYSCALL_GETPID, [0, 0, 0])
 }
 
+pub fn sys_setpgid(pid: usize, pgid: usize) -> isize {
+    syscall(SYSCALL_SETPGID, [pid, pgid, 0])
+}
+
+pub fn sys_getpgid(pid: usize) -> isize {
+    syscall(SYSCALL_GETPGID, [pid, 0, 0])
+}
+
+pub fn sys_getsid(pid: usize) -> isize {
+    syscall(SYSCALL_GETSID, [pid, 0, 0])
+}
+
+pub fn sys_setsid() -> isize {
+    syscall(SYSCALL_SETSID, [0, 0, 0])
+}
+
 pub fn sys_fork() -> isize {
-    syscall(SYSCALL_FORK, [0, 0, 0])
+    syscall(SYSCALL_CLONE, [0, 0, 0])
 }
 
-pub fn sys_exec(path: &str, args: &[*const u8]) -> isize {
+/// The child calls `entry(arg)` and exits with what it returns, it can't
+/// return from here since it may run on another stack.
+pub fn sys_clone(
+    entry: fn(usize) -> i32,
+    arg: usize,
+    flags: usize,
+    stack: usize,
+    ptid: *mut u32,
+    tls: usize,
+    ctid: *mut u32,
+) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            "bnez a0, 1f",
+            // only the registers are left to the child
+            "mv a0, {arg}",
+            "jalr {entry}",
+            "mv a7, {exit}",
+            "ecall",
+            "1:",
+            entry = in(reg) entry,
+            arg = in(reg) arg,
+            exit = in(reg) SYSCALL_THREAD_EXIT,
+            inlateout("x10") flags => ret,
+            in("x11") stack,
+            in("x12") ptid,
+            in("x13") tls,
+            in("x14") ctid,
+            in("x17") SYSCALL_CLONE
+        );
+    }
+    ret
+}
+
+/// `envs` may be null for no environment.
+pub fn sys_execve(path: &str, args: &[*const u8], envs: *const *const u8) -> isize {
     syscall(
-        SYSCALL_EXEC,
-        [path.as_ptr() as usize, args.as_ptr() as usize, 0],
+        SYSCALL_EXECVE,
+        [
+            path.as_ptr() as usize,
+            args.as_ptr() as usize,
+            envs as usize,
+        ],
     )
 }
 
-pub fn sys_waitpid(pid: isize, exit_code: *mut i32) -> isize {
-    syscall(SYSCALL_WAITPID, [pid as usize, exit_code as usize, 0])
+pub fn sys_waitpid(pid: isize, exit_code: *mut i32, options: u32) -> isize {
+    syscall(
+        SYSCALL_WAITPID,
+        [pid as usize, exit_code as usize, options as usize],
+    )
 }
 
 pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
@@ -119,6 +309,19 @@ pub fn sys_waittid(tid: usize) -> isize {
     syscall(SYSCALL_WAITTID, [tid, 0, 0])
 }
 
+pub fn sys_thread_exit(exit_code: i32) -> ! {
+    syscall(SYSCALL_THREAD_EXIT, [exit_code as usize, 0, 0]);
+    panic!("sys_thread_exit never returns!");
+}
+
+pub fn sys_thread_detach(tid: usize) -> isize {
+    syscall(SYSCALL_THREAD_DETACH, [tid, 0, 0])
+}
+
+pub fn sys_thread_kill(tid: usize) -> isize {
+    syscall(SYSCALL_THREAD_KILL, [tid, 0, 0])
+}
+
 pub fn sys_mutex_create(blocking: bool) -> isize {
     syscall(SYSCALL_MUTEX_CREATE, [blocking as usize, 0, 0])
 }
@@ -131,6 +334,10 @@ pub fn sys_mutex_unlock(id: usize) -> isize {
     syscall(SYSCALL_MUTEX_UNLOCK, [id, 0, 0])
 }
 
+pub fn sys_mutex_lock_timeout(id: usize, timeout_ms: usize) -> isize {
+    syscall(SYSCALL_MUTEX_LOCK_TIMEOUT, [id, timeout_ms, 0])
+}
+
 pub fn sys_semaphore_create(res_count: usize) -> isize {
     syscall(SYSCALL_SEMAPHORE_CREATE, [res_count, 0, 0])
 }
@@ -143,6 +350,18 @@ pub fn sys_semaphore_down(sem_id: usize) -> isize {
     syscall(SYSCALL_SEMAPHORE_DOWN, [sem_id, 0, 0])
 }
 
+pub fn sys_semaphore_down_timeout(sem_id: usize, timeout_ms: usize) -> isize {
+    syscall(SYSCALL_SEMAPHORE_DOWN_TIMEOUT, [sem_id, timeout_ms, 0])
+}
+
+pub fn sys_semaphore_open(key: usize, res_count: usize) -> isize {
+    syscall(SYSCALL_SEMAPHORE_OPEN, [key, res_count, 0])
+}
+
+pub fn sys_enable_deadlock_detect(enabled: usize) -> isize {
+    syscall(SYSCALL_ENABLE_DEADLOCK_DETECT, [enabled, 0, 0])
+}
+
 pub fn sys_condvar_create(_arg: usize) -> isize {
     syscall(SYSCALL_CONDVAR_CREATE, [_arg, 0, 0])
 }
@@ -154,3 +373,103 @@ pub fn sys_condvar_signal(condvar_id: usize) -> isize {
 pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
     syscall(SYSCALL_CONDVAR_WAIT, [condvar_id, mutex_id, 0])
 }
+
+pub fn sys_condvar_wait_timeout(condvar_id: usize, mutex_id: usize, timeout_ms: usize) -> isize {
+    syscall(
+        SYSCALL_CONDVAR_WAIT_TIMEOUT,
+        [condvar_id, mutex_id, timeout_ms],
+    )
+}
+
+pub fn sys_condvar_broadcast(condvar_id: usize) -> isize {
+    syscall(SYSCALL_CONDVAR_BROADCAST, [condvar_id, 0, 0])
+}
+
+pub fn sys_rwlock_create(policy: usize) -> isize {
+    syscall(SYSCALL_RWLOCK_CREATE, [policy, 0, 0])
+}
+
+pub fn sys_rwlock_read_lock(rwlock_id: usize) -> isize {
+    syscall(SYSCALL_RWLOCK_READ_LOCK, [rwlock_id, 0, 0])
+}
+
+pub fn sys_rwlock_write_lock(rwlock_id: usize) -> isize {
+    syscall(SYSCALL_RWLOCK_WRITE_LOCK, [rwlock_id, 0, 0])
+}
+
+pub fn sys_rwlock_unlock(rwlock_id: usize) -> isize {
+    syscall(SYSCALL_RWLOCK_UNLOCK, [rwlock_id, 0, 0])
+}
+
+pub fn sys_barrier_create(count: usize) -> isize {
+    syscall(SYSCALL_BARRIER_CREATE, [count, 0, 0])
+}
+
+pub fn sys_barrier_wait(barrier_id: usize) -> isize {
+    syscall(SYSCALL_BARRIER_WAIT, [barrier_id, 0, 0])
+}
+
+pub fn sys_swap_stat(stat: *mut SwapStat) -> isize {
+    syscall(SYSCALL_SWAP_STAT, [stat as usize, 0, 0])
+}
+
+pub fn sys_brk(addr: usize) -> isize {
+    syscall(SYSCALL_BRK, [addr, 0, 0])
+}
+
+pub fn sys_mmap(addr: usize, len: usize, prot: u32, flags: u32, fd: isize, offset: usize) -> isize {
+    syscall6(
+        SYSCALL_MMAP,
+        [
+            addr,
+            len,
+            prot as usize,
+            flags as usize,
+            fd as usize,
+            offset,
+        ],
+    )
+}
+
+pub fn sys_munmap(addr: usize, len: usize) -> isize {
+    syscall(SYSCALL_MUNMAP, [addr, len, 0])
+}
+
+pub fn sys_mprotect(addr: usize, len: usize, prot: u32) -> isize {
+    syscall(SYSCALL_MPROTECT, [addr, len, prot as usize])
+}
+
+pub fn sys_shm_create(key: usize, size: usize) -> isize {
+    syscall(SYSCALL_SHM_CREATE, [key, size, 0])
+}
+
+pub fn sys_shm_attach(id: usize) -> isize {
+    syscall(SYSCALL_SHM_ATTACH, [id, 0, 0])
+}
+
+pub fn sys_shm_detach(addr: usize) -> isize {
+    syscall(SYSCALL_SHM_DETACH, [addr, 0, 0])
+}
+
+pub fn sys_shm_remove(id: usize) -> isize {
+    syscall(SYSCALL_SHM_REMOVE, [id, 0, 0])
+}
+
+pub fn sys_set_priority(priority: isize) -> isize {
+    syscall(SYSCALL_SET_PRIORITY, [priority as usize, 0, 0])
+}
+
+pub fn sys_sched_stat(stat: *mut SchedStat) -> isize {
+    syscall(SYSCALL_SCHED_STAT, [stat as usize, 0, 0])
+}
+
+pub fn sys_trace(pid: usize, flags: usize) -> isize {
+    syscall(SYSCALL_TRACE, [pid, flags, 0])
+}
+
+pub fn sys_trace_read(pid: usize, records: &mut [TraceRecord]) -> isize {
+    syscall(
+        SYSCALL_TRACE_READ,
+        [pid, records.as_mut_ptr() as usize, records.len()],
+    )
+}
@@ -0,0 +1,43 @@
+//! Thread-local storage. The kernel gives every thread its own copy of the
+//! `.tdata` and `.tbss` sections and points `tp` at it, which is where the
+//! compiler looks for `#[thread_local]` statics.
+
+/// A thread-local variable declared with `thread_local!`, each thread
+/// accesses its own copy. Destructors are not run when a thread exits.
+pub struct LocalKey<T: 'static> {
+    #[doc(hidden)]
+    pub inner: fn() -> *const T,
+}
+
+impl<T: 'static> LocalKey<T> {
+    /// Borrow the copy of the current thread, use a `Cell` or the like to
+    /// change it.
+    pub fn with<F, R>(&'static self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        f(unsafe { &*(self.inner)() })
+    }
+}
+
+/// Declare thread-local variables, initialized with constant expressions:
+/// `thread_local!(static COUNT: Cell<usize> = Cell::new(0));`
+#[macro_export]
+#[allow_internal_unstable(thread_local)]
+macro_rules! thread_local {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr; $($rest:tt)*) => {
+        $crate::thread_local!($(#[$attr])* $vis static $name: $t = $init);
+        $crate::thread_local!($($rest)*);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr) => {
+        $(#[$attr])* $vis const $name: $crate::tls::LocalKey<$t> = {
+            fn __get() -> *const $t {
+                #[thread_local]
+                static VALUE: $t = $init;
+                &VALUE
+            }
+            $crate::tls::LocalKey { inner: __get }
+        };
+    };
+}